@@ -1,9 +1,11 @@
-use std::path::PathBuf;
-
 fn main() {
+    //The hand written definitions are used unless they are being checked against the
+    //headers, which needs clang and the Looking Glass submodule
+    #[cfg(feature = "bindgen")]
     gen_bindings();
 }
 
+#[cfg(feature = "bindgen")]
 fn gen_bindings() {
     let bindings = bindgen::Builder::default()
         .header("src/shm_datastructs/wrapper.h")
@@ -12,7 +14,7 @@ fn gen_bindings() {
         .generate()
         .expect("Unable to generate bindings");
 
-    let out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let out_path = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
     bindings
         .write_to_file(out_path.join("common_bindings.rs"))
         .expect("Unable to write bindings to file");
@@ -0,0 +1,47 @@
+/* C interface to lookinggla-rs, exported when built with the `capi` feature:
+ *   cargo rustc --lib --release --crate-type cdylib --features capi
+ */
+#ifndef LOOKINGGLA_RS_H
+#define LOOKINGGLA_RS_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define LGRS_OK 0
+#define LGRS_ERROR -1
+
+typedef struct LgrsConnection LgrsConnection;
+
+typedef struct LgrsFrameInfo {
+    uint32_t serial;
+    /* KVMFR FrameType of the pixel data */
+    uint32_t format;
+    uint32_t width;
+    uint32_t height;
+    /* Row length in bytes */
+    uint32_t pitch;
+} LgrsFrameInfo;
+
+/* Maps the shared memory file or kvmfr device at path, returning NULL on failure. */
+LgrsConnection *lgrs_open(const char *path);
+/* As lgrs_open, but opens a shared_memory flink file. */
+LgrsConnection *lgrs_open_flink(const char *path);
+/* Starts a session with the host. Retry until it succeeds, as the host may not be running yet. */
+int lgrs_init(LgrsConnection *conn);
+/* Copies the newest frame, returning 1 if there was one, 0 if not or LGRS_ERROR. */
+int lgrs_poll_frame(LgrsConnection *conn);
+/* Returns the data of the last polled frame, valid until the next poll, or NULL if there is none. */
+const uint8_t *lgrs_frame_data(const LgrsConnection *conn, LgrsFrameInfo *info);
+/* Ends the session and frees the connection. */
+void lgrs_close(LgrsConnection *conn);
+/* Returns the last error on this thread, or NULL. */
+const char *lgrs_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,115 @@
+//! Instrumentation for counting heap allocations, used to check that the frame path does
+//! not allocate once it has warmed up.
+//!
+//! Counting only happens once [CountingAllocator] is installed as the global allocator:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOC: lookinggla_rs::alloc_count::CountingAllocator =
+//!     lookinggla_rs::alloc_count::CountingAllocator;
+//! ```
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+thread_local! {
+    /// Allocations made by the current thread
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A global allocator which forwards to [System], counting the allocations made by each
+/// thread.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+fn count() {
+    //The counter may already have been destroyed if this thread is exiting
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+/// Returns the number of allocations, including reallocations, made by the current thread
+/// so far. This is always zero unless [CountingAllocator] is the global allocator.
+pub fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Runs a closure, returning its result along with the number of allocations it made on
+/// the current thread.
+pub fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, u64) {
+    let before = allocations();
+    let res = f();
+    (res, allocations() - before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        convert::{self, ToneMap},
+        pool::{FrameLayout, FramePool, FramePoolOpts},
+        types::PixelFormat,
+    };
+
+    #[test]
+    fn counts_allocations() {
+        let (_, count) = count_allocations(|| std::hint::black_box(vec![0u8; 16]));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn steady_state_frames_do_not_allocate() {
+        let layout = FrameLayout {
+            width: 32,
+            height: 16,
+            pitch: 32 * 4,
+            format: PixelFormat::Bgra,
+        };
+        let frame = vec![0x80u8; layout.len()];
+        let mut pool = FramePool::new(FramePoolOpts::default());
+        let mut rgba = Vec::new();
+        let mut run_frame = || {
+            let mut buf = pool.acquire(layout);
+            buf.extend_from_slice(&frame);
+            convert::to_rgba8_into(
+                &buf,
+                layout.width,
+                layout.height,
+                layout.pitch,
+                layout.format,
+                ToneMap::Clamp,
+                &mut rgba,
+            );
+            while pool.poll_event().is_some() {}
+            pool.release(buf);
+        };
+
+        //The first frame allocates the pool's buffers and the conversion output
+        let (_, warmup) = count_allocations(&mut run_frame);
+        assert!(warmup > 0);
+        for _ in 0..10 {
+            let (_, count) = count_allocations(&mut run_frame);
+            assert_eq!(count, 0);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Connects to a Looking Glass host and prints what it is sending, for working out why a
+//! client is not showing anything.
+//!
+//! Usage: `lg-info [--flink] [--seconds N] [PATH]`
+use std::{
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use lookinggla_rs::{
+    client::{KVMFRChans, LGEvent, LGMPConnection, LGMPOpts, LGMPSource},
+    error::LGError,
+    types::FrameInfo,
+};
+
+const DEFAULT_PATH: &str = "/dev/shm/looking-glass";
+const USAGE: &str = "Usage: lg-info [--flink] [--seconds N] [PATH]
+
+PATH defaults to /dev/shm/looking-glass, and is opened as a kvmfr device or ivshmem file
+unless --flink is given. Frames and cursor updates are sampled for --seconds, default 2.";
+/// Usage errors use the same code as [lookinggla_rs::error::ErrorCategory::Usage]
+const USAGE_EXIT_CODE: u8 = 64;
+/// How long to keep retrying init while the host may still be starting
+const INIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Args {
+    source: LGMPSource,
+    sample: Duration,
+}
+
+/// Returns None if only the usage was asked for.
+fn parse_args() -> Result<Option<Args>, String> {
+    let mut flink = false;
+    let mut sample = Duration::from_secs(2);
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--flink" => flink = true,
+            "--seconds" => {
+                let secs = args.next().ok_or("--seconds needs a value")?;
+                let secs: f64 = secs
+                    .parse()
+                    .map_err(|_| format!("Invalid seconds {secs}"))?;
+                sample = Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())?;
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(None);
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => Err(format!("Unexpected argument {arg}"))?,
+        }
+    }
+
+    let path = path.unwrap_or_else(|| DEFAULT_PATH.into());
+    let source = if flink {
+        LGMPSource::Flink(path)
+    } else {
+        LGMPSource::Device(path.into())
+    };
+    Ok(Some(Args { source, sample }))
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => return ExitCode::SUCCESS,
+        Err(msg) => {
+            eprintln!("{msg}\n\n{USAGE}");
+            return ExitCode::from(USAGE_EXIT_CODE);
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err.report());
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), LGError> {
+    let opts = LGMPOpts::builder(args.source)
+        .auto_tick(true)
+        .collect_metrics(true)
+        .build();
+    let mut conn = LGMPConnection::open(opts)?;
+    init(&mut conn)?;
+
+    if let Some(info) = conn.host_info() {
+        println!("KVMFR version:  {} (magic ok)", info.version);
+        println!("Host version:   {}", info.host_version);
+        println!("Host features:  {:?}", info.features);
+        if let Some(vm) = &info.vm {
+            println!("Capture:        {}", vm.capture);
+            println!(
+                "Guest CPU:      {} ({} sockets, {} cores, {} threads)",
+                vm.model, vm.sockets, vm.cores, vm.cpus
+            );
+        }
+        if let Some(os) = &info.os {
+            println!("Guest OS:       {:?} {}", os.os, os.name);
+        }
+    }
+    if let Some(quirks) = conn.quirks().filter(|quirks| !quirks.is_empty()) {
+        println!("Quirks:         {quirks:?}");
+    }
+
+    let sampled = sample(&mut conn, args.sample)?;
+    println!();
+    match &sampled.frame {
+        Some(frame) => {
+            println!("Frame format:   {:?}", frame.format);
+            println!(
+                "Resolution:     {}x{} (screen {}x{})",
+                frame.frame_width, frame.frame_height, frame.screen_width, frame.screen_height
+            );
+            println!("Rotation:       {:?}", frame.rotation);
+            println!("Pitch:          {} bytes", frame.pitch);
+        }
+        None => println!("Frame format:   no frames recieved"),
+    }
+    let secs = args.sample.as_secs_f64().max(f64::EPSILON);
+    println!("Frame rate:     {:.1} fps", sampled.frames as f64 / secs);
+    println!(
+        "Cursor rate:    {:.1} updates/s",
+        sampled.cursor_updates as f64 / secs
+    );
+
+    println!();
+    for (name, channel) in [("Frame", KVMFRChans::Frame), ("Cursor", KVMFRChans::Cursor)] {
+        if let Some(status) = conn.queue_status(channel)? {
+            println!(
+                "{name} queue:{:pad$}pending {}, {:?} until timeout",
+                "",
+                status.pending,
+                status.until_timeout,
+                pad = 8 - name.len()
+            );
+        }
+    }
+    let stats = conn.stats();
+    println!(
+        "Skipped frames: {}, anomalies: {}, fast forwards: {}",
+        stats.frames_skipped, stats.anomalies, stats.fast_forwards
+    );
+    let health = conn.health()?;
+    println!("Health:         {:?}", health.status);
+    for reason in health.reasons {
+        println!("                {reason:?}");
+    }
+    Ok(())
+}
+
+/// Initialises the session, retrying while the host may still be starting up.
+fn init(conn: &mut LGMPConnection) -> Result<(), LGError> {
+    let start = Instant::now();
+    loop {
+        match conn.init() {
+            Err(err) if err.requires_reconnect() && start.elapsed() < INIT_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            res => return res,
+        }
+    }
+}
+
+struct Sampled {
+    frame: Option<FrameInfo>,
+    frames: u64,
+    cursor_updates: u64,
+}
+
+/// Reads from both queues for the given time, keeping the newest frame details.
+fn sample(conn: &mut LGMPConnection, time: Duration) -> Result<Sampled, LGError> {
+    let mut sampled = Sampled {
+        frame: None,
+        frames: 0,
+        cursor_updates: 0,
+    };
+    let start = Instant::now();
+    while start.elapsed() < time {
+        match conn.poll_event()? {
+            LGEvent::Frame(frame) => {
+                sampled.frames += 1;
+                if let Ok(info) = frame.info() {
+                    sampled.frame = Some(info);
+                }
+            }
+            LGEvent::Cursor(_) => sampled.cursor_updates += 1,
+            LGEvent::Idle => std::thread::sleep(Duration::from_millis(1)),
+            _ => (),
+        }
+    }
+    Ok(sampled)
+}
@@ -0,0 +1,149 @@
+//! Discovery of the optional features compiled into the crate, and of what the machine it
+//! is running on supports, so that frontends can adapt without knowing how the crate was
+//! built.
+use std::path::PathBuf;
+
+bitflags::bitflags! {
+    /// Optional parts of the crate which were enabled when it was built.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Features: u32 {
+        /// The LGMP client and host, from the `lgmp` feature
+        const LGMP = 1 << 0;
+        /// LGMP is implemented in Rust rather than by the C library
+        const NATIVE_LGMP = 1 << 1;
+        /// Export of frames as dmabufs, available with `lgmp` on Linux
+        const DMABUF = 1 << 2;
+        const WGPU = 1 << 3;
+        const OPENCL = 1 << 4;
+        const PNG = 1 << 5;
+        const METRICS = 1 << 6;
+        const CAPI = 1 << 7;
+        /// The mock host, from the `testing` feature
+        const TESTING = 1 << 8;
+        /// Conversion and copies of large frames are split across threads
+        const PARALLEL = 1 << 9;
+        const BLACK_BOX = 1 << 10;
+    }
+}
+
+impl Features {
+    /// Returns the features this build of the crate was compiled with.
+    pub fn compiled() -> Features {
+        let flags = [
+            (cfg!(feature = "lgmp"), Features::LGMP),
+            (cfg!(feature = "native-lgmp"), Features::NATIVE_LGMP),
+            (
+                cfg!(all(feature = "lgmp", target_os = "linux")),
+                Features::DMABUF,
+            ),
+            (cfg!(feature = "wgpu"), Features::WGPU),
+            (cfg!(feature = "opencl"), Features::OPENCL),
+            (cfg!(feature = "png"), Features::PNG),
+            (cfg!(feature = "metrics"), Features::METRICS),
+            (cfg!(feature = "capi"), Features::CAPI),
+            (cfg!(feature = "testing"), Features::TESTING),
+            (cfg!(feature = "parallel"), Features::PARALLEL),
+            (cfg!(feature = "black-box"), Features::BLACK_BOX),
+        ];
+        flags
+            .into_iter()
+            .filter(|(enabled, _)| *enabled)
+            .fold(Features::empty(), |acc, (_, flag)| acc | flag)
+    }
+}
+
+/// Widest SIMD instruction set used by the pixel conversions in [crate::convert].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SimdLevel {
+    /// Only the scalar fallbacks are used
+    None,
+    Ssse3,
+    Avx2,
+    Neon,
+}
+
+impl SimdLevel {
+    /// Returns the SIMD level supported by the current CPU.
+    pub fn detect() -> SimdLevel {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return SimdLevel::Avx2;
+            }
+            if is_x86_feature_detected!("ssse3") {
+                return SimdLevel::Ssse3;
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            return SimdLevel::Neon;
+        }
+        #[allow(unreachable_code)]
+        SimdLevel::None
+    }
+}
+
+/// What is available to the current process, as returned by [capabilities].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Optional features the crate was compiled with
+    pub features: Features,
+    /// kvmfr device nodes found under `/dev`, sorted by name
+    pub kvmfr_devices: Vec<PathBuf>,
+    /// Whether frames could be exported as dmabufs, which needs both [Features::DMABUF]
+    /// and a kvmfr device to connect through
+    pub dmabuf_export: bool,
+    pub simd: SimdLevel,
+}
+
+/// Reports the features compiled into the crate and the capabilities of the machine it
+/// is running on.
+///
+/// This looks for kvmfr devices each time it is called, so should not be called on
+/// every frame.
+pub fn capabilities() -> Capabilities {
+    let features = Features::compiled();
+    let kvmfr_devices = kvmfr_devices();
+    Capabilities {
+        dmabuf_export: features.contains(Features::DMABUF) && !kvmfr_devices.is_empty(),
+        features,
+        kvmfr_devices,
+        simd: SimdLevel::detect(),
+    }
+}
+
+/// Lists the kvmfr devices created by the kvmfr kernel module.
+fn kvmfr_devices() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir("/dev") else {
+        return Vec::new();
+    };
+    let mut devices: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("kvmfr"))
+        .map(|entry| entry.path())
+        .collect();
+    devices.sort();
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_compiled_features() {
+        let caps = capabilities();
+        assert_eq!(
+            caps.features.contains(Features::LGMP),
+            cfg!(feature = "lgmp")
+        );
+        assert_eq!(
+            caps.features.contains(Features::TESTING),
+            cfg!(feature = "testing")
+        );
+        assert!(caps.dmabuf_export <= caps.features.contains(Features::DMABUF));
+        if cfg!(target_arch = "aarch64") {
+            assert_eq!(caps.simd, SimdLevel::Neon);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Minimal C ABI over [LGMPConnection], for projects which cannot use the Rust API
+//! directly. The matching declarations are in `include/lookinggla_rs.h`, and the shared
+//! library is built with `cargo rustc --lib --release --crate-type cdylib --features capi`.
+//!
+//! Connections are opaque pointers created by [lgrs_open] and freed by [lgrs_close]. Calls
+//! returning `int` give [LGRS_OK] on success and [LGRS_ERROR] on failure, after which
+//! [lgrs_last_error] describes what went wrong. Only the frame channel is subscribed, and
+//! it is ticked automatically whenever it is polled.
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    ptr,
+};
+
+use crate::{
+    client::{ArcFrame, LGMPConnection, LGMPOpts, LGMPSource},
+    error::LGError,
+};
+
+pub const LGRS_OK: c_int = 0;
+pub const LGRS_ERROR: c_int = -1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A connection along with the last frame copied out of it.
+pub struct LgrsConnection {
+    conn: LGMPConnection,
+    frame: Option<ArcFrame>,
+}
+
+/// Description of the frame returned by [lgrs_frame_data].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LgrsFrameInfo {
+    pub serial: u32,
+    /// KVMFR `FrameType` of the pixel data
+    pub format: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Row length in bytes
+    pub pitch: u32,
+}
+
+fn set_error(err: LGError) {
+    let msg = CString::new(err.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg));
+}
+
+fn status(result: Result<(), LGError>) -> c_int {
+    match result {
+        Ok(()) => LGRS_OK,
+        Err(err) => {
+            set_error(err);
+            LGRS_ERROR
+        }
+    }
+}
+
+unsafe fn open(path: *const c_char, source: fn(String) -> LGMPSource) -> *mut LgrsConnection {
+    if path.is_null() {
+        set_error(LGError::NullPointer);
+        return ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    let opts = LGMPOpts::builder(source(path))
+        .subscribe_cursor(false)
+        .auto_tick(true)
+        .build();
+    match LGMPConnection::open(opts) {
+        Ok(conn) => Box::into_raw(Box::new(LgrsConnection { conn, frame: None })),
+        Err(err) => {
+            set_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Maps the shared memory file or kvmfr device at `path`, returning null on failure.
+///
+/// # Safety
+/// `path` must be null or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lgrs_open(path: *const c_char) -> *mut LgrsConnection {
+    #[cfg(unix)]
+    return open(path, |path| LGMPSource::Device(path.into()));
+    #[cfg(not(unix))]
+    return open(path, LGMPSource::Flink);
+}
+
+/// As [lgrs_open], but opens a `shared_memory` flink file such as those created by the
+/// host in this crate.
+///
+/// # Safety
+/// `path` must be null or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lgrs_open_flink(path: *const c_char) -> *mut LgrsConnection {
+    open(path, LGMPSource::Flink)
+}
+
+/// Starts a session with the host. This must be retried until it succeeds, as the host
+/// may not have started yet.
+///
+/// # Safety
+/// `conn` must have been returned by [lgrs_open] and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn lgrs_init(conn: *mut LgrsConnection) -> c_int {
+    let conn = &mut *conn;
+    status(conn.conn.init())
+}
+
+/// Copies the newest waiting frame out of shared memory, returning 1 if there was one, 0
+/// if nothing new has arrived or [LGRS_ERROR] on failure. Older waiting frames are skipped.
+///
+/// # Safety
+/// `conn` must have been returned by [lgrs_open] and not yet closed. This invalidates any
+/// pointer previously returned by [lgrs_frame_data].
+#[no_mangle]
+pub unsafe extern "C" fn lgrs_poll_frame(conn: *mut LgrsConnection) -> c_int {
+    let conn = &mut *conn;
+    let frame = match conn.conn.get_latest_frame_update() {
+        Ok(Some(frame)) => frame.to_arc(),
+        Ok(None) => return 0,
+        Err(err) => Err(err),
+    };
+    match frame {
+        Ok(frame) => {
+            conn.frame = Some(frame);
+            1
+        }
+        Err(err) => {
+            set_error(err);
+            LGRS_ERROR
+        }
+    }
+}
+
+/// Returns the pixel data of the last frame read by [lgrs_poll_frame], writing its
+/// description to `info` if it is not null. Returns null if no frame has been read yet.
+///
+/// # Safety
+/// `conn` must have been returned by [lgrs_open] and not yet closed, and `info` must be
+/// null or valid for writes. The returned data is valid until the next call to
+/// [lgrs_poll_frame] or [lgrs_close].
+#[no_mangle]
+pub unsafe extern "C" fn lgrs_frame_data(
+    conn: *const LgrsConnection,
+    info: *mut LgrsFrameInfo,
+) -> *const u8 {
+    let Some(frame) = &(*conn).frame else {
+        return ptr::null();
+    };
+    if !info.is_null() {
+        info.write(LgrsFrameInfo {
+            serial: frame.serial,
+            format: frame.format.into(),
+            width: frame.width,
+            height: frame.height,
+            pitch: frame.pitch,
+        });
+    }
+    frame.data().as_ptr()
+}
+
+/// Ends the session and frees the connection. Does nothing if `conn` is null.
+///
+/// # Safety
+/// `conn` must be null or have been returned by [lgrs_open] and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn lgrs_close(conn: *mut LgrsConnection) {
+    if !conn.is_null() {
+        drop(Box::from_raw(conn));
+    }
+}
+
+/// Returns a description of the last error on this thread, or null if there has not been
+/// one. The string is valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn lgrs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |msg| msg.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_open_failure() {
+        let path = CString::new("/nonexistent/looking-glass").unwrap();
+        let conn = unsafe { lgrs_open(path.as_ptr()) };
+        assert!(conn.is_null());
+        assert!(!lgrs_last_error().is_null());
+        unsafe { lgrs_close(conn) };
+    }
+}
@@ -0,0 +1,119 @@
+//! Helpers for saving frames as PNG screenshots, without needing a renderer.
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{CapturedFrame, LGEvent, LGMPConnection},
+    convert::{self, ToneMap},
+    error::LGError,
+};
+
+/// Time to wait between polls while waiting for a frame.
+const POLL_PERIOD: Duration = Duration::from_millis(1);
+
+/// Waits for the next frame to arrive on a connection and copies it out of shared memory.
+///
+/// Other events received in the meantime are discarded. Fails with
+/// [LGError::CaptureTimedOut] if no frame arrives within `timeout`.
+pub fn capture_next(
+    conn: &mut LGMPConnection,
+    timeout: Duration,
+) -> Result<CapturedFrame, LGError> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let LGEvent::Frame(frame) = conn.poll_event()? {
+            let info = frame.info()?;
+            return Ok(CapturedFrame {
+                serial: info.serial,
+                info,
+                data: frame.copy_data()?,
+            });
+        }
+        conn.tick_frame()?;
+        std::thread::sleep(POLL_PERIOD);
+    }
+    Err(LGError::CaptureTimedOut)
+}
+
+/// Encodes a captured frame as an 8 bit RGBA PNG. HDR frames are tone mapped with
+/// [ToneMap::Reinhard].
+pub fn write_png(frame: &CapturedFrame, writer: impl Write) -> Result<(), LGError> {
+    let info = &frame.info;
+    let tone_map = if info.format.is_hdr() {
+        ToneMap::Reinhard
+    } else {
+        ToneMap::Clamp
+    };
+    let pixels = convert::to_rgba8(
+        &frame.data,
+        info.data_width,
+        info.data_height,
+        info.pitch,
+        info.format,
+        tone_map,
+    );
+
+    let mut encoder = png::Encoder::new(writer, info.data_width, info.data_height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(())
+}
+
+/// Waits for the next frame as in [capture_next], and saves it as a PNG file.
+pub fn capture_png(
+    conn: &mut LGMPConnection,
+    path: impl AsRef<Path>,
+    timeout: Duration,
+) -> Result<(), LGError> {
+    let frame = capture_next(conn, timeout)?;
+    let file = std::fs::File::create(path)?;
+    write_png(&frame, std::io::BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::FrameBuffer,
+        types::{FrameFlags, FrameInfo, PixelFormat, Rotation},
+    };
+
+    #[test]
+    fn writes_png() {
+        let frame = CapturedFrame {
+            serial: 1,
+            info: FrameInfo {
+                format_ver: 1,
+                serial: 1,
+                format: PixelFormat::Bgra,
+                screen_width: 2,
+                screen_height: 1,
+                data_width: 2,
+                data_height: 1,
+                frame_width: 2,
+                frame_height: 1,
+                rotation: Rotation::Rot0,
+                pitch: 8,
+                stride: 2,
+                flags: FrameFlags::empty(),
+            },
+            data: FrameBuffer::copy_from(
+                &[0x30, 0x20, 0x10, 0xff, 0, 0, 0, 0xff],
+                &Default::default(),
+            ),
+        };
+        let mut out = Vec::new();
+        write_png(&frame, &mut out).unwrap();
+
+        let mut reader = png::Decoder::new(std::io::Cursor::new(out))
+            .read_info()
+            .unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, [0x10, 0x20, 0x30, 0xff, 0, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,249 @@
+//! Classification of frame contents on a background thread, for policies such as only
+//! recording while a game is on screen or skipping loading screens.
+//!
+//! Frames are shrunk to a small RGBA thumbnail before being handed over, so the classifier
+//! never holds on to shared memory and the copy is cheap enough to make for every frame.
+use std::{
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+#[cfg(feature = "lgmp")]
+use crate::{client::KVMFRFrameHandle, error::LGError};
+use crate::{
+    convert::{self, ToneMap},
+    threads::{ThreadMonitor, ThreadRole},
+    types::PixelFormat,
+};
+
+/// A downsampled copy of a frame, passed to a [FrameClassifier].
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    /// Serial of the frame the thumbnail was taken from
+    pub serial: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed 8 bit RGBA pixels
+    pub data: Vec<u8>,
+}
+
+/// Works out a label describing the contents of a frame.
+///
+/// This is implemented for any `FnMut(&Thumbnail) -> L` closure, so a trait implementation
+/// is only needed for classifiers with more involved state.
+pub trait FrameClassifier: Send + 'static {
+    type Label: Clone + Send + 'static;
+
+    fn classify(&mut self, thumbnail: &Thumbnail) -> Self::Label;
+}
+
+impl<F, L> FrameClassifier for F
+where
+    F: FnMut(&Thumbnail) -> L + Send + 'static,
+    L: Clone + Send + 'static,
+{
+    type Label = L;
+
+    fn classify(&mut self, thumbnail: &Thumbnail) -> L {
+        self(thumbnail)
+    }
+}
+
+/// The label given to a frame by a [FrameClassifier].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification<L> {
+    /// Serial of the frame which was classified
+    pub serial: u32,
+    pub label: L,
+}
+
+/// Options for a [ClassifierWorker].
+#[derive(Debug, Clone)]
+pub struct ClassifierOpts {
+    /// Longest edge of the thumbnails passed to the classifier, in pixels
+    pub max_size: u32,
+    pub tone_map: ToneMap,
+}
+
+impl Default for ClassifierOpts {
+    fn default() -> Self {
+        ClassifierOpts {
+            max_size: 64,
+            tone_map: ToneMap::Clamp,
+        }
+    }
+}
+
+/// Runs a [FrameClassifier] on its own thread.
+///
+/// At most one thumbnail waits for the classifier at a time, and frames submitted while it
+/// is busy are skipped, so a slow classifier falls behind rather than delaying the
+/// connection or building up a backlog.
+pub struct ClassifierWorker<L> {
+    opts: ClassifierOpts,
+    sender: Option<SyncSender<Thumbnail>>,
+    latest: Arc<Mutex<Option<Classification<L>>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<L: Clone + Send + 'static> ClassifierWorker<L> {
+    pub fn spawn(
+        mut classifier: impl FrameClassifier<Label = L>,
+        opts: ClassifierOpts,
+    ) -> ClassifierWorker<L> {
+        let (sender, receiver) = mpsc::sync_channel::<Thumbnail>(1);
+        let latest = Arc::new(Mutex::new(None));
+        let results = latest.clone();
+        let thread = std::thread::Builder::new()
+            .name("lg-classifier".into())
+            .spawn(move || {
+                let monitor = ThreadMonitor::register(ThreadRole::Classifier);
+                for thumbnail in receiver {
+                    let label = classifier.classify(&thumbnail);
+                    if let Ok(mut latest) = results.lock() {
+                        *latest = Some(Classification {
+                            serial: thumbnail.serial,
+                            label,
+                        });
+                    }
+                    monitor.beat();
+                }
+            })
+            .expect("Failed to spawn classifier thread");
+        ClassifierWorker {
+            opts,
+            sender: Some(sender),
+            latest,
+            thread: Some(thread),
+        }
+    }
+
+    /// Downsamples a frame and queues it for classification, returning false if it was
+    /// skipped because the classifier is still busy with an earlier frame.
+    ///
+    /// Panics if `data` is too small for a frame with the provided dimensions.
+    pub fn submit(
+        &self,
+        serial: u32,
+        data: &[u8],
+        (width, height): (u32, u32),
+        pitch: u32,
+        format: PixelFormat,
+    ) -> bool {
+        let Some(sender) = &self.sender else {
+            return false;
+        };
+        let thumbnail = thumbnail(serial, data, (width, height), pitch, format, &self.opts);
+        sender.try_send(thumbnail).is_ok()
+    }
+
+    /// As [Self::submit], but takes the frame from a client connection.
+    #[cfg(feature = "lgmp")]
+    pub fn submit_frame(&self, frame: &KVMFRFrameHandle) -> Result<bool, LGError> {
+        let info = frame.info()?;
+        Ok(self.submit(
+            info.serial,
+            frame.data()?,
+            (info.data_width, info.data_height),
+            info.pitch,
+            info.format,
+        ))
+    }
+
+    /// Returns the label of the most recently classified frame, if any has finished.
+    pub fn latest(&self) -> Option<Classification<L>> {
+        self.latest.lock().ok()?.clone()
+    }
+}
+
+impl<L> Drop for ClassifierWorker<L> {
+    fn drop(&mut self) {
+        //Closing the channel ends the worker once it finishes the current thumbnail
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Takes a nearest neighbour sample of a frame no larger than `opts.max_size` on either
+/// edge, converted to RGBA.
+fn thumbnail(
+    serial: u32,
+    data: &[u8],
+    (width, height): (u32, u32),
+    pitch: u32,
+    format: PixelFormat,
+    opts: &ClassifierOpts,
+) -> Thumbnail {
+    let scale = width.max(height).div_ceil(opts.max_size.max(1)).max(1);
+    let (thumb_width, thumb_height) = (width.div_ceil(scale), height.div_ceil(scale));
+    let bpp = format.bytes_per_pixel() as usize;
+
+    let mut sampled = Vec::with_capacity(thumb_width as usize * thumb_height as usize * bpp);
+    for y in 0..thumb_height {
+        let row = (y * scale) as usize * pitch as usize;
+        for x in 0..thumb_width {
+            let start = row + (x * scale) as usize * bpp;
+            sampled.extend_from_slice(&data[start..start + bpp]);
+        }
+    }
+    let data = convert::to_rgba8(
+        &sampled,
+        thumb_width,
+        thumb_height,
+        thumb_width * bpp as u32,
+        format,
+        opts.tone_map,
+    );
+    Thumbnail {
+        serial,
+        width: thumb_width,
+        height: thumb_height,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn downsamples_frames() {
+        //A 4x2 BGRA frame, where each pixel's blue channel holds its column
+        let data: Vec<u8> = (0..8).flat_map(|i| [i % 4, 0, 0, 255]).collect();
+        let opts = ClassifierOpts {
+            max_size: 2,
+            ..Default::default()
+        };
+        let thumb = thumbnail(1, &data, (4, 2), 16, PixelFormat::Bgra, &opts);
+        assert_eq!((thumb.width, thumb.height), (2, 1));
+        assert_eq!(thumb.data, [0, 0, 0, 255, 0, 0, 2, 255]);
+    }
+
+    #[test]
+    fn classifies_on_worker() {
+        let worker = ClassifierWorker::spawn(
+            |thumb: &Thumbnail| thumb.data[3] == 255,
+            ClassifierOpts::default(),
+        );
+        assert!(worker.submit(7, &[0, 0, 0, 255], (1, 1), 4, PixelFormat::Bgra));
+
+        let start = Instant::now();
+        while worker.latest().is_none() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            worker.latest(),
+            Some(Classification {
+                serial: 7,
+                label: true
+            })
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use crate::types::PixelFormat;
+
+/// Frame data copied out of shared memory into reference counted storage, created by
+/// [super::KVMFRFrameHandle::to_arc] or [super::KVMFRFrameHandle::to_arc_rgba8].
+///
+/// Unlike a frame handle this has no lifetime and is cheap to clone, so it can be held
+/// inside the retained widget trees of GUI frameworks which need `'static` image data.
+#[derive(Debug, Clone)]
+pub struct ArcFrame {
+    /// Serial of the frame the data was copied from
+    pub serial: u32,
+    pub format: PixelFormat,
+    /// Width of the frame data, in pixels
+    pub width: u32,
+    /// Height of the frame data, in pixels
+    pub height: u32,
+    /// Row length in bytes
+    pub pitch: u32,
+    data: Arc<[u8]>,
+}
+
+impl ArcFrame {
+    pub(super) fn new(
+        serial: u32,
+        format: PixelFormat,
+        (width, height): (u32, u32),
+        pitch: u32,
+        data: Arc<[u8]>,
+    ) -> ArcFrame {
+        ArcFrame {
+            serial,
+            format,
+            width,
+            height,
+            pitch,
+            data,
+        }
+    }
+
+    /// Returns the pixel data, which is `pitch * height` bytes long.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns a new reference to the pixel data, for frameworks which take shared byte
+    /// buffers.
+    pub fn shared_data(&self) -> Arc<[u8]> {
+        self.data.clone()
+    }
+
+    /// Returns true if rows are tightly packed, which many image types require.
+    pub fn is_packed(&self) -> bool {
+        self.pitch == self.width * self.format.bytes_per_pixel()
+    }
+}
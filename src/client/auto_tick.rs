@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+/// Tracks how often the accessors of a channel are called, so that they can tick the
+/// channel themselves when [super::LGMPOptsBuilder::auto_tick] is enabled.
+///
+/// The margin used in place of the tick period is the longest recent gap between calls,
+/// decaying slowly so that one stall keeps it raised for a while. It is doubled whenever the
+/// host times the channel out anyway.
+pub(super) struct AutoTick {
+    last_call: Option<Instant>,
+    margin: Duration,
+    timeouts: u64,
+}
+
+impl AutoTick {
+    pub(super) fn new() -> AutoTick {
+        AutoTick {
+            last_call: None,
+            margin: Duration::ZERO,
+            timeouts: 0,
+        }
+    }
+
+    /// Records a call at `now`, returning the margin before the projected host timeout at
+    /// which the channel should be fast-forwarded.
+    ///
+    /// `timeouts` is the number of queue timeouts seen on the channel so far. The margin is
+    /// kept between `min_margin` and half of `timeout`.
+    pub(super) fn record_call(
+        &mut self,
+        now: Instant,
+        timeouts: u64,
+        min_margin: Duration,
+        timeout: Duration,
+    ) -> Duration {
+        if let Some(last) = self.last_call {
+            let gap = now.saturating_duration_since(last);
+            self.margin = gap.max(self.margin - self.margin / 8);
+        }
+        if timeouts > self.timeouts {
+            self.margin *= 2;
+        }
+        self.timeouts = timeouts;
+        self.last_call = Some(now);
+        self.margin = self.margin.min(timeout / 2).max(min_margin);
+        self.margin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn margin_follows_call_gaps() {
+        let min = Duration::from_millis(1);
+        let timeout = Duration::from_millis(1000);
+        let start = Instant::now();
+        let mut tick = AutoTick::new();
+        assert_eq!(tick.record_call(start, 0, min, timeout), min);
+
+        //A frame-rate caller needs a margin of one frame
+        let mut now = start;
+        for _ in 0..4 {
+            now += Duration::from_millis(16);
+            assert_eq!(
+                tick.record_call(now, 0, min, timeout),
+                Duration::from_millis(16)
+            );
+        }
+
+        //Stalls raise the margin until they have passed, and timeouts raise it further
+        now += Duration::from_millis(200);
+        assert_eq!(
+            tick.record_call(now, 0, min, timeout),
+            Duration::from_millis(200)
+        );
+        now += Duration::from_millis(16);
+        assert_eq!(
+            tick.record_call(now, 0, min, timeout),
+            Duration::from_millis(175)
+        );
+        now += Duration::from_millis(16);
+        assert_eq!(
+            tick.record_call(now, 1, min, timeout),
+            Duration::from_micros(306_250)
+        );
+        now += Duration::from_millis(900);
+        assert_eq!(tick.record_call(now, 1, min, timeout), timeout / 2);
+    }
+}
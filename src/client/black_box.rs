@@ -0,0 +1,294 @@
+//! Keeps the last few frames received by a connection on disk, along with a journal of its
+//! events, so that intermittent problems reported by users can be looked into afterwards.
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use super::{
+    replay::{write_record, KIND_FRAME, RECORDING_MAGIC},
+    KVMFRFrameHandle, LGEvent,
+};
+use crate::{
+    error::LGError,
+    threads::{ThreadMonitor, ThreadRole},
+};
+
+/// Name of the journal within a black box directory
+const JOURNAL_NAME: &str = "journal.log";
+/// Name of the recording written when a black box is finalized
+const RECORDING_NAME: &str = "recording.lgrec";
+const SLOT_PREFIX: &str = "frame-";
+const SLOT_SUFFIX: &str = ".gz";
+
+/// Something to be written by the black box thread. Times are in microseconds since the
+/// black box was started.
+enum Entry {
+    Frame {
+        time: u64,
+        serial: Option<u32>,
+        udata: u32,
+        bytes: Vec<u8>,
+    },
+    Event {
+        time: u64,
+        text: String,
+    },
+}
+
+/// A black box started by [super::LGMPConnection::start_black_box].
+///
+/// Each frame kept is written to a slot file holding a single gzip compressed message in
+/// the format used by recordings, and the slots are reused once there are as many as were
+/// asked for. Each line of the journal is the time since the black box was started in
+/// microseconds, followed by a description of the event.
+pub(super) struct BlackBox {
+    dir: PathBuf,
+    start: Instant,
+    sender: Option<mpsc::Sender<Entry>>,
+    //Set while a frame is waiting to be written, during which newer frames are not kept
+    busy: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), LGError>>>,
+    //Cleared by stop, so that dropping the black box without stopping it finalizes it
+    finalize: bool,
+}
+
+impl BlackBox {
+    pub(super) fn start(dir: PathBuf, frames: usize) -> Result<BlackBox, LGError> {
+        std::fs::create_dir_all(&dir)?;
+        //Slots left by an earlier black box would be mixed in with the new ones
+        for slot in slots(&dir)? {
+            std::fs::remove_file(slot)?;
+        }
+        match std::fs::remove_file(dir.join(RECORDING_NAME)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)?,
+            _ => {}
+        }
+        let journal = BufWriter::new(File::create(dir.join(JOURNAL_NAME))?);
+
+        let (sender, receiver) = mpsc::channel();
+        let busy = Arc::new(AtomicBool::new(false));
+        let mut writer = SlotWriter {
+            dir: dir.clone(),
+            journal,
+            busy: busy.clone(),
+            slots: frames.max(1),
+            next_slot: 0,
+        };
+        let thread = std::thread::Builder::new()
+            .name("lg-black-box".into())
+            .spawn(move || {
+                let monitor = ThreadMonitor::register(ThreadRole::BlackBox);
+                for entry in receiver {
+                    writer.write(entry)?;
+                    monitor.beat();
+                }
+                Ok(())
+            })
+            .map_err(LGError::ThreadSpawnError)?;
+
+        Ok(BlackBox {
+            dir,
+            start: Instant::now(),
+            sender: Some(sender),
+            busy,
+            thread: Some(thread),
+            finalize: true,
+        })
+    }
+
+    /// Queues a frame to be kept, unless the previous one is still being written.
+    pub(super) fn record_frame(&self, frame: &KVMFRFrameHandle) {
+        let time = self.time();
+        let serial = frame.as_frame().ok().map(|header| header.frameSerial);
+        if self.busy.swap(true, Ordering::AcqRel) {
+            let frame = describe_frame(serial);
+            let text = format!("{frame} not kept, the previous frame was still being written");
+            self.send(Entry::Event { time, text });
+            return;
+        }
+        let (udata, bytes) = frame.raw();
+        self.send(Entry::Frame {
+            time,
+            serial,
+            udata,
+            bytes: bytes.to_vec(),
+        });
+    }
+
+    /// Notes the result of [super::LGMPConnection::poll_event] in the journal. Frames are
+    /// noted once they have been kept, and cursor updates, idle polls and stats are not
+    /// noted at all.
+    pub(super) fn record_event(&self, event: &Result<LGEvent, LGError>) {
+        let text = match event {
+            Ok(LGEvent::FormatChanged(ver)) => format!("format changed to version {ver}"),
+            Ok(LGEvent::HostLost) => "host lost".into(),
+            Ok(LGEvent::Reconnected) => "reconnected".into(),
+            Ok(LGEvent::HostConfigChanged) => "host config changed".into(),
+            Ok(LGEvent::SessionStarted(id)) => format!("session started as client {id}"),
+            Ok(LGEvent::SessionLost(id)) => format!("session lost for client {id}"),
+            Ok(LGEvent::Anomaly(anomaly)) => format!("anomaly {anomaly:?}"),
+            Ok(LGEvent::Frame(_) | LGEvent::Cursor(_) | LGEvent::Idle | LGEvent::Stats(_)) => {
+                return
+            }
+            Err(e) => format!("error: {e}"),
+        };
+        let time = self.time();
+        self.send(Entry::Event { time, text });
+    }
+
+    /// Stops writing without finalizing, returning any error which stopped the black box
+    /// writing early.
+    pub(super) fn stop(mut self) -> Result<(), LGError> {
+        self.finalize = false;
+        self.join()
+    }
+
+    fn time(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn send(&self, entry: Entry) {
+        //Sending only fails once the thread has stopped, which is reported by stop
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(entry);
+        }
+    }
+
+    fn join(&mut self) -> Result<(), LGError> {
+        //Closing the channel ends the thread once it has written everything queued
+        self.sender = None;
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(res)) => res,
+            Some(Err(_)) => Err(LGError::WorkerPanicked),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BlackBox {
+    fn drop(&mut self) {
+        //Errors can't be reported from here, and whatever was written is left on disk
+        let _ = self.join();
+        if self.finalize {
+            let _ = finalize_black_box(&self.dir);
+        }
+    }
+}
+
+/// Writes entries on the black box thread.
+struct SlotWriter {
+    dir: PathBuf,
+    journal: BufWriter<File>,
+    busy: Arc<AtomicBool>,
+    slots: usize,
+    next_slot: usize,
+}
+
+impl SlotWriter {
+    fn write(&mut self, entry: Entry) -> Result<(), LGError> {
+        match entry {
+            Entry::Frame {
+                time,
+                serial,
+                udata,
+                bytes,
+            } => {
+                let slot = self.next_slot;
+                let path = self.dir.join(format!("{SLOT_PREFIX}{slot}{SLOT_SUFFIX}"));
+                //Slots are replaced by renaming so that a crash never leaves one half written
+                let tmp = path.with_extension("tmp");
+                let file = BufWriter::new(File::create(&tmp)?);
+                let mut encoder = GzEncoder::new(file, Compression::fast());
+                write_record(&mut encoder, KIND_FRAME, time, udata, &bytes)?;
+                encoder.finish()?.flush()?;
+                std::fs::rename(&tmp, &path)?;
+                self.busy.store(false, Ordering::Release);
+
+                let frame = describe_frame(serial);
+                writeln!(self.journal, "{time} {frame} kept in slot {slot}")?;
+                self.next_slot = (slot + 1) % self.slots;
+            }
+            Entry::Event { time, text } => writeln!(self.journal, "{time} {text}")?,
+        }
+        //Flushed every time so that the journal is up to date if the process is killed
+        self.journal.flush()?;
+        Ok(())
+    }
+}
+
+/// Gathers the frames kept by a black box started with
+/// [super::LGMPConnection::start_black_box] into a recording in the same directory, in the
+/// order they were received, which can be played back with [super::ReplayConnection].
+/// Returns the path to the recording.
+///
+/// This is done automatically when a connection is dropped with its black box running, but
+/// can be called on the directory left behind if the process was killed.
+pub fn finalize_black_box(dir: impl AsRef<Path>) -> Result<PathBuf, LGError> {
+    let dir = dir.as_ref();
+    let mut records = Vec::new();
+    for slot in slots(dir)? {
+        let mut record = Vec::new();
+        GzDecoder::new(File::open(slot)?).read_to_end(&mut record)?;
+        records.push(record);
+    }
+    //Slots are reused, so the order they were written in is taken from each message's time,
+    //which follows its one byte kind
+    records.sort_by_key(|record| {
+        record
+            .get(1..9)
+            .and_then(|time| time.try_into().ok())
+            .map_or(0, u64::from_le_bytes)
+    });
+
+    let path = dir.join(RECORDING_NAME);
+    let mut recording = BufWriter::new(File::create(&path)?);
+    recording.write_all(RECORDING_MAGIC)?;
+    for record in &records {
+        recording.write_all(record)?;
+    }
+    recording.flush()?;
+
+    let mut journal = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(dir.join(JOURNAL_NAME))?;
+    writeln!(
+        journal,
+        "finalized with {} frames in {RECORDING_NAME}",
+        records.len()
+    )?;
+    Ok(path)
+}
+
+/// Lists the slot files in a black box directory.
+fn slots(dir: &Path) -> Result<Vec<PathBuf>, LGError> {
+    let mut slots = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_slot = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(SLOT_PREFIX) && name.ends_with(SLOT_SUFFIX));
+        if is_slot {
+            slots.push(path);
+        }
+    }
+    Ok(slots)
+}
+
+fn describe_frame(serial: Option<u32>) -> String {
+    match serial {
+        Some(serial) => format!("frame {serial}"),
+        None => "frame with an invalid header".into(),
+    }
+}
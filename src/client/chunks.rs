@@ -0,0 +1,130 @@
+use std::iter::FusedIterator;
+
+/// A contiguous piece of a frame's pixel data, returned by
+/// [KVMFRFrameHandle::chunks](super::KVMFRFrameHandle::chunks).
+///
+/// Chunks always start at the beginning of a row unless a single row is larger than the
+/// chunk size, in which case that row is split into pieces which each hold whole pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameChunk<'a> {
+    /// First row covered by the chunk
+    pub row: u32,
+    /// Number of rows covered by the chunk, where all but the last include their padding
+    pub rows: u32,
+    /// Offset of the chunk from the start of the frame data, in bytes
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+/// Iterator over the chunks of a frame, from the top. Every chunk holds as many rows as
+/// fit, so that frames can be fed through small buffers without copying them first.
+pub struct FrameChunks<'a> {
+    data: &'a [u8],
+    pitch: usize,
+    row_len: usize,
+    bpp: usize,
+    height: u32,
+    max_len: usize,
+    row: u32,
+    row_offset: usize,
+}
+
+impl<'a> FrameChunks<'a> {
+    /// Panics if `max_len` is smaller than a single pixel.
+    pub(super) fn new(
+        data: &'a [u8],
+        pitch: usize,
+        bpp: usize,
+        (width, height): (u32, u32),
+        max_len: usize,
+    ) -> FrameChunks<'a> {
+        assert!(max_len >= bpp, "Chunk size must hold at least one pixel");
+        FrameChunks {
+            data,
+            pitch,
+            row_len: width as usize * bpp,
+            bpp,
+            height,
+            max_len,
+            row: 0,
+            row_offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for FrameChunks<'a> {
+    type Item = FrameChunk<'a>;
+
+    fn next(&mut self) -> Option<FrameChunk<'a>> {
+        if self.row >= self.height || self.row_len == 0 {
+            return None;
+        }
+        let row = self.row;
+        let row_start = row as usize * self.pitch;
+
+        if self.row_offset == 0 && self.row_len <= self.max_len {
+            //Every row but the last is followed by its padding
+            let fit = 1 + (self.max_len - self.row_len) / self.pitch;
+            let rows = (self.height - row).min(fit.try_into().unwrap_or(u32::MAX));
+            let len = (rows as usize - 1) * self.pitch + self.row_len;
+            self.row += rows;
+            return Some(FrameChunk {
+                row,
+                rows,
+                offset: row_start,
+                data: &self.data[row_start..row_start + len],
+            });
+        }
+
+        let offset = row_start + self.row_offset;
+        let len = (self.max_len - self.max_len % self.bpp).min(self.row_len - self.row_offset);
+        self.row_offset += len;
+        if self.row_offset == self.row_len {
+            self.row += 1;
+            self.row_offset = 0;
+        }
+        Some(FrameChunk {
+            row,
+            rows: 1,
+            offset,
+            data: &self.data[offset..offset + len],
+        })
+    }
+}
+
+impl FusedIterator for FrameChunks<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extents(chunks: FrameChunks) -> Vec<(u32, u32, usize, usize)> {
+        chunks
+            .map(|chunk| (chunk.row, chunk.rows, chunk.offset, chunk.data.len()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_frame_into_chunks() {
+        //A 3x3 frame of 1 byte pixels, with a pitch of 4
+        let data = [0, 1, 2, 9, 3, 4, 5, 9, 6, 7, 8, 9];
+        assert_eq!(
+            extents(FrameChunks::new(&data, 4, 1, (3, 3), 8)),
+            [(0, 2, 0, 7), (2, 1, 8, 3)]
+        );
+        assert_eq!(
+            extents(FrameChunks::new(&data, 4, 1, (3, 3), 64)),
+            [(0, 3, 0, 11)]
+        );
+    }
+
+    #[test]
+    fn splits_rows_larger_than_chunks() {
+        //A 3x2 frame of 2 byte pixels, split into chunks smaller than a row
+        let data = [0; 12];
+        assert_eq!(
+            extents(FrameChunks::new(&data, 6, 2, (3, 2), 5)),
+            [(0, 1, 0, 4), (0, 1, 4, 2), (1, 1, 6, 4), (1, 1, 10, 2)]
+        );
+    }
+}
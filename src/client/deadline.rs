@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+/// The point at which the host will drop this client from a queue, measured from the last
+/// time the client saw the queue empty.
+///
+/// The host times a subscriber out once it has held messages for the whole timeout, so a
+/// channel must be fast-forwarded before the deadline is reached rather than after.
+///
+/// Returned by [super::LGMPConnection::deadline], for applications which schedule their
+/// own ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    last_empty: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    pub(super) fn new(last_empty: Instant, timeout: Duration) -> Deadline {
+        Deadline {
+            last_empty,
+            timeout,
+        }
+    }
+
+    /// Time since the queue was last seen empty, or zero if `now` is before then.
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_empty)
+    }
+
+    /// Time left before the host drops the client, or zero if it already has.
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.timeout.saturating_sub(self.elapsed(now))
+    }
+
+    /// Returns true if the deadline will have been reached by `now + margin`, where `margin`
+    /// is the longest the channel may go unchecked. Reaching the deadline exactly counts,
+    /// so a zero timeout is always due.
+    pub fn due_within(&self, now: Instant, margin: Duration) -> bool {
+        self.remaining(now) <= margin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn deadline_is_due_once_margin_reaches_it() {
+        let start = Instant::now();
+        let deadline = Deadline::new(start, 10 * MS);
+
+        for (elapsed, margin, due) in [
+            (0, 1, false),
+            (8, 1, false),
+            //Next check lands exactly on the deadline
+            (9, 1, true),
+            (10, 0, true),
+            (15, 0, true),
+            (0, 10, true),
+        ] {
+            let now = start + elapsed * MS;
+            assert_eq!(
+                deadline.due_within(now, margin * MS),
+                due,
+                "{elapsed}ms elapsed with a {margin}ms margin"
+            );
+        }
+        assert_eq!(deadline.remaining(start + 4 * MS), 6 * MS);
+        assert_eq!(deadline.remaining(start + 15 * MS), Duration::ZERO);
+    }
+
+    #[test]
+    fn deadline_handles_edge_cases() {
+        let start = Instant::now();
+
+        //A zero timeout is due immediately, even with no margin
+        let zero = Deadline::new(start, Duration::ZERO);
+        assert!(zero.due_within(start, Duration::ZERO));
+        assert_eq!(zero.remaining(start), Duration::ZERO);
+
+        //Clocks read before the heartbeat count as no time having passed
+        let later = Deadline::new(start + 5 * MS, 10 * MS);
+        assert_eq!(later.elapsed(start), Duration::ZERO);
+        assert!(!later.due_within(start, 9 * MS));
+
+        //Timeouts too large to add to an Instant do not overflow
+        let forever = Deadline::new(start, Duration::MAX);
+        assert!(!forever.due_within(start + 1000 * MS, 1000 * MS));
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use super::{KVMFRCursorHandle, LGEvent, LGMPConnection};
+use crate::{error::LGError, types::FrameInfo};
+
+/// Time [Dispatcher::run] sleeps for when there is nothing to handle.
+const IDLE_SLEEP: Duration = Duration::from_millis(1);
+
+type FrameHandler<'a> = Box<dyn FnMut(&FrameInfo, &[u8]) + 'a>;
+type CursorHandler<'a> = Box<dyn FnMut(&KVMFRCursorHandle) + 'a>;
+
+/// Hands frames and cursor updates from an [LGMPConnection] to registered callbacks, as an
+/// alternative to polling it directly.
+///
+/// Either let [Self::run] own the loop, or call [Self::dispatch_pending] from an existing
+/// event loop, at least as often as the tick period set in [super::LGMPOpts].
+pub struct Dispatcher<'a> {
+    conn: LGMPConnection,
+    frame_handlers: Vec<FrameHandler<'a>>,
+    cursor_handlers: Vec<CursorHandler<'a>>,
+}
+
+impl<'a> Dispatcher<'a> {
+    pub fn new(conn: LGMPConnection) -> Dispatcher<'a> {
+        Dispatcher {
+            conn,
+            frame_handlers: Vec::new(),
+            cursor_handlers: Vec::new(),
+        }
+    }
+
+    /// Registers a callback which is given the description and pixel data of each frame.
+    /// Frames whose header is invalid are skipped.
+    pub fn on_frame(&mut self, handler: impl FnMut(&FrameInfo, &[u8]) + 'a) -> &mut Self {
+        self.frame_handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Registers a callback which is given each cursor update.
+    pub fn on_cursor(&mut self, handler: impl FnMut(&KVMFRCursorHandle) + 'a) -> &mut Self {
+        self.cursor_handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Returns the underlying connection, for reading its stats or host details.
+    pub fn connection(&mut self) -> &mut LGMPConnection {
+        &mut self.conn
+    }
+
+    /// Returns the underlying connection, dropping the registered callbacks.
+    pub fn into_connection(self) -> LGMPConnection {
+        self.conn
+    }
+
+    /// Ticks both channels and passes every waiting frame and cursor update to the
+    /// callbacks, returning how many were handled.
+    pub fn dispatch_pending(&mut self) -> Result<usize, LGError> {
+        self.conn.tick_frame()?;
+        self.conn.tick_cursor()?;
+        let mut handled = 0;
+        loop {
+            match self.dispatch_one()? {
+                Some(true) => handled += 1,
+                Some(false) => (),
+                None => return Ok(handled),
+            }
+        }
+    }
+
+    /// Runs [Self::dispatch_pending] until the session ends, either because the host went
+    /// away without `auto_reconnect` enabled, or because it was closed.
+    pub fn run(&mut self) -> Result<(), LGError> {
+        while self.conn.is_active() {
+            if self.dispatch_pending()? == 0 {
+                std::thread::sleep(IDLE_SLEEP);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a single event, returning whether it was passed to a callback, or None if
+    /// there is nothing left to handle for now.
+    fn dispatch_one(&mut self) -> Result<Option<bool>, LGError> {
+        match self.conn.poll_event()? {
+            LGEvent::Frame(frame) => {
+                let (Ok(info), Ok(data)) = (frame.info(), frame.data()) else {
+                    return Ok(Some(false));
+                };
+                for handler in &mut self.frame_handlers {
+                    handler(&info, data);
+                }
+                Ok(Some(true))
+            }
+            LGEvent::Cursor(cursor) => {
+                for handler in &mut self.cursor_handlers {
+                    handler(&cursor);
+                }
+                Ok(Some(true))
+            }
+            LGEvent::Idle | LGEvent::HostLost => Ok(None),
+            _ => Ok(Some(false)),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use crate::{error::LGError, types::PixelFormat};
+
+/// `KVMFR_DMABUF_CREATE` ioctl from the kvmfr kernel module,
+/// `_IOW('u', 0x42, struct kvmfr_dmabuf_create)`
+const KVMFR_DMABUF_CREATE: libc::c_ulong = 1 << 30
+    | (size_of::<KVMFRDmabufCreate>() as libc::c_ulong) << 16
+    | (b'u' as libc::c_ulong) << 8
+    | 0x42;
+/// Asks the kvmfr module to set `O_CLOEXEC` on the new dmabuf
+const KVMFR_DMABUF_FLAG_CLOEXEC: u8 = 0x1;
+
+/// Argument to [KVMFR_DMABUF_CREATE], matching `struct kvmfr_dmabuf_create`
+#[repr(C)]
+struct KVMFRDmabufCreate {
+    flags: u8,
+    offset: u64,
+    size: u64,
+}
+
+/// A kvmfr device which the shared memory was mapped from, used to export regions of it
+/// as dmabufs.
+pub(crate) struct KVMFRDevice {
+    file: File,
+    /// Address at which the device is mapped
+    base: usize,
+    size: usize,
+}
+
+impl KVMFRDevice {
+    pub(crate) fn new(file: File, base: *const u8, size: usize) -> KVMFRDevice {
+        KVMFRDevice {
+            file,
+            base: base as usize,
+            size,
+        }
+    }
+
+    /// Exports the mapped memory from `ptr` to `ptr + len` as a dmabuf, returning the
+    /// descriptor along with the offset of `ptr` within it.
+    ///
+    /// The exported region is widened to page boundaries, as the kvmfr module works in
+    /// whole pages.
+    pub(crate) fn export(&self, ptr: *const u8, len: usize) -> Result<(OwnedFd, u32), LGError> {
+        let start = (ptr as usize)
+            .checked_sub(self.base)
+            .filter(|start| start + len <= self.size)
+            .ok_or(LGError::FrameDataOutOfBounds)?;
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let region_start = start & !(page - 1);
+        let region_end = (start + len).next_multiple_of(page).min(self.size);
+
+        let create = KVMFRDmabufCreate {
+            flags: KVMFR_DMABUF_FLAG_CLOEXEC,
+            offset: region_start as u64,
+            size: (region_end - region_start) as u64,
+        };
+        let fd = unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                KVMFR_DMABUF_CREATE as _,
+                &create as *const KVMFRDmabufCreate,
+            )
+        };
+        if fd < 0 {
+            Err(std::io::Error::last_os_error())?
+        }
+        //The kvmfr module returns a new descriptor which we now own
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok((fd, (start - region_start) as u32))
+    }
+}
+
+/// A frame exported as a dmabuf by [super::KVMFRFrameHandle::export_dmabuf], along with the
+/// layout needed to import it into EGL or Vulkan.
+///
+/// The buffer refers directly to the shared memory, so the host may overwrite it once the
+/// frame handle has been dropped.
+#[derive(Debug)]
+pub struct DmabufFrame {
+    pub fd: OwnedFd,
+    /// Offset in bytes of the first pixel within the buffer
+    pub offset: u32,
+    /// Row length in bytes
+    pub stride: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+}
+
+impl DmabufFrame {
+    /// Returns the DRM fourcc code describing the frame's pixel format, as passed to
+    /// `EGL_LINUX_DRM_FOURCC_EXT`, or None if there isn't one.
+    pub fn drm_format(&self) -> Option<u32> {
+        let fourcc = |code: &[u8; 4]| u32::from_le_bytes(*code);
+        match self.format {
+            PixelFormat::Bgra => Some(fourcc(b"AR24")),
+            PixelFormat::Rgba => Some(fourcc(b"AB24")),
+            PixelFormat::Bgr32 => Some(fourcc(b"XR24")),
+            PixelFormat::Rgba10 => Some(fourcc(b"AB30")),
+            PixelFormat::Rgba16F => Some(fourcc(b"AB4H")),
+            PixelFormat::Rgb24 => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn create_ioctl_matches_kernel() {
+        //As defined by kvmfr.h
+        assert_eq!(size_of::<KVMFRDmabufCreate>(), 24);
+        assert_eq!(KVMFR_DMABUF_CREATE, 0x40187542);
+    }
+}
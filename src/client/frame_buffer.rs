@@ -0,0 +1,152 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::copy::CopyStrategy;
+
+/// Frame data copied out of shared memory, so that it remains available after the
+/// message has been released back to the host.
+///
+/// With the `paranoid` feature enabled on unix, the data is placed directly before an
+/// inaccessible guard page (with another one before it), so any out of bounds access
+/// caused by malformed host metadata faults immediately rather than reading unrelated
+/// memory.
+pub struct FrameBuffer {
+    inner: Inner,
+}
+
+enum Inner {
+    #[cfg_attr(all(unix, feature = "paranoid"), allow(dead_code))]
+    Heap(Vec<u8>),
+    #[cfg(all(unix, feature = "paranoid"))]
+    Guarded(GuardedAlloc),
+}
+
+impl FrameBuffer {
+    /// Copies the provided data into a new buffer.
+    pub(crate) fn copy_from(data: &[u8], strategy: &CopyStrategy) -> FrameBuffer {
+        #[cfg(all(unix, feature = "paranoid"))]
+        if let Some(mut alloc) = GuardedAlloc::new(data.len()) {
+            strategy.copy(data, &mut alloc);
+            return FrameBuffer {
+                inner: Inner::Guarded(alloc),
+            };
+        }
+        FrameBuffer {
+            inner: Inner::Heap(strategy.copy_to_vec(data)),
+        }
+    }
+}
+
+impl Deref for FrameBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.inner {
+            Inner::Heap(data) => data,
+            #[cfg(all(unix, feature = "paranoid"))]
+            Inner::Guarded(alloc) => alloc,
+        }
+    }
+}
+
+impl DerefMut for FrameBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.inner {
+            Inner::Heap(data) => data,
+            #[cfg(all(unix, feature = "paranoid"))]
+            Inner::Guarded(alloc) => alloc,
+        }
+    }
+}
+
+/// An anonymous mapping with a `PROT_NONE` page on either side of the usable region, which
+/// ends exactly at the start of the trailing guard page.
+#[cfg(all(unix, feature = "paranoid"))]
+struct GuardedAlloc {
+    map: *mut u8,
+    map_len: usize,
+    data: *mut u8,
+    len: usize,
+}
+
+#[cfg(all(unix, feature = "paranoid"))]
+impl GuardedAlloc {
+    /// Returns None if the mapping could not be created.
+    fn new(len: usize) -> Option<GuardedAlloc> {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let data_pages = len.div_ceil(page).max(1);
+        let map_len = (data_pages + 2) * page;
+        unsafe {
+            let map = libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if map == libc::MAP_FAILED {
+                return None;
+            }
+            let map = map.cast::<u8>();
+            let trailing = map.add(map_len - page);
+            if libc::mprotect(map.cast(), page, libc::PROT_NONE) != 0
+                || libc::mprotect(trailing.cast(), page, libc::PROT_NONE) != 0
+            {
+                libc::munmap(map.cast(), map_len);
+                return None;
+            }
+            Some(GuardedAlloc {
+                map,
+                map_len,
+                data: trailing.sub(len),
+                len,
+            })
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "paranoid"))]
+impl Deref for GuardedAlloc {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+#[cfg(all(unix, feature = "paranoid"))]
+impl DerefMut for GuardedAlloc {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.data, self.len) }
+    }
+}
+
+#[cfg(all(unix, feature = "paranoid"))]
+impl Drop for GuardedAlloc {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.map.cast(), self.map_len);
+        }
+    }
+}
+
+//The mapping is owned exclusively by this struct
+#[cfg(all(unix, feature = "paranoid"))]
+unsafe impl Send for GuardedAlloc {}
+#[cfg(all(unix, feature = "paranoid"))]
+unsafe impl Sync for GuardedAlloc {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_data() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(5000).collect();
+        let mut buf = FrameBuffer::copy_from(&data, &CopyStrategy::with_chunk_size(1000));
+        assert_eq!(&buf[..], &data[..]);
+        buf[4999] = 0;
+        assert_eq!(buf.len(), 5000);
+        assert!(FrameBuffer::copy_from(&[], &CopyStrategy::default()).is_empty());
+    }
+}
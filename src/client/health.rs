@@ -0,0 +1,214 @@
+use std::time::{Duration, Instant};
+
+use super::{ConnectionStats, QueueStatus};
+
+/// Fraction of frames which may be skipped before the connection is degraded.
+const DEGRADED_DROP_RATE: f64 = 0.05;
+/// Fraction of frames which may be skipped before the connection is critical.
+const CRITICAL_DROP_RATE: f64 = 0.25;
+
+/// Overall quality of a connection, ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HealthStatus {
+    Good,
+    /// Frames are being delivered, but with visible stutter
+    Degraded,
+    /// Frames are not being delivered, or the host is close to dropping this client
+    Critical,
+}
+
+/// Something which made a connection less than [HealthStatus::Good].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthReason {
+    /// There is no valid session with the host
+    HostLost,
+    /// The longest gap between calls to [super::LGMPConnection::tick_frame] differed from
+    /// the frame tick period by this much
+    TickJitter(Duration),
+    /// This fraction of the frames sent by the host were skipped
+    FramesDropped(f64),
+    /// The frame queue was never found empty, so the client is falling behind the host
+    Backlog,
+    /// The host dropped this client this many times for not emptying a queue in time
+    QueueTimeouts(u64),
+    /// The session was re-established this many times
+    Reconnected(u64),
+}
+
+/// A summary of connection quality returned by [super::LGMPConnection::health].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// The worst status of any of the reasons
+    pub status: HealthStatus,
+    /// Everything which contributed to the status, empty if it is good
+    pub reasons: Vec<HealthReason>,
+}
+
+/// A snapshot of whether the host is still there, returned by
+/// [super::LGMPConnection::connection_health].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionHealth {
+    /// There is a session with the host and the host still considers it valid
+    pub host_alive: bool,
+    /// State of the frame queue, or None if the host is not alive
+    pub frame: Option<QueueStatus>,
+    /// State of the cursor queue, or None if the host is not alive
+    pub cursor: Option<QueueStatus>,
+}
+
+impl ConnectionHealth {
+    /// Returns the time left before the host drops this client from the first queue it
+    /// would time out on, or None if no queues are subscribed to.
+    pub fn until_timeout(&self) -> Option<Duration> {
+        [self.frame, self.cursor]
+            .into_iter()
+            .flatten()
+            .filter(|status| status.subscribed)
+            .map(|status| status.until_timeout)
+            .min()
+    }
+}
+
+/// Activity seen on a connection since its health was last reported.
+pub(super) struct HealthTracker {
+    last_stats: ConnectionStats,
+    last_tick: Option<Instant>,
+    tick_jitter: Duration,
+}
+
+impl HealthTracker {
+    pub(super) fn new() -> HealthTracker {
+        HealthTracker {
+            last_stats: ConnectionStats::default(),
+            last_tick: None,
+            tick_jitter: Duration::ZERO,
+        }
+    }
+
+    /// Records a call to a tick function which should be called at the given period.
+    pub(super) fn record_tick(&mut self, period: Duration) {
+        let now = Instant::now();
+        if let Some(last) = self.last_tick.replace(now) {
+            self.tick_jitter = self.tick_jitter.max((now - last).abs_diff(period));
+        }
+    }
+
+    /// Builds a report from the changes to the stats since the last report.
+    ///
+    /// Tick jitter is judged against the queue timeout, as ticks exist to keep the host
+    /// from timing this client out.
+    pub(super) fn report(
+        &mut self,
+        stats: &ConnectionStats,
+        session_valid: bool,
+        timeout: Duration,
+    ) -> HealthReport {
+        let last = std::mem::replace(&mut self.last_stats, *stats);
+        let jitter = std::mem::take(&mut self.tick_jitter);
+
+        let mut reasons = Vec::new();
+        let mut status = HealthStatus::Good;
+        let mut add = |reason, reason_status| {
+            reasons.push(reason);
+            status = status.max(reason_status);
+        };
+
+        if !session_valid {
+            add(HealthReason::HostLost, HealthStatus::Critical);
+        }
+        if jitter > timeout / 2 {
+            add(HealthReason::TickJitter(jitter), HealthStatus::Critical);
+        } else if jitter > timeout / 8 {
+            add(HealthReason::TickJitter(jitter), HealthStatus::Degraded);
+        }
+
+        let frames = stats.frames - last.frames;
+        let skipped = stats.frames_skipped - last.frames_skipped;
+        if frames + skipped > 0 {
+            let rate = skipped as f64 / (frames + skipped) as f64;
+            if rate >= CRITICAL_DROP_RATE {
+                add(HealthReason::FramesDropped(rate), HealthStatus::Critical);
+            } else if rate >= DEGRADED_DROP_RATE {
+                add(HealthReason::FramesDropped(rate), HealthStatus::Degraded);
+            }
+        }
+
+        let reads = stats.frame_queue.reads - last.frame_queue.reads;
+        if reads > 0 && stats.frame_queue.empty == last.frame_queue.empty {
+            add(HealthReason::Backlog, HealthStatus::Degraded);
+        }
+
+        let timeouts = (stats.frame_queue.timeouts + stats.cursor_queue.timeouts)
+            - (last.frame_queue.timeouts + last.cursor_queue.timeouts);
+        if timeouts > 0 {
+            add(
+                HealthReason::QueueTimeouts(timeouts),
+                HealthStatus::Critical,
+            );
+        }
+
+        let reconnects = stats.reconnects - last.reconnects;
+        if reconnects > 0 {
+            add(
+                HealthReason::Reconnected(reconnects),
+                HealthStatus::Degraded,
+            );
+        }
+
+        HealthReport { status, reasons }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn reports_changes_since_last_report() {
+        let mut tracker = HealthTracker::new();
+        let mut stats = ConnectionStats::default();
+        let report = tracker.report(&stats, true, TIMEOUT);
+        assert_eq!(report.status, HealthStatus::Good);
+        assert!(report.reasons.is_empty());
+
+        stats.frames = 90;
+        stats.frames_skipped = 10;
+        stats.reconnects = 1;
+        let report = tracker.report(&stats, true, TIMEOUT);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(
+            report.reasons,
+            vec![
+                HealthReason::FramesDropped(0.1),
+                HealthReason::Reconnected(1)
+            ]
+        );
+
+        stats.frame_queue.timeouts = 2;
+        let report = tracker.report(&stats, false, TIMEOUT);
+        assert_eq!(report.status, HealthStatus::Critical);
+        assert_eq!(
+            report.reasons,
+            vec![HealthReason::HostLost, HealthReason::QueueTimeouts(2)]
+        );
+
+        let report = tracker.report(&stats, true, TIMEOUT);
+        assert_eq!(report.status, HealthStatus::Good);
+    }
+
+    #[test]
+    fn reports_backlog_when_queue_is_never_empty() {
+        let mut tracker = HealthTracker::new();
+        let mut stats = ConnectionStats::default();
+        stats.frame_queue.reads = 5;
+        let report = tracker.report(&stats, true, TIMEOUT);
+        assert_eq!(report.reasons, vec![HealthReason::Backlog]);
+
+        stats.frame_queue.reads = 10;
+        stats.frame_queue.empty = 4;
+        let report = tracker.report(&stats, true, TIMEOUT);
+        assert!(report.reasons.is_empty());
+    }
+}
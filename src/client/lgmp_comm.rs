@@ -1,23 +1,454 @@
 use std::{
-    mem::{size_of, size_of_val, transmute},
-    sync::{Arc, Mutex},
+    cell::Cell,
+    collections::VecDeque,
+    mem::size_of,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
-use ligmars::client::{Client, InPlaceMessage};
+use zerocopy::{ConvertError, FromBytes, IntoBytes};
 
-use crate::{error::LGError, shm_datastructs};
+use crate::lgmp_impl::client::{Client, InPlaceMessage, SharedMemoryBlock};
 
+#[cfg(feature = "black-box")]
+use super::black_box::BlackBox;
+#[cfg(target_os = "linux")]
+use super::dmabuf::DmabufFrame;
+use super::{
+    arc_frame::ArcFrame,
+    auto_tick::AutoTick,
+    chunks::FrameChunks,
+    deadline::Deadline,
+    health::{ConnectionHealth, HealthReport, HealthTracker},
+    message_channel::MessageChannel,
+    owned_frame::OwnedFrame,
+    pacing::FramePacer,
+    quirks::Quirks,
+    receivers::{CursorReceiver, FrameReceiver, ReceiverParts},
+    replay::{RecordedMessage, Recorder},
+    shm_source::{DeviceHandle, ShmMapOpts},
+    tiles::{self, FrameTiles},
+    FrameBuffer, LGMPSource,
+};
+use crate::{
+    convert::{self, ToneMap},
+    copy::CopyStrategy,
+    error::LGError,
+    inspect, parallel,
+    pool::{FrameLayout, FramePool},
+    shm_datastructs,
+    types::{CursorFlags, DamageRect, FrameInfo, HostFeatures, HostInfo, HostMessage, PixelFormat},
+};
+
+/// Default time to wait between re-opening the shared memory file and initialising a
+/// session when reconnecting.
+const DEFAULT_SETTLE_PERIOD: Duration = Duration::from_millis(200);
+/// Default time after which the host is assumed to drop a subscriber which has not
+/// emptied its queue, matching the host's queue subscription timeout.
+const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_millis(1000);
+/// Default interval at which the tick functions are expected to be called.
+const DEFAULT_TICK_PERIOD: Duration = Duration::from_millis(1);
+/// Length of the window over which frame and cursor update rates are measured.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Cursor shape updates per second above which [Anomaly::CursorShapeStorm] is reported if
+/// no limit has been set. The official host sends one per shape change, so this is far
+/// beyond anything a well behaved guest produces.
+const DEFAULT_SHAPE_STORM_THRESHOLD: u32 = 120;
+/// Number of times [LGMPConnection::wait_for_frame] checks the queue before it starts
+/// sleeping between checks.
+const WAIT_SPINS: u32 = 64;
+/// First sleep taken by [LGMPConnection::wait_for_frame] once it stops spinning.
+const WAIT_MIN_SLEEP: Duration = Duration::from_micros(50);
+/// Timeout of each [LGMPConnection::wait_for_frame] call made by [LGMPConnection::frames].
+const FRAMES_WAIT: Duration = Duration::from_millis(100);
+/// Cursor shape updates per second allowed from hosts with [Quirks::LIMIT_CURSOR_SHAPES],
+/// if no limit has been set.
+const QUIRK_SHAPE_LIMIT: u32 = 30;
+
+/// ID of the LGMP queue which KVMFR hosts send frames on.
+pub const FRAME_QUEUE_ID: u32 = shm_datastructs::LGMP_Q_FRAME;
+/// ID of the LGMP queue which KVMFR hosts send cursor updates on.
+pub const CURSOR_QUEUE_ID: u32 = shm_datastructs::LGMP_Q_POINTER;
+
+/// Options for an LGMP client connection, created using [LGMPOpts::builder].
 #[derive(Clone)]
 pub struct LGMPOpts {
-    pub shm_path: String,
-    pub timeout: Duration,
+    source: LGMPSource,
+    map_opts: ShmMapOpts,
+    frame: ChanOpts,
+    cursor: ChanOpts,
+    settle_period: Duration,
+    auto_reconnect: bool,
+    stats_interval: Option<Duration>,
+    priority: ChannelPriority,
+    backpressure: Backpressure,
+    required_features: HostFeatures,
+    cursor_shape_limit: Option<u32>,
+    max_frame_rate: Option<u32>,
+    metrics: bool,
+    force_quirks: Quirks,
+    disable_quirks: Quirks,
+    auto_tick: bool,
+    detect_config_changes: bool,
+    lifecycle_events: bool,
+    copy_strategy: CopyStrategy,
+}
+
+/// Which channel [LGMPConnection::poll_event] should favour when both have messages
+/// waiting.
+///
+/// This only orders reads within a single poll. Each queue is read through its own
+/// handle with its own lock, and the connection's lock is only taken briefly to check
+/// the session, so a burst of cursor messages never holds up frames at the lock level.
+/// Applications which need the queues to be drained independently should read them on
+/// separate threads with [LGMPConnection::split] instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelPriority {
+    /// Always return pending frames before cursor updates. As the frame queue is short,
+    /// cursor updates are delayed by at most a couple of polls.
+    #[default]
+    Frames,
+    /// Always return pending cursor updates before frames, for clients which care more
+    /// about cursor latency than frame latency.
+    Cursor,
+    /// Alternate between the channels on each poll.
+    Alternate,
+}
+
+/// What happens to frames waiting in the frame queue when the client falls behind the
+/// host.
+///
+/// LGMP does not expose how many messages are waiting, so lag is measured as the number
+/// of frames read in a row without the queue being found empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Skip waiting frames only when the host is about to time this client out, which is
+    /// checked by [LGMPConnection::tick_frame].
+    #[default]
+    AvoidTimeout,
+    /// Always skip to the newest frame before reading one, for the lowest latency.
+    Latest,
+    /// Never skip frames, so that every frame is processed. If the client falls too far
+    /// behind, the host drops it and the session has to be re-established.
+    Never,
+    /// Skip to the newest frame once more than this many frames have been read without
+    /// catching up. The host may still drop the client if frames are processed too slowly.
+    BoundedLag(u32),
+}
+
+/// Settings for a single queue.
+#[derive(Clone)]
+struct ChanOpts {
+    queue_id: u32,
+    subscribe: bool,
+    timeout: Duration,
+    tick_period: Duration,
+}
+
+impl ChanOpts {
+    fn new(queue_id: u32) -> Self {
+        ChanOpts {
+            queue_id,
+            subscribe: true,
+            timeout: DEFAULT_QUEUE_TIMEOUT,
+            tick_period: DEFAULT_TICK_PERIOD,
+        }
+    }
+}
+
+impl LGMPOpts {
+    /// Starts building options for a connection to the host on the provided shared memory.
+    pub fn builder(source: impl Into<LGMPSource>) -> LGMPOptsBuilder {
+        LGMPOptsBuilder {
+            opts: LGMPOpts {
+                source: source.into(),
+                map_opts: ShmMapOpts::default(),
+                frame: ChanOpts::new(FRAME_QUEUE_ID),
+                cursor: ChanOpts::new(CURSOR_QUEUE_ID),
+                settle_period: DEFAULT_SETTLE_PERIOD,
+                auto_reconnect: false,
+                stats_interval: None,
+                priority: ChannelPriority::default(),
+                backpressure: Backpressure::default(),
+                required_features: HostFeatures::empty(),
+                cursor_shape_limit: None,
+                max_frame_rate: None,
+                metrics: false,
+                force_quirks: Quirks::empty(),
+                disable_quirks: Quirks::empty(),
+                auto_tick: false,
+                detect_config_changes: false,
+                lifecycle_events: false,
+                copy_strategy: CopyStrategy::default(),
+            },
+        }
+    }
+}
+
+/// Builder for [LGMPOpts].
+#[derive(Clone)]
+pub struct LGMPOptsBuilder {
+    opts: LGMPOpts,
+}
+
+impl LGMPOptsBuilder {
+    /// Sets the queue timeout used for both the frame and cursor queues.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.frame_timeout(timeout).cursor_timeout(timeout)
+    }
+
+    /// Sets the time after which the host will drop this client if it has not emptied the
+    /// frame queue. Defaults to 1s.
+    pub fn frame_timeout(mut self, timeout: Duration) -> Self {
+        self.opts.frame.timeout = timeout;
+        self
+    }
+
+    /// Sets the time after which the host will drop this client if it has not emptied the
+    /// cursor queue. Defaults to 1s.
+    pub fn cursor_timeout(mut self, timeout: Duration) -> Self {
+        self.opts.cursor.timeout = timeout;
+        self
+    }
+
+    /// Sets how often [LGMPConnection::tick_frame] will be called. Defaults to 1ms.
+    pub fn frame_tick_period(mut self, period: Duration) -> Self {
+        self.opts.frame.tick_period = period;
+        self
+    }
+
+    /// Sets how often [LGMPConnection::tick_cursor] will be called. Defaults to 1ms.
+    pub fn cursor_tick_period(mut self, period: Duration) -> Self {
+        self.opts.cursor.tick_period = period;
+        self
+    }
+
+    /// Sets whether to subscribe to the frame queue. Defaults to true.
+    ///
+    /// The host only tracks, and times out, clients on the queues which they subscribe to,
+    /// so consumers which never read a channel should leave it unsubscribed. Reads and
+    /// ticks on an unsubscribed channel do nothing.
+    pub fn subscribe_frames(mut self, subscribe: bool) -> Self {
+        self.opts.frame.subscribe = subscribe;
+        self
+    }
+
+    /// Sets whether to subscribe to the cursor queue. Defaults to true. See
+    /// [Self::subscribe_frames].
+    pub fn subscribe_cursor(mut self, subscribe: bool) -> Self {
+        self.opts.cursor.subscribe = subscribe;
+        self
+    }
+
+    /// Sets the ID of the LGMP queue which frames are read from. Defaults to
+    /// [FRAME_QUEUE_ID].
+    ///
+    /// This only needs changing for hosts which publish more than one display.
+    pub fn frame_queue_id(mut self, queue_id: u32) -> Self {
+        self.opts.frame.queue_id = queue_id;
+        self
+    }
+
+    /// Sets the ID of the LGMP queue which cursor updates are read from. Defaults to
+    /// [CURSOR_QUEUE_ID].
+    pub fn cursor_queue_id(mut self, queue_id: u32) -> Self {
+        self.opts.cursor.queue_id = queue_id;
+        self
+    }
+
+    /// Sets how the shared memory is mapped. Defaults to mapping all of it with no hints.
+    pub fn map_opts(mut self, opts: ShmMapOpts) -> Self {
+        self.opts.map_opts = opts;
+        self
+    }
+
+    /// Sets how long to wait after re-opening the shared memory before initialising a
+    /// session when reconnecting, either through [LGMPConnection::reconnect] or
+    /// `auto_reconnect`. This is not applied to the first session, which is initialised by
+    /// calling [LGMPConnection::init] after [LGMPConnection::open]. Defaults to 200ms.
+    pub fn settle_period(mut self, period: Duration) -> Self {
+        self.opts.settle_period = period;
+        self
+    }
+
+    /// If set, [LGMPConnection::poll_event] will transparently re-establish the session
+    /// when the host restarts, reporting [LGEvent::Reconnected] once it is back.
+    /// Defaults to false.
+    pub fn auto_reconnect(mut self, auto_reconnect: bool) -> Self {
+        self.opts.auto_reconnect = auto_reconnect;
+        self
+    }
+
+    /// If set, the details sent by the host are hashed each time a session is
+    /// initialised, and [LGEvent::HostConfigChanged] is reported if they differ from those
+    /// of the previous session. Defaults to false.
+    pub fn detect_config_changes(mut self, detect: bool) -> Self {
+        self.opts.detect_config_changes = detect;
+        self
+    }
+
+    /// If set, [LGEvent::SessionStarted] and [LGEvent::SessionLost] are reported as sessions
+    /// begin and end, so that supervising processes can follow session churn.
+    /// Defaults to false.
+    pub fn lifecycle_events(mut self, enabled: bool) -> Self {
+        self.opts.lifecycle_events = enabled;
+        self
+    }
+
+    /// Sets how frame data is copied out of shared memory by [KVMFRFrameHandle::copy_data]
+    /// and [KVMFRFrameHandle::copy_to]. See [LGMPConnection::set_copy_strategy] for
+    /// changing it once a frame is available to calibrate with.
+    pub fn copy_strategy(mut self, strategy: CopyStrategy) -> Self {
+        self.opts.copy_strategy = strategy;
+        self
+    }
+
+    /// If set, [LGMPConnection::poll_event] will report [LGEvent::Stats] at roughly this
+    /// interval. Defaults to None.
+    pub fn stats_interval(mut self, interval: Option<Duration>) -> Self {
+        self.opts.stats_interval = interval;
+        self
+    }
+
+    /// Sets which channel is favoured when both have messages waiting. Defaults to
+    /// [ChannelPriority::Frames].
+    pub fn priority(mut self, priority: ChannelPriority) -> Self {
+        self.opts.priority = priority;
+        self
+    }
+
+    /// Sets what happens to waiting frames when the client falls behind. Defaults to
+    /// [Backpressure::AvoidTimeout].
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.opts.backpressure = backpressure;
+        self
+    }
+
+    /// Sets features which the host must support. If any are missing, [LGMPConnection::init]
+    /// will fail with [LGError::UnsupportedFeature]. Defaults to none.
+    ///
+    /// KVMFR gives clients no way to tell the host which features they use, so this is
+    /// only checked on the client side.
+    pub fn require_features(mut self, features: HostFeatures) -> Self {
+        self.opts.required_features = features;
+        self
+    }
+
+    /// Limits the number of cursor shape updates delivered each second. Once the limit is
+    /// reached, further updates in the same second are still delivered but have their
+    /// shape removed, protecting renderers from hosts which send shapes continuously.
+    ///
+    /// The last shape sent during a storm may be one of those removed, so renderers
+    /// should be prepared for the cursor to look wrong until the guest next changes it.
+    /// Defaults to None.
+    pub fn cursor_shape_limit(mut self, limit: Option<u32>) -> Self {
+        self.opts.cursor_shape_limit = limit;
+        self
+    }
+
+    /// Limits the number of frames delivered each second, for consumers which have no use
+    /// for every frame the host sends. Frames arriving faster than this are coalesced by
+    /// fast-forwarding the queue, so the frame delivered once the interval has passed is
+    /// always the newest one. Defaults to None.
+    ///
+    /// This applies to frames read from the connection itself, but not a split off
+    /// [FrameReceiver]. Limits of fewer than two frames per frame timeout are raised to
+    /// that, so that the host does not time this client out.
+    pub fn max_frame_rate(mut self, fps: Option<u32>) -> Self {
+        self.opts.max_frame_rate = fps;
+        self
+    }
+
+    /// Enables collection of frame timing metrics, which are reported in
+    /// [ConnectionStats::frames_per_sec] and the frame latency fields. Defaults to false.
+    pub fn collect_metrics(mut self, collect: bool) -> Self {
+        self.opts.metrics = collect;
+        self
+    }
+
+    /// Enables workarounds even if the host is not known to need them.
+    pub fn force_quirks(mut self, quirks: Quirks) -> Self {
+        self.opts.force_quirks = quirks;
+        self
+    }
+
+    /// Disables workarounds even if the host is known to need them. This takes precedence
+    /// over [Self::force_quirks].
+    pub fn disable_quirks(mut self, quirks: Quirks) -> Self {
+        self.opts.disable_quirks = quirks;
+        self
+    }
+
+    /// If set, the accessors ([LGMPConnection::poll_event], [LGMPConnection::get_frame_update]
+    /// and [LGMPConnection::get_cursor_update]) tick the channels they read themselves, so
+    /// that callers who only read at frame rate are not timed out by the host. Defaults to
+    /// false.
+    ///
+    /// Instead of the tick period, the channel is fast-forwarded once the host timeout is
+    /// closer than the longest recent gap between calls, which is increased further if the
+    /// host times the client out anyway. The tick functions can still be called alongside
+    /// this, for callers which sometimes stop reading.
+    pub fn auto_tick(mut self, auto_tick: bool) -> Self {
+        self.opts.auto_tick = auto_tick;
+        self
+    }
+
+    pub fn build(self) -> LGMPOpts {
+        self.opts
+    }
 }
 
 pub struct LGMPConnection {
-    client: Arc<Mutex<ligmars::client::Client>>,
+    client: Arc<Mutex<crate::lgmp_impl::client::Client>>,
+    //The kvmfr device which the client's shared memory was mapped from, if any
+    device: DeviceHandle,
     session: Option<LGMPSession>,
     opts: LGMPOpts,
+    reconnect_state: ReconnectState,
+    //Events which have been detected but not yet returned from poll_event
+    pending_events: VecDeque<LGEvent<'static>>,
+    stats: ConnectionStats,
+    last_stats: Instant,
+    //Whether the cursor channel goes first on the next poll, for ChannelPriority::Alternate
+    cursor_turn: bool,
+    capture: FrameCapture,
+    cursor_rate: CursorRate,
+    frame_metrics: FrameMetrics,
+    recorder: Option<Recorder<Box<dyn std::io::Write + Send>>>,
+    //Shared with poll_event while the event it returns borrows the connection
+    #[cfg(feature = "black-box")]
+    black_box: Option<Arc<BlackBox>>,
+    health: HealthTracker,
+    frame_auto_tick: AutoTick,
+    cursor_auto_tick: AutoTick,
+    //Hash of the udata sent by the host for the last session, if config changes are detected
+    udata_hash: Option<u64>,
+    pacer: FramePacer,
+    //Hash of the last frame checked by KVMFRFrameHandle::is_duplicate_of_previous
+    last_frame_hash: Cell<Option<u64>>,
+}
+
+impl Drop for LGMPConnection {
+    fn drop(&mut self) {
+        //Failing to unsubscribe only means that the host will time this client out
+        let _ = self.close_session();
+    }
+}
+
+/// Progress of an automatic reconnection.
+enum ReconnectState {
+    /// The session is healthy, or has never been initialised
+    Idle,
+    /// Reading a queue showed that the session was lost, which has already been reported
+    Dropped,
+    /// The session was lost, and the shared memory file has not yet been re-opened
+    Lost,
+    /// The shared memory file was re-opened at the given time, and we are waiting for
+    /// the settle period to pass before initialising a new session
+    Settling(Instant),
 }
 
 impl LGMPConnection {
@@ -26,18 +457,254 @@ impl LGMPConnection {
     ///
     /// After calling this,
     pub fn open(opts: LGMPOpts) -> Result<LGMPConnection, LGError> {
-        let shm_file = shared_memory::ShmemConf::new()
-            .flink(&opts.shm_path)
-            .open()?;
-        let client = Client::init(Box::new(shm_file))?;
+        let (client, device) = open_client(&opts)?;
+        let pacer = FramePacer::new(opts.max_frame_rate, opts.frame.timeout);
 
         Ok(LGMPConnection {
             client: Arc::new(Mutex::new(client)),
+            device,
             session: None,
             opts,
+            reconnect_state: ReconnectState::Idle,
+            pending_events: VecDeque::new(),
+            stats: ConnectionStats::default(),
+            last_stats: Instant::now(),
+            cursor_turn: false,
+            capture: FrameCapture::default(),
+            cursor_rate: CursorRate::new(),
+            frame_metrics: FrameMetrics::new(),
+            recorder: None,
+            #[cfg(feature = "black-box")]
+            black_box: None,
+            health: HealthTracker::new(),
+            frame_auto_tick: AutoTick::new(),
+            cursor_auto_tick: AutoTick::new(),
+            udata_hash: None,
+            pacer,
+            last_frame_hash: Cell::new(None),
         })
     }
 
+    /// Creates a new LGMP client handle on an already open memfd (or other mappable file
+    /// descriptor), such as one received over a unix socket from a memfd-backed IVSHMEM
+    /// setup. As with [Self::open], the session still needs initialising.
+    ///
+    /// The descriptor is kept open for the lifetime of the connection so that it can be
+    /// re-mapped by [Self::reconnect].
+    #[cfg(unix)]
+    pub fn open_memfd(
+        fd: std::os::fd::OwnedFd,
+        timeout: Duration,
+    ) -> Result<LGMPConnection, LGError> {
+        Self::open(LGMPOpts::builder(fd).timeout(timeout).build())
+    }
+
+    /// Tears down the current session, re-opens the shared memory file and initialises a
+    /// new session, blocking for the settle period in between.
+    ///
+    /// This is needed whenever the host has been restarted, as the old session will never
+    /// become valid again.
+    pub fn reconnect(&mut self) -> Result<(), LGError> {
+        self.reconnect_state = ReconnectState::Lost;
+        self.reopen()?;
+        std::thread::sleep(self.opts.settle_period);
+        self.init()?;
+        self.reconnect_state = ReconnectState::Idle;
+        Ok(())
+    }
+
+    /// Unsubscribes from both queues and ends the current session, so that the host stops
+    /// tracking this client straight away rather than waiting for it to time out.
+    ///
+    /// A new session can be started afterwards with [Self::init]. This does nothing if no
+    /// session has been initialised, and is also attempted when the connection is dropped.
+    pub fn close_session(&mut self) -> Result<(), LGError> {
+        let Some(mut sess) = self.session.take() else {
+            return Ok(());
+        };
+        self.pending_events.clear();
+        self.reconnect_state = ReconnectState::Idle;
+        //Both queues are unsubscribed even if the first fails
+        let frame = sess.frame_chan.take().map(|mut chan| chan.unsubscribe());
+        let cursor = sess.cursor_chan.take().map(|mut chan| chan.unsubscribe());
+        frame.transpose()?;
+        cursor.transpose()?;
+        Ok(())
+    }
+
+    /// Splits the current session into a [FrameReceiver] and a [CursorReceiver], which each
+    /// own one of its queues, so that frames and cursor updates can be read on different
+    /// threads without serialising through this connection.
+    ///
+    /// The connection is consumed, as it has nothing left to read. Automatic
+    /// reconnection is not possible once split, so a new connection must be opened if
+    /// the host restarts.
+    ///
+    /// Fails with [LGError::SessionInvalid] if a session has not yet been initialised, or
+    /// an LGMPErrQueueUnsubscribed error if either queue is not subscribed to or has
+    /// already been split off.
+    pub fn split(mut self) -> Result<(FrameReceiver, CursorReceiver), LGError> {
+        let sess = self.session.as_mut().ok_or(LGError::SessionInvalid)?;
+        if sess.frame_chan.is_none() || sess.cursor_chan.is_none() {
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueUnsubscribed,
+            ))?
+        }
+        let cursor = self.split_cursor()?;
+        let sess = self.session.as_mut().ok_or(LGError::SessionInvalid)?;
+        let chan = sess.frame_chan.take().ok_or(LGError::SessionInvalid)?;
+        let frames = FrameReceiver::new(
+            ReceiverParts {
+                chan,
+                last_heartbeat: sess.last_frame_heartbeat,
+                timeout: self.opts.frame.timeout,
+                tick_period: self.opts.frame.tick_period,
+                client: self.client.clone(),
+            },
+            self.device.clone(),
+            sess.quirks.contains(Quirks::IGNORE_DAMAGE),
+            self.opts.copy_strategy,
+            sess.serials.last,
+        );
+        Ok((frames, cursor))
+    }
+
+    /// As [Self::split], but only moves the cursor queue into a [CursorReceiver], leaving
+    /// frames to be read from this connection. Cursor reads and ticks on this connection
+    /// do nothing afterwards.
+    pub fn split_cursor(&mut self) -> Result<CursorReceiver, LGError> {
+        let sess = self.session.as_mut().ok_or(LGError::SessionInvalid)?;
+        let chan = sess
+            .cursor_chan
+            .take()
+            .ok_or(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueUnsubscribed,
+            ))?;
+        Ok(CursorReceiver::new(
+            ReceiverParts {
+                chan,
+                last_heartbeat: sess.last_cursor_heartbeat,
+                timeout: self.opts.cursor.timeout,
+                tick_period: self.opts.cursor.tick_period,
+                client: self.client.clone(),
+            },
+            sess.shape_limit,
+            sess.host_info.features,
+        ))
+    }
+
+    /// Subscribes to another queue published by the host, such as one added by a newer or
+    /// experimental version of KVMFR, whose messages are returned as raw bytes.
+    ///
+    /// The subscription belongs to the current session, so stops working if the session is
+    /// lost and must be made again after reconnecting. Fails with [LGError::SessionInvalid]
+    /// if a session has not yet been initialised, or an LGMPErrNoSuchQueue error if the
+    /// host has no queue with this ID.
+    pub fn subscribe_extra(&mut self, queue_id: u32) -> Result<MessageChannel, LGError> {
+        if self.session.is_none() {
+            Err(LGError::SessionInvalid)?
+        }
+        let chan = self.client.lock()?.client_subscribe(queue_id)?;
+        Ok(MessageChannel::new(queue_id, chan, self.client.clone()))
+    }
+
+    /// Returns true if there is a session, or one is being re-established, and the host has
+    /// not gone away without `auto_reconnect` enabled.
+    pub fn is_active(&self) -> bool {
+        match self.session {
+            Some(_) if self.opts.auto_reconnect => true,
+            Some(_) => self.client.lock().is_ok_and(|c| c.client_session_valid()),
+            None => !matches!(self.reconnect_state, ReconnectState::Idle),
+        }
+    }
+
+    /// Drops the current session and replaces the client with a fresh one.
+    fn reopen(&mut self) -> Result<(), LGError> {
+        //Queues must be released before the client which they belong to
+        self.session = None;
+        self.pending_events.clear();
+        let (client, device) = open_client(&self.opts)?;
+        self.client = Arc::new(Mutex::new(client));
+        self.device = device;
+        self.reconnect_state = ReconnectState::Settling(Instant::now());
+        Ok(())
+    }
+
+    /// As [Self::reopen], but treats the host not being available yet as success so that
+    /// it can be retried later.
+    fn try_reopen(&mut self) -> Result<(), LGError> {
+        match self.reopen() {
+            Err(
+                LGError::SHMDeviceError(_)
+                | LGError::SHMFileError(_)
+                | LGError::LGMPCommunicationError(_)
+                | LGError::HostUnavailable,
+            )
+            | Ok(()) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Advances an automatic reconnection if one is needed.
+    ///
+    /// Returns None if the session is healthy, otherwise the event which should be reported
+    /// to the caller of poll_event.
+    fn drive_reconnect(&mut self) -> Result<Option<LGEvent<'static>>, LGError> {
+        match self.reconnect_state {
+            ReconnectState::Idle => {
+                if self.session.is_none() || self.client.lock()?.client_session_valid() {
+                    return Ok(None);
+                }
+                let lost = self.session.take().map(|sess| sess.client_id);
+                self.reconnect_state = ReconnectState::Lost;
+                self.try_reopen()?;
+                match lost {
+                    Some(client_id) if self.opts.lifecycle_events => {
+                        Ok(Some(LGEvent::SessionLost(client_id)))
+                    }
+                    _ => Ok(Some(LGEvent::HostLost)),
+                }
+            }
+            ReconnectState::Dropped => {
+                self.session = None;
+                self.reconnect_state = ReconnectState::Lost;
+                self.try_reopen()?;
+                Ok(Some(LGEvent::HostLost))
+            }
+            ReconnectState::Lost => {
+                self.try_reopen()?;
+                Ok(Some(LGEvent::HostLost))
+            }
+            ReconnectState::Settling(reopened_at) => {
+                if reopened_at.elapsed() < self.opts.settle_period {
+                    return Ok(Some(LGEvent::HostLost));
+                }
+                match self.init() {
+                    Ok(()) => {
+                        self.reconnect_state = ReconnectState::Idle;
+                        self.stats.reconnects += 1;
+                        Ok(Some(LGEvent::Reconnected))
+                    }
+                    Err(e) if is_session_error(&e) => {
+                        //Host has not started its new session yet
+                        self.reconnect_state = ReconnectState::Lost;
+                        self.try_reopen()?;
+                        Ok(Some(LGEvent::HostLost))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// As [session_lost_event], for errors found before the session's queues are read.
+    fn session_lost(&mut self, e: LGError) -> Result<LGEvent<'static>, LGError> {
+        let Some(client_id) = self.client_id() else {
+            return Err(e);
+        };
+        session_lost_event(e, &self.opts, &mut self.reconnect_state, client_id)
+    }
+
     /// Initialises a client session.
     ///
     /// Note that this must not be called immediately after the connection is created;
@@ -48,42 +715,66 @@ impl LGMPConnection {
     pub fn init(&mut self) -> Result<(), LGError> {
         let mut client = self.client.lock()?;
         //Init client session
-        let (udata_raw, _client_id) = client.client_session_init()?;
+        let (udata_raw, client_id) = client.client_session_init()?;
         //Version checks
-        if udata_raw.len() != size_of::<shm_datastructs::KVMFR>() {
-            Err(LGError::KVMFRVersionMismatch(
-                shm_datastructs::KVMFR_VERSION,
-            ))?
-        }
-        let udata: &shm_datastructs::KVMFR =
-            unsafe { &*(udata_raw.as_ptr() as *const shm_datastructs::KVMFR) };
-        let magic: &[u8] = unsafe { &*(&udata.magic as *const [i8] as *const [u8]) };
-        if magic != &shm_datastructs::KVMFR_MAGIC[0..udata.magic.len()]
-            || udata.version != shm_datastructs::KVMFR_VERSION
-        {
-            Err(LGError::KVMFRVersionMismatch(
-                shm_datastructs::KVMFR_VERSION,
-            ))?
+        let host_info = inspect::parse_host_info(&udata_raw)?;
+        host_info.require(self.opts.required_features)?;
+        if self.opts.detect_config_changes {
+            let hash = xxhash_rust::xxh3::xxh3_64(&udata_raw);
+            if self
+                .udata_hash
+                .replace(hash)
+                .is_some_and(|last| last != hash)
+            {
+                self.pending_events.push_back(LGEvent::HostConfigChanged);
+            }
         }
+        let quirks = (Quirks::detect(&host_info) | self.opts.force_quirks)
+            .difference(self.opts.disable_quirks);
+        let shape_limit = match quirks.contains(Quirks::LIMIT_CURSOR_SHAPES) {
+            true => self.opts.cursor_shape_limit.or(Some(QUIRK_SHAPE_LIMIT)),
+            false => self.opts.cursor_shape_limit,
+        };
 
         //Subscribe to channels
-        let frame_chan = client.client_subscribe(shm_datastructs::LGMP_Q_FRAME)?;
-        let cursor_chan = client.client_subscribe(shm_datastructs::LGMP_Q_POINTER)?;
+        let frame_chan = match self.opts.frame.subscribe {
+            true => Some(client.client_subscribe(self.opts.frame.queue_id)?),
+            false => None,
+        };
+        let cursor_chan = match self.opts.cursor.subscribe {
+            true => Some(client.client_subscribe(self.opts.cursor.queue_id)?),
+            false => None,
+        };
 
         //Set timeouts
         let now = Instant::now();
-        let last_frame_heartbeat = now - self.opts.timeout;
-        let last_cursor_heartbeat = now - self.opts.timeout;
+        let last_frame_heartbeat = now - self.opts.frame.timeout;
+        let last_cursor_heartbeat = now - self.opts.cursor.timeout;
 
         //Session struct
         let session = LGMPSession {
+            client_id,
+            started_at: now,
+            lost: false,
             frame_chan,
             cursor_chan,
             last_frame_heartbeat,
             last_cursor_heartbeat,
+            last_frame_message: None,
+            last_cursor_message: None,
+            serials: FrameSerials::default(),
+            backlog: (last_frame_heartbeat, 0),
+            host_info,
+            quirks,
+            shape_limit,
         };
 
         self.session = Some(session);
+        if self.opts.lifecycle_events {
+            //Reported ahead of any config change found while initialising
+            self.pending_events
+                .push_front(LGEvent::SessionStarted(client_id));
+        }
 
         Ok(())
     }
@@ -96,143 +787,1954 @@ impl LGMPConnection {
     /// Specifically, messages will be skipped if we have not completely emptied out the
     /// queue recently.
     ///
-    /// It is recommended that this function be called around every 1ms.
-    pub fn tick_frame(&mut self, tick_period: Duration) -> Result<(), LGError> {
-        if let Some(ref mut sess) = self.session {
-            let projected_timeout = sess.last_frame_heartbeat + self.opts.timeout;
-            if Instant::now() + tick_period > projected_timeout {
-                sess.fast_forward(KVMFRChans::Frame)?;
-                sess.last_frame_heartbeat = Instant::now();
-            }
-        }
-        Ok(())
+    /// This should be called at the frame tick period set in [LGMPOpts], which defaults
+    /// to every 1ms. Frames are only skipped here if the [Backpressure] policy allows it.
+    pub fn tick_frame(&mut self) -> Result<(), LGError> {
+        self.health.record_tick(self.opts.frame.tick_period);
+        self.tick_chan(KVMFRChans::Frame, self.opts.frame.tick_period)
     }
 
     /// See [tick_frame]
     ///
-    /// It is recommended that this function be called around every 1ms.
-    pub fn tick_cursor(&mut self, tick_period: Duration) -> Result<(), LGError> {
+    /// This should be called at the cursor tick period set in [LGMPOpts], which defaults
+    /// to every 1ms.
+    pub fn tick_cursor(&mut self) -> Result<(), LGError> {
+        self.tick_chan(KVMFRChans::Cursor, self.opts.cursor.tick_period)
+    }
+
+    /// Fast-forwards a channel if its projected host timeout is within `margin` of now.
+    fn tick_chan(&mut self, channel: KVMFRChans, margin: Duration) -> Result<(), LGError> {
+        let timeout = match channel {
+            KVMFRChans::Frame => self.opts.frame.timeout,
+            KVMFRChans::Cursor => self.opts.cursor.timeout,
+        };
+        let skips = match self.opts.backpressure {
+            Backpressure::AvoidTimeout | Backpressure::Latest => true,
+            Backpressure::Never | Backpressure::BoundedLag(_) => false,
+        };
+        if channel == KVMFRChans::Frame && !skips {
+            return Ok(());
+        }
         if let Some(ref mut sess) = self.session {
-            let projected_timeout = sess.last_cursor_heartbeat + self.opts.timeout;
-            if Instant::now() + tick_period > projected_timeout {
-                sess.fast_forward(KVMFRChans::Cursor)?;
-                sess.last_cursor_heartbeat = Instant::now();
+            let hb = match channel {
+                KVMFRChans::Frame => sess.last_frame_heartbeat,
+                KVMFRChans::Cursor => sess.last_cursor_heartbeat,
+            };
+            if Deadline::new(hb, timeout).due_within(Instant::now(), margin) {
+                if sess.fast_forward(channel, &mut self.stats)? {
+                    self.stats.fast_forwards += 1;
+                }
+                match channel {
+                    KVMFRChans::Frame => sess.last_frame_heartbeat = Instant::now(),
+                    KVMFRChans::Cursor => sess.last_cursor_heartbeat = Instant::now(),
+                }
             }
         }
         Ok(())
     }
 
-    /// Retrieves an update from the frame channel if one is available, returning a handle
-    /// to it if so. The channel will remain locked until this value is dropped.
-    pub fn get_frame_update(&mut self) -> Result<Option<KVMFRFrameHandle>, LGError> {
-        if let Some(ref mut sess) = self.session {
-            let msg = sess.pop_ref(KVMFRChans::Frame)?;
-            Ok(msg.map(|m| KVMFRFrameHandle { _msg_handle: m }))
-        } else {
-            Ok(None)
+    /// Ticks a channel from one of the accessors, if `auto_tick` is enabled.
+    fn auto_tick(&mut self, channel: KVMFRChans) -> Result<(), LGError> {
+        if !self.opts.auto_tick {
+            return Ok(());
         }
+        let (ticker, chan_opts) = match channel {
+            KVMFRChans::Frame => (&mut self.frame_auto_tick, &self.opts.frame),
+            KVMFRChans::Cursor => (&mut self.cursor_auto_tick, &self.opts.cursor),
+        };
+        let margin = ticker.record_call(
+            Instant::now(),
+            self.stats.queue_mut(channel).timeouts,
+            chan_opts.tick_period,
+            chan_opts.timeout,
+        );
+        self.tick_chan(channel, margin)
     }
 
-    /// Retrieves an update from the cursor channel if one is available, returning a handle
-    /// to it if so. The channel will remain locked until this value is dropped.
-    pub fn get_cursor_update(&mut self) -> Result<Option<KVMFRCursorHandle>, LGError> {
+    /// Skips waiting frames before one is read, if the [Backpressure] policy calls for it.
+    fn apply_backpressure(&mut self) -> Result<(), LGError> {
+        let Some(ref mut sess) = self.session else {
+            return Ok(());
+        };
+        let skip = match self.opts.backpressure {
+            Backpressure::Latest => true,
+            Backpressure::BoundedLag(max) => sess.backlog() > max,
+            Backpressure::AvoidTimeout | Backpressure::Never => false,
+        };
+        if skip && sess.fast_forward(KVMFRChans::Frame, &mut self.stats)? {
+            self.stats.fast_forwards += 1;
+            sess.backlog = (sess.last_frame_heartbeat, 0);
+        }
+        Ok(())
+    }
+
+    /// Returns true if a frame may be delivered now under the frame rate limit set with
+    /// [LGMPOptsBuilder::max_frame_rate]. Frames which have arrived since the last one was
+    /// delivered are coalesced first, so that only the newest is left.
+    fn pace_frames(&mut self) -> Result<bool, LGError> {
+        if !self.pacer.is_enabled() {
+            return Ok(true);
+        }
         if let Some(ref mut sess) = self.session {
-            let msg = sess.pop_ref(KVMFRChans::Cursor)?;
-            Ok(msg.map(|m| KVMFRCursorHandle { _msg_handle: m }))
-        } else {
-            Ok(None)
+            sess.fast_forward(KVMFRChans::Frame, &mut self.stats)?;
         }
+        Ok(self.pacer.is_due(Instant::now()))
     }
-}
 
-pub struct KVMFRFrameHandle<'a> {
-    _msg_handle: InPlaceMessage<'a>,
-}
+    /// Returns the details sent by the host when the current session was initialised,
+    /// such as its version and supported features.
+    ///
+    /// If a session has not yet been initialised, this will return None.
+    pub fn host_info(&self) -> Option<&HostInfo> {
+        self.session.as_ref().map(|sess| &sess.host_info)
+    }
 
-impl KVMFRFrameHandle<'_> {
-    pub fn as_frame<'a>(&'a self) -> Result<&'a shm_datastructs::KVMFRFrame, LGError> {
-        let msg = &self._msg_handle.mem;
-        if msg.size < size_of::<shm_datastructs::KVMFRFrame>() {
-            Err(LGError::FrameChannelMessageTooSmall)
-        } else {
-            let ptr = msg.mem;
-            let res = unsafe { &*ptr.cast::<shm_datastructs::KVMFRFrame>() };
-            Ok(res)
-        }
+    /// Returns the workarounds applied to the host of the current session, after any
+    /// overrides set in [LGMPOpts].
+    ///
+    /// If a session has not yet been initialised, this will return None.
+    pub fn quirks(&self) -> Option<Quirks> {
+        self.session.as_ref().map(|sess| sess.quirks)
     }
-}
 
-pub struct KVMFRCursorHandle<'a> {
-    _msg_handle: InPlaceMessage<'a>,
-}
+    /// Returns the id assigned to this client by the host for the current session.
+    ///
+    /// If a session has not yet been initialised, this will return None.
+    pub fn client_id(&self) -> Option<u32> {
+        self.session.as_ref().map(|sess| sess.client_id)
+    }
 
-impl KVMFRCursorHandle<'_> {
-    pub fn as_ptr_msg<'a>(&'a self) -> Result<&'a shm_datastructs::KVMFRCursor, LGError> {
-        let msg = &self._msg_handle.mem;
-        if msg.size < size_of::<shm_datastructs::KVMFRCursor>() {
-            Err(LGError::CursorChannelMessageTooSmall)
-        } else {
-            let ptr = msg.mem;
-            let res = unsafe { &*ptr.cast::<shm_datastructs::KVMFRCursor>() };
-            Ok(res)
-        }
+    /// Returns the time at which the current session was initialised.
+    ///
+    /// If a session has not yet been initialised, this will return None.
+    pub fn session_started(&self) -> Option<Instant> {
+        self.session.as_ref().map(|sess| sess.started_at)
     }
-}
 
-/// Selector for the channels subscribed to by LGMP client
-enum KVMFRChans {
-    Frame,
-    Cursor,
-}
+    /// Returns the KVMFR protocol version reported by the host of the current session.
+    ///
+    /// If a session has not yet been initialised, this will return None.
+    pub fn kvmfr_version(&self) -> Option<u32> {
+        self.host_info().map(|info| info.version)
+    }
 
-/// Holds handles to channels listened to by an LGMP client, as well as the
-/// times at which they last received an LGMPErrQueueEmpty response.
-struct LGMPSession {
-    frame_chan: ligmars::client::ClientQueueHandle,
-    cursor_chan: ligmars::client::ClientQueueHandle,
+    /// Returns true if the host of the current session supports all of the provided
+    /// features.
+    ///
+    /// If a session has not yet been initialised, this will always return false.
+    pub fn supports(&self, features: HostFeatures) -> bool {
+        self.host_info().is_some_and(|info| info.supports(features))
+    }
 
-    last_frame_heartbeat: Instant,
-    last_cursor_heartbeat: Instant,
-}
+    /// Sends a message to the host on the cursor channel, returning its serial.
+    ///
+    /// Fails with [LGError::UnsupportedFeature] if the host of the current session has not
+    /// advertised the features the message needs, rather than sending something the host
+    /// would ignore. Fails with [LGError::SessionInvalid] if a session has not yet been
+    /// initialised, or an LGMPErrQueueUnsubscribed error if the cursor channel was not
+    /// subscribed to.
+    pub fn send_message(&mut self, msg: HostMessage) -> Result<u32, LGError> {
+        let sess = self.session.as_mut().ok_or(LGError::SessionInvalid)?;
+        sess.host_info.require(msg.required_features())?;
+        let chan =
+            sess.cursor_chan
+                .as_mut()
+                .ok_or(crate::lgmp_impl::error::Error::InternalError(
+                    crate::lgmp_impl::error::Status::LGMPErrQueueUnsubscribed,
+                ))?;
+        Ok(chan.send_data(msg.to_bytes())?)
+    }
 
-impl LGMPSession {
-    /// Returns a refererence to the data contained within the next message in the
-    /// requested channel. This reference also holds a lock on the channel.
+    /// Returns true if there is at least one unread message waiting on the frame channel.
     ///
-    /// If the channel is empty, returns Ok(None)
-    fn pop_ref(&mut self, channel: KVMFRChans) -> Result<Option<InPlaceMessage>, LGError> {
-        let (chan, hb) = match channel {
-            KVMFRChans::Frame => (&mut self.frame_chan, &mut self.last_frame_heartbeat),
-            KVMFRChans::Cursor => (&mut self.cursor_chan, &mut self.last_cursor_heartbeat),
+    /// Unlike [get_frame_update], this only peeks at the queue position and does not hold
+    /// a lock on the message, making it cheap enough to call every vsync to decide whether
+    /// the frame upload path needs to run at all.
+    /// If a session has not yet been initialised, this will always return false.
+    pub fn has_frame_pending(&mut self) -> Result<bool, LGError> {
+        if let Some(ref mut sess) = self.session {
+            sess.has_pending(KVMFRChans::Frame, &mut self.stats)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Checks both channels for updates, returning the first one found.
+    ///
+    /// Frame updates are checked before cursor updates, unless a different
+    /// [ChannelPriority] was configured. If the host session has become
+    /// invalid, [LGEvent::HostLost] is returned instead of any pending messages.
+    /// If a session has not yet been initialised, this will always return [LGEvent::Idle].
+    ///
+    /// Before a frame is returned, [LGEvent::FormatChanged] and [LGEvent::Anomaly] are
+    /// reported first if applicable, so callers can reconfigure before handling it.
+    ///
+    /// If `auto_reconnect` is enabled, a session lost due to the host restarting or
+    /// timing this client out will be re-established over the course of several polls,
+    /// returning [LGEvent::Reconnected] once complete.
+    pub fn poll_event(&mut self) -> Result<LGEvent<'_>, LGError> {
+        #[cfg(feature = "black-box")]
+        let black_box = self.black_box.clone();
+        let event = self.next_event();
+        #[cfg(feature = "black-box")]
+        if let Some(black_box) = black_box {
+            black_box.record_event(&event);
+        }
+        event
+    }
+
+    fn next_event(&mut self) -> Result<LGEvent<'_>, LGError> {
+        if self.opts.auto_reconnect {
+            if let Some(event) = self.drive_reconnect()? {
+                return Ok(event);
+            }
+        }
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(event);
+        }
+        let ticked = self
+            .auto_tick(KVMFRChans::Frame)
+            .and_then(|()| self.auto_tick(KVMFRChans::Cursor));
+        if let Err(e) = ticked {
+            return self.session_lost(e);
+        }
+        if let Some(interval) = self.opts.stats_interval {
+            if self.last_stats.elapsed() >= interval {
+                self.last_stats = Instant::now();
+                return Ok(LGEvent::Stats(self.stats));
+            }
+        }
+
+        let frame_due = match self.apply_backpressure().and_then(|()| self.pace_frames()) {
+            Ok(frame_due) => frame_due,
+            Err(e) => return self.session_lost(e),
+        };
+        let sess = match self.session {
+            Some(ref mut sess) => sess,
+            None => return Ok(LGEvent::Idle),
         };
 
-        let msg = match chan.pop_in_place() {
-            Ok(msg) => Ok(Some(msg)),
-            Err(ligmars::error::Error::InternalError(
-                ligmars::error::Status::LGMPErrQueueEmpty,
-            )) => {
-                *hb = Instant::now();
-                Ok(None)
+        if !self.client.lock()?.client_session_valid() {
+            if self.opts.lifecycle_events && !sess.lost {
+                sess.lost = true;
+                return Ok(LGEvent::SessionLost(sess.client_id));
             }
-            Err(e) => Err(e),
-        }?;
+            return Ok(LGEvent::HostLost);
+        }
 
-        Ok(msg)
-    }
+        if frame_due {
+            if let Err(e) = sess.check_frame(&mut self.pending_events, &mut self.stats) {
+                let state = &mut self.reconnect_state;
+                return session_lost_event(e, &self.opts, state, sess.client_id);
+            }
+            if let Some(event) = self.pending_events.pop_front() {
+                return Ok(event);
+            }
+        }
 
-    /// Marks all but the most recent message in a channel as read.
-    fn fast_forward(&mut self, channel: KVMFRChans) -> Result<(), LGError> {
-        let (chan, hb) = match channel {
-            KVMFRChans::Frame => (&mut self.frame_chan, &mut self.last_frame_heartbeat),
-            KVMFRChans::Cursor => (&mut self.cursor_chan, &mut self.last_cursor_heartbeat),
+        //Frames are checked first unless the priority says otherwise
+        let cursor_first = match self.opts.priority {
+            ChannelPriority::Frames => false,
+            ChannelPriority::Cursor => true,
+            ChannelPriority::Alternate => {
+                self.cursor_turn = !self.cursor_turn;
+                self.cursor_turn
+            }
+        };
+        let cursor_pending = match cursor_first {
+            true => sess.has_pending(KVMFRChans::Cursor, &mut self.stats),
+            false => Ok(false),
+        };
+        let skip_frame = match cursor_pending {
+            Ok(cursor_pending) => !frame_due || cursor_pending,
+            Err(e) => {
+                let state = &mut self.reconnect_state;
+                return session_lost_event(e, &self.opts, state, sess.client_id);
+            }
         };
 
-        chan.advance_to_last().or_else(|e| match e {
-            ligmars::error::Error::InternalError(ligmars::error::Status::LGMPErrQueueEmpty) => {
-                *hb = Instant::now();
-                Ok(())
+        if !skip_frame {
+            //Discounted again if the queue turns out to be empty
+            sess.record_backlog();
+        }
+        if let (false, Some(ref mut chan)) = (skip_frame, &mut sess.frame_chan) {
+            let hb = &mut sess.last_frame_heartbeat;
+            let popped = match pop_chan_ref(chan, hb, &mut self.stats.frame_queue) {
+                Ok(popped) => popped,
+                Err(e) => {
+                    let state = &mut self.reconnect_state;
+                    return session_lost_event(e, &self.opts, state, sess.client_id);
+                }
+            };
+            if let Some(m) = popped {
+                sess.last_frame_message = Some(Instant::now());
+                let mut frame = KVMFRFrameHandle {
+                    _msg_handle: MessageRef::Live(m),
+                    device: self.device.clone(),
+                    ignore_damage: sess.quirks.contains(Quirks::IGNORE_DAMAGE),
+                    copy_strategy: self.opts.copy_strategy,
+                    dropped: 0,
+                    last_hash: &self.last_frame_hash,
+                    duplicate: Cell::new(None),
+                };
+                //The host may have moved the queue on since the head was checked
+                record_frame(
+                    &mut frame,
+                    &mut sess.serials,
+                    &mut self.pending_events,
+                    &mut self.stats,
+                    &mut self.pacer,
+                    self.opts.metrics.then_some(&mut self.frame_metrics),
+                );
+                self.capture
+                    .check(&frame, &mut self.pending_events, &mut self.stats);
+                if let Some(ref mut recorder) = self.recorder {
+                    recorder.record_frame(&frame)?;
+                }
+                #[cfg(feature = "black-box")]
+                if let Some(ref black_box) = self.black_box {
+                    black_box.record_frame(&frame);
+                }
+                return Ok(LGEvent::Frame(frame));
             }
-            e => Err(e)?,
-        })
+        }
+        if let Some(ref mut chan) = sess.cursor_chan {
+            let hb = &mut sess.last_cursor_heartbeat;
+            let popped = match pop_chan_ref(chan, hb, &mut self.stats.cursor_queue) {
+                Ok(popped) => popped,
+                Err(e) => {
+                    let state = &mut self.reconnect_state;
+                    return session_lost_event(e, &self.opts, state, sess.client_id);
+                }
+            };
+            if let Some(m) = popped {
+                sess.last_cursor_message = Some(Instant::now());
+                if m.mem.size < size_of::<shm_datastructs::KVMFRCursor>() {
+                    //Dropping the message discards it
+                    self.stats.anomalies += 1;
+                    return Ok(LGEvent::Anomaly(Anomaly::CursorMessageTooSmall));
+                }
+                self.stats.cursor_updates += 1;
+                let suppress_shape = self.cursor_rate.record(
+                    CursorFlags::from_bits_retain(m.mem.udata),
+                    sess.shape_limit,
+                    &mut self.stats,
+                );
+                if self.cursor_rate.take_storm() {
+                    let storm = LGEvent::Anomaly(Anomaly::CursorShapeStorm);
+                    self.pending_events.push_back(storm);
+                }
+                let cursor = KVMFRCursorHandle {
+                    _msg_handle: MessageRef::Live(m),
+                    suppress_shape,
+                };
+                if let Some(ref mut recorder) = self.recorder {
+                    recorder.record_cursor(&cursor)?;
+                }
+                return Ok(LGEvent::Cursor(cursor));
+            }
+        }
+
+        Ok(LGEvent::Idle)
+    }
+
+    /// Peeks at a queue to report whether messages are waiting and how close the host is to
+    /// timing this client out, so that applications can tell they are falling behind
+    /// before the tick functions have to fast-forward.
+    ///
+    /// If a session has not yet been initialised, this will return None.
+    pub fn queue_status(&mut self, channel: KVMFRChans) -> Result<Option<QueueStatus>, LGError> {
+        let timeout = match channel {
+            KVMFRChans::Frame => self.opts.frame.timeout,
+            KVMFRChans::Cursor => self.opts.cursor.timeout,
+        };
+        match self.session {
+            Some(ref mut sess) => Ok(Some(sess.status(channel, timeout, &mut self.stats)?)),
+            None => Ok(None),
+        }
     }
+
+    /// Returns when the host will drop this client from a queue unless it is emptied or
+    /// fast-forwarded first, as [Self::tick_frame] and [Self::tick_cursor] check. Unlike
+    /// [Self::queue_status], this does not read the queue, so the deadline only moves once
+    /// a read finds the queue empty.
+    ///
+    /// If a session has not yet been initialised or the queue is not subscribed, this will
+    /// return None.
+    pub fn deadline(&self, channel: KVMFRChans) -> Option<Deadline> {
+        let sess = self.session.as_ref()?;
+        let (chan, hb, timeout) = match channel {
+            KVMFRChans::Frame => (
+                &sess.frame_chan,
+                sess.last_frame_heartbeat,
+                self.opts.frame.timeout,
+            ),
+            KVMFRChans::Cursor => (
+                &sess.cursor_chan,
+                sess.last_cursor_heartbeat,
+                self.opts.cursor.timeout,
+            ),
+        };
+        chan.as_ref().map(|_| Deadline::new(hb, timeout))
+    }
+
+    /// Reports whether the host's session is still valid, along with the state of both
+    /// queues, so that applications can show that the host has gone as soon as it happens
+    /// rather than waiting for an error. See [ConnectionHealth].
+    pub fn connection_health(&mut self) -> Result<ConnectionHealth, LGError> {
+        let host_alive = self.session.is_some() && self.client.lock()?.client_session_valid();
+        if !host_alive {
+            //The queues can't be read once the session is gone
+            return Ok(ConnectionHealth {
+                host_alive,
+                frame: None,
+                cursor: None,
+            });
+        }
+        Ok(ConnectionHealth {
+            host_alive,
+            frame: self.queue_status(KVMFRChans::Frame)?,
+            cursor: self.queue_status(KVMFRChans::Cursor)?,
+        })
+    }
+
+    /// Returns counters describing the activity seen on this connection so far.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    /// Changes how frame data is copied out of shared memory, such as to a strategy
+    /// picked by [CopyStrategy::calibrate] from the data of a frame. Receivers which have
+    /// already been split off keep the strategy they were split with.
+    pub fn set_copy_strategy(&mut self, strategy: CopyStrategy) {
+        self.opts.copy_strategy = strategy;
+    }
+
+    /// Combines tick jitter, dropped frames, queue backlog, timeouts and reconnects into a
+    /// single coarse status, for showing a connection quality indicator.
+    ///
+    /// Each report covers the time since the previous one, so this should be called
+    /// periodically, such as once a second.
+    pub fn health(&mut self) -> Result<HealthReport, LGError> {
+        let session_valid = self.session.is_some() && self.client.lock()?.client_session_valid();
+        Ok(self
+            .health
+            .report(&self.stats, session_valid, self.opts.frame.timeout))
+    }
+
+    /// Arms a one-shot capture of the frame with the given serial, or the first frame after
+    /// it if that one is skipped. Once the frame has been received, its contents can be
+    /// collected with [Self::take_capture].
+    ///
+    /// Arming a new capture replaces any which has not yet triggered, and discards any
+    /// captured frame which has not been collected.
+    pub fn capture_at_serial(&mut self, serial: u32) {
+        self.capture = FrameCapture {
+            armed: Some(serial),
+            captured: None,
+        };
+    }
+
+    /// Returns the frame captured by [Self::capture_at_serial], if it has been received.
+    pub fn take_capture(&mut self) -> Option<CapturedFrame> {
+        self.capture.captured.take()
+    }
+
+    /// Starts writing every frame and cursor message received on this connection to a
+    /// recording, which can be played back with [super::ReplayConnection]. Replaces any
+    /// recording already in progress.
+    pub fn start_recording(
+        &mut self,
+        writer: impl std::io::Write + Send + 'static,
+    ) -> Result<(), LGError> {
+        let writer: Box<dyn std::io::Write + Send> = Box::new(writer);
+        self.recorder = Some(Recorder::new(writer)?);
+        Ok(())
+    }
+
+    /// Stops recording, returning the writer which the recording was written to.
+    pub fn stop_recording(&mut self) -> Option<Box<dyn std::io::Write + Send>> {
+        self.recorder.take().map(Recorder::into_inner)
+    }
+
+    /// Starts keeping the last `frames` frames received on this connection in `dir`, along
+    /// with a journal of the events returned by [Self::poll_event], so that problems such
+    /// as intermittent corruption can be looked into after the fact. Replaces any black box
+    /// already running, which is stopped as by [Self::stop_black_box], and anything left in
+    /// `dir` by an earlier one.
+    ///
+    /// Frames are compressed and written out on a background thread as they arrive, so
+    /// that they survive the process being killed. Frames which arrive while the previous
+    /// one is still being written are not kept, which is noted in the journal.
+    ///
+    /// If the connection is dropped with the black box running, including while unwinding
+    /// from a panic, the frames are gathered into a recording which can be played back with
+    /// [super::ReplayConnection]. See [super::finalize_black_box] to do the same after the
+    /// process was killed.
+    #[cfg(feature = "black-box")]
+    pub fn start_black_box(
+        &mut self,
+        dir: impl Into<std::path::PathBuf>,
+        frames: usize,
+    ) -> Result<(), LGError> {
+        //Any error from the old black box is of no use to the new one
+        let _ = self.stop_black_box();
+        self.black_box = Some(Arc::new(BlackBox::start(dir.into(), frames)?));
+        Ok(())
+    }
+
+    /// Stops the black box started with [Self::start_black_box] without gathering its
+    /// frames into a recording, returning any error which stopped it writing early.
+    #[cfg(feature = "black-box")]
+    pub fn stop_black_box(&mut self) -> Result<(), LGError> {
+        match self.black_box.take().and_then(Arc::into_inner) {
+            Some(black_box) => black_box.stop(),
+            None => Ok(()),
+        }
+    }
+
+    /// As [Self::get_frame_update], but first marks any older frames waiting in the queue as
+    /// read so that only the newest is returned, for viewers which only care about latency.
+    ///
+    /// Frames skipped this way are reported by [KVMFRFrameHandle::dropped_since_last].
+    pub fn get_latest_frame_update(&mut self) -> Result<Option<KVMFRFrameHandle<'_>>, LGError> {
+        if let Some(ref mut sess) = self.session {
+            sess.fast_forward(KVMFRChans::Frame, &mut self.stats)?;
+        }
+        self.get_frame_update()
+    }
+
+    /// Waits for up to `timeout` for a frame to arrive, returning it as
+    /// [Self::get_frame_update] would, or None if the deadline passed first.
+    ///
+    /// While waiting, both channels are ticked often enough to keep the session alive.
+    /// The queue is spun on briefly, then polled with sleeps which double each time up to
+    /// the shorter of the two tick periods. If a session has not yet been initialised, this
+    /// returns None immediately.
+    pub fn wait_for_frame(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<KVMFRFrameHandle<'_>>, LGError> {
+        if self.session.is_none() {
+            return Ok(None);
+        }
+        let start = Instant::now();
+        let max_sleep = self
+            .opts
+            .frame
+            .tick_period
+            .min(self.opts.cursor.tick_period);
+        let mut sleep = WAIT_MIN_SLEEP.min(max_sleep);
+        let mut spins = 0;
+        loop {
+            //Checked before ticking, which could fast-forward past frames already queued
+            if self.pace_frames()? && self.has_frame_pending()? {
+                break;
+            }
+            self.tick_chan(KVMFRChans::Frame, self.opts.frame.tick_period)?;
+            self.tick_chan(KVMFRChans::Cursor, self.opts.cursor.tick_period)?;
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            if spins < WAIT_SPINS {
+                spins += 1;
+                std::hint::spin_loop();
+            } else {
+                std::thread::sleep(sleep.min(remaining));
+                sleep = (sleep * 2).min(max_sleep);
+            }
+        }
+        self.get_frame_update()
+    }
+
+    /// Returns an iterator which waits for each frame in turn and copies it out as an
+    /// [OwnedFrame] along with its metadata, ticking both channels while it waits.
+    ///
+    /// Errors are returned as items. If one means that the session has been lost, the
+    /// session is closed and the iterator ends after returning it. The iterator also ends
+    /// straight away if there is no session.
+    pub fn frames(&mut self) -> impl Iterator<Item = Result<OwnedFrame, LGError>> + '_ {
+        std::iter::from_fn(move || loop {
+            self.session.as_ref()?;
+            //Copying the frame out first releases the connection for close_session
+            let frame = self
+                .wait_for_frame(FRAMES_WAIT)
+                .map(|f| f.map(|f| f.to_owned()));
+            match frame {
+                Ok(Some(frame)) => return Some(frame),
+                Ok(None) => continue,
+                Err(e) => {
+                    //Ending the iterator next time, rather than repeating the error forever
+                    if e.requires_reconnect() {
+                        let _ = self.close_session();
+                    }
+                    return Some(Err(e));
+                }
+            }
+        })
+    }
+
+    /// Retrieves an update from the frame channel if one is available, returning a handle
+    /// to it if so. The channel will remain locked until this value is dropped.
+    ///
+    /// Frames skipped before it and changes to the format version are counted as they are
+    /// by [Self::poll_event], which reports them on its next call.
+    pub fn get_frame_update(&mut self) -> Result<Option<KVMFRFrameHandle<'_>>, LGError> {
+        self.auto_tick(KVMFRChans::Frame)?;
+        self.apply_backpressure()?;
+        if !self.pace_frames()? {
+            return Ok(None);
+        }
+        if let Some(ref mut sess) = self.session {
+            //Discounted again if the queue turns out to be empty
+            sess.record_backlog();
+            let Some(ref mut chan) = sess.frame_chan else {
+                return Ok(None);
+            };
+            let hb = &mut sess.last_frame_heartbeat;
+            let Some(m) = pop_chan_ref(chan, hb, &mut self.stats.frame_queue)? else {
+                return Ok(None);
+            };
+            sess.last_frame_message = Some(Instant::now());
+            let mut frame = KVMFRFrameHandle {
+                _msg_handle: MessageRef::Live(m),
+                device: self.device.clone(),
+                ignore_damage: sess.quirks.contains(Quirks::IGNORE_DAMAGE),
+                copy_strategy: self.opts.copy_strategy,
+                dropped: 0,
+                last_hash: &self.last_frame_hash,
+                duplicate: Cell::new(None),
+            };
+            //Frames read here are never checked at the head of the queue first
+            record_frame(
+                &mut frame,
+                &mut sess.serials,
+                &mut self.pending_events,
+                &mut self.stats,
+                &mut self.pacer,
+                self.opts.metrics.then_some(&mut self.frame_metrics),
+            );
+            self.capture
+                .check(&frame, &mut self.pending_events, &mut self.stats);
+            if let Some(ref mut recorder) = self.recorder {
+                recorder.record_frame(&frame)?;
+            }
+            #[cfg(feature = "black-box")]
+            if let Some(ref black_box) = self.black_box {
+                black_box.record_frame(&frame);
+            }
+            Ok(Some(frame))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Retrieves an update from the cursor channel if one is available, returning a handle
+    /// to it if so. The channel will remain locked until this value is dropped.
+    pub fn get_cursor_update(&mut self) -> Result<Option<KVMFRCursorHandle<'_>>, LGError> {
+        self.auto_tick(KVMFRChans::Cursor)?;
+        if let Some(ref mut sess) = self.session {
+            let shape_limit = sess.shape_limit;
+            let Some(m) = sess.pop_ref(KVMFRChans::Cursor, &mut self.stats)? else {
+                return Ok(None);
+            };
+            let suppress_shape = self.cursor_rate.record(
+                CursorFlags::from_bits_retain(m.mem.udata),
+                shape_limit,
+                &mut self.stats,
+            );
+            if self.cursor_rate.take_storm() {
+                let storm = LGEvent::Anomaly(Anomaly::CursorShapeStorm);
+                self.pending_events.push_back(storm);
+            }
+            let cursor = KVMFRCursorHandle {
+                _msg_handle: MessageRef::Live(m),
+                suppress_shape,
+            };
+            if let Some(ref mut recorder) = self.recorder {
+                recorder.record_cursor(&cursor)?;
+            }
+            Ok(Some(cursor))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A single happening on an LGMP connection, as returned by [LGMPConnection::poll_event].
+#[non_exhaustive]
+pub enum LGEvent<'a> {
+    /// A new frame was received from the host. The frame channel stays locked until
+    /// the handle is dropped.
+    Frame(KVMFRFrameHandle<'a>),
+    /// A cursor update was received from the host. The cursor channel stays locked
+    /// until the handle is dropped.
+    Cursor(KVMFRCursorHandle<'a>),
+    /// The next frame has a different format version from the previous one, meaning that
+    /// its size, format or layout has changed. Contains the new format version.
+    FormatChanged(u32),
+    /// The host has stopped responding or has been restarted, so the current session
+    /// is no longer valid.
+    HostLost,
+    /// A new session has been established after the host was restarted. Any state
+    /// derived from previous frames should be reset.
+    Reconnected,
+    /// The host sent different details when the latest session was initialised than for
+    /// the previous one, such as a new version or feature set, so anything negotiated
+    /// from [LGMPConnection::host_info] should be redone. Only reported if enabled with
+    /// [LGMPOptsBuilder::detect_config_changes], after [LGEvent::Reconnected].
+    HostConfigChanged,
+    /// A session was initialised, either by [LGMPConnection::init] or when reconnecting.
+    /// Contains the id the host assigned to this client. Only reported if enabled with
+    /// [LGMPOptsBuilder::lifecycle_events].
+    SessionStarted(u32),
+    /// The host stopped tracking the session with the given client id. Reported in place
+    /// of the first [LGEvent::HostLost] if enabled with [LGMPOptsBuilder::lifecycle_events],
+    /// but not when the session is ended with [LGMPConnection::close_session].
+    SessionLost(u32),
+    /// Nothing happened since the last poll.
+    Idle,
+    /// Periodic connection statistics, if enabled with [LGMPOptsBuilder::stats_interval].
+    Stats(ConnectionStats),
+    /// Something unexpected was received from the host. This is not fatal, but may
+    /// indicate a misbehaving or incompatible host.
+    Anomaly(Anomaly),
+}
+
+/// Unexpected occurrences reported by [LGEvent::Anomaly].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Anomaly {
+    /// A message on the frame channel was too small to hold a frame header, and was
+    /// discarded.
+    FrameMessageTooSmall,
+    /// A message on the cursor channel was too small to hold a cursor header, and was
+    /// discarded.
+    CursorMessageTooSmall,
+    /// The given number of frames were missed between the previous frame and the next
+    /// one, either because this client fell behind or the queue was fast-forwarded.
+    FramesSkipped(u32),
+    /// The host sent more cursor shape updates within a second than the limit set with
+    /// [LGMPOptsBuilder::cursor_shape_limit], or a default threshold if there is none.
+    /// Reported once per second while the storm continues.
+    CursorShapeStorm,
+    /// The frame waited for by [LGMPConnection::capture_at_serial] could not be copied,
+    /// and the capture was cancelled. The frame itself is still returned.
+    CaptureFailed,
+}
+
+/// A copy of a frame taken by [LGMPConnection::capture_at_serial].
+pub struct CapturedFrame {
+    pub serial: u32,
+    pub info: FrameInfo,
+    pub data: FrameBuffer,
+}
+
+/// State of a capture requested by [LGMPConnection::capture_at_serial].
+#[derive(Default)]
+struct FrameCapture {
+    /// Serial of the frame to capture, if a capture is waiting to trigger
+    armed: Option<u32>,
+    captured: Option<CapturedFrame>,
+}
+
+impl FrameCapture {
+    /// Copies the frame if it is the one being waited for, or comes after it. If the copy
+    /// fails the capture is cancelled and reported as an anomaly rather than failing the
+    /// read of the frame.
+    fn check(
+        &mut self,
+        frame: &KVMFRFrameHandle,
+        events: &mut VecDeque<LGEvent<'static>>,
+        stats: &mut ConnectionStats,
+    ) {
+        if self.armed.is_none() {
+            return;
+        }
+        if self.try_capture(frame).is_err() {
+            self.armed = None;
+            stats.anomalies += 1;
+            events.push_back(LGEvent::Anomaly(Anomaly::CaptureFailed));
+        }
+    }
+
+    fn try_capture(&mut self, frame: &KVMFRFrameHandle) -> Result<(), LGError> {
+        let Some(target) = self.armed else {
+            return Ok(());
+        };
+        let serial = frame.as_frame()?.frameSerial;
+        //As with skipped frames, anything past half the serial space is before the target
+        if serial.wrapping_sub(target) >= u32::MAX / 2 {
+            return Ok(());
+        }
+        self.armed = None;
+        self.captured = Some(CapturedFrame {
+            serial,
+            info: frame.info()?,
+            data: frame.copy_data()?,
+        });
+        Ok(())
+    }
+}
+
+/// Counters describing the activity seen on an [LGMPConnection].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConnectionStats {
+    /// Frames returned by [LGMPConnection::poll_event] and [LGMPConnection::get_frame_update]
+    pub frames: u64,
+    /// Cursor updates returned by [LGMPConnection::poll_event]
+    pub cursor_updates: u64,
+    /// Frames which were sent by the host but never seen by this client
+    pub frames_skipped: u64,
+    /// Anomalies reported by [LGMPConnection::poll_event]
+    pub anomalies: u64,
+    /// Sessions re-established automatically after the host restarted
+    pub reconnects: u64,
+    /// Errors returned by LGMP when reading from the frame queue
+    pub frame_queue: QueueErrorStats,
+    /// Errors returned by LGMP when reading from the cursor queue
+    pub cursor_queue: QueueErrorStats,
+    /// Cursor updates which carried a new shape
+    pub cursor_shapes: u64,
+    /// Cursor shapes removed by the limit set with [LGMPOptsBuilder::cursor_shape_limit]
+    pub cursor_shapes_dropped: u64,
+    /// Cursor updates received during the most recent full second with any activity
+    pub cursor_updates_per_sec: u32,
+    /// Cursor shapes received during the most recent full second with any activity
+    pub cursor_shapes_per_sec: u32,
+    /// Fast forwards of either queue performed by the tick functions while messages
+    /// were waiting. Any frames dropped as a result are counted in `frames_skipped`.
+    pub fast_forwards: u64,
+    /// Frames returned during the most recent full second with any frames. Only collected
+    /// if enabled with [LGMPOptsBuilder::collect_metrics]
+    pub frames_per_sec: u32,
+    /// Mean time between a frame first being seen at the head of the queue and it being
+    /// returned, over the same second as `frames_per_sec`.
+    ///
+    /// KVMFR does not timestamp frames, so this measures delays on the client side rather
+    /// than the time since the host published the frame.
+    pub frame_latency_avg: Duration,
+    /// Longest frame latency over the same second as `frames_per_sec`
+    pub frame_latency_max: Duration,
+}
+
+impl ConnectionStats {
+    pub(super) fn queue_mut(&mut self, channel: KVMFRChans) -> &mut QueueErrorStats {
+        match channel {
+            KVMFRChans::Frame => &mut self.frame_queue,
+            KVMFRChans::Cursor => &mut self.cursor_queue,
+        }
+    }
+}
+
+/// Cursor updates seen within the current rate window.
+pub(super) struct CursorRate {
+    window_start: Instant,
+    updates: u32,
+    shapes: u32,
+    //Whether a storm has been detected but not yet reported
+    storm: bool,
+}
+
+impl CursorRate {
+    pub(super) fn new() -> CursorRate {
+        CursorRate {
+            window_start: Instant::now(),
+            updates: 0,
+            shapes: 0,
+            storm: false,
+        }
+    }
+
+    /// Records a cursor update with the given flags, noting a storm if there have been too
+    /// many shapes this window. Returns true if the update's shape should be removed.
+    pub(super) fn record(
+        &mut self,
+        flags: CursorFlags,
+        limit: Option<u32>,
+        stats: &mut ConnectionStats,
+    ) -> bool {
+        if self.window_start.elapsed() >= RATE_WINDOW {
+            stats.cursor_updates_per_sec = self.updates;
+            stats.cursor_shapes_per_sec = self.shapes;
+            *self = CursorRate::new();
+        }
+        self.updates += 1;
+        if !flags.contains(CursorFlags::SHAPE) {
+            return false;
+        }
+        self.shapes += 1;
+        stats.cursor_shapes += 1;
+
+        if self.shapes == limit.unwrap_or(DEFAULT_SHAPE_STORM_THRESHOLD) + 1 {
+            stats.anomalies += 1;
+            self.storm = true;
+        }
+        let suppress = limit.is_some_and(|limit| self.shapes > limit);
+        if suppress {
+            stats.cursor_shapes_dropped += 1;
+        }
+        suppress
+    }
+
+    /// Returns true once for each storm detected by [Self::record].
+    fn take_storm(&mut self) -> bool {
+        std::mem::take(&mut self.storm)
+    }
+}
+
+/// Frame timings seen within the current rate window.
+struct FrameMetrics {
+    window_start: Instant,
+    frames: u32,
+    latency_total: Duration,
+    latency_max: Duration,
+    latency_samples: u32,
+}
+
+impl FrameMetrics {
+    fn new() -> FrameMetrics {
+        FrameMetrics {
+            window_start: Instant::now(),
+            frames: 0,
+            latency_total: Duration::ZERO,
+            latency_max: Duration::ZERO,
+            latency_samples: 0,
+        }
+    }
+
+    /// Records a frame being returned, along with its latency if it is known.
+    fn record(&mut self, latency: Option<Duration>, stats: &mut ConnectionStats) {
+        if self.window_start.elapsed() >= RATE_WINDOW {
+            stats.frames_per_sec = self.frames;
+            stats.frame_latency_avg = self.latency_total / self.latency_samples.max(1);
+            stats.frame_latency_max = self.latency_max;
+            *self = FrameMetrics::new();
+        }
+        self.frames += 1;
+        if let Some(latency) = latency {
+            self.latency_total += latency;
+            self.latency_max = self.latency_max.max(latency);
+            self.latency_samples += 1;
+        }
+    }
+}
+
+/// A snapshot of a single queue, returned by [LGMPConnection::queue_status].
+///
+/// LGMP does not expose how many messages are waiting or this client's position in the
+/// queue, so falling behind is measured by how long it has been since the queue was last
+/// found empty, which is what the host's timeout is based on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStatus {
+    pub subscribed: bool,
+    /// A message is waiting to be read
+    pub pending: bool,
+    /// Serial of the frame at the head of the frame queue, if there is one
+    pub head_serial: Option<u32>,
+    /// Serial of the last frame returned from the frame queue
+    pub last_serial: Option<u32>,
+    /// Time since a message was last read from the queue, or None if there has not been
+    /// one this session
+    pub since_message: Option<Duration>,
+    /// Time since the queue was last found empty
+    pub since_empty: Duration,
+    /// Time left before the host drops this client unless the queue is emptied, which is
+    /// zero once the timeout has passed
+    pub until_timeout: Duration,
+}
+
+/// Counts of each LGMP error status returned when reading from a single queue.
+///
+/// A growing `corrupted` or `invalid_session` count points to a misbehaving host, whereas
+/// `timeouts` means this client was too slow to empty the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueErrorStats {
+    /// Attempts to read from the queue, including peeks and fast forwards
+    pub reads: u64,
+    /// The queue had no messages waiting. This is expected whenever the client is idle.
+    pub empty: u64,
+    /// A message in the queue was corrupted
+    pub corrupted: u64,
+    /// The host dropped this client for not emptying the queue in time
+    pub timeouts: u64,
+    /// The session was no longer valid, usually because the host restarted
+    pub invalid_session: u64,
+    /// Any other error status
+    pub other: u64,
+}
+
+impl QueueErrorStats {
+    /// Returns the fraction of reads which found the queue empty. A client which keeps up
+    /// with the host sees a ratio close to 1, as it polls far more often than messages
+    /// arrive.
+    pub fn empty_ratio(&self) -> f64 {
+        if self.reads == 0 {
+            return 0.0;
+        }
+        self.empty as f64 / self.reads as f64
+    }
+
+    /// Counts a read from the queue, along with its error status if it failed.
+    pub(super) fn record<T>(&mut self, res: &Result<T, crate::lgmp_impl::error::Error>) {
+        use crate::lgmp_impl::error::Status;
+        self.reads += 1;
+        let Err(e) = res else {
+            return;
+        };
+        let counter = match e {
+            crate::lgmp_impl::error::Error::InternalError(Status::LGMPErrQueueEmpty) => {
+                &mut self.empty
+            }
+            crate::lgmp_impl::error::Error::InternalError(Status::LGMPErrCorrupted) => {
+                &mut self.corrupted
+            }
+            crate::lgmp_impl::error::Error::InternalError(Status::LGMPErrQueueTimeout) => {
+                &mut self.timeouts
+            }
+            crate::lgmp_impl::error::Error::InternalError(Status::LGMPErrInvalidSession) => {
+                &mut self.invalid_session
+            }
+            _ => &mut self.other,
+        };
+        *counter += 1;
+    }
+}
+
+/// Memory behind a frame or cursor handle.
+enum MessageRef<'a> {
+    /// A message in shared memory, whose channel stays locked until this is dropped
+    Live(InPlaceMessage<'a>),
+    /// A message played back from a recording
+    Recorded(&'a RecordedMessage),
+}
+
+impl MessageRef<'_> {
+    fn mem(&self) -> SharedMemoryBlock {
+        match self {
+            MessageRef::Live(msg) => SharedMemoryBlock {
+                udata: msg.mem.udata,
+                mem: msg.mem.mem,
+                size: msg.mem.size,
+            },
+            MessageRef::Recorded(msg) => SharedMemoryBlock {
+                udata: msg.udata,
+                //Recorded messages are never written through this pointer
+                mem: msg.as_bytes().as_ptr() as *mut std::ffi::c_void,
+                size: msg.as_bytes().len(),
+            },
+        }
+    }
+
+    /// Returns the contents of the message.
+    fn bytes(&self) -> &[u8] {
+        self.raw(usize::MAX).1
+    }
+
+    /// Returns the message's user data and contents, up to `len` bytes.
+    fn raw(&self, len: usize) -> (u32, &[u8]) {
+        let mem = self.mem();
+        //The message stays valid for as long as this reference
+        let bytes = unsafe { std::slice::from_raw_parts(mem.mem.cast::<u8>(), len.min(mem.size)) };
+        (mem.udata, bytes)
+    }
+}
+
+pub struct KVMFRFrameHandle<'a> {
+    _msg_handle: MessageRef<'a>,
+    device: DeviceHandle,
+    //Set by Quirks::IGNORE_DAMAGE
+    ignore_damage: bool,
+    copy_strategy: CopyStrategy,
+    //Frames missed between the previous frame and this one
+    dropped: u32,
+    //Hash of the last frame checked for duplicates on the source of this frame
+    last_hash: &'a Cell<Option<u64>>,
+    //Cached result of is_duplicate_of_previous
+    duplicate: Cell<Option<bool>>,
+}
+
+impl<'a> KVMFRFrameHandle<'a> {
+    pub(super) fn live(
+        msg: InPlaceMessage<'a>,
+        device: DeviceHandle,
+        ignore_damage: bool,
+        copy_strategy: CopyStrategy,
+        last_hash: &'a Cell<Option<u64>>,
+    ) -> KVMFRFrameHandle<'a> {
+        KVMFRFrameHandle {
+            _msg_handle: MessageRef::Live(msg),
+            device,
+            ignore_damage,
+            copy_strategy,
+            dropped: 0,
+            last_hash,
+            duplicate: Cell::new(None),
+        }
+    }
+
+    pub(super) fn set_dropped(&mut self, dropped: u32) {
+        self.dropped = dropped;
+    }
+
+    /// Creates a handle to a frame played back from a recording.
+    pub(super) fn recorded(
+        msg: &'a RecordedMessage,
+        dropped: u32,
+        last_hash: &'a Cell<Option<u64>>,
+    ) -> KVMFRFrameHandle<'a> {
+        KVMFRFrameHandle {
+            _msg_handle: MessageRef::Recorded(msg),
+            device: Default::default(),
+            ignore_damage: false,
+            copy_strategy: CopyStrategy::default(),
+            dropped,
+            last_hash,
+            duplicate: Cell::new(None),
+        }
+    }
+
+    /// Returns the message's user data and contents for recording. Unused space after the
+    /// pixel data is left out, unless the header is invalid.
+    pub(super) fn raw(&self) -> (u32, &[u8]) {
+        let end = match self.data() {
+            Ok(data) => data.as_ptr() as usize + data.len() - self._msg_handle.mem().mem as usize,
+            Err(_) => usize::MAX,
+        };
+        self._msg_handle.raw(end)
+    }
+}
+
+impl KVMFRFrameHandle<'_> {
+    pub fn as_frame(&self) -> Result<&shm_datastructs::KVMFRFrame, LGError> {
+        match shm_datastructs::KVMFRFrame::ref_from_prefix(self._msg_handle.bytes()) {
+            Ok((frame, _)) => Ok(frame),
+            Err(ConvertError::Alignment(_)) => Err(LGError::MisalignedMessage),
+            Err(_) => Err(LGError::FrameChannelMessageTooSmall),
+        }
+    }
+
+    /// Returns a validated description of the frame.
+    pub fn info(&self) -> Result<FrameInfo, LGError> {
+        FrameInfo::try_from(self.as_frame()?)
+    }
+
+    /// Returns the number of frames sent by the host between the previous frame returned
+    /// by this connection and this one, which is non-zero if this client fell behind or
+    /// the queue was fast-forwarded.
+    ///
+    /// Renderers should reset any temporal effects when frames have been missed. This is
+    /// always zero for the first frame of a session.
+    pub fn dropped_since_last(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns true if this frame is identical to the previous frame this was called on,
+    /// so that recorders and encoders can skip frames which the host repeats.
+    ///
+    /// The first call hashes the frame's layout and pixel data, so this should only be
+    /// called once the host has finished writing the frame. Frames which this is never
+    /// called on are not hashed, and are not compared against.
+    pub fn is_duplicate_of_previous(&self) -> Result<bool, LGError> {
+        if let Some(duplicate) = self.duplicate.get() {
+            return Ok(duplicate);
+        }
+        let frame = self.as_frame()?;
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        for field in [frame.type_, frame.dataWidth, frame.dataHeight, frame.pitch] {
+            hasher.update(&field.to_ne_bytes());
+        }
+        hasher.update(self.data()?);
+        let hash = hasher.digest();
+        let duplicate = self.last_hash.replace(Some(hash)) == Some(hash);
+        self.duplicate.set(Some(duplicate));
+        Ok(duplicate)
+    }
+
+    /// Returns the regions of the frame which have changed since the previous frame.
+    ///
+    /// An empty slice means that the whole frame should be treated as damaged, as the
+    /// host sends no rects when it doesn't know what has changed. This is always the case
+    /// if [Quirks::IGNORE_DAMAGE] has been applied. Fails if any rect lies outside of the
+    /// frame.
+    pub fn damage_rects(&self) -> Result<&[DamageRect], LGError> {
+        let frame = self.as_frame()?;
+        if self.ignore_damage {
+            return Ok(&[]);
+        }
+        let count = frame.damageRectsCount;
+        if count as usize > frame.damageRects.len() {
+            Err(LGError::InvalidDamageRectCount(count))?
+        }
+        //DamageRect has the same layout as FrameDamageRect
+        let rects = <[DamageRect]>::ref_from_bytes(frame.damageRects[..count as usize].as_bytes())
+            .map_err(|_| LGError::MisalignedMessage)?;
+        for rect in rects {
+            let right = u64::from(rect.x) + u64::from(rect.width);
+            let bottom = u64::from(rect.y) + u64::from(rect.height);
+            if right > u64::from(frame.frameWidth) || bottom > u64::from(frame.frameHeight) {
+                Err(LGError::DamageRectOutOfBounds)?
+            }
+        }
+        Ok(rects)
+    }
+
+    /// Returns the pixel data of the frame, which is `pitch * dataHeight` bytes long.
+    ///
+    /// The host may still be writing to the end of the data when the frame is first
+    /// received. Fails if the header describes data which lies outside of the message.
+    pub fn data(&self) -> Result<&[u8], LGError> {
+        let frame = self.as_frame()?;
+        //Pixel data follows the frame buffer header, which holds the host's write pointer
+        let start = (frame.offset as usize).checked_add(shm_datastructs::FRAME_BUFFER_HEADER_SIZE);
+        let len = (frame.pitch as usize).checked_mul(frame.dataHeight as usize);
+        let (Some(start), Some(len)) = (start, len) else {
+            return Err(LGError::FrameDataOutOfBounds);
+        };
+        start
+            .checked_add(len)
+            .and_then(|end| self._msg_handle.bytes().get(start..end))
+            .ok_or(LGError::FrameDataOutOfBounds)
+    }
+
+    /// Returns the number of whole rows of pixel data held within the frame's message.
+    ///
+    /// This is the frame's data height, which for [truncated](FrameInfo::is_truncated)
+    /// frames is already less than its height, unless the header describes more rows than
+    /// the message holds. Rows past this are never part of the frame.
+    pub fn valid_rows(&self) -> Result<u32, LGError> {
+        let frame = self.as_frame()?;
+        let start =
+            (frame.offset as usize).saturating_add(shm_datastructs::FRAME_BUFFER_HEADER_SIZE);
+        let available = self._msg_handle.bytes().len().saturating_sub(start);
+        Ok(match frame.pitch as usize {
+            0 => frame.dataHeight,
+            pitch => frame
+                .dataHeight
+                .min((available / pitch).min(u32::MAX as usize) as u32),
+        })
+    }
+
+    /// Returns the pixel data of the rows counted by [Self::valid_rows], so that the valid
+    /// part of a frame can still be presented when [Self::data] fails because the header
+    /// describes more data than the message holds.
+    pub fn valid_data(&self) -> Result<&[u8], LGError> {
+        let rows = self.valid_rows()?;
+        let frame = self.as_frame()?;
+        let start = frame.offset as usize + shm_datastructs::FRAME_BUFFER_HEADER_SIZE;
+        let len = frame.pitch as usize * rows as usize;
+        self._msg_handle
+            .bytes()
+            .get(start..start + len)
+            .ok_or(LGError::FrameDataOutOfBounds)
+    }
+
+    /// Returns how many bytes of the pixel data the host has written so far.
+    ///
+    /// The host posts frames before it has finished copying them, and updates this as the
+    /// copy progresses, so it is read atomically from the frame buffer header.
+    pub fn bytes_written(&self) -> Result<u32, LGError> {
+        let frame = self.as_frame()?;
+        let start = frame.offset as usize;
+        let header = start
+            .checked_add(shm_datastructs::FRAME_BUFFER_HEADER_SIZE)
+            .and_then(|end| self._msg_handle.bytes().get(start..end))
+            .ok_or(LGError::FrameDataOutOfBounds)?;
+        let ptr = header.as_ptr().cast::<u32>().cast_mut();
+        if !ptr.is_aligned() {
+            Err(LGError::MisalignedMessage)?
+        }
+        //The pointer is in bounds and aligned, and the host only ever writes it atomically
+        let written = unsafe { AtomicU32::from_ptr(ptr) };
+        Ok(written.load(Ordering::Acquire))
+    }
+
+    /// Returns the pixel data of each row of the frame, without any padding at the end of
+    /// rows.
+    pub fn rows(&self) -> Result<impl ExactSizeIterator<Item = &[u8]>, LGError> {
+        let info = self.info()?;
+        let row_len = info.data_width as usize * info.format.bytes_per_pixel() as usize;
+        Ok(tiles::rows(
+            self.data()?,
+            info.pitch as usize,
+            row_len,
+            info.data_height,
+        ))
+    }
+
+    /// Splits the pixel data of the frame into tiles of the given size in pixels, so that
+    /// they can be processed or uploaded independently.
+    ///
+    /// Panics if either dimension is zero.
+    pub fn tiles(&self, width: u32, height: u32) -> Result<FrameTiles<'_>, LGError> {
+        let info = self.info()?;
+        Ok(FrameTiles::new(
+            self.data()?,
+            info.pitch as usize,
+            info.format.bytes_per_pixel() as usize,
+            (info.data_width, info.data_height),
+            (width, height),
+        ))
+    }
+
+    /// Splits the pixel data of the frame into contiguous chunks of at most `max_len` bytes
+    /// which start on row boundaries where possible, for feeding it through small buffers
+    /// such as DMA descriptors or socket writes without copying the whole frame first.
+    ///
+    /// Panics if `max_len` is smaller than a single pixel.
+    pub fn chunks(&self, max_len: usize) -> Result<FrameChunks<'_>, LGError> {
+        let info = self.info()?;
+        Ok(FrameChunks::new(
+            self.data()?,
+            info.pitch as usize,
+            info.format.bytes_per_pixel() as usize,
+            (info.data_width, info.data_height),
+            max_len,
+        ))
+    }
+
+    /// Copies the pixel data of the frame into a buffer whose rows are `dst_pitch` bytes
+    /// apart, such as a mapped GPU staging buffer. Only the pixels of each row are copied,
+    /// so padding at the end of rows in either buffer is skipped. Rows are copied as set by
+    /// [LGMPOptsBuilder::copy_strategy].
+    ///
+    /// Returns [LGError::DestinationTooSmall] if a row or the whole frame does not fit.
+    pub fn copy_to(&self, dst: &mut [u8], dst_pitch: usize) -> Result<(), LGError> {
+        let info = self.info()?;
+        let row_len = info.data_width as usize * info.format.bytes_per_pixel() as usize;
+        check_destination(dst, dst_pitch, row_len, info.data_height)?;
+        if row_len == 0 || info.data_height == 0 {
+            return Ok(());
+        }
+        let (data, pitch, strategy) = (self.data()?, info.pitch as usize, self.copy_strategy);
+        let rows = data
+            .len()
+            .div_ceil(pitch)
+            .min(dst.len().div_ceil(dst_pitch));
+        let dst_len = dst.len().min(rows * dst_pitch);
+        parallel::for_each_band(
+            &mut dst[..dst_len],
+            dst_pitch,
+            parallel::min_band_chunks(dst_pitch),
+            |first_row, band| {
+                for (i, dst) in band.chunks_mut(dst_pitch).enumerate() {
+                    let src = &data[(first_row + i) * pitch..];
+                    strategy.copy(&src[..row_len], dst);
+                }
+            },
+        );
+        Ok(())
+    }
+
+    /// As [Self::copy_to], but converts the frame to 8 bit RGBA while copying. See
+    /// [convert::to_rgba8].
+    pub fn copy_to_rgba8(
+        &self,
+        dst: &mut [u8],
+        dst_pitch: usize,
+        tone_map: ToneMap,
+    ) -> Result<(), LGError> {
+        let info = self.info()?;
+        check_destination(
+            dst,
+            dst_pitch,
+            info.data_width as usize * 4,
+            info.data_height,
+        )?;
+        convert::to_rgba8_pitched(
+            self.data()?,
+            info.data_width,
+            info.data_height,
+            info.pitch,
+            info.format,
+            tone_map,
+            dst,
+            dst_pitch,
+        );
+        Ok(())
+    }
+
+    /// Copies the pixel data of the frame out of shared memory. See [Self::data].
+    ///
+    /// The copy is made as set by [LGMPOptsBuilder::copy_strategy]. With the `paranoid`
+    /// feature enabled, it is surrounded by guard pages.
+    pub fn copy_data(&self) -> Result<FrameBuffer, LGError> {
+        Ok(FrameBuffer::copy_from(self.data()?, &self.copy_strategy))
+    }
+
+    /// Copies the frame out of shared memory along with its metadata and damage, so that
+    /// the handle can be dropped straight away. See [OwnedFrame].
+    ///
+    /// The copy is made as set by [LGMPOptsBuilder::copy_strategy].
+    pub fn to_owned(&self) -> Result<OwnedFrame, LGError> {
+        Ok(OwnedFrame::new(
+            self.info()?,
+            self.damage_rects()?.to_vec(),
+            self.copy_strategy.copy_to_vec(self.data()?),
+        ))
+    }
+
+    /// As [Self::to_owned], but copies the pixel data into a buffer from a pool. The
+    /// buffer can be returned with [OwnedFrame::release_to] once processing is done.
+    pub fn to_owned_in(&self, pool: &mut FramePool) -> Result<OwnedFrame, LGError> {
+        let info = self.info()?;
+        let mut buf = pool.acquire(FrameLayout::from(&info));
+        self.copy_strategy.extend_vec(&mut buf, self.data()?);
+        Ok(OwnedFrame::new(info, self.damage_rects()?.to_vec(), buf))
+    }
+
+    /// Copies the pixel data of the frame into reference counted storage which can outlive
+    /// the handle. See [ArcFrame].
+    pub fn to_arc(&self) -> Result<ArcFrame, LGError> {
+        let info = self.info()?;
+        Ok(ArcFrame::new(
+            info.serial,
+            info.format,
+            (info.data_width, info.data_height),
+            info.pitch,
+            Arc::from(self.data()?),
+        ))
+    }
+
+    /// As [Self::to_arc], but converts the frame to tightly packed 8 bit RGBA, which is what
+    /// most GUI image types expect. See [convert::to_rgba8].
+    pub fn to_arc_rgba8(&self, tone_map: ToneMap) -> Result<ArcFrame, LGError> {
+        let info = self.info()?;
+        let rgba = convert::to_rgba8(
+            self.data()?,
+            info.data_width,
+            info.data_height,
+            info.pitch,
+            info.format,
+            tone_map,
+        );
+        Ok(ArcFrame::new(
+            info.serial,
+            PixelFormat::Rgba,
+            (info.data_width, info.data_height),
+            info.data_width * 4,
+            Arc::from(rgba),
+        ))
+    }
+
+    /// Exports the pixel data of the frame as a dmabuf, so that it can be imported into
+    /// EGL or Vulkan without being copied.
+    ///
+    /// This is only possible when the connection was opened on a kvmfr device node, such
+    /// as `/dev/kvmfr0`, otherwise [LGError::DmabufUnsupported] is returned.
+    #[cfg(target_os = "linux")]
+    pub fn export_dmabuf(&self) -> Result<DmabufFrame, LGError> {
+        let device = self.device.as_ref().ok_or(LGError::DmabufUnsupported)?;
+        let info = self.info()?;
+        let data = self.data()?;
+        let (fd, offset) = device.export(data.as_ptr(), data.len())?;
+        Ok(DmabufFrame {
+            fd,
+            offset,
+            stride: info.pitch,
+            width: info.data_width,
+            height: info.data_height,
+            format: info.format,
+        })
+    }
+}
+
+pub struct KVMFRCursorHandle<'a> {
+    _msg_handle: MessageRef<'a>,
+    /// Whether the shape was removed by the connection's shape limit
+    suppress_shape: bool,
+}
+
+impl<'a> KVMFRCursorHandle<'a> {
+    pub(super) fn live(msg: InPlaceMessage<'a>, suppress_shape: bool) -> KVMFRCursorHandle<'a> {
+        KVMFRCursorHandle {
+            _msg_handle: MessageRef::Live(msg),
+            suppress_shape,
+        }
+    }
+
+    /// Creates a handle to a cursor update played back from a recording.
+    pub(super) fn recorded(msg: &'a RecordedMessage) -> KVMFRCursorHandle<'a> {
+        KVMFRCursorHandle {
+            _msg_handle: MessageRef::Recorded(msg),
+            suppress_shape: false,
+        }
+    }
+
+    /// Returns the message's user data and contents for recording.
+    pub(super) fn raw(&self) -> (u32, &[u8]) {
+        self._msg_handle.raw(usize::MAX)
+    }
+}
+
+impl KVMFRCursorHandle<'_> {
+    pub fn as_ptr_msg(&self) -> Result<&shm_datastructs::KVMFRCursor, LGError> {
+        match shm_datastructs::KVMFRCursor::ref_from_prefix(self._msg_handle.bytes()) {
+            Ok((cursor, _)) => Ok(cursor),
+            Err(ConvertError::Alignment(_)) => Err(LGError::MisalignedMessage),
+            Err(_) => Err(LGError::CursorChannelMessageTooSmall),
+        }
+    }
+
+    /// Returns the flags sent with this update. Any unknown bits are preserved.
+    ///
+    /// If the update's shape was removed by [LGMPOptsBuilder::cursor_shape_limit], the
+    /// shape flag is cleared.
+    pub fn flags(&self) -> CursorFlags {
+        let flags = CursorFlags::from_bits_retain(self._msg_handle.mem().udata);
+        if self.suppress_shape {
+            flags.difference(CursorFlags::SHAPE)
+        } else {
+            flags
+        }
+    }
+
+    /// Returns true if this update carries a new cursor position.
+    pub fn has_position(&self) -> bool {
+        self.flags().contains(CursorFlags::POSITION)
+    }
+
+    /// Returns true if the cursor should be drawn.
+    pub fn is_visible(&self) -> bool {
+        self.flags().contains(CursorFlags::VISIBLE)
+    }
+
+    /// Returns true if this update carries a new cursor shape.
+    pub fn has_shape(&self) -> bool {
+        self.flags().contains(CursorFlags::SHAPE)
+    }
+
+    /// Returns the shape bitmap which follows the cursor header. This is only meaningful
+    /// if the message has the shape flag set.
+    pub fn shape_data(&self) -> Result<&[u8], LGError> {
+        self._msg_handle
+            .bytes()
+            .get(size_of::<shm_datastructs::KVMFRCursor>()..)
+            .ok_or(LGError::CursorChannelMessageTooSmall)
+    }
+}
+
+/// Selector for the channels subscribed to by LGMP client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KVMFRChans {
+    Frame,
+    Cursor,
+}
+
+/// Holds handles to channels listened to by an LGMP client, as well as the
+/// times at which they last received an LGMPErrQueueEmpty response.
+///
+/// Channels which were not subscribed to are None, and behave as though always empty.
+struct LGMPSession {
+    /// Id assigned to this client by the host
+    client_id: u32,
+    started_at: Instant,
+    /// Whether [LGEvent::SessionLost] has been reported for this session
+    lost: bool,
+
+    frame_chan: Option<crate::lgmp_impl::client::ClientQueueHandle>,
+    cursor_chan: Option<crate::lgmp_impl::client::ClientQueueHandle>,
+
+    last_frame_heartbeat: Instant,
+    last_cursor_heartbeat: Instant,
+    /// Times at which a message was last popped from each channel
+    last_frame_message: Option<Instant>,
+    last_cursor_message: Option<Instant>,
+
+    serials: FrameSerials,
+    /// Time the frame queue was last found empty when frames started being counted, and
+    /// the number read since, for [Backpressure::BoundedLag]
+    backlog: (Instant, u32),
+    /// Details sent by the host when the session was initialised
+    host_info: HostInfo,
+    /// Workarounds applied to this host
+    quirks: Quirks,
+    /// Limit on cursor shapes per second, including any applied by quirks
+    shape_limit: Option<u32>,
+}
+
+impl LGMPSession {
+    /// Returns the number of frames read since the frame queue was last found empty.
+    fn backlog(&self) -> u32 {
+        match self.backlog {
+            (since, count) if since == self.last_frame_heartbeat => count,
+            _ => 0,
+        }
+    }
+
+    /// Counts a frame read from the frame queue towards the backlog.
+    fn record_backlog(&mut self) {
+        self.backlog = (self.last_frame_heartbeat, self.backlog() + 1);
+    }
+
+    /// Returns a refererence to the data contained within the next message in the
+    /// requested channel. This reference also holds a lock on the channel.
+    ///
+    /// If the channel is empty or not subscribed to, returns Ok(None)
+    fn pop_ref(
+        &mut self,
+        channel: KVMFRChans,
+        stats: &mut ConnectionStats,
+    ) -> Result<Option<InPlaceMessage<'_>>, LGError> {
+        let (chan, hb, last_message) = match channel {
+            KVMFRChans::Frame => (
+                &mut self.frame_chan,
+                &mut self.last_frame_heartbeat,
+                &mut self.last_frame_message,
+            ),
+            KVMFRChans::Cursor => (
+                &mut self.cursor_chan,
+                &mut self.last_cursor_heartbeat,
+                &mut self.last_cursor_message,
+            ),
+        };
+        let Some(chan) = chan else {
+            return Ok(None);
+        };
+
+        let msg = pop_chan_ref(chan, hb, stats.queue_mut(channel))?;
+        if msg.is_some() {
+            *last_message = Some(Instant::now());
+        }
+        Ok(msg)
+    }
+
+    /// Peeks at the requested channel to describe how far behind the host this client is.
+    fn status(
+        &mut self,
+        channel: KVMFRChans,
+        timeout: Duration,
+        stats: &mut ConnectionStats,
+    ) -> Result<QueueStatus, LGError> {
+        let (chan, hb, last_message) = match channel {
+            KVMFRChans::Frame => (
+                &mut self.frame_chan,
+                &mut self.last_frame_heartbeat,
+                self.last_frame_message,
+            ),
+            KVMFRChans::Cursor => (
+                &mut self.cursor_chan,
+                &mut self.last_cursor_heartbeat,
+                self.last_cursor_message,
+            ),
+        };
+        let mut status = QueueStatus {
+            subscribed: chan.is_some(),
+            pending: false,
+            since_message: last_message.map(|at| at.elapsed()),
+            head_serial: None,
+            last_serial: match channel {
+                KVMFRChans::Frame => self.serials.last,
+                KVMFRChans::Cursor => None,
+            },
+            since_empty: Duration::ZERO,
+            until_timeout: timeout,
+        };
+        let Some(chan) = chan else {
+            return Ok(status);
+        };
+
+        let res = chan.peek_raw();
+        stats.queue_mut(channel).record(&res);
+        match res {
+            Ok(block) => {
+                status.pending = true;
+                if channel == KVMFRChans::Frame {
+                    //The message remains valid until we mark it as done
+                    let bytes =
+                        unsafe { std::slice::from_raw_parts(block.mem.cast::<u8>(), block.size) };
+                    status.head_serial = shm_datastructs::KVMFRFrame::read_from_prefix(bytes)
+                        .ok()
+                        .map(|(header, _)| header.frameSerial);
+                }
+            }
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+            )) => *hb = Instant::now(),
+            Err(e) => Err(e)?,
+        }
+        let deadline = Deadline::new(*hb, timeout);
+        let now = Instant::now();
+        status.since_empty = deadline.elapsed(now);
+        status.until_timeout = deadline.remaining(now);
+        Ok(status)
+    }
+
+    /// Checks whether the requested channel has an unread message without popping it
+    /// or holding a lock on its contents.
+    fn has_pending(
+        &mut self,
+        channel: KVMFRChans,
+        stats: &mut ConnectionStats,
+    ) -> Result<bool, LGError> {
+        let (chan, hb) = match channel {
+            KVMFRChans::Frame => (&mut self.frame_chan, &mut self.last_frame_heartbeat),
+            KVMFRChans::Cursor => (&mut self.cursor_chan, &mut self.last_cursor_heartbeat),
+        };
+        let Some(chan) = chan else {
+            return Ok(false);
+        };
+
+        let res = chan.peek_raw();
+        stats.queue_mut(channel).record(&res);
+        match res {
+            Ok(_) => Ok(true),
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+            )) => {
+                *hb = Instant::now();
+                Ok(false)
+            }
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// Inspects the frame at the head of the frame queue without popping it, queuing up any
+    /// events which should be reported before it is delivered.
+    ///
+    /// Each frame is only inspected once, so that these events are not repeated.
+    fn check_frame(
+        &mut self,
+        events: &mut VecDeque<LGEvent<'static>>,
+        stats: &mut ConnectionStats,
+    ) -> Result<(), LGError> {
+        let Some(ref mut chan) = self.frame_chan else {
+            return Ok(());
+        };
+        let res = chan.peek_raw();
+        stats.frame_queue.record(&res);
+        let block = match res {
+            Ok(block) => block,
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+            )) => {
+                self.last_frame_heartbeat = Instant::now();
+                return Ok(());
+            }
+            Err(e) => Err(e)?,
+        };
+
+        //The message remains valid until we mark it as done
+        let bytes = unsafe { std::slice::from_raw_parts(block.mem.cast::<u8>(), block.size) };
+        let Ok((header, _)) = shm_datastructs::KVMFRFrame::read_from_prefix(bytes) else {
+            chan.message_done()?;
+            stats.anomalies += 1;
+            events.push_back(LGEvent::Anomaly(Anomaly::FrameMessageTooSmall));
+            return Ok(());
+        };
+        self.serials.check(&header, events, stats);
+        Ok(())
+    }
+
+    /// Marks all but the most recent message in a channel as read, returning true if there
+    /// were any messages waiting.
+    fn fast_forward(
+        &mut self,
+        channel: KVMFRChans,
+        stats: &mut ConnectionStats,
+    ) -> Result<bool, LGError> {
+        let (chan, hb) = match channel {
+            KVMFRChans::Frame => (&mut self.frame_chan, &mut self.last_frame_heartbeat),
+            KVMFRChans::Cursor => (&mut self.cursor_chan, &mut self.last_cursor_heartbeat),
+        };
+        let Some(chan) = chan else {
+            return Ok(false);
+        };
+
+        let res = chan.advance_to_last();
+        stats.queue_mut(channel).record(&res);
+        match res {
+            Ok(()) => Ok(true),
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+            )) => {
+                *hb = Instant::now();
+                Ok(false)
+            }
+            Err(e) => Err(e)?,
+        }
+    }
+}
+
+/// Serials and format versions of the frames seen in a session. These are kept apart from
+/// the frame queue so that they can be updated while a popped frame borrows it.
+#[derive(Default)]
+struct FrameSerials {
+    /// Serial of the frame at the head of the queue which has already been checked for
+    /// events by [LGMPSession::check_frame]
+    checked: Option<u32>,
+    /// Time at which the frame at the head of the queue was first checked
+    checked_at: Option<Instant>,
+    /// Serial of the last frame popped from the queue
+    last: Option<u32>,
+    /// Format version of the last frame checked
+    format_ver: Option<u32>,
+}
+
+impl FrameSerials {
+    /// Reports frames skipped before this one and changes to the format version, unless
+    /// the frame has already been checked.
+    fn check(
+        &mut self,
+        header: &shm_datastructs::KVMFRFrame,
+        events: &mut VecDeque<LGEvent<'static>>,
+        stats: &mut ConnectionStats,
+    ) {
+        let (serial, format_ver) = (header.frameSerial, header.formatVer);
+        if self.checked == Some(serial) {
+            return;
+        }
+        self.checked = Some(serial);
+        self.checked_at = Some(Instant::now());
+
+        let skipped = frames_between(self.last, serial);
+        if skipped > 0 {
+            stats.frames_skipped += u64::from(skipped);
+            stats.anomalies += 1;
+            events.push_back(LGEvent::Anomaly(Anomaly::FramesSkipped(skipped)));
+        }
+        if self.format_ver != Some(format_ver) {
+            self.format_ver = Some(format_ver);
+            events.push_back(LGEvent::FormatChanged(format_ver));
+        }
+    }
+
+    /// Records a frame being popped from the queue, checking it first if it is not the one
+    /// last checked at the head of the queue. Returns the number of frames dropped before
+    /// it, and the time since it was first checked.
+    fn pop(
+        &mut self,
+        header: &shm_datastructs::KVMFRFrame,
+        events: &mut VecDeque<LGEvent<'static>>,
+        stats: &mut ConnectionStats,
+    ) -> (u32, Option<Duration>) {
+        self.check(header, events, stats);
+        let dropped = frames_between(self.last, header.frameSerial);
+        self.last = Some(header.frameSerial);
+        (dropped, self.checked_at.take().map(|at| at.elapsed()))
+    }
+}
+
+/// Counts a frame popped by any of the connection's read paths in its stats and metrics,
+/// and sets the number of frames dropped before it. If the frame was not the one last
+/// checked at the head of the queue, it is checked now and its events are reported after
+/// it.
+///
+/// This takes the connection's fields separately so that it can be called while the frame
+/// borrows the session's queue.
+fn record_frame(
+    frame: &mut KVMFRFrameHandle,
+    serials: &mut FrameSerials,
+    events: &mut VecDeque<LGEvent<'static>>,
+    stats: &mut ConnectionStats,
+    pacer: &mut FramePacer,
+    metrics: Option<&mut FrameMetrics>,
+) {
+    let mut latency = None;
+    if let Ok(header) = frame.as_frame() {
+        (frame.dropped, latency) = serials.pop(header, events, stats);
+    }
+    stats.frames += 1;
+    pacer.record(Instant::now());
+    if let Some(metrics) = metrics {
+        #[cfg(feature = "metrics")]
+        if let Some(latency) = latency {
+            super::metrics_export::record_frame_latency(latency);
+        }
+        metrics.record(latency, stats);
+    }
+}
+
+/// Checks that a buffer with the given pitch can hold `rows` rows of `row_len` bytes.
+fn check_destination(
+    dst: &[u8],
+    dst_pitch: usize,
+    row_len: usize,
+    rows: u32,
+) -> Result<(), LGError> {
+    let needed = match rows {
+        0 => 0,
+        rows => dst_pitch * (rows as usize - 1) + row_len,
+    };
+    if dst_pitch < row_len || dst.len() < needed {
+        Err(LGError::DestinationTooSmall)?
+    }
+    Ok(())
+}
+
+/// Returns the number of frames the host sent between two serials, or zero if there is no
+/// previous serial.
+pub(super) fn frames_between(last: Option<u32>, serial: u32) -> u32 {
+    let Some(last) = last else {
+        return 0;
+    };
+    //Anything past half the serial space is assumed to be the host going backwards
+    let skipped = serial.wrapping_sub(last).wrapping_sub(1);
+    if skipped < u32::MAX / 2 {
+        skipped
+    } else {
+        0
+    }
+}
+
+/// Opens the shared memory named in the options and initialises a client on it, also
+/// returning the kvmfr device behind it if there is one.
+fn open_client(opts: &LGMPOpts) -> Result<(Client, DeviceHandle), LGError> {
+    let source = opts.source.open(&opts.map_opts)?;
+    Ok((Client::init(source.shm)?, source.device))
+}
+
+/// Returns true if the error indicates that the session is no longer valid and must be
+/// re-initialised.
+fn is_session_error(e: &LGError) -> bool {
+    matches!(e, LGError::SessionInvalid | LGError::ClientTimedOut)
+}
+
+/// Returns the event which [LGMPConnection::poll_event] reports in place of an error from
+/// the session with the given client id, if `auto_reconnect` is enabled and the error
+/// means that the session was lost. The session is then re-established from the next poll.
+///
+/// This takes the connection's fields separately so that it can be called while the
+/// session's queues are borrowed.
+fn session_lost_event(
+    e: LGError,
+    opts: &LGMPOpts,
+    reconnect_state: &mut ReconnectState,
+    client_id: u32,
+) -> Result<LGEvent<'static>, LGError> {
+    if !opts.auto_reconnect || !e.requires_reconnect() {
+        return Err(e);
+    }
+    *reconnect_state = ReconnectState::Dropped;
+    match opts.lifecycle_events {
+        true => Ok(LGEvent::SessionLost(client_id)),
+        false => Ok(LGEvent::HostLost),
+    }
+}
+
+/// Pops the next message from a single channel, recording any error in the channel's
+/// counters and updating its heartbeat if the channel turns out to be empty.
+///
+/// This takes the channel and heartbeat separately so that callers can borrow the
+/// frame and cursor channels of a session independently of one another.
+pub(super) fn pop_chan_ref<'a>(
+    chan: &'a mut crate::lgmp_impl::client::ClientQueueHandle,
+    hb: &mut Instant,
+    errors: &mut QueueErrorStats,
+) -> Result<Option<InPlaceMessage<'a>>, LGError> {
+    let res = chan.pop_in_place();
+    errors.record(&res);
+    let msg = match res {
+        Ok(msg) => Ok(Some(msg)),
+        Err(crate::lgmp_impl::error::Error::InternalError(
+            crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+        )) => {
+            *hb = Instant::now();
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }?;
+
+    Ok(msg)
 }
@@ -0,0 +1,287 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use super::{ArcFrame, ConnectionStats, LGEvent, LGMPConnection, LGMPOpts, LGMPSource};
+use crate::{
+    cursor::{CursorState, CursorTracker},
+    error::LGError,
+    threads::{ThreadMonitor, ThreadRole},
+    types::{HostInfo, HostMessage},
+};
+
+/// How long [LookingGlass::connect] waits for the host to accept a session.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time between attempts to initialise a session while connecting.
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+/// Time the worker thread sleeps for when there is nothing to read.
+const IDLE_SLEEP: Duration = Duration::from_millis(1);
+
+/// A client which reads from the host on a background thread, keeping copies of the
+/// latest frame and cursor state.
+///
+/// This wires up sensible defaults: the session is re-established if the host restarts,
+/// the queues are kept alive without any ticking from the caller, and frames are copied
+/// out as [ArcFrame]s which can be kept for as long as needed. Use [LGMPConnection]
+/// directly for control over when and how frames are read.
+///
+/// The worker thread is stopped when this is dropped.
+pub struct LookingGlass {
+    shared: Arc<Shared>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    //Serial of the frame last returned by next_frame
+    last_serial: Option<u32>,
+}
+
+/// State updated by the worker thread.
+#[derive(Default)]
+struct State {
+    frame: Option<ArcFrame>,
+    cursor: CursorState,
+    stats: ConnectionStats,
+    host_info: Option<HostInfo>,
+    error: Option<LGError>,
+    //Set once the worker thread has exited, which the error being taken doesn't undo
+    stopped: bool,
+    //Messages waiting to be sent by the worker thread
+    outgoing: VecDeque<HostMessage>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    //Signalled whenever a frame arrives or the worker stops
+    changed: Condvar,
+}
+
+impl LookingGlass {
+    /// Connects to the host on the provided shared memory, such as `/dev/kvmfr0` or a
+    /// `/dev/shm` file, and starts reading from it.
+    pub fn connect(source: impl Into<LGMPSource>) -> Result<LookingGlass, LGError> {
+        Self::connect_with(
+            LGMPOpts::builder(source)
+                .auto_reconnect(true)
+                .auto_tick(true)
+                .build(),
+        )
+    }
+
+    /// As [Self::connect], but using the provided options. `auto_reconnect` and
+    /// `auto_tick` should normally be enabled, as nothing else will keep the session alive.
+    pub fn connect_with(opts: LGMPOpts) -> Result<LookingGlass, LGError> {
+        let shared = Arc::new(Shared::default());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        //Connections can not be moved between threads, so it is opened on the worker
+        let (ready_tx, ready_rx) = mpsc::sync_channel(1);
+        let thread_shared = shared.clone();
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name("lg-worker".into())
+            .spawn(move || {
+                let monitor = ThreadMonitor::register(ThreadRole::ClientWorker);
+                let mut conn = match open(opts) {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        thread_shared.lock().stopped = true;
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                thread_shared.lock().host_info = conn.host_info().cloned();
+                let _ = ready_tx.send(Ok(()));
+
+                let mut cursor = CursorTracker::new();
+                while !thread_stop.load(Ordering::Relaxed) {
+                    if let Err(e) = poll(&mut conn, &mut cursor, &thread_shared) {
+                        let mut state = thread_shared.lock();
+                        state.error = Some(e);
+                        state.stopped = true;
+                        drop(state);
+                        thread_shared.changed.notify_all();
+                        return;
+                    }
+                    monitor.beat();
+                }
+            })
+            .map_err(LGError::ThreadSpawnError)?;
+
+        let lg = LookingGlass {
+            shared,
+            stop,
+            thread: Some(thread),
+            last_serial: None,
+        };
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(lg),
+            Ok(Err(e)) => Err(e),
+            //The worker panicked while connecting
+            Err(_) => Err(LGError::WorkerPanicked),
+        }
+    }
+
+    /// Returns the most recent frame received from the host, if there has been one.
+    pub fn latest_frame(&self) -> Option<ArcFrame> {
+        self.shared.lock().frame.clone()
+    }
+
+    /// Waits for a frame newer than the one this last returned, for up to `timeout`.
+    ///
+    /// Returns None if no new frame arrived in time, or the worker thread has stopped.
+    pub fn next_frame(&mut self, timeout: Duration) -> Option<ArcFrame> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(frame) = &state.frame {
+                if Some(frame.serial) != self.last_serial {
+                    self.last_serial = Some(frame.serial);
+                    return Some(frame.clone());
+                }
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || state.stopped {
+                return None;
+            }
+            state = match self.shared.changed.wait_timeout(state, remaining) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    /// Returns the current state of the guest cursor.
+    ///
+    /// Cursor shapes which cannot be decoded are skipped, leaving the previous shape.
+    pub fn cursor(&self) -> CursorState {
+        self.shared.lock().cursor.clone()
+    }
+
+    /// Returns the statistics of the underlying connection.
+    pub fn stats(&self) -> ConnectionStats {
+        self.shared.lock().stats
+    }
+
+    /// Returns the details sent by the host when the current session was initialised.
+    pub fn host_info(&self) -> Option<HostInfo> {
+        self.shared.lock().host_info.clone()
+    }
+
+    /// Queues a message to be sent to the host by the worker thread.
+    ///
+    /// Fails with [LGError::UnsupportedFeature] if the host has not advertised the features
+    /// the message needs. Messages which can not be sent yet, because the host's queue is
+    /// full or the session is being re-established, are kept until they can be.
+    pub fn send_message(&self, msg: HostMessage) -> Result<(), LGError> {
+        let mut state = self.shared.lock();
+        if let Some(info) = &state.host_info {
+            info.require(msg.required_features())?;
+        }
+        state.outgoing.push_back(msg);
+        Ok(())
+    }
+
+    /// Returns true until the worker thread stops due to an error.
+    pub fn is_running(&self) -> bool {
+        !self.shared.lock().stopped
+    }
+
+    /// Takes the error which stopped the worker thread, if it has stopped.
+    pub fn take_error(&self) -> Option<LGError> {
+        self.shared.lock().error.take()
+    }
+}
+
+impl Drop for LookingGlass {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        //The state is always left consistent, so a panic while holding it is harmless
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Opens a connection and initialises its session, retrying while the host has not yet
+/// seen the new client.
+fn open(opts: LGMPOpts) -> Result<LGMPConnection, LGError> {
+    let mut conn = LGMPConnection::open(opts)?;
+    let start = Instant::now();
+    loop {
+        match conn.init() {
+            Ok(()) => return Ok(conn),
+            Err(e) if e.requires_reconnect() && start.elapsed() < CONNECT_TIMEOUT => {
+                std::thread::sleep(CONNECT_RETRY_INTERVAL)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Handles a single event from the connection on the worker thread.
+fn poll(
+    conn: &mut LGMPConnection,
+    cursor: &mut CursorTracker,
+    shared: &Shared,
+) -> Result<(), LGError> {
+    let mut idle = false;
+    let mut reconnected = false;
+    match conn.poll_event()? {
+        LGEvent::Frame(frame) => {
+            //Frames describing data outside of their message are dropped
+            if let Ok(frame) = frame.to_arc() {
+                shared.lock().frame = Some(frame);
+                shared.changed.notify_all();
+            }
+        }
+        LGEvent::Cursor(update) => {
+            //Shapes which can not be decoded leave the previous shape in place
+            let _ = cursor.update(&update);
+            shared.lock().cursor = cursor.snapshot();
+        }
+        LGEvent::Reconnected => reconnected = true,
+        LGEvent::Idle | LGEvent::HostLost => idle = true,
+        _ => {}
+    }
+
+    loop {
+        let Some(msg) = shared.lock().outgoing.pop_front() else {
+            break;
+        };
+        match conn.send_message(msg) {
+            Ok(_) => {}
+            //The host may have restarted without the feature since the message was queued
+            Err(LGError::UnsupportedFeature(_)) => {}
+            Err(e) if e.is_retryable() || e.requires_reconnect() => {
+                shared.lock().outgoing.push_front(msg);
+                break;
+            }
+            Err(e) => Err(e)?,
+        }
+    }
+
+    let mut state = shared.lock();
+    state.stats = conn.stats();
+    if reconnected {
+        state.host_info = conn.host_info().cloned();
+    }
+    drop(state);
+    if idle {
+        std::thread::sleep(IDLE_SLEEP);
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::sync::{Arc, Mutex};
+
+use crate::lgmp_impl::client::{Client, ClientQueueHandle, InPlaceMessage};
+
+use crate::error::LGError;
+
+/// A subscription to an arbitrary LGMP queue, created with
+/// [super::LGMPConnection::subscribe_extra].
+///
+/// The host times out clients which do not empty a queue quickly enough, so this must be
+/// read regularly, or have its backlog discarded with [Self::skip_to_latest]. The queue is
+/// unsubscribed from when this is dropped.
+pub struct MessageChannel {
+    queue_id: u32,
+    chan: ClientQueueHandle,
+    //Keeps the shared memory mapped for as long as the queue handle exists
+    _client: Arc<Mutex<Client>>,
+}
+
+/// A message read from a [MessageChannel]. It is read in place from shared memory, and
+/// marked as done when dropped.
+pub struct RawMessage<'a> {
+    msg: InPlaceMessage<'a>,
+}
+
+impl RawMessage<'_> {
+    /// Returns the user data which the host posted alongside the message.
+    pub fn udata(&self) -> u32 {
+        self.msg.mem.udata
+    }
+
+    /// Returns the contents of the message.
+    pub fn data(&self) -> &[u8] {
+        &self.msg
+    }
+}
+
+impl MessageChannel {
+    pub(super) fn new(
+        queue_id: u32,
+        chan: ClientQueueHandle,
+        client: Arc<Mutex<Client>>,
+    ) -> MessageChannel {
+        MessageChannel {
+            queue_id,
+            chan,
+            _client: client,
+        }
+    }
+
+    /// Returns the ID of the queue this is subscribed to.
+    pub fn queue_id(&self) -> u32 {
+        self.queue_id
+    }
+
+    /// Returns the next message in the queue, or None if there are no new messages.
+    pub fn recv(&mut self) -> Result<Option<RawMessage<'_>>, LGError> {
+        match self.chan.pop_in_place() {
+            Ok(msg) => Ok(Some(RawMessage { msg })),
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+            )) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// Sends a message to the host on this queue, returning its serial. The host must be
+    /// expecting client messages on the queue for it to be read.
+    pub fn send(&mut self, data: Vec<u8>) -> Result<u32, LGError> {
+        Ok(self.chan.send_data(data)?)
+    }
+
+    /// Discards every message in the queue apart from the newest one.
+    pub fn skip_to_latest(&mut self) -> Result<(), LGError> {
+        match self.chan.advance_to_last() {
+            Ok(())
+            | Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+            )) => Ok(()),
+            Err(e) => Err(e)?,
+        }
+    }
+}
+
+impl Drop for MessageChannel {
+    fn drop(&mut self) {
+        //Failing to unsubscribe only means that the host will time this client out
+        let _ = self.chan.unsubscribe();
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+
+use super::{ConnectionStats, QueueErrorStats};
+use crate::pool::FramePool;
+
+/// Registers descriptions of the metrics exported by this crate with the installed
+/// `metrics` recorder. This only needs calling once, before any are exported.
+pub fn describe_metrics() {
+    describe_counter!("lookinggla_frames_total", "Frames received from the host");
+    describe_counter!(
+        "lookinggla_cursor_updates_total",
+        "Cursor updates received from the host"
+    );
+    describe_counter!(
+        "lookinggla_frames_skipped_total",
+        "Frames sent by the host which were never seen by the client"
+    );
+    describe_counter!(
+        "lookinggla_anomalies_total",
+        "Unexpected messages received from the host"
+    );
+    describe_counter!(
+        "lookinggla_reconnects_total",
+        "Sessions re-established after the host restarted"
+    );
+    describe_counter!(
+        "lookinggla_cursor_shapes_total",
+        "Cursor updates which carried a new shape"
+    );
+    describe_counter!(
+        "lookinggla_cursor_shapes_dropped_total",
+        "Cursor shapes removed by the shape rate limit"
+    );
+    describe_counter!(
+        "lookinggla_fast_forwards_total",
+        "Queue fast forwards performed while messages were waiting"
+    );
+    describe_counter!("lookinggla_queue_reads_total", "Reads from an LGMP queue");
+    describe_counter!(
+        "lookinggla_queue_errors_total",
+        "Error statuses returned when reading from an LGMP queue"
+    );
+    describe_gauge!(
+        "lookinggla_frames_per_second",
+        "Frames received during the last second"
+    );
+    describe_gauge!(
+        "lookinggla_cursor_updates_per_second",
+        "Cursor updates received during the last second"
+    );
+    describe_gauge!(
+        "lookinggla_cursor_shapes_per_second",
+        "Cursor shapes received during the last second"
+    );
+    describe_histogram!(
+        "lookinggla_frame_latency_seconds",
+        metrics::Unit::Seconds,
+        "Time between a frame being seen in the queue and it being returned"
+    );
+    describe_counter!(
+        "lookinggla_pool_exhausted_total",
+        "Frame pool buffers allocated because none were kept for reuse"
+    );
+    describe_gauge!(
+        "lookinggla_pool_memory_bytes",
+        metrics::Unit::Bytes,
+        "Bytes held by buffers from a frame pool"
+    );
+}
+
+impl ConnectionStats {
+    /// Sets the counters and gauges exported through the `metrics` facade to these stats,
+    /// labelled with the given connection name so that several connections can be told
+    /// apart.
+    ///
+    /// Rates are only collected if enabled with
+    /// [LGMPOptsBuilder::collect_metrics](super::LGMPOptsBuilder::collect_metrics), in
+    /// which case frame latencies are also recorded in a histogram as frames arrive. The
+    /// histogram is shared between all connections.
+    pub fn export_metrics(&self, connection: &str) {
+        let labels = [("connection", connection.to_owned())];
+        let counters = [
+            ("lookinggla_frames_total", self.frames),
+            ("lookinggla_cursor_updates_total", self.cursor_updates),
+            ("lookinggla_frames_skipped_total", self.frames_skipped),
+            ("lookinggla_anomalies_total", self.anomalies),
+            ("lookinggla_reconnects_total", self.reconnects),
+            ("lookinggla_cursor_shapes_total", self.cursor_shapes),
+            (
+                "lookinggla_cursor_shapes_dropped_total",
+                self.cursor_shapes_dropped,
+            ),
+            ("lookinggla_fast_forwards_total", self.fast_forwards),
+        ];
+        for (name, value) in counters {
+            counter!(name, &labels).absolute(value);
+        }
+        let gauges = [
+            ("lookinggla_frames_per_second", self.frames_per_sec),
+            (
+                "lookinggla_cursor_updates_per_second",
+                self.cursor_updates_per_sec,
+            ),
+            (
+                "lookinggla_cursor_shapes_per_second",
+                self.cursor_shapes_per_sec,
+            ),
+        ];
+        for (name, value) in gauges {
+            gauge!(name, &labels).set(value);
+        }
+
+        export_queue(connection, "frame", &self.frame_queue);
+        export_queue(connection, "cursor", &self.cursor_queue);
+    }
+}
+
+impl FramePool {
+    /// Sets the counters and gauges exported through the `metrics` facade to the state of
+    /// this pool, labelled with the given pool name.
+    pub fn export_metrics(&self, pool: &str) {
+        let labels = [("pool", pool.to_owned())];
+        counter!("lookinggla_pool_exhausted_total", &labels).absolute(self.exhausted());
+        gauge!("lookinggla_pool_memory_bytes", &labels).set(self.memory_used() as f64);
+    }
+}
+
+fn export_queue(connection: &str, queue: &'static str, stats: &QueueErrorStats) {
+    let labels = [
+        ("connection", connection.to_owned()),
+        ("queue", queue.to_owned()),
+    ];
+    counter!("lookinggla_queue_reads_total", &labels).absolute(stats.reads);
+
+    let statuses = [
+        ("empty", stats.empty),
+        ("corrupted", stats.corrupted),
+        ("timeout", stats.timeouts),
+        ("invalid_session", stats.invalid_session),
+        ("other", stats.other),
+    ];
+    for (status, value) in statuses {
+        let labels = [
+            ("connection", connection.to_owned()),
+            ("queue", queue.to_owned()),
+            ("status", status.to_owned()),
+        ];
+        counter!("lookinggla_queue_errors_total", &labels).absolute(value);
+    }
+}
+
+/// Records the latency of a single frame in the latency histogram.
+pub(super) fn record_frame_latency(latency: Duration) {
+    histogram!("lookinggla_frame_latency_seconds").record(latency.as_secs_f64());
+}
@@ -1,2 +1,50 @@
-mod framerelay_client;
+mod arc_frame;
+mod auto_tick;
+#[cfg(feature = "black-box")]
+mod black_box;
+mod chunks;
+mod deadline;
+mod dispatcher;
+#[cfg(target_os = "linux")]
+mod dmabuf;
+mod frame_buffer;
+mod health;
 mod lgmp_comm;
+mod looking_glass;
+mod message_channel;
+#[cfg(feature = "metrics")]
+mod metrics_export;
+mod owned_frame;
+mod pacing;
+mod quirks;
+mod receivers;
+mod replay;
+mod shm_source;
+mod tiles;
+
+pub use arc_frame::ArcFrame;
+#[cfg(feature = "black-box")]
+pub use black_box::finalize_black_box;
+pub use chunks::{FrameChunk, FrameChunks};
+pub use deadline::Deadline;
+pub use dispatcher::Dispatcher;
+#[cfg(target_os = "linux")]
+pub use dmabuf::DmabufFrame;
+pub use frame_buffer::FrameBuffer;
+pub use health::{ConnectionHealth, HealthReason, HealthReport, HealthStatus};
+pub use lgmp_comm::{
+    Anomaly, Backpressure, CapturedFrame, ChannelPriority, ConnectionStats, KVMFRChans,
+    KVMFRCursorHandle, KVMFRFrameHandle, LGEvent, LGMPConnection, LGMPOpts, LGMPOptsBuilder,
+    QueueErrorStats, QueueStatus, CURSOR_QUEUE_ID, FRAME_QUEUE_ID,
+};
+pub use looking_glass::LookingGlass;
+pub use message_channel::{MessageChannel, RawMessage};
+#[cfg(feature = "metrics")]
+pub use metrics_export::describe_metrics;
+pub use owned_frame::OwnedFrame;
+pub use quirks::Quirks;
+pub use receivers::{CursorReceiver, FrameReceiver};
+pub use replay::ReplayConnection;
+pub(crate) use shm_source::SharedMem;
+pub use shm_source::{LGMPSource, ShmMapOpts};
+pub use tiles::{FrameTile, FrameTiles};
@@ -0,0 +1,40 @@
+use crate::{
+    pool::FramePool,
+    types::{DamageRect, FrameInfo},
+};
+
+/// A frame copied out of shared memory along with its metadata, created by
+/// [super::KVMFRFrameHandle::to_owned] or [super::KVMFRFrameHandle::to_owned_in].
+///
+/// Holding a frame handle stops the host from reusing the message, so slow processing
+/// should be done on an owned frame once the handle has been dropped.
+#[derive(Debug, Clone)]
+pub struct OwnedFrame {
+    pub info: FrameInfo,
+    /// Regions which changed since the previous frame, or empty if the whole frame did
+    pub damage: Vec<DamageRect>,
+    data: Vec<u8>,
+}
+
+impl OwnedFrame {
+    pub(super) fn new(info: FrameInfo, damage: Vec<DamageRect>, data: Vec<u8>) -> OwnedFrame {
+        OwnedFrame { info, damage, data }
+    }
+
+    /// Returns the pixel data, which is `pitch * data_height` bytes long.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the buffer holding the pixel data, so that it can be handed back to the
+    /// [FramePool] it came from.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Returns the pixel data to the pool it was copied into by
+    /// [super::KVMFRFrameHandle::to_owned_in].
+    pub fn release_to(self, pool: &mut FramePool) {
+        pool.release(self.data);
+    }
+}
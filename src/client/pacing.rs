@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+/// Limits how often frames are delivered, for [super::LGMPOptsBuilder::max_frame_rate].
+///
+/// The interval is capped at half of the frame queue timeout, as a frame left waiting for
+/// longer than that would get the client timed out by the host.
+pub(super) struct FramePacer {
+    interval: Option<Duration>,
+    last_delivered: Option<Instant>,
+}
+
+impl FramePacer {
+    pub(super) fn new(max_rate: Option<u32>, timeout: Duration) -> FramePacer {
+        FramePacer {
+            interval: max_rate
+                .filter(|&rate| rate > 0)
+                .map(|rate| (Duration::from_secs(1) / rate).min(timeout / 2)),
+            last_delivered: None,
+        }
+    }
+
+    /// Returns true if frames are being limited at all.
+    pub(super) fn is_enabled(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    /// Returns true if a frame may be delivered at `now`.
+    pub(super) fn is_due(&self, now: Instant) -> bool {
+        match (self.interval, self.last_delivered) {
+            (Some(interval), Some(last)) => now.saturating_duration_since(last) >= interval,
+            _ => true,
+        }
+    }
+
+    /// Records that a frame was delivered at `now`.
+    pub(super) fn record(&mut self, now: Instant) {
+        self.last_delivered = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_frame_rate() {
+        let timeout = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(Some(10), timeout);
+        assert!(pacer.is_due(start));
+        pacer.record(start);
+        assert!(!pacer.is_due(start + Duration::from_millis(50)));
+        assert!(pacer.is_due(start + Duration::from_millis(100)));
+
+        //Rates too low to keep the queue alive are raised
+        let mut pacer = FramePacer::new(Some(1), timeout);
+        pacer.record(start);
+        assert!(pacer.is_due(start + Duration::from_millis(500)));
+
+        let mut pacer = FramePacer::new(None, timeout);
+        assert!(!pacer.is_enabled());
+        pacer.record(start);
+        assert!(pacer.is_due(start));
+    }
+}
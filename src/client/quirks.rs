@@ -0,0 +1,102 @@
+use crate::types::HostInfo;
+
+bitflags::bitflags! {
+    /// Compatibility behaviours enabled to work around known problems with some hosts.
+    ///
+    /// These are detected from the details sent by the host when a session is initialised,
+    /// and can be forced on or off with [super::LGMPOptsBuilder::force_quirks] and
+    /// [super::LGMPOptsBuilder::disable_quirks].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct Quirks: u32 {
+        /// Damage rects sent by the host cannot be trusted, so every frame is treated as
+        /// fully damaged
+        const IGNORE_DAMAGE = 1 << 0;
+        /// The host resends the cursor shape alongside position updates, so repeated shapes
+        /// are rate limited even if no limit has been set
+        const LIMIT_CURSOR_SHAPES = 1 << 1;
+    }
+}
+
+/// A known problem, and the hosts which have it.
+struct QuirkRule {
+    /// Capture backend reported by the host, compared case insensitively
+    capture: Option<&'static str>,
+    /// Start of the host's version string
+    host_version: Option<&'static str>,
+    quirks: Quirks,
+}
+
+/// Hosts with known problems. A host matches a rule if it matches every field which is set.
+const QUIRK_TABLE: &[QuirkRule] = &[
+    //NvFBC reports damage from its own change detection, which misses some updates
+    QuirkRule {
+        capture: Some("NvFBC"),
+        host_version: None,
+        quirks: Quirks::IGNORE_DAMAGE,
+    },
+    //B5 hosts set the shape flag on every cursor update
+    QuirkRule {
+        capture: None,
+        host_version: Some("B5"),
+        quirks: Quirks::LIMIT_CURSOR_SHAPES,
+    },
+];
+
+impl Quirks {
+    /// Returns the quirks which apply to a host, according to the built in quirk table.
+    pub fn detect(info: &HostInfo) -> Quirks {
+        let capture = info.vm.as_ref().map(|vm| vm.capture.as_str());
+        QUIRK_TABLE
+            .iter()
+            .filter(|rule| {
+                rule.capture.is_none_or(|expected| {
+                    capture.is_some_and(|capture| capture.eq_ignore_ascii_case(expected))
+                })
+            })
+            .filter(|rule| {
+                rule.host_version
+                    .is_none_or(|prefix| info.host_version.starts_with(prefix))
+            })
+            .fold(Quirks::empty(), |quirks, rule| quirks | rule.quirks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{HostFeatures, VmInfo};
+
+    fn host(host_version: &str, capture: Option<&str>) -> HostInfo {
+        HostInfo {
+            version: 20,
+            host_version: host_version.into(),
+            features: HostFeatures::empty(),
+            vm: capture.map(|capture| VmInfo {
+                uuid: [0; 16],
+                capture: capture.into(),
+                cpus: 1,
+                cores: 1,
+                sockets: 1,
+                model: String::new(),
+            }),
+            os: None,
+        }
+    }
+
+    #[test]
+    fn detects_quirks_from_host_info() {
+        assert_eq!(Quirks::detect(&host("B7", None)), Quirks::empty());
+        assert_eq!(
+            Quirks::detect(&host("B7", Some("nvfbc"))),
+            Quirks::IGNORE_DAMAGE
+        );
+        assert_eq!(
+            Quirks::detect(&host("B5.0.1", Some("NvFBC"))),
+            Quirks::IGNORE_DAMAGE | Quirks::LIMIT_CURSOR_SHAPES
+        );
+        assert_eq!(
+            Quirks::detect(&host("B5", Some("DXGI"))),
+            Quirks::LIMIT_CURSOR_SHAPES
+        );
+    }
+}
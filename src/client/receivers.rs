@@ -0,0 +1,229 @@
+use std::{
+    cell::Cell,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::lgmp_impl::client::{Client, ClientQueueHandle};
+
+use super::{
+    deadline::Deadline,
+    lgmp_comm::{frames_between, pop_chan_ref, CursorRate},
+    shm_source::DeviceHandle,
+    ConnectionStats, KVMFRChans, KVMFRCursorHandle, KVMFRFrameHandle,
+};
+use crate::{
+    copy::CopyStrategy,
+    error::LGError,
+    types::{CursorFlags, HostFeatures, HostMessage},
+};
+
+/// A queue moved out of an [super::LGMPConnection] session, along with the state needed
+/// to keep it alive.
+struct Receiver {
+    channel: KVMFRChans,
+    chan: ClientQueueHandle,
+    last_heartbeat: Instant,
+    timeout: Duration,
+    tick_period: Duration,
+    stats: ConnectionStats,
+    //Keeps the shared memory mapped for as long as the queue handle exists
+    _client: Arc<Mutex<Client>>,
+}
+
+/// Everything needed to move a queue out of a session.
+pub(super) struct ReceiverParts {
+    pub(super) chan: ClientQueueHandle,
+    pub(super) last_heartbeat: Instant,
+    pub(super) timeout: Duration,
+    pub(super) tick_period: Duration,
+    pub(super) client: Arc<Mutex<Client>>,
+}
+
+impl Receiver {
+    fn new(channel: KVMFRChans, parts: ReceiverParts) -> Receiver {
+        Receiver {
+            channel,
+            chan: parts.chan,
+            last_heartbeat: parts.last_heartbeat,
+            timeout: parts.timeout,
+            tick_period: parts.tick_period,
+            stats: ConnectionStats::default(),
+            _client: parts.client,
+        }
+    }
+
+    fn tick(&mut self) -> Result<(), LGError> {
+        let deadline = Deadline::new(self.last_heartbeat, self.timeout);
+        if !deadline.due_within(Instant::now(), self.tick_period) {
+            return Ok(());
+        }
+        let res = self.chan.advance_to_last();
+        self.stats.queue_mut(self.channel).record(&res);
+        match res {
+            Ok(()) => self.stats.fast_forwards += 1,
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                crate::lgmp_impl::error::Status::LGMPErrQueueEmpty,
+            )) => (),
+            Err(e) => Err(e)?,
+        }
+        self.last_heartbeat = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        //Failing to unsubscribe only means that the host will time this client out
+        let _ = self.chan.unsubscribe();
+    }
+}
+
+/// The frame queue of a session, split off with [super::LGMPConnection::split] so that it
+/// can be read on a render thread.
+///
+/// Reading and ticking only touch this queue, so never contend with cursor reads. The
+/// connection's damage quirk carries over, but frames are not recorded, captured or
+/// timed.
+///
+/// Once the host restarts, reads fail and a new connection must be opened. The queue is
+/// unsubscribed when this is dropped.
+pub struct FrameReceiver {
+    inner: Receiver,
+    device: DeviceHandle,
+    ignore_damage: bool,
+    copy_strategy: CopyStrategy,
+    last_serial: Option<u32>,
+    //Hash of the last frame checked by KVMFRFrameHandle::is_duplicate_of_previous
+    last_frame_hash: Cell<Option<u64>>,
+}
+
+impl FrameReceiver {
+    pub(super) fn new(
+        parts: ReceiverParts,
+        device: DeviceHandle,
+        ignore_damage: bool,
+        copy_strategy: CopyStrategy,
+        last_serial: Option<u32>,
+    ) -> FrameReceiver {
+        FrameReceiver {
+            inner: Receiver::new(KVMFRChans::Frame, parts),
+            device,
+            ignore_damage,
+            copy_strategy,
+            last_serial,
+            last_frame_hash: Cell::new(None),
+        }
+    }
+
+    /// As [super::LGMPConnection::tick_frame]. This should be called at the frame tick
+    /// period set in [super::LGMPOpts].
+    pub fn tick(&mut self) -> Result<(), LGError> {
+        self.inner.tick()
+    }
+
+    /// As [super::LGMPConnection::get_frame_update].
+    pub fn get_frame_update(&mut self) -> Result<Option<KVMFRFrameHandle<'_>>, LGError> {
+        let inner = &mut self.inner;
+        let hb = &mut inner.last_heartbeat;
+        let Some(m) = pop_chan_ref(&mut inner.chan, hb, &mut inner.stats.frame_queue)? else {
+            return Ok(None);
+        };
+        let mut frame = KVMFRFrameHandle::live(
+            m,
+            self.device.clone(),
+            self.ignore_damage,
+            self.copy_strategy,
+            &self.last_frame_hash,
+        );
+        if let Ok(serial) = frame.as_frame().map(|header| header.frameSerial) {
+            frame.set_dropped(frames_between(self.last_serial, serial));
+            self.last_serial = Some(serial);
+        }
+        Ok(Some(frame))
+    }
+
+    /// Returns counters for the activity seen on this queue. Only the frame queue and
+    /// fast-forward counters are used.
+    pub fn stats(&self) -> ConnectionStats {
+        self.inner.stats
+    }
+}
+
+/// The cursor queue of a session, split off with [super::LGMPConnection::split] or
+/// [super::LGMPConnection::split_cursor] so that it can be read on an input or UI thread.
+///
+/// Reading and ticking only touch this queue, so never contend with frame reads. The
+/// connection's shape limit carries over, but cursor updates are not recorded and shape
+/// storms are only counted in [Self::stats].
+///
+/// If the connection's session is closed or re-established, reads fail and the cursor
+/// queue should be split off again. The queue is unsubscribed when this is dropped.
+pub struct CursorReceiver {
+    inner: Receiver,
+    shape_limit: Option<u32>,
+    rate: CursorRate,
+    //Features advertised by the host of the session this was split from
+    features: HostFeatures,
+}
+
+impl CursorReceiver {
+    pub(super) fn new(
+        parts: ReceiverParts,
+        shape_limit: Option<u32>,
+        features: HostFeatures,
+    ) -> CursorReceiver {
+        CursorReceiver {
+            inner: Receiver::new(KVMFRChans::Cursor, parts),
+            shape_limit,
+            rate: CursorRate::new(),
+            features,
+        }
+    }
+
+    /// As [super::LGMPConnection::tick_cursor]. This should be called at the cursor tick
+    /// period set in [super::LGMPOpts].
+    pub fn tick(&mut self) -> Result<(), LGError> {
+        self.inner.tick()
+    }
+
+    /// As [super::LGMPConnection::get_cursor_update].
+    pub fn get_cursor_update(&mut self) -> Result<Option<KVMFRCursorHandle<'_>>, LGError> {
+        let inner = &mut self.inner;
+        let hb = &mut inner.last_heartbeat;
+        let Some(m) = pop_chan_ref(&mut inner.chan, hb, &mut inner.stats.cursor_queue)? else {
+            return Ok(None);
+        };
+        inner.stats.cursor_updates += 1;
+        let flags = CursorFlags::from_bits_retain(m.mem.udata);
+        let suppress_shape = self.rate.record(flags, self.shape_limit, &mut inner.stats);
+        Ok(Some(KVMFRCursorHandle::live(m, suppress_shape)))
+    }
+
+    /// As [super::LGMPConnection::send_message].
+    pub fn send_message(&mut self, msg: HostMessage) -> Result<u32, LGError> {
+        let missing = msg.required_features().difference(self.features);
+        if !missing.is_empty() {
+            Err(LGError::UnsupportedFeature(missing))?
+        }
+        Ok(self.inner.chan.send_data(msg.to_bytes())?)
+    }
+
+    /// Returns counters for the activity seen on this queue. Only the cursor and
+    /// fast-forward counters are used.
+    pub fn stats(&self) -> ConnectionStats {
+        self.inner.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receivers_are_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<FrameReceiver>();
+        assert_send::<CursorReceiver>();
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io::{Read, Write},
+    mem::size_of,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use zerocopy::FromBytes;
+
+use super::{lgmp_comm::frames_between, Anomaly, KVMFRCursorHandle, KVMFRFrameHandle, LGEvent};
+use crate::{error::LGError, shm_datastructs};
+
+/// Identifies a recording, including the version of its format
+pub(super) const RECORDING_MAGIC: &[u8; 8] = b"LGMPREC\x01";
+/// Record kind for a message from the frame channel
+pub(super) const KIND_FRAME: u8 = 0;
+/// Record kind for a message from the cursor channel
+const KIND_CURSOR: u8 = 1;
+/// Longest message accepted from a recording, which is enough for an 8K frame with 8
+/// bytes per pixel
+const MAX_RECORD_LEN: u64 = 512 * 1024 * 1024;
+
+/// A message read back from a recording.
+pub(super) struct RecordedMessage {
+    pub(super) udata: u32,
+    /// Time since the start of the recording at which the message was received
+    time: Duration,
+    /// Contents of the message, stored as words so that headers can be read in place
+    data: Vec<u64>,
+    len: usize,
+}
+
+impl RecordedMessage {
+    fn new(udata: u32, time: Duration, bytes: &[u8]) -> RecordedMessage {
+        let mut data = vec![0u64; bytes.len().div_ceil(size_of::<u64>())];
+        //The buffer is at least as long as the message
+        let dst =
+            unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr().cast::<u8>(), bytes.len()) };
+        dst.copy_from_slice(bytes);
+        RecordedMessage {
+            udata,
+            time,
+            data,
+            len: bytes.len(),
+        }
+    }
+
+    pub(super) fn as_bytes(&self) -> &[u8] {
+        //The buffer is at least as long as the message
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr().cast::<u8>(), self.len) }
+    }
+}
+
+/// Writes messages received by an [super::LGMPConnection] to a recording.
+///
+/// Each message is stored as its channel, the time since recording started in
+/// microseconds, its user data and its contents, all little endian.
+pub(super) struct Recorder<W> {
+    writer: W,
+    start: Instant,
+}
+
+impl<W: Write> Recorder<W> {
+    pub(super) fn new(mut writer: W) -> Result<Recorder<W>, LGError> {
+        writer.write_all(RECORDING_MAGIC)?;
+        Ok(Recorder {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    pub(super) fn record_frame(&mut self, frame: &KVMFRFrameHandle) -> Result<(), LGError> {
+        let (udata, bytes) = frame.raw();
+        self.write(KIND_FRAME, udata, bytes)
+    }
+
+    pub(super) fn record_cursor(&mut self, cursor: &KVMFRCursorHandle) -> Result<(), LGError> {
+        let (udata, bytes) = cursor.raw();
+        self.write(KIND_CURSOR, udata, bytes)
+    }
+
+    fn write(&mut self, kind: u8, udata: u32, bytes: &[u8]) -> Result<(), LGError> {
+        let time = self.start.elapsed().as_micros() as u64;
+        write_record(&mut self.writer, kind, time, udata, bytes)
+    }
+
+    pub(super) fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Writes a single message in the format described on [Recorder], with `time` in
+/// microseconds.
+pub(super) fn write_record(
+    writer: &mut impl Write,
+    kind: u8,
+    time: u64,
+    udata: u32,
+    bytes: &[u8],
+) -> Result<(), LGError> {
+    writer.write_all(&[kind])?;
+    writer.write_all(&time.to_le_bytes())?;
+    writer.write_all(&udata.to_le_bytes())?;
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+/// Plays back a recording made with [super::LGMPConnection::start_recording], with the same
+/// accessors as a live connection.
+///
+/// This allows rendering bugs to be reproduced without a host, and tests to be run
+/// against known traffic.
+pub struct ReplayConnection {
+    frames: VecDeque<RecordedMessage>,
+    cursors: VecDeque<RecordedMessage>,
+    /// The message most recently returned, which handles borrow from
+    current: Option<RecordedMessage>,
+    /// Whether messages are held back until the time they were originally received
+    paced: bool,
+    start: Instant,
+    /// Format version of the last frame returned from [Self::poll_event]
+    format_ver: Option<u32>,
+    /// Serial of the last frame returned
+    last_serial: Option<u32>,
+    /// Hash of the last frame checked by [KVMFRFrameHandle::is_duplicate_of_previous]
+    last_frame_hash: Cell<Option<u64>>,
+}
+
+impl ReplayConnection {
+    /// Loads a recording from a file. See [Self::from_reader].
+    pub fn open(path: impl AsRef<Path>, paced: bool) -> Result<ReplayConnection, LGError> {
+        let file = std::fs::File::open(path)?;
+        Self::from_reader(std::io::BufReader::new(file), paced)
+    }
+
+    /// Loads a recording into memory.
+    ///
+    /// If `paced` is set, each message only becomes available once the time between it and
+    /// the start of the recording has passed since this was called. Otherwise messages are
+    /// returned as quickly as they are asked for, which makes playback deterministic.
+    pub fn from_reader(mut reader: impl Read, paced: bool) -> Result<ReplayConnection, LGError> {
+        let mut magic = [0; RECORDING_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != RECORDING_MAGIC {
+            Err(LGError::InvalidRecording("Not an LGMP recording".into()))?
+        }
+
+        let mut frames = VecDeque::new();
+        let mut cursors = VecDeque::new();
+        let mut kind = [0; 1];
+        while reader.read(&mut kind)? != 0 {
+            let mut header = [0; 20];
+            read_record(&mut reader, &mut header)?;
+            let time = u64::from_le_bytes(header[0..8].try_into().unwrap());
+            let udata = u32::from_le_bytes(header[8..12].try_into().unwrap());
+            let len = u64::from_le_bytes(header[12..20].try_into().unwrap());
+
+            if len > MAX_RECORD_LEN {
+                Err(LGError::InvalidRecording(format!(
+                    "Message of {len} bytes is too long"
+                )))?
+            }
+            //Grown as data arrives, so a corrupt length can't allocate more than the file holds
+            let mut bytes = Vec::new();
+            if reader.by_ref().take(len).read_to_end(&mut bytes)? as u64 != len {
+                Err(LGError::InvalidRecording("Recording was truncated".into()))?
+            }
+            let msg = RecordedMessage::new(udata, Duration::from_micros(time), &bytes);
+            match kind[0] {
+                KIND_FRAME => frames.push_back(msg),
+                KIND_CURSOR => cursors.push_back(msg),
+                other => Err(LGError::InvalidRecording(format!(
+                    "Unknown record kind {other}"
+                )))?,
+            }
+        }
+
+        Ok(ReplayConnection {
+            frames,
+            cursors,
+            current: None,
+            paced,
+            start: Instant::now(),
+            format_ver: None,
+            last_serial: None,
+            last_frame_hash: Cell::new(None),
+        })
+    }
+
+    /// Returns true once every message in the recording has been returned.
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty() && self.cursors.is_empty()
+    }
+
+    /// Returns the next event from the recording, in the order in which they were received.
+    ///
+    /// As with a live connection, [LGEvent::FormatChanged] is reported before a frame with
+    /// a new format version, and malformed messages are reported as anomalies.
+    pub fn poll_event(&mut self) -> Result<LGEvent<'_>, LGError> {
+        let frame_due = self.frames.front().filter(|msg| self.is_due(msg));
+        let cursor_due = self.cursors.front().filter(|msg| self.is_due(msg));
+        let take_frame = match (frame_due, cursor_due) {
+            (Some(frame), Some(cursor)) => frame.time <= cursor.time,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return Ok(LGEvent::Idle),
+        };
+
+        if take_frame {
+            let msg = self.frames.front().unwrap();
+            let Ok((header, _)) = shm_datastructs::KVMFRFrame::read_from_prefix(msg.as_bytes())
+            else {
+                self.frames.pop_front();
+                return Ok(LGEvent::Anomaly(Anomaly::FrameMessageTooSmall));
+            };
+            let format_ver = header.formatVer;
+            if self.format_ver != Some(format_ver) {
+                self.format_ver = Some(format_ver);
+                return Ok(LGEvent::FormatChanged(format_ver));
+            }
+            Ok(LGEvent::Frame(self.take_frame().unwrap()))
+        } else {
+            let msg = self.cursors.pop_front().unwrap();
+            if msg.len < size_of::<shm_datastructs::KVMFRCursor>() {
+                return Ok(LGEvent::Anomaly(Anomaly::CursorMessageTooSmall));
+            }
+            let msg = self.current.insert(msg);
+            Ok(LGEvent::Cursor(KVMFRCursorHandle::recorded(msg)))
+        }
+    }
+
+    /// Returns the next recorded frame, if one is due.
+    pub fn get_frame_update(&mut self) -> Result<Option<KVMFRFrameHandle<'_>>, LGError> {
+        if !self.frames.front().is_some_and(|msg| self.is_due(msg)) {
+            return Ok(None);
+        }
+        Ok(self.take_frame())
+    }
+
+    /// Returns the newest recorded frame which is due, discarding any older ones.
+    pub fn get_latest_frame_update(&mut self) -> Result<Option<KVMFRFrameHandle<'_>>, LGError> {
+        while self.frames.get(1).is_some_and(|msg| self.is_due(msg)) {
+            self.frames.pop_front();
+        }
+        self.get_frame_update()
+    }
+
+    /// Returns the next recorded cursor update, if one is due.
+    pub fn get_cursor_update(&mut self) -> Result<Option<KVMFRCursorHandle<'_>>, LGError> {
+        if !self.cursors.front().is_some_and(|msg| self.is_due(msg)) {
+            return Ok(None);
+        }
+        self.current = self.cursors.pop_front();
+        Ok(self.current.as_ref().map(KVMFRCursorHandle::recorded))
+    }
+
+    /// Pops the next frame, tracking its serial to count the frames dropped before it.
+    fn take_frame(&mut self) -> Option<KVMFRFrameHandle<'_>> {
+        let msg = self.current.insert(self.frames.pop_front()?);
+        let mut dropped = 0;
+        if let Ok((header, _)) = shm_datastructs::KVMFRFrame::read_from_prefix(msg.as_bytes()) {
+            let serial = header.frameSerial;
+            dropped = frames_between(self.last_serial, serial);
+            self.last_serial = Some(serial);
+        }
+        Some(KVMFRFrameHandle::recorded(
+            msg,
+            dropped,
+            &self.last_frame_hash,
+        ))
+    }
+
+    fn is_due(&self, msg: &RecordedMessage) -> bool {
+        !self.paced || self.start.elapsed() >= msg.time
+    }
+}
+
+/// Fills a buffer from a recording, treating running out of data as a truncated recording.
+fn read_record(reader: &mut impl Read, buf: &mut [u8]) -> Result<(), LGError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            LGError::InvalidRecording("Recording was truncated".into())
+        }
+        _ => e.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_recorded_messages() {
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        recorder.write(KIND_FRAME, 0, &[1, 2, 3]).unwrap();
+        recorder
+            .write(
+                KIND_CURSOR,
+                7,
+                &[0; size_of::<shm_datastructs::KVMFRCursor>()],
+            )
+            .unwrap();
+        let bytes = recorder.into_inner();
+
+        let mut replay = ReplayConnection::from_reader(&bytes[..], false).unwrap();
+        assert!(matches!(
+            replay.poll_event().unwrap(),
+            LGEvent::Anomaly(Anomaly::FrameMessageTooSmall)
+        ));
+        match replay.get_cursor_update().unwrap() {
+            Some(cursor) => assert_eq!(cursor.raw().0, 7),
+            None => panic!("Expected a cursor update"),
+        }
+        assert!(replay.is_finished());
+
+        assert!(matches!(
+            ReplayConnection::from_reader(&bytes[..bytes.len() - 1], false),
+            Err(LGError::InvalidRecording(_))
+        ));
+        assert!(matches!(
+            ReplayConnection::from_reader(&b"NOTARECORDING"[..], false),
+            Err(LGError::InvalidRecording(_))
+        ));
+
+        //A huge length is rejected rather than allocated
+        let mut huge = RECORDING_MAGIC.to_vec();
+        huge.push(KIND_FRAME);
+        huge.extend_from_slice(&[0; 12]);
+        huge.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            ReplayConnection::from_reader(&huge[..], false),
+            Err(LGError::InvalidRecording(_))
+        ));
+    }
+}
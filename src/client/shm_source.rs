@@ -0,0 +1,301 @@
+#[cfg(unix)]
+use std::{fs::File, os::fd::OwnedFd, path::PathBuf, sync::Arc};
+
+use crate::lgmp_impl::shm_file::ShmFileHandle;
+
+#[cfg(target_os = "linux")]
+use super::dmabuf::KVMFRDevice;
+use crate::error::LGError;
+
+/// `KVMFR_DMABUF_GETSIZE` ioctl from the kvmfr kernel module, `_IO('u', 0x44)`
+#[cfg(target_os = "linux")]
+const KVMFR_DMABUF_GETSIZE: libc::c_ulong = (b'u' as libc::c_ulong) << 8 | 0x44;
+
+/// Location of the shared memory used to communicate with the host.
+#[derive(Debug, Clone)]
+pub enum LGMPSource {
+    /// A `shared_memory` flink file, as created by [crate::host::LGMPHostConnection] or an
+    /// ivshmem file under `/dev/shm`
+    Flink(String),
+    /// A shared memory segment opened by its OS id, for deployments which name the
+    /// segment directly instead of through a flink file
+    OsId(String),
+    /// A kvmfr device node such as `/dev/kvmfr0`, or any other file which can be mapped
+    #[cfg(unix)]
+    Device(PathBuf),
+    /// An already open file descriptor referring to a memfd or either of the above, for
+    /// example one passed in from outside of a sandbox
+    #[cfg(unix)]
+    Fd(Arc<OwnedFd>),
+}
+
+#[cfg(unix)]
+impl From<PathBuf> for LGMPSource {
+    fn from(value: PathBuf) -> Self {
+        LGMPSource::Device(value)
+    }
+}
+
+#[cfg(unix)]
+impl From<OwnedFd> for LGMPSource {
+    fn from(value: OwnedFd) -> Self {
+        LGMPSource::Fd(Arc::new(value))
+    }
+}
+
+/// Options for how the shared memory of an [LGMPSource] is mapped, set with
+/// [LGMPOptsBuilder::map_opts](super::LGMPOptsBuilder::map_opts).
+///
+/// The hints are only applied on linux, and are ignored if the kernel does not support
+/// them for the kind of memory being mapped.
+#[derive(Debug, Clone, Default)]
+pub struct ShmMapOpts {
+    /// Size in bytes of the shared memory to use, for devices which can't report their
+    /// size. Defaults to the size of the file or segment, and must not be larger.
+    pub size: Option<usize>,
+    /// Fault in the whole mapping up front, so that the first frames don't pay for it
+    pub populate: bool,
+    /// Ask for the mapping to be backed by transparent huge pages
+    pub huge_pages: bool,
+}
+
+/// The kvmfr device behind a mapping, if there is one. Dmabufs can only be exported on
+/// linux, so elsewhere there is nothing to keep hold of.
+#[cfg(target_os = "linux")]
+pub(crate) type DeviceHandle = Option<Arc<KVMFRDevice>>;
+#[cfg(not(target_os = "linux"))]
+pub(crate) type DeviceHandle = ();
+
+/// Shared memory mapped by [LGMPSource::open].
+pub(crate) struct OpenSource {
+    pub(crate) shm: Box<dyn ShmFileHandle>,
+    pub(crate) device: DeviceHandle,
+}
+
+impl LGMPSource {
+    /// Maps the shared memory described by this source.
+    pub(crate) fn open(&self, opts: &ShmMapOpts) -> Result<OpenSource, LGError> {
+        let source = match self {
+            LGMPSource::Flink(path) => {
+                SharedMem::open(shared_memory::ShmemConf::new().flink(path), opts)?
+            }
+            LGMPSource::OsId(id) => {
+                SharedMem::open(shared_memory::ShmemConf::new().os_id(id), opts)?
+            }
+            #[cfg(unix)]
+            LGMPSource::Device(path) => {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)?;
+                MappedFile::open(file, opts)?
+            }
+            #[cfg(unix)]
+            LGMPSource::Fd(fd) => MappedFile::open(File::from(fd.try_clone()?), opts)?,
+        };
+        Ok(source)
+    }
+}
+
+/// A `shared_memory` segment, of which only the first `size` bytes are used.
+pub(crate) struct SharedMem {
+    shm: shared_memory::Shmem,
+    size: usize,
+}
+
+//Shmem only holds the mapping and the handles needed to unmap it, neither of which are
+//tied to the thread that created them
+unsafe impl Send for SharedMem {}
+unsafe impl Sync for SharedMem {}
+
+impl SharedMem {
+    /// Wraps the whole of a segment, such as one created by a host.
+    pub(crate) fn new(shm: shared_memory::Shmem) -> SharedMem {
+        let size = shm.len();
+        SharedMem { shm, size }
+    }
+
+    fn open(conf: shared_memory::ShmemConf, opts: &ShmMapOpts) -> Result<OpenSource, LGError> {
+        let shm = conf.open()?;
+        let size = checked_size(opts, shm.len())?;
+        advise(shm.as_ptr(), size, opts);
+        Ok(OpenSource {
+            shm: Box::new(SharedMem { shm, size }),
+            device: Default::default(),
+        })
+    }
+}
+
+impl ShmFileHandle for SharedMem {
+    fn get_mut_ptr(&mut self) -> *mut std::ffi::c_void {
+        self.shm.as_ptr().cast()
+    }
+
+    fn get_size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Returns the size set in the options, or `available` if there is none.
+fn checked_size(opts: &ShmMapOpts, available: usize) -> Result<usize, LGError> {
+    match opts.size {
+        Some(size) if size > available => Err(LGError::SHMSizeTooLarge(size)),
+        Some(size) => Ok(size),
+        None => Ok(available),
+    }
+}
+
+/// Applies the hints set in the options to a mapping. These only affect performance, so
+/// failures are ignored.
+#[allow(unused_variables)]
+fn advise(ptr: *mut u8, len: usize, opts: &ShmMapOpts) {
+    #[cfg(target_os = "linux")]
+    {
+        let hints = [
+            (opts.populate, libc::MADV_WILLNEED),
+            (opts.huge_pages, libc::MADV_HUGEPAGE),
+        ];
+        for (_, advice) in hints.into_iter().filter(|(enabled, _)| *enabled) {
+            //Mappings always start on a page boundary
+            unsafe { libc::madvise(ptr.cast(), len, advice) };
+        }
+    }
+}
+
+/// A memory mapping of a kvmfr device or plain file.
+#[cfg(unix)]
+struct MappedFile {
+    mapped: memmap2::MmapRaw,
+}
+
+#[cfg(unix)]
+impl MappedFile {
+    fn map(file: &File, opts: &ShmMapOpts) -> Result<MappedFile, LGError> {
+        use std::os::unix::fs::FileTypeExt;
+
+        //Devices can't be checked against their size, which is the point of setting one
+        let size = match opts.size {
+            Some(size) if file.metadata()?.file_type().is_char_device() => size,
+            _ => checked_size(opts, file_size(file)?)?,
+        };
+        let mut map_opts = memmap2::MmapOptions::new();
+        map_opts.len(size);
+        if opts.populate {
+            map_opts.populate();
+        }
+        let mapped = map_opts.map_raw(file)?;
+        advise(mapped.as_mut_ptr(), size, opts);
+        Ok(MappedFile { mapped })
+    }
+
+    /// Maps a file, keeping hold of it if it is a kvmfr device so that dmabufs can be
+    /// exported later.
+    fn open(file: File, opts: &ShmMapOpts) -> Result<OpenSource, LGError> {
+        let mapped = MappedFile::map(&file, opts)?;
+        #[cfg(target_os = "linux")]
+        let device = {
+            use std::os::unix::fs::FileTypeExt;
+
+            let is_device = file.metadata()?.file_type().is_char_device();
+            is_device.then(|| {
+                Arc::new(KVMFRDevice::new(
+                    file,
+                    mapped.mapped.as_ptr(),
+                    mapped.mapped.len(),
+                ))
+            })
+        };
+        #[cfg(not(target_os = "linux"))]
+        let device = Default::default();
+        Ok(OpenSource {
+            shm: Box::new(mapped),
+            device,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl ShmFileHandle for MappedFile {
+    fn get_mut_ptr(&mut self) -> *mut std::ffi::c_void {
+        self.mapped.as_mut_ptr().cast()
+    }
+
+    fn get_size(&self) -> usize {
+        self.mapped.len()
+    }
+}
+
+/// Works out the size of the shared memory behind a file. Device nodes report a size of
+/// zero, so for these the kvmfr module is asked instead.
+#[cfg(unix)]
+fn file_size(file: &File) -> Result<usize, LGError> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = file.metadata()?;
+    if !metadata.file_type().is_char_device() {
+        return Ok(metadata.len() as usize);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        //The kvmfr module returns the device size directly from the ioctl
+        let size = unsafe { libc::ioctl(file.as_raw_fd(), KVMFR_DMABUF_GETSIZE as _) };
+        if size < 0 {
+            Err(std::io::Error::last_os_error())?
+        }
+        Ok(size as usize)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))?
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::os::fd::FromRawFd;
+
+    use super::*;
+
+    #[test]
+    fn maps_memfd() {
+        let fd = unsafe {
+            let raw = libc::memfd_create(c"lookinggla-rs-test".as_ptr(), 0);
+            assert!(raw >= 0);
+            OwnedFd::from_raw_fd(raw)
+        };
+        let file = File::from(fd);
+        file.set_len(64 * 1024).unwrap();
+
+        let source = LGMPSource::from(OwnedFd::from(file));
+        let mut shm = source.open(&ShmMapOpts::default()).unwrap().shm;
+        assert_eq!(shm.get_size(), 64 * 1024);
+        assert!(!shm.get_mut_ptr().is_null());
+    }
+
+    #[test]
+    fn applies_map_opts() {
+        let shm = shared_memory::ShmemConf::new()
+            .size(64 * 1024)
+            .create()
+            .unwrap();
+        let source = LGMPSource::OsId(shm.get_os_id().to_owned());
+        let opts = ShmMapOpts {
+            size: Some(16 * 1024),
+            populate: true,
+            huge_pages: true,
+        };
+        assert_eq!(source.open(&opts).unwrap().shm.get_size(), 16 * 1024);
+
+        let too_large = ShmMapOpts {
+            size: Some(1024 * 1024),
+            ..Default::default()
+        };
+        assert!(matches!(
+            source.open(&too_large),
+            Err(LGError::SHMSizeTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,136 @@
+use std::iter::FusedIterator;
+
+/// A rectangular region of a frame's pixel data, returned by
+/// [KVMFRFrameHandle::tiles](super::KVMFRFrameHandle::tiles).
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTile<'a> {
+    /// Column of the tile's left edge, in pixels
+    pub x: u32,
+    /// Row of the tile's top edge, in pixels
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from the start of the tile's first row to the end of its last
+    data: &'a [u8],
+    pitch: usize,
+    row_len: usize,
+}
+
+impl<'a> FrameTile<'a> {
+    /// Returns the pixel data of each row of the tile.
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &'a [u8]> + 'a {
+        rows(self.data, self.pitch, self.row_len, self.height)
+    }
+}
+
+/// Iterator over the tiles of a frame, in rows from the top left. Tiles on the right and
+/// bottom edges are smaller if the frame is not an exact multiple of the tile size.
+pub struct FrameTiles<'a> {
+    data: &'a [u8],
+    pitch: usize,
+    bpp: usize,
+    frame_width: u32,
+    frame_height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    x: u32,
+    y: u32,
+}
+
+impl<'a> FrameTiles<'a> {
+    /// Panics if either tile dimension is zero.
+    pub(super) fn new(
+        data: &'a [u8],
+        pitch: usize,
+        bpp: usize,
+        (frame_width, frame_height): (u32, u32),
+        (tile_width, tile_height): (u32, u32),
+    ) -> FrameTiles<'a> {
+        assert!(
+            tile_width > 0 && tile_height > 0,
+            "Tile size must be non-zero"
+        );
+        FrameTiles {
+            data,
+            pitch,
+            bpp,
+            frame_width,
+            frame_height,
+            tile_width,
+            tile_height,
+            x: 0,
+            y: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for FrameTiles<'a> {
+    type Item = FrameTile<'a>;
+
+    fn next(&mut self) -> Option<FrameTile<'a>> {
+        if self.x >= self.frame_width {
+            self.x = 0;
+            self.y = self.y.saturating_add(self.tile_height);
+        }
+        if self.y >= self.frame_height || self.frame_width == 0 {
+            return None;
+        }
+        let (x, y) = (self.x, self.y);
+        let width = self.tile_width.min(self.frame_width - x);
+        let height = self.tile_height.min(self.frame_height - y);
+        self.x = self.x.saturating_add(self.tile_width);
+
+        let row_len = width as usize * self.bpp;
+        let start = y as usize * self.pitch + x as usize * self.bpp;
+        let end = start + (height as usize - 1) * self.pitch + row_len;
+        Some(FrameTile {
+            x,
+            y,
+            width,
+            height,
+            data: &self.data[start..end],
+            pitch: self.pitch,
+            row_len,
+        })
+    }
+}
+
+impl FusedIterator for FrameTiles<'_> {}
+
+/// Splits pitched pixel data into rows of `row_len` bytes.
+pub(super) fn rows(
+    data: &[u8],
+    pitch: usize,
+    row_len: usize,
+    height: u32,
+) -> impl ExactSizeIterator<Item = &[u8]> {
+    (0..height as usize).map(move |row| &data[row * pitch..row * pitch + row_len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_frame_into_tiles() {
+        //A 3x3 frame of 1 byte pixels, with a pitch of 4
+        let data = [0, 1, 2, 9, 3, 4, 5, 9, 6, 7, 8, 9];
+        let tiles: Vec<_> = FrameTiles::new(&data, 4, 1, (3, 3), (2, 2)).collect();
+        assert_eq!(tiles.len(), 4);
+
+        let extents: Vec<_> = tiles
+            .iter()
+            .map(|tile| (tile.x, tile.y, tile.width, tile.height))
+            .collect();
+        assert_eq!(
+            extents,
+            [(0, 0, 2, 2), (2, 0, 1, 2), (0, 2, 2, 1), (2, 2, 1, 1)]
+        );
+
+        let rows = |tile: &FrameTile| tile.rows().map(<[u8]>::to_vec).collect::<Vec<_>>();
+        assert_eq!(rows(&tiles[0]), [vec![0, 1], vec![3, 4]]);
+        assert_eq!(rows(&tiles[1]), [vec![2], vec![5]]);
+        assert_eq!(rows(&tiles[2]), [vec![6, 7]]);
+        assert_eq!(rows(&tiles[3]), [vec![8]]);
+    }
+}
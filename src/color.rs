@@ -0,0 +1,238 @@
+//! Optional colour correction of frame data, using either a simple gamma curve or a 3D LUT
+//! loaded from a `.cube` file.
+use crate::{error::LGError, types::PixelFormat};
+
+/// Largest LUT size allowed by the `.cube` spec.
+const MAX_LUT_SIZE: usize = 256;
+
+/// A colour correction to apply to frames before presentation or recording.
+#[derive(Debug, Clone)]
+pub enum ColorCorrection {
+    /// Raise each channel to the given power, with channels normalised to 0-1
+    Gamma(f32),
+    /// Map each pixel through a 3D lookup table
+    Lut(Lut3D),
+}
+
+/// A 3D colour lookup table with trilinear interpolation.
+#[derive(Debug, Clone)]
+pub struct Lut3D {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// Output colours, with red changing fastest as in the `.cube` format
+    table: Vec<[f32; 3]>,
+}
+
+impl Lut3D {
+    /// Creates a LUT of the given size which leaves colours unchanged.
+    pub fn identity(size: usize) -> Lut3D {
+        let size = size.clamp(2, MAX_LUT_SIZE);
+        let scale = (size - 1) as f32;
+        let mut table = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    table.push([r as f32 / scale, g as f32 / scale, b as f32 / scale]);
+                }
+            }
+        }
+        Lut3D {
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table,
+        }
+    }
+
+    /// Parses a LUT from the contents of an Adobe/Resolve `.cube` file.
+    ///
+    /// Only 3D LUTs are supported.
+    pub fn parse_cube(text: &str) -> Result<Lut3D, LGError> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let first = parts.next().unwrap_or_default();
+            match first {
+                "TITLE" => (),
+                "LUT_3D_SIZE" => {
+                    let value = parts
+                        .next()
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .filter(|v| (2..=MAX_LUT_SIZE).contains(v))
+                        .ok_or_else(|| invalid_cube("invalid LUT_3D_SIZE"))?;
+                    size = Some(value);
+                }
+                "LUT_1D_SIZE" => Err(invalid_cube("1D LUTs are not supported"))?,
+                "DOMAIN_MIN" => domain_min = parse_triple(parts)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(parts)?,
+                _ if first.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    table.push(parse_triple(line.split_whitespace())?);
+                }
+                //Unknown keywords are allowed by the spec
+                _ => (),
+            }
+        }
+
+        let size = size.ok_or_else(|| invalid_cube("missing LUT_3D_SIZE"))?;
+        if table.len() != size * size * size {
+            Err(invalid_cube("wrong number of table entries"))?
+        }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            Err(invalid_cube("empty domain"))?
+        }
+
+        Ok(Lut3D {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[(b * self.size + g) * self.size + r]
+    }
+
+    /// Looks up a colour, with each channel normalised to 0-1.
+    pub fn sample(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let max = (self.size - 1) as f32;
+        let mut idx = [0; 3];
+        let mut frac = [0.0; 3];
+        for c in 0..3 {
+            let range = self.domain_max[c] - self.domain_min[c];
+            let pos = ((rgb[c] - self.domain_min[c]) / range).clamp(0.0, 1.0) * max;
+            //Keep one entry spare so that idx + 1 is always valid
+            idx[c] = (pos as usize).min(self.size - 2);
+            frac[c] = pos - idx[c] as f32;
+        }
+
+        //Blend the 8 surrounding entries, weighted by distance
+        let mut out = [0.0; 3];
+        for corner in 0..8usize {
+            let d = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight: f32 = (0..3)
+                .map(|c| if d[c] == 1 { frac[c] } else { 1.0 - frac[c] })
+                .product();
+            if weight == 0.0 {
+                continue;
+            }
+            let value = self.entry(idx[0] + d[0], idx[1] + d[1], idx[2] + d[2]);
+            for (out, value) in out.iter_mut().zip(value) {
+                *out += value * weight;
+            }
+        }
+        out
+    }
+}
+
+impl ColorCorrection {
+    /// Applies the correction in place to 8 bit per channel frame data.
+    ///
+    /// Returns an error for formats with more than 8 bits per channel. Any alpha or padding
+    /// bytes are left untouched. Panics if the rows in `data` are shorter than `width`.
+    pub fn apply(
+        &self,
+        data: &mut [u8],
+        width: u32,
+        height: u32,
+        pitch: u32,
+        format: PixelFormat,
+    ) -> Result<(), LGError> {
+        //Offsets of the red, green and blue bytes within each pixel
+        let (bpp, offsets) = match format {
+            PixelFormat::Bgra | PixelFormat::Bgr32 => (4, [2, 1, 0]),
+            PixelFormat::Rgba => (4, [0, 1, 2]),
+            PixelFormat::Rgb24 => (3, [0, 1, 2]),
+            format => Err(LGError::UnsupportedPixelFormat(format))?,
+        };
+        let row_len = width as usize * bpp;
+        let rows = data.chunks_mut(pitch as usize).take(height as usize);
+        let pixels = rows.flat_map(|row| row[..row_len].chunks_exact_mut(bpp));
+
+        match self {
+            ColorCorrection::Gamma(gamma) => {
+                //A gamma curve is the same for every channel, so reduce it to a byte table
+                let table: Vec<u8> = (0..=255u8)
+                    .map(|v| to_u8((f32::from(v) / 255.0).powf(*gamma)))
+                    .collect();
+                for px in pixels {
+                    for offset in offsets {
+                        px[offset] = table[px[offset] as usize];
+                    }
+                }
+            }
+            ColorCorrection::Lut(lut) => {
+                for px in pixels {
+                    let out = lut.sample(offsets.map(|o| f32::from(px[o]) / 255.0));
+                    for (offset, value) in offsets.into_iter().zip(out) {
+                        px[offset] = to_u8(value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn invalid_cube(reason: &str) -> LGError {
+    LGError::InvalidCubeLut(reason.to_string())
+}
+
+fn parse_triple<'a>(mut parts: impl Iterator<Item = &'a str>) -> Result<[f32; 3], LGError> {
+    let mut out = [0.0; 3];
+    for value in out.iter_mut() {
+        *value = parts
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| invalid_cube("expected three numbers"))?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_lut_preserves_pixels() {
+        let mut data: Vec<u8> = (0..=255u8).cycle().take(64 * 4).collect();
+        let original = data.clone();
+        ColorCorrection::Lut(Lut3D::identity(17))
+            .apply(&mut data, 8, 8, 32, PixelFormat::Bgra)
+            .unwrap();
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn gamma_changes_colour_but_not_alpha() {
+        let mut data = vec![128, 128, 128, 128];
+        ColorCorrection::Gamma(2.0)
+            .apply(&mut data, 1, 1, 4, PixelFormat::Rgba)
+            .unwrap();
+        assert_eq!(data, vec![64, 64, 64, 128]);
+    }
+
+    #[test]
+    fn parses_cube_file() {
+        let cube = "TITLE \"invert\"\n# comment\nLUT_3D_SIZE 2\n\
+            1 1 1\n0 1 1\n1 0 1\n0 0 1\n1 1 0\n0 1 0\n1 0 0\n0 0 0\n";
+        let lut = Lut3D::parse_cube(cube).unwrap();
+        assert_eq!(lut.sample([0.0, 0.0, 0.0]), [1.0, 1.0, 1.0]);
+        assert_eq!(lut.sample([1.0, 0.0, 1.0]), [0.0, 1.0, 0.0]);
+
+        assert!(Lut3D::parse_cube("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+    }
+}
@@ -0,0 +1,801 @@
+//! Conversion of frame data from any KVMFR pixel format into tightly packed RGBA, for
+//! consumers which only handle a single layout, or into planar YUV for video encoders,
+//! along with software rotation of frames.
+use crate::{
+    error::LGError,
+    parallel,
+    types::{PixelFormat, Rotation},
+};
+
+/// How HDR colour values above 1.0 are brought into range when converting to 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMap {
+    /// Clip values above 1.0, preserving SDR content exactly
+    #[default]
+    Clamp,
+    /// Compress highlights with the Reinhard curve `x / (1 + x)`
+    Reinhard,
+}
+
+/// Range of the values written by [to_nv12] and [to_i420].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YuvRange {
+    /// Luma in 16-235 and chroma in 16-240, as expected by most encoders
+    #[default]
+    Limited,
+    /// Luma and chroma use the full 0-255 range
+    Full,
+}
+
+impl YuvRange {
+    /// BT.709 coefficients for Y, U and V in 8 bit fixed point, along with the offset
+    /// added to luma.
+    fn coefficients(self) -> ([[i32; 3]; 3], i32) {
+        match self {
+            YuvRange::Limited => ([[47, 157, 16], [-25, -87, 112], [112, -102, -10]], 16),
+            YuvRange::Full => ([[54, 183, 19], [-29, -99, 128], [128, -116, -12]], 0),
+        }
+    }
+}
+
+/// Converts a frame to tightly packed 8 bit RGBA.
+///
+/// 10 bit frames are reduced to 8 bits per channel. 16 bit float frames hold linear
+/// scRGB, so are tone mapped and then encoded with the sRGB transfer function. Formats
+/// without alpha are given an opaque alpha channel.
+///
+/// 8 bit formats are converted using SIMD where the CPU supports it.
+///
+/// Panics if `data` is too small for a frame with the provided dimensions.
+pub fn to_rgba8(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+    tone_map: ToneMap,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    to_rgba8_into(data, width, height, pitch, format, tone_map, &mut out);
+    out
+}
+
+/// As [to_rgba8], but writes into an existing buffer, which is resized to fit the frame.
+/// This does not allocate once the buffer is large enough.
+pub fn to_rgba8_into(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+    tone_map: ToneMap,
+    out: &mut Vec<u8>,
+) {
+    out.clear();
+    out.resize(width as usize * height as usize * 4, 0);
+    let out_pitch = width as usize * 4;
+    to_rgba8_pitched(data, width, height, pitch, format, tone_map, out, out_pitch);
+}
+
+/// As [to_rgba8], but writes each row of the output `out_pitch` bytes after the previous
+/// one, leaving any padding at the end of rows untouched. This suits mapped GPU staging
+/// buffers, which often require rows to be aligned.
+///
+/// Panics if `out` is too small for the converted frame, or `out_pitch` is smaller than
+/// a row of RGBA pixels.
+#[allow(clippy::too_many_arguments)]
+pub fn to_rgba8_pitched(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+    tone_map: ToneMap,
+    out: &mut [u8],
+    out_pitch: usize,
+) {
+    let bpp = format.bytes_per_pixel() as usize;
+    let row_len = width as usize * bpp;
+    let out_row_len = width as usize * 4;
+    let pitch = pitch as usize;
+    if row_len == 0 || height == 0 || pitch == 0 {
+        return;
+    }
+    assert!(
+        out_pitch >= out_row_len,
+        "Output pitch is smaller than a row"
+    );
+    let rows = (height as usize)
+        .min(data.len().div_ceil(pitch))
+        .min(out.len().div_ceil(out_pitch));
+    let out_len = out.len().min(rows * out_pitch);
+    parallel::for_each_band(
+        &mut out[..out_len],
+        out_pitch,
+        parallel::min_band_chunks(out_pitch),
+        |first_row, band| {
+            for (i, dst) in band.chunks_mut(out_pitch).enumerate() {
+                let src = &data[(first_row + i) * pitch..];
+                row_to_rgba8(&src[..row_len], &mut dst[..out_row_len], format, tone_map);
+            }
+        },
+    );
+}
+
+/// Converts a single row of pixels to 8 bit RGBA for [to_rgba8_pitched].
+fn row_to_rgba8(src: &[u8], dst: &mut [u8], format: PixelFormat, tone_map: ToneMap) {
+    if !format.is_hdr() {
+        convert_row(src, dst, format);
+        return;
+    }
+    let bpp = format.bytes_per_pixel() as usize;
+    for (px, dst) in src.chunks_exact(bpp).zip(dst.chunks_exact_mut(4)) {
+        let rgba = match format {
+            PixelFormat::Rgba10 => {
+                let [r, g, b, a] = unpack_rgba10(px);
+                [
+                    (r >> 2) as u8,
+                    (g >> 2) as u8,
+                    (b >> 2) as u8,
+                    (a * 85) as u8,
+                ]
+            }
+            PixelFormat::Rgba16F => {
+                let [r, g, b, a] = unpack_rgba16f(px);
+                let encode = |v: f32| to_u8(srgb_encode(apply_tone_map(v, tone_map)));
+                [encode(r), encode(g), encode(b), to_u8(a)]
+            }
+            _ => unreachable!("8 bit formats are handled above"),
+        };
+        dst.copy_from_slice(&rgba);
+    }
+}
+
+/// Converts an HDR frame to tightly packed RGBA with each channel stored as the bits of
+/// an IEEE 754 half precision float, suitable for uploading to a 16 bit float texture.
+///
+/// 16 bit float frames are passed through unchanged. 10 bit frames are normalised so that
+/// the largest value is 1.0, without linearising. Returns an error for 8 bit formats.
+///
+/// Panics if `data` is too small for a frame with the provided dimensions.
+pub fn to_rgba16f(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+) -> Result<Vec<u16>, LGError> {
+    if !format.is_hdr() {
+        Err(LGError::UnsupportedPixelFormat(format))?
+    }
+    let mut out = Vec::with_capacity(width as usize * height as usize * 4);
+    for px in pixels(data, width, height, pitch, format) {
+        match format {
+            PixelFormat::Rgba16F => {
+                out.extend(px.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])))
+            }
+            _ => {
+                let [r, g, b, a] = unpack_rgba10(px);
+                let scale = |v: u32, max: f32| f32_to_f16(v as f32 / max);
+                out.extend([
+                    scale(r, 1023.0),
+                    scale(g, 1023.0),
+                    scale(b, 1023.0),
+                    scale(a, 3.0),
+                ]);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Rotates tightly packed pixel data clockwise, returning the rotated data along with its
+/// new width and height.
+///
+/// Passing the frame's [Rotation](crate::types::FrameInfo::rotation) gives the orientation
+/// in which the Looking Glass client displays it, for renderers which can't rotate when
+/// drawing. This works with any format, such as the output of [to_rgba8].
+///
+/// Frames with no pixels, or no bytes per pixel, give an empty buffer. Panics if `data` is
+/// smaller than `width * height * bytes_per_pixel`.
+pub fn rotate(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bytes_per_pixel: u32,
+    rotation: Rotation,
+) -> (Vec<u8>, u32, u32) {
+    let (w, h, bpp) = (width as usize, height as usize, bytes_per_pixel as usize);
+    let data = &data[..w * h * bpp];
+    if rotation == Rotation::Rot0 {
+        return (data.to_vec(), width, height);
+    }
+    let (out_w, out_h) = match rotation.swaps_dimensions() {
+        true => (height, width),
+        false => (width, height),
+    };
+    if data.is_empty() {
+        return (Vec::new(), out_w, out_h);
+    }
+
+    let mut out = vec![0; data.len()];
+    for (i, px) in data.chunks_exact(bpp).enumerate() {
+        let (x, y) = (i % w, i / w);
+        //Position of the source pixel in the output
+        let (ox, oy) = match rotation {
+            Rotation::Rot0 => (x, y),
+            Rotation::Rot90 => (h - 1 - y, x),
+            Rotation::Rot180 => (w - 1 - x, h - 1 - y),
+            Rotation::Rot270 => (y, w - 1 - x),
+        };
+        let offset = (oy * out_w as usize + ox) * bpp;
+        out[offset..offset + bpp].copy_from_slice(px);
+    }
+    (out, out_w, out_h)
+}
+
+/// Converts an 8 bit frame to NV12 using BT.709 coefficients, writing luma into `y` and
+/// interleaved U and V samples into `uv`. Each row of a plane starts `pitch` bytes after
+/// the previous one, and padding at the end of rows is left untouched.
+///
+/// Chroma is averaged over each 2x2 block of pixels, so the chroma plane has half the
+/// width and height of the frame, rounded up. Returns an error for HDR formats, or if
+/// either plane is too small.
+///
+/// Panics if `data` is too small for a frame with the provided dimensions.
+#[allow(clippy::too_many_arguments)]
+pub fn to_nv12(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+    range: YuvRange,
+    y: &mut [u8],
+    y_pitch: usize,
+    uv: &mut [u8],
+    uv_pitch: usize,
+) -> Result<(), LGError> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    check_plane(uv, uv_pitch, chroma_width as usize * 2, chroma_height)?;
+    to_yuv420(
+        data,
+        (width, height, pitch),
+        format,
+        range,
+        y,
+        y_pitch,
+        |x, row, [u, v]| {
+            let offset = row * uv_pitch + x * 2;
+            uv[offset..offset + 2].copy_from_slice(&[u, v]);
+        },
+    )
+}
+
+/// As [to_nv12], but writes U and V samples into separate planes, as in I420.
+#[allow(clippy::too_many_arguments)]
+pub fn to_i420(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+    range: YuvRange,
+    y: &mut [u8],
+    y_pitch: usize,
+    u: &mut [u8],
+    u_pitch: usize,
+    v: &mut [u8],
+    v_pitch: usize,
+) -> Result<(), LGError> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    check_plane(u, u_pitch, chroma_width as usize, chroma_height)?;
+    check_plane(v, v_pitch, chroma_width as usize, chroma_height)?;
+    to_yuv420(
+        data,
+        (width, height, pitch),
+        format,
+        range,
+        y,
+        y_pitch,
+        |x, row, [u_val, v_val]| {
+            u[row * u_pitch + x] = u_val;
+            v[row * v_pitch + x] = v_val;
+        },
+    )
+}
+
+/// Writes the luma plane of a frame, and passes the chroma of each 2x2 block of pixels to
+/// `write_chroma` along with the block's position in the chroma plane.
+fn to_yuv420(
+    data: &[u8],
+    (width, height, pitch): (u32, u32, u32),
+    format: PixelFormat,
+    range: YuvRange,
+    y: &mut [u8],
+    y_pitch: usize,
+    mut write_chroma: impl FnMut(usize, usize, [u8; 2]),
+) -> Result<(), LGError> {
+    let [r, g, b] = match format {
+        PixelFormat::Bgra | PixelFormat::Bgr32 => [2, 1, 0],
+        PixelFormat::Rgba | PixelFormat::Rgb24 => [0, 1, 2],
+        _ => Err(LGError::UnsupportedPixelFormat(format))?,
+    };
+    check_plane(y, y_pitch, width as usize, height)?;
+    let ([y_coef, u_coef, v_coef], y_offset) = range.coefficients();
+    let apply = |coef: [i32; 3], rgb: [i32; 3]| {
+        (coef[0] * rgb[0] + coef[1] * rgb[1] + coef[2] * rgb[2] + 128) >> 8
+    };
+
+    let bpp = format.bytes_per_pixel() as usize;
+    let (width, height, pitch) = (width as usize, height as usize, pitch as usize);
+    let rgb_at = |x: usize, row: usize| {
+        let px = &data[row * pitch + x * bpp..][..bpp];
+        [px[r], px[g], px[b]].map(i32::from)
+    };
+    for row in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            let mut sum = [0; 3];
+            let mut count = 0;
+            for (px_x, px_row) in [(x, row), (x + 1, row), (x, row + 1), (x + 1, row + 1)] {
+                if px_x >= width || px_row >= height {
+                    continue;
+                }
+                let rgb = rgb_at(px_x, px_row);
+                y[px_row * y_pitch + px_x] = (apply(y_coef, rgb) + y_offset).clamp(0, 255) as u8;
+                sum = [sum[0] + rgb[0], sum[1] + rgb[1], sum[2] + rgb[2]];
+                count += 1;
+            }
+            let avg = sum.map(|c| (c + count / 2) / count);
+            let chroma = [u_coef, v_coef].map(|coef| (apply(coef, avg) + 128).clamp(0, 255) as u8);
+            write_chroma(x / 2, row / 2, chroma);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a pitched plane can hold `height` rows of `row_len` bytes.
+fn check_plane(plane: &[u8], pitch: usize, row_len: usize, height: u32) -> Result<(), LGError> {
+    let required = match height {
+        0 => 0,
+        _ => pitch * (height as usize - 1) + row_len,
+    };
+    if pitch < row_len || plane.len() < required {
+        Err(LGError::DestinationTooSmall)?
+    }
+    Ok(())
+}
+
+/// Converts a row of pixels in an 8 bit format to RGBA, using the fastest available
+/// implementation for as much of the row as possible.
+fn convert_row(src: &[u8], dst: &mut [u8], format: PixelFormat) {
+    let done = match format {
+        PixelFormat::Rgba => {
+            dst.copy_from_slice(src);
+            return;
+        }
+        PixelFormat::Bgra | PixelFormat::Bgr32 => {
+            simd::swap_red_blue(src, dst, format == PixelFormat::Bgr32)
+        }
+        _ => simd::rgb24_to_rgba(src, dst),
+    };
+
+    //Scalar fallback for whatever is left over
+    let bpp = format.bytes_per_pixel() as usize;
+    let src = src[done * bpp..].chunks_exact(bpp);
+    for (px, out) in src.zip(dst[done * 4..].chunks_exact_mut(4)) {
+        let rgba = match format {
+            PixelFormat::Bgra => [px[2], px[1], px[0], px[3]],
+            PixelFormat::Bgr32 => [px[2], px[1], px[0], 0xff],
+            _ => [px[0], px[1], px[2], 0xff],
+        };
+        out.copy_from_slice(&rgba);
+    }
+}
+
+/// SIMD implementations of the 8 bit conversions. Each returns the number of pixels it
+/// converted from the start of the row, leaving the rest for the scalar fallback.
+mod simd {
+    /// Swaps the red and blue channels of 4 byte pixels, also setting alpha to opaque if
+    /// `opaque` is set.
+    pub fn swap_red_blue(src: &[u8], dst: &mut [u8], opaque: bool) -> usize {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return unsafe { x86::swap_red_blue_avx2(src, dst, opaque) };
+            }
+            if is_x86_feature_detected!("ssse3") {
+                return unsafe { x86::swap_red_blue_ssse3(src, dst, opaque) };
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            return unsafe { neon::swap_red_blue(src, dst, opaque) };
+        }
+        #[allow(unreachable_code)]
+        {
+            let _ = (src, dst, opaque);
+            0
+        }
+    }
+
+    /// Unpacks 3 byte RGB pixels into 4 byte RGBA pixels with opaque alpha.
+    pub fn rgb24_to_rgba(src: &[u8], dst: &mut [u8]) -> usize {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("ssse3") {
+                return unsafe { x86::rgb24_to_rgba_ssse3(src, dst) };
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            return unsafe { neon::rgb24_to_rgba(src, dst) };
+        }
+        #[allow(unreachable_code)]
+        {
+            let _ = (src, dst);
+            0
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    mod x86 {
+        use std::arch::x86_64::*;
+
+        /// Alpha bits of a little endian RGBA pixel
+        const ALPHA: i32 = 0xff00_0000u32 as i32;
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn swap_red_blue_avx2(src: &[u8], dst: &mut [u8], opaque: bool) -> usize {
+            let chunks = src.len().min(dst.len()) / 32;
+            //Shuffles operate within each 128 bit lane, so the mask is repeated
+            let mask = _mm256_setr_epi8(
+                2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15, 2, 1, 0, 3, 6, 5, 4, 7, 10,
+                9, 8, 11, 14, 13, 12, 15,
+            );
+            let alpha = _mm256_set1_epi32(if opaque { ALPHA } else { 0 });
+            for i in 0..chunks {
+                let v = _mm256_loadu_si256(src.as_ptr().add(i * 32).cast());
+                let v = _mm256_or_si256(_mm256_shuffle_epi8(v, mask), alpha);
+                _mm256_storeu_si256(dst.as_mut_ptr().add(i * 32).cast(), v);
+            }
+            chunks * 8
+        }
+
+        #[target_feature(enable = "ssse3")]
+        pub unsafe fn swap_red_blue_ssse3(src: &[u8], dst: &mut [u8], opaque: bool) -> usize {
+            let chunks = src.len().min(dst.len()) / 16;
+            let mask = _mm_setr_epi8(2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15);
+            let alpha = _mm_set1_epi32(if opaque { ALPHA } else { 0 });
+            for i in 0..chunks {
+                let v = _mm_loadu_si128(src.as_ptr().add(i * 16).cast());
+                let v = _mm_or_si128(_mm_shuffle_epi8(v, mask), alpha);
+                _mm_storeu_si128(dst.as_mut_ptr().add(i * 16).cast(), v);
+            }
+            chunks * 4
+        }
+
+        #[target_feature(enable = "ssse3")]
+        pub unsafe fn rgb24_to_rgba_ssse3(src: &[u8], dst: &mut [u8]) -> usize {
+            //Each step reads 16 bytes but only uses the first 12, so stop early enough
+            //that the read stays within the row
+            let chunks = (src.len().saturating_sub(4) / 12).min(dst.len() / 16);
+            let mask = _mm_setr_epi8(0, 1, 2, -1, 3, 4, 5, -1, 6, 7, 8, -1, 9, 10, 11, -1);
+            let alpha = _mm_set1_epi32(ALPHA);
+            for i in 0..chunks {
+                let v = _mm_loadu_si128(src.as_ptr().add(i * 12).cast());
+                let v = _mm_or_si128(_mm_shuffle_epi8(v, mask), alpha);
+                _mm_storeu_si128(dst.as_mut_ptr().add(i * 16).cast(), v);
+            }
+            chunks * 4
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    mod neon {
+        use std::arch::aarch64::*;
+
+        pub unsafe fn swap_red_blue(src: &[u8], dst: &mut [u8], opaque: bool) -> usize {
+            let chunks = src.len().min(dst.len()) / 64;
+            for i in 0..chunks {
+                let v = vld4q_u8(src.as_ptr().add(i * 64));
+                let alpha = if opaque { vdupq_n_u8(0xff) } else { v.3 };
+                vst4q_u8(
+                    dst.as_mut_ptr().add(i * 64),
+                    uint8x16x4_t(v.2, v.1, v.0, alpha),
+                );
+            }
+            chunks * 16
+        }
+
+        pub unsafe fn rgb24_to_rgba(src: &[u8], dst: &mut [u8]) -> usize {
+            let chunks = (src.len() / 48).min(dst.len() / 64);
+            for i in 0..chunks {
+                let v = vld3q_u8(src.as_ptr().add(i * 48));
+                vst4q_u8(
+                    dst.as_mut_ptr().add(i * 64),
+                    uint8x16x4_t(v.0, v.1, v.2, vdupq_n_u8(0xff)),
+                );
+            }
+            chunks * 16
+        }
+    }
+}
+
+/// Iterates over the pixels of a frame, skipping any padding at the end of each row.
+fn pixels(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+) -> impl Iterator<Item = &[u8]> {
+    let bpp = format.bytes_per_pixel() as usize;
+    let row_len = width as usize * bpp;
+    data.chunks(pitch as usize)
+        .take(height as usize)
+        .flat_map(move |row| row[..row_len].chunks_exact(bpp))
+}
+
+/// Splits a little endian `R10G10B10A2` pixel into its channels.
+fn unpack_rgba10(px: &[u8]) -> [u32; 4] {
+    let v = u32::from_le_bytes([px[0], px[1], px[2], px[3]]);
+    [v & 0x3ff, (v >> 10) & 0x3ff, (v >> 20) & 0x3ff, v >> 30]
+}
+
+/// Splits a pixel of four little endian half floats into its channels.
+fn unpack_rgba16f(px: &[u8]) -> [f32; 4] {
+    let channel = |i: usize| f16_to_f32(u16::from_le_bytes([px[i * 2], px[i * 2 + 1]]));
+    [channel(0), channel(1), channel(2), channel(3)]
+}
+
+fn apply_tone_map(value: f32, tone_map: ToneMap) -> f32 {
+    let value = value.max(0.0);
+    match tone_map {
+        ToneMap::Clamp => value.min(1.0),
+        ToneMap::Reinhard => value / (1.0 + value),
+    }
+}
+
+fn srgb_encode(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Converts the bits of a half precision float to an f32.
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = (bits >> 10) & 0x1f;
+    let mantissa = f32::from(bits & 0x3ff);
+    match exp {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(i32::from(exp) - 15),
+    }
+}
+
+/// Converts an f32 to the bits of a half precision float, rounding towards zero. Only
+/// needs to handle the range 0-1 used by [to_rgba16f].
+fn f32_to_f16(value: f32) -> u16 {
+    let value = value.clamp(0.0, 1.0);
+    if value < 2f32.powi(-14) {
+        //Subnormal
+        return (value * 2f32.powi(24)) as u16;
+    }
+    let bits = value.to_bits();
+    let exp = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    ((exp as u16) << 10) | ((bits >> 13) & 0x3ff) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_sdr_formats() {
+        let bgra = [1, 2, 3, 4];
+        assert_eq!(
+            to_rgba8(&bgra, 1, 1, 4, PixelFormat::Bgra, ToneMap::Clamp),
+            [3, 2, 1, 4]
+        );
+        //Padding at the end of each row is skipped
+        let rgb = [1, 2, 3, 0, 0, 4, 5, 6, 0, 0];
+        assert_eq!(
+            to_rgba8(&rgb, 1, 2, 5, PixelFormat::Rgb24, ToneMap::Clamp),
+            [1, 2, 3, 0xff, 4, 5, 6, 0xff]
+        );
+        assert!(to_rgba8(&[], 0, 0, 0, PixelFormat::Bgra, ToneMap::Clamp).is_empty());
+    }
+
+    #[test]
+    fn converts_into_pitched_rows() {
+        //Two rows of two BGRA pixels, each with 4 bytes of padding
+        let src = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+        ];
+        let mut out = [0xaa; 2 * 12];
+        to_rgba8_pitched(
+            &src,
+            2,
+            2,
+            12,
+            PixelFormat::Bgra,
+            ToneMap::Clamp,
+            &mut out,
+            12,
+        );
+        assert_eq!(
+            out,
+            [
+                3, 2, 1, 4, 7, 6, 5, 8, 0xaa, 0xaa, 0xaa, 0xaa, 11, 10, 9, 12, 15, 14, 13, 16,
+                0xaa, 0xaa, 0xaa, 0xaa
+            ]
+        );
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        //Long enough for every SIMD width, with a remainder for the scalar fallback
+        let src: Vec<u8> = (0..=255u8).cycle().take(67 * 4).collect();
+        for format in [PixelFormat::Bgra, PixelFormat::Bgr32, PixelFormat::Rgb24] {
+            let bpp = format.bytes_per_pixel() as usize;
+            let width = src.len() / bpp;
+            let out = to_rgba8(
+                &src,
+                width as u32,
+                1,
+                src.len() as u32,
+                format,
+                ToneMap::Clamp,
+            );
+            let expected: Vec<u8> = src
+                .chunks_exact(bpp)
+                .flat_map(|px| match format {
+                    PixelFormat::Bgra => [px[2], px[1], px[0], px[3]],
+                    PixelFormat::Bgr32 => [px[2], px[1], px[0], 0xff],
+                    _ => [px[0], px[1], px[2], 0xff],
+                })
+                .collect();
+            assert_eq!(out, expected, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn converts_hdr_formats() {
+        let rgba10 = (1023 | (512 << 10) | (3 << 30) as u32).to_le_bytes();
+        assert_eq!(
+            to_rgba8(&rgba10, 1, 1, 4, PixelFormat::Rgba10, ToneMap::Clamp),
+            [255, 128, 0, 255]
+        );
+
+        //1.0, 4.0, 0.0, 1.0
+        let rgba16f: Vec<u8> = [0x3c00u16, 0x4400, 0, 0x3c00]
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        assert_eq!(
+            to_rgba8(&rgba16f, 1, 1, 8, PixelFormat::Rgba16F, ToneMap::Clamp),
+            [255, 255, 0, 255]
+        );
+        let mapped = to_rgba8(&rgba16f, 1, 1, 8, PixelFormat::Rgba16F, ToneMap::Reinhard);
+        assert!(mapped[0] < mapped[1] && mapped[1] < 255);
+
+        assert_eq!(
+            to_rgba16f(&rgba16f, 1, 1, 8, PixelFormat::Rgba16F).unwrap(),
+            [0x3c00, 0x4400, 0, 0x3c00]
+        );
+        assert_eq!(
+            to_rgba16f(&rgba10, 1, 1, 4, PixelFormat::Rgba10).unwrap()[0],
+            0x3c00
+        );
+        assert!(to_rgba16f(&[0; 4], 1, 1, 4, PixelFormat::Bgra).is_err());
+    }
+
+    #[test]
+    fn converts_to_yuv() {
+        //Top left block is black and white, the rest is red, with padding after each row
+        let white = [0xff; 4];
+        let red = [0, 0, 0xff, 0xff];
+        let mut src = Vec::new();
+        for row in [[[0; 4], white, red], [white, [0; 4], red], [red, red, red]] {
+            src.extend(row.concat());
+            src.extend([0; 4]);
+        }
+
+        let mut y = [0xaa; 3 * 4];
+        let mut uv = [0xaa; 2 * 4];
+        to_nv12(
+            &src,
+            3,
+            3,
+            16,
+            PixelFormat::Bgra,
+            YuvRange::Limited,
+            &mut y,
+            4,
+            &mut uv,
+            4,
+        )
+        .unwrap();
+        assert_eq!(y, [16, 235, 63, 0xaa, 235, 16, 63, 0xaa, 63, 63, 63, 0xaa]);
+        assert_eq!(uv, [128, 128, 103, 240, 103, 240, 103, 240]);
+
+        let (mut u, mut v) = ([0; 4], [0; 4]);
+        to_i420(
+            &src,
+            3,
+            3,
+            16,
+            PixelFormat::Bgra,
+            YuvRange::Full,
+            &mut y,
+            4,
+            &mut u,
+            2,
+            &mut v,
+            2,
+        )
+        .unwrap();
+        assert_eq!(y[..3], [0, 255, 54]);
+        assert_eq!((u, v), ([128, 99, 99, 99], [128, 255, 255, 255]));
+
+        let (mut small, mut uv) = ([0; 8], [0; 8]);
+        assert!(to_nv12(
+            &src,
+            3,
+            3,
+            16,
+            PixelFormat::Bgra,
+            YuvRange::Full,
+            &mut small,
+            3,
+            &mut uv,
+            4
+        )
+        .is_err());
+        assert!(to_nv12(
+            &[0; 8],
+            1,
+            1,
+            8,
+            PixelFormat::Rgba16F,
+            YuvRange::Full,
+            &mut y,
+            4,
+            &mut uv,
+            4
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rotates_clockwise() {
+        //1 2 3
+        //4 5 6
+        let data = [1, 2, 3, 4, 5, 6];
+        assert_eq!(
+            rotate(&data, 3, 2, 1, Rotation::Rot90),
+            (vec![4, 1, 5, 2, 6, 3], 2, 3)
+        );
+        assert_eq!(
+            rotate(&data, 3, 2, 1, Rotation::Rot180),
+            (vec![6, 5, 4, 3, 2, 1], 3, 2)
+        );
+        assert_eq!(
+            rotate(&data, 3, 2, 1, Rotation::Rot270),
+            (vec![3, 6, 2, 5, 1, 4], 2, 3)
+        );
+        let (two_bytes, _, _) = rotate(&[1, 1, 2, 2], 2, 1, 2, Rotation::Rot180);
+        assert_eq!(two_bytes, [2, 2, 1, 1]);
+        assert_eq!(rotate(&[1, 2], 1, 1, 0, Rotation::Rot90), (vec![], 1, 1));
+        assert_eq!(rotate(&[], 0, 2, 4, Rotation::Rot270), (vec![], 2, 0));
+    }
+}
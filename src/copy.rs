@@ -0,0 +1,182 @@
+//! Tunable copying of frame data to and from shared memory.
+use std::time::{Duration, Instant};
+
+/// Size of a CPU cache line, which small chunk sizes are rounded to.
+pub const CACHE_LINE_SIZE: usize = 64;
+/// Size of a memory page, which large chunk sizes are rounded to.
+pub const PAGE_SIZE: usize = 4096;
+/// Default chunk size, matching the chunk size used by Looking Glass itself.
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+/// Chunk sizes tried by [CopyStrategy::calibrate].
+const CALIBRATION_CHUNK_SIZES: [usize; 5] = [
+    64 * 1024,
+    256 * 1024,
+    1024 * 1024,
+    4 * 1024 * 1024,
+    16 * 1024 * 1024,
+];
+/// Amount of data copied for each chunk size tried during calibration.
+const CALIBRATION_SAMPLE_SIZE: usize = 32 * 1024 * 1024;
+
+/// Describes how large copies should be broken up.
+///
+/// Rather than one large memcpy, data is copied in chunks. On the host side this also
+/// allows readers to begin processing the start of a frame whilst the rest is still being
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyStrategy {
+    chunk_size: usize,
+}
+
+impl Default for CopyStrategy {
+    fn default() -> Self {
+        CopyStrategy {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+impl CopyStrategy {
+    /// Creates a strategy which copies in chunks of roughly `chunk_size` bytes. The size is
+    /// rounded up to a whole number of pages, or of cache lines if smaller than a page.
+    pub fn with_chunk_size(chunk_size: usize) -> CopyStrategy {
+        let align = if chunk_size >= PAGE_SIZE {
+            PAGE_SIZE
+        } else {
+            CACHE_LINE_SIZE
+        };
+        CopyStrategy {
+            chunk_size: chunk_size.max(1).next_multiple_of(align),
+        }
+    }
+
+    /// Returns the chunk size used by this strategy in bytes.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Picks a chunk size by timing copies out of `src` with a range of candidate sizes,
+    /// and returning the fastest.
+    ///
+    /// `src` should be the memory which will be copied from, such as the pixel data of a
+    /// frame which is still in shared memory, as copies from ordinary heap memory perform
+    /// differently. Up to 32MiB of it is copied twice for each size, so this should only be
+    /// done once at startup. The default strategy is returned if `src` is empty.
+    pub fn calibrate(src: &[u8]) -> CopyStrategy {
+        if src.is_empty() {
+            return CopyStrategy::default();
+        }
+        let src = &src[..src.len().min(CALIBRATION_SAMPLE_SIZE)];
+        let mut dst = vec![0u8; src.len()];
+
+        let mut best = (Duration::MAX, CopyStrategy::default());
+        for chunk_size in CALIBRATION_CHUNK_SIZES {
+            let strategy = CopyStrategy::with_chunk_size(chunk_size);
+            //Warm up the caches and page tables before timing
+            strategy.copy(src, &mut dst);
+            let started = Instant::now();
+            strategy.copy(src, &mut dst);
+            let elapsed = started.elapsed();
+            if elapsed < best.0 {
+                best = (elapsed, strategy);
+            }
+        }
+
+        best.1
+    }
+
+    /// Copies `src` into the start of `dst`.
+    ///
+    /// Panics if `dst` is shorter than `src`.
+    pub fn copy(&self, src: &[u8], dst: &mut [u8]) {
+        let dst = &mut dst[..src.len()];
+        for (src, dst) in src
+            .chunks(self.chunk_size)
+            .zip(dst.chunks_mut(self.chunk_size))
+        {
+            dst.copy_from_slice(src);
+        }
+    }
+
+    /// Copies `src` into a new vector.
+    #[cfg_attr(not(feature = "lgmp"), allow(dead_code))]
+    pub(crate) fn copy_to_vec(&self, src: &[u8]) -> Vec<u8> {
+        let mut dst = Vec::with_capacity(src.len());
+        self.extend_vec(&mut dst, src);
+        dst
+    }
+
+    /// Copies `src` onto the end of `dst`.
+    #[cfg_attr(not(feature = "lgmp"), allow(dead_code))]
+    pub(crate) fn extend_vec(&self, dst: &mut Vec<u8>, src: &[u8]) {
+        dst.reserve(src.len());
+        for chunk in src.chunks(self.chunk_size) {
+            dst.extend_from_slice(chunk);
+        }
+    }
+
+    /// Copies `src` to `dst`, calling `progress` with the total number of bytes written
+    /// after each chunk.
+    ///
+    /// # Safety
+    /// `dst` must be valid for writes of `src.len()` bytes, and must not overlap `src`.
+    #[cfg_attr(not(feature = "lgmp"), allow(dead_code))]
+    pub(crate) unsafe fn copy_to_ptr(
+        &self,
+        src: &[u8],
+        dst: *mut u8,
+        mut progress: impl FnMut(usize),
+    ) {
+        let mut written = 0;
+        for chunk in src.chunks(self.chunk_size) {
+            std::ptr::copy_nonoverlapping(chunk.as_ptr(), dst.add(written), chunk.len());
+            written += chunk.len();
+            progress(written);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_size_is_aligned() {
+        assert_eq!(
+            CopyStrategy::with_chunk_size(0).chunk_size(),
+            CACHE_LINE_SIZE
+        );
+        assert_eq!(CopyStrategy::with_chunk_size(100).chunk_size(), 128);
+        assert_eq!(
+            CopyStrategy::with_chunk_size(PAGE_SIZE + 1).chunk_size(),
+            2 * PAGE_SIZE
+        );
+    }
+
+    #[test]
+    fn copies_partial_chunks() {
+        let src: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut dst = vec![0u8; 1100];
+        let strategy = CopyStrategy::with_chunk_size(CACHE_LINE_SIZE);
+        strategy.copy(&src, &mut dst);
+        assert_eq!(&dst[..1000], &src[..]);
+        assert!(dst[1000..].iter().all(|b| *b == 0));
+
+        let mut dst = vec![0u8; 1000];
+        let mut progress = Vec::new();
+        unsafe { strategy.copy_to_ptr(&src, dst.as_mut_ptr(), |n| progress.push(n)) };
+        assert_eq!(dst, src);
+        assert_eq!(progress.len(), 16);
+        assert_eq!(progress.last(), Some(&1000));
+
+        assert_eq!(strategy.copy_to_vec(&src), src);
+    }
+
+    #[test]
+    fn calibrates_on_provided_memory() {
+        assert_eq!(CopyStrategy::calibrate(&[]), CopyStrategy::default());
+        let src = vec![0xa5u8; 4 * 1024 * 1024];
+        let strategy = CopyStrategy::calibrate(&src);
+        assert!(CALIBRATION_CHUNK_SIZES.contains(&strategy.chunk_size()));
+    }
+}
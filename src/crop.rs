@@ -0,0 +1,223 @@
+//! Detection of letterbox and pillarbox black bars in frames, so that only the region
+//! containing content needs to be displayed or scaled.
+use crate::{error::LGError, types::PixelFormat};
+
+/// Options controlling how black bars are detected.
+#[derive(Debug, Clone)]
+pub struct CropDetectorOpts {
+    /// Largest value of any colour channel for a pixel to still be considered black
+    pub threshold: u8,
+    /// Number of consecutive frames a new crop must be detected in before it is suggested,
+    /// so that dark scenes don't cause the crop to jump around.
+    pub hysteresis: u32,
+}
+
+impl Default for CropDetectorOpts {
+    fn default() -> Self {
+        CropDetectorOpts {
+            threshold: 16,
+            hysteresis: 30,
+        }
+    }
+}
+
+/// A region of a frame, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropRect {
+    /// Returns a rect covering the whole of a frame with the given dimensions.
+    pub fn full(width: u32, height: u32) -> CropRect {
+        CropRect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+}
+
+/// Tracks the black bars around the content of successive frames.
+pub struct CropDetector {
+    opts: CropDetectorOpts,
+    /// Dimensions of the previous frame
+    prev_size: Option<(u32, u32)>,
+    /// Crop currently being suggested
+    current: Option<CropRect>,
+    /// Crop which differs from the current one, along with how many frames in a row it
+    /// has been detected for
+    candidate: Option<(CropRect, u32)>,
+}
+
+impl CropDetector {
+    pub fn new(opts: CropDetectorOpts) -> CropDetector {
+        CropDetector {
+            opts,
+            prev_size: None,
+            current: None,
+            candidate: None,
+        }
+    }
+
+    /// Forgets all previous frames, so that the whole frame is suggested until a new crop
+    /// has been stable for the hysteresis period.
+    pub fn reset(&mut self) {
+        self.prev_size = None;
+        self.current = None;
+        self.candidate = None;
+    }
+
+    /// Returns the crop currently being suggested, or None if no frames have been checked.
+    pub fn current(&self) -> Option<CropRect> {
+        self.current
+    }
+
+    /// Checks a frame for black bars, returning the suggested crop.
+    ///
+    /// The suggestion starts out as the whole frame, and whenever the frame size changes is
+    /// reset back to it. Frames which are entirely black leave the suggestion unchanged.
+    ///
+    /// Returns an error for formats with more than 8 bits per channel. Panics if `data` is
+    /// too small for a frame with the provided dimensions.
+    pub fn detect(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        pitch: u32,
+        format: PixelFormat,
+    ) -> Result<CropRect, LGError> {
+        let bpp = match format {
+            PixelFormat::Bgra | PixelFormat::Rgba | PixelFormat::Bgr32 => 4,
+            PixelFormat::Rgb24 => 3,
+            format => Err(LGError::UnsupportedPixelFormat(format))?,
+        };
+        if self.prev_size != Some((width, height)) {
+            self.prev_size = Some((width, height));
+            self.current = Some(CropRect::full(width, height));
+            self.candidate = None;
+        }
+        let current = self.current.unwrap_or(CropRect::full(width, height));
+
+        let Some(found) = find_content(data, width, height, pitch, bpp, self.opts.threshold) else {
+            return Ok(current);
+        };
+        if found == current {
+            self.candidate = None;
+            return Ok(current);
+        }
+
+        let seen = match self.candidate {
+            Some((rect, seen)) if rect == found => seen + 1,
+            _ => 1,
+        };
+        if seen >= self.opts.hysteresis {
+            self.current = Some(found);
+            self.candidate = None;
+            Ok(found)
+        } else {
+            self.candidate = Some((found, seen));
+            Ok(current)
+        }
+    }
+}
+
+/// Finds the smallest rect containing every non-black pixel, or None if the frame is
+/// entirely black.
+fn find_content(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: usize,
+    threshold: u8,
+) -> Option<CropRect> {
+    let (width, height, pitch) = (width as usize, height as usize, pitch as usize);
+    //Colour channels always occupy the first three bytes of each pixel
+    let is_black = |x: usize, y: usize| {
+        let offset = y * pitch + x * bpp;
+        data[offset..offset + 3].iter().all(|&c| c <= threshold)
+    };
+    let row_black = |y: usize| (0..width).all(|x| is_black(x, y));
+
+    let top = (0..height).find(|&y| !row_black(y))?;
+    let bottom = (top..height).rev().find(|&y| !row_black(y))?;
+    let col_black = |x: usize| (top..=bottom).all(|y| is_black(x, y));
+    let left = (0..width).find(|&x| !col_black(x))?;
+    let right = (left..width).rev().find(|&x| !col_black(x))?;
+
+    Some(CropRect {
+        x: left as u32,
+        y: top as u32,
+        width: (right - left + 1) as u32,
+        height: (bottom - top + 1) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a black RGBA frame with a white rectangle of content.
+    fn letterboxed(width: u32, height: u32, content: CropRect) -> Vec<u8> {
+        let mut data = vec![0; (width * height * 4) as usize];
+        for y in content.y..content.y + content.height {
+            for x in content.x..content.x + content.width {
+                let offset = ((y * width + x) * 4) as usize;
+                data[offset..offset + 4].fill(0xff);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn finds_letterbox_after_hysteresis() {
+        let content = CropRect {
+            x: 0,
+            y: 4,
+            width: 16,
+            height: 8,
+        };
+        let data = letterboxed(16, 16, content);
+        let mut detector = CropDetector::new(CropDetectorOpts {
+            threshold: 16,
+            hysteresis: 3,
+        });
+
+        for _ in 0..2 {
+            let crop = detector
+                .detect(&data, 16, 16, 64, PixelFormat::Rgba)
+                .unwrap();
+            assert_eq!(crop, CropRect::full(16, 16));
+        }
+        let crop = detector
+            .detect(&data, 16, 16, 64, PixelFormat::Rgba)
+            .unwrap();
+        assert_eq!(crop, content);
+
+        //A black frame, such as during a fade, keeps the current crop
+        let black = vec![0; 16 * 16 * 4];
+        let crop = detector
+            .detect(&black, 16, 16, 64, PixelFormat::Rgba)
+            .unwrap();
+        assert_eq!(crop, content);
+    }
+
+    #[test]
+    fn finds_pillarbox() {
+        let content = CropRect {
+            x: 3,
+            y: 0,
+            width: 10,
+            height: 8,
+        };
+        let data = letterboxed(16, 8, content);
+        assert_eq!(find_content(&data, 16, 8, 64, 4, 16), Some(content));
+        assert_eq!(find_content(&[0; 64], 4, 4, 16, 4, 16), None);
+    }
+}
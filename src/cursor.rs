@@ -0,0 +1,552 @@
+//! Helpers for working with the guest cursor.
+use std::sync::Arc;
+
+use xxhash_rust::xxh3::Xxh3;
+
+#[cfg(feature = "lgmp")]
+use crate::client::KVMFRCursorHandle;
+use crate::{
+    error::LGError,
+    shm_datastructs,
+    types::{CursorType, Rotation},
+};
+
+/// Describes where the visible part of a cursor shape should be drawn on the output.
+///
+/// All values are given in output space, i.e. after the frame rotation has been applied.
+/// Renderers are expected to rotate the cursor shape by the same amount as the frame, and
+/// `src_x`/`src_y` index into that rotated shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorPlacement {
+    /// Horizontal output position of the first visible pixel of the shape
+    pub dst_x: u32,
+    /// Vertical output position of the first visible pixel of the shape
+    pub dst_y: u32,
+    /// Horizontal offset into the shape of the first visible pixel
+    pub src_x: u32,
+    /// Vertical offset into the shape of the first visible pixel
+    pub src_y: u32,
+    /// Width of the visible region
+    pub width: u32,
+    /// Height of the visible region
+    pub height: u32,
+}
+
+/// Works out where a cursor should be drawn on top of a frame, taking into account the
+/// hotspot offset, frame rotation and any part of the cursor which falls off the edge of
+/// the frame.
+///
+/// `pos` and `hotspot` are the values sent by the host, `shape` is the unrotated size of
+/// the cursor shape and `frame` is the unrotated size of the frame.
+///
+/// Returns None if no part of the cursor is visible.
+pub fn place_cursor(
+    pos: (i32, i32),
+    hotspot: (i32, i32),
+    shape: (u32, u32),
+    frame: (u32, u32),
+    rotation: Rotation,
+) -> Option<CursorPlacement> {
+    //Work in i64 so that nothing here can overflow
+    let (x, y) = (
+        i64::from(pos.0) - i64::from(hotspot.0),
+        i64::from(pos.1) - i64::from(hotspot.1),
+    );
+    let (w, h) = (i64::from(shape.0), i64::from(shape.1));
+    let (fw, fh) = (i64::from(frame.0), i64::from(frame.1));
+
+    //Rotate the cursor rect into output space
+    let (x, y, w, h, out_w, out_h) = match rotation {
+        Rotation::Rot0 => (x, y, w, h, fw, fh),
+        Rotation::Rot90 => (fh - (y + h), x, h, w, fh, fw),
+        Rotation::Rot180 => (fw - (x + w), fh - (y + h), w, h, fw, fh),
+        Rotation::Rot270 => (y, fw - (x + w), h, w, fh, fw),
+    };
+
+    //Clip against the output
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x + w).min(out_w);
+    let bottom = (y + h).min(out_h);
+    if right <= left || bottom <= top {
+        return None;
+    }
+
+    Some(CursorPlacement {
+        dst_x: left as u32,
+        dst_y: top as u32,
+        src_x: (left - x) as u32,
+        src_y: (top - y) as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
+
+/// A cursor shape decoded into straight (non-premultiplied) RGBA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorShape {
+    pub width: u32,
+    pub height: u32,
+    /// Offset of the cursor hotspot from the top left of the shape
+    pub hotspot: (i32, i32),
+    /// Pixel data, 4 bytes per pixel with rows packed tightly together
+    pub rgba: Vec<u8>,
+}
+
+/// Xcursor chunk type for image chunks
+const XCURSOR_IMAGE_TYPE: u32 = 0xfffd_0002;
+/// Sizes of the Xcursor file header and image chunk header, in bytes
+const XCURSOR_FILE_HEADER_LEN: u32 = 16;
+const XCURSOR_IMAGE_HEADER_LEN: u32 = 36;
+
+impl CursorShape {
+    /// Converts the shape into premultiplied ARGB pixels, each held in a native endian
+    /// `u32` with alpha in the top byte.
+    ///
+    /// This is the layout taken by `XcursorImage`, by Wayland `ARGB8888` buffers attached
+    /// to a cursor surface, and by the 32 bit DIBs passed to `CreateIconIndirect` on
+    /// Windows, so can be handed to the windowing system to use the shape as a hardware
+    /// cursor rather than drawing it into each frame.
+    pub fn to_premultiplied_argb(&self) -> Vec<u32> {
+        self.rgba
+            .chunks_exact(4)
+            .map(|px| {
+                let alpha = u32::from(px[3]);
+                let premul = |c: u8| (u32::from(c) * alpha + 127) / 255;
+                alpha << 24 | premul(px[0]) << 16 | premul(px[1]) << 8 | premul(px[2])
+            })
+            .collect()
+    }
+
+    /// Encodes the shape as a single image Xcursor file, which can be installed into a
+    /// cursor theme or loaded with `XcursorFilenameLoadImage`.
+    ///
+    /// Xcursor requires the hotspot to lie within the image, so it is clamped if the host
+    /// placed it outside.
+    pub fn to_xcursor(&self) -> Vec<u8> {
+        let clamp = |hot: i32, size: u32| hot.clamp(0, size.saturating_sub(1) as i32) as u32;
+        let nominal_size = self.width.max(self.height);
+        let image_pos = XCURSOR_FILE_HEADER_LEN + 12;
+        let header = [
+            //File header, with a table of contents holding a single image
+            u32::from_le_bytes(*b"Xcur"),
+            XCURSOR_FILE_HEADER_LEN,
+            0x0001_0000,
+            1,
+            XCURSOR_IMAGE_TYPE,
+            nominal_size,
+            image_pos,
+            //Image chunk header
+            XCURSOR_IMAGE_HEADER_LEN,
+            XCURSOR_IMAGE_TYPE,
+            nominal_size,
+            1,
+            self.width,
+            self.height,
+            clamp(self.hotspot.0, self.width),
+            clamp(self.hotspot.1, self.height),
+            0,
+        ];
+
+        let pixels = self.to_premultiplied_argb();
+        let mut out = Vec::with_capacity((header.len() + pixels.len()) * 4);
+        for word in header.into_iter().chain(pixels) {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Decodes the shape carried by a cursor message into RGBA.
+///
+/// `data` is the bitmap following the [shm_datastructs::KVMFRCursor] header, as returned by
+/// [crate::client::KVMFRCursorHandle::shape_data]. Cursors which invert the pixels beneath
+/// them cannot be represented in RGBA; monochrome inverting pixels are drawn black and
+/// masked colour inverting pixels are drawn using their colour.
+pub fn decode_shape(
+    cursor: &shm_datastructs::KVMFRCursor,
+    data: &[u8],
+) -> Result<CursorShape, LGError> {
+    let cursor_type = CursorType::try_from(cursor.type_).map_err(LGError::UnknownCursorType)?;
+    let (width, pitch) = (cursor.width as usize, cursor.pitch as usize);
+    //Monochrome cursors carry an AND mask followed by an XOR mask, each of half the height
+    let height = match cursor_type {
+        CursorType::Monochrome => cursor.height as usize / 2,
+        _ => cursor.height as usize,
+    };
+
+    let row_len = match cursor_type {
+        CursorType::Monochrome => width.div_ceil(8),
+        _ => width
+            .checked_mul(4)
+            .ok_or(LGError::CursorChannelMessageTooSmall)?,
+    };
+    let rows = match cursor_type {
+        CursorType::Monochrome => height * 2,
+        _ => height,
+    };
+    //Sizes come from the host, so must not be trusted not to overflow
+    let needed = match rows.checked_sub(1) {
+        Some(last) => last
+            .checked_mul(pitch)
+            .and_then(|len| len.checked_add(row_len)),
+        None => Some(0),
+    };
+    if pitch < row_len || needed.is_none_or(|needed| data.len() < needed) {
+        Err(LGError::CursorChannelMessageTooSmall)?
+    }
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row = &data[y * pitch..];
+        match cursor_type {
+            CursorType::Color => {
+                for bgra in row[..row_len].chunks_exact(4) {
+                    rgba.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+                }
+            }
+            CursorType::MaskedColor => {
+                //The alpha channel is a mask; where set the colour is XORed with the screen
+                for bgra in row[..row_len].chunks_exact(4) {
+                    //XORing with black leaves the screen unchanged, so treat it as transparent
+                    let alpha = if bgra[3] != 0 && bgra[..3] == [0, 0, 0] {
+                        0
+                    } else {
+                        0xff
+                    };
+                    rgba.extend_from_slice(&[bgra[2], bgra[1], bgra[0], alpha]);
+                }
+            }
+            CursorType::Monochrome => {
+                let xor_row = &data[(y + height) * pitch..];
+                for x in 0..width {
+                    let bit = 0x80 >> (x % 8);
+                    let and = row[x / 8] & bit != 0;
+                    let xor = xor_row[x / 8] & bit != 0;
+                    let pixel = match (and, xor) {
+                        (false, false) => [0x00, 0x00, 0x00, 0xff],
+                        (false, true) => [0xff, 0xff, 0xff, 0xff],
+                        (true, false) => [0x00, 0x00, 0x00, 0x00],
+                        (true, true) => [0x00, 0x00, 0x00, 0xff],
+                    };
+                    rgba.extend_from_slice(&pixel);
+                }
+            }
+        }
+    }
+
+    Ok(CursorShape {
+        width: width as u32,
+        height: height as u32,
+        hotspot: (i32::from(cursor.hx), i32::from(cursor.hy)),
+        rgba,
+    })
+}
+
+/// The current state of the guest cursor, as tracked by [CursorTracker].
+#[derive(Debug, Clone, Default)]
+pub struct CursorState {
+    /// Position of the cursor hotspot, or None if the host has not yet sent one
+    pub position: Option<(i32, i32)>,
+    pub visible: bool,
+    /// Most recent cursor shape, or None if the host has not yet sent one
+    pub shape: Option<Arc<CursorShape>>,
+    /// Incremented each time the shape changes, so that renderers can tell when they
+    /// need to re-upload it. Shapes which the host resends unchanged do not count.
+    pub shape_generation: u64,
+}
+
+/// Combines the position-only and shape-carrying cursor updates sent by the host into a
+/// single view of the cursor.
+#[derive(Debug, Clone, Default)]
+pub struct CursorTracker {
+    state: CursorState,
+    /// Hash of the undecoded bitmap and layout of the current shape, so that resent shapes
+    /// don't need decoding again
+    shape_hash: Option<u64>,
+}
+
+impl CursorTracker {
+    pub fn new() -> CursorTracker {
+        Self::default()
+    }
+
+    /// Applies a cursor update received from the host, returning true if it changed the
+    /// shape. Updates which carry the same shape as before are treated as position only.
+    ///
+    /// Requires the `lgmp` feature.
+    ///
+    /// If the update carries a shape which cannot be decoded, the error is returned and
+    /// the previous shape is kept, although the position and visibility are still updated.
+    #[cfg(feature = "lgmp")]
+    pub fn update(&mut self, cursor: &KVMFRCursorHandle) -> Result<bool, LGError> {
+        let header = cursor.as_ptr_msg()?;
+        let shape = match cursor.has_shape() {
+            true => Some(cursor.shape_data()?),
+            false => None,
+        };
+        let position = cursor.has_position();
+        self.apply(header, position, cursor.is_visible(), shape)
+    }
+
+    #[cfg_attr(not(feature = "lgmp"), allow(dead_code))]
+    fn apply(
+        &mut self,
+        header: &shm_datastructs::KVMFRCursor,
+        position: bool,
+        visible: bool,
+        shape: Option<&[u8]>,
+    ) -> Result<bool, LGError> {
+        if position {
+            self.state.position = Some((i32::from(header.x), i32::from(header.y)));
+        }
+        self.state.visible = visible;
+        let Some(data) = shape else {
+            return Ok(false);
+        };
+
+        let mut hasher = Xxh3::new();
+        let layout = [header.type_, header.width, header.height, header.pitch];
+        for field in layout {
+            hasher.update(&field.to_ne_bytes());
+        }
+        hasher.update(&header.hx.to_ne_bytes());
+        hasher.update(&header.hy.to_ne_bytes());
+        hasher.update(data);
+        let hash = hasher.digest();
+        if self.shape_hash == Some(hash) {
+            return Ok(false);
+        }
+
+        let shape = decode_shape(header, data)?;
+        self.shape_hash = Some(hash);
+        self.state.shape = Some(Arc::new(shape));
+        self.state.shape_generation += 1;
+        Ok(true)
+    }
+
+    /// Returns the current cursor state. The shape is shared rather than copied, so this
+    /// is cheap enough to call every frame.
+    pub fn snapshot(&self) -> CursorState {
+        self.state.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_fully_on_screen() {
+        let placement = place_cursor((100, 50), (2, 3), (32, 32), (1920, 1080), Rotation::Rot0);
+        assert_eq!(
+            placement,
+            Some(CursorPlacement {
+                dst_x: 98,
+                dst_y: 47,
+                src_x: 0,
+                src_y: 0,
+                width: 32,
+                height: 32,
+            })
+        );
+    }
+
+    #[test]
+    fn cursor_clipped_by_top_left() {
+        let placement = place_cursor((0, 0), (4, 6), (32, 32), (1920, 1080), Rotation::Rot0);
+        assert_eq!(
+            placement,
+            Some(CursorPlacement {
+                dst_x: 0,
+                dst_y: 0,
+                src_x: 4,
+                src_y: 6,
+                width: 28,
+                height: 26,
+            })
+        );
+    }
+
+    #[test]
+    fn cursor_clipped_by_bottom_right() {
+        let placement = place_cursor((1910, 1075), (0, 0), (32, 32), (1920, 1080), Rotation::Rot0);
+        assert_eq!(
+            placement,
+            Some(CursorPlacement {
+                dst_x: 1910,
+                dst_y: 1075,
+                src_x: 0,
+                src_y: 0,
+                width: 10,
+                height: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn cursor_off_screen() {
+        assert_eq!(
+            place_cursor((-40, 10), (0, 0), (32, 32), (1920, 1080), Rotation::Rot0),
+            None
+        );
+        assert_eq!(
+            place_cursor((1920, 10), (0, 0), (32, 32), (1920, 1080), Rotation::Rot0),
+            None
+        );
+    }
+
+    #[test]
+    fn cursor_rotated() {
+        //A 16x8 cursor at (10, 20) on a 100x50 frame
+        let rot90 = place_cursor((10, 20), (0, 0), (16, 8), (100, 50), Rotation::Rot90).unwrap();
+        assert_eq!((rot90.dst_x, rot90.dst_y), (22, 10));
+        assert_eq!((rot90.width, rot90.height), (8, 16));
+
+        let rot180 = place_cursor((10, 20), (0, 0), (16, 8), (100, 50), Rotation::Rot180).unwrap();
+        assert_eq!((rot180.dst_x, rot180.dst_y), (74, 22));
+        assert_eq!((rot180.width, rot180.height), (16, 8));
+
+        let rot270 = place_cursor((10, 20), (0, 0), (16, 8), (100, 50), Rotation::Rot270).unwrap();
+        assert_eq!((rot270.dst_x, rot270.dst_y), (20, 74));
+        assert_eq!((rot270.width, rot270.height), (8, 16));
+    }
+
+    #[test]
+    fn cursor_rotated_and_clipped() {
+        //Cursor hanging off the left of the unrotated frame ends up off the bottom
+        let placement =
+            place_cursor((-4, 20), (0, 0), (16, 8), (100, 50), Rotation::Rot90).unwrap();
+        assert_eq!((placement.dst_x, placement.dst_y), (22, 0));
+        assert_eq!((placement.src_x, placement.src_y), (0, 4));
+        assert_eq!((placement.width, placement.height), (8, 12));
+    }
+
+    fn cursor_header(
+        cursor_type: CursorType,
+        width: u32,
+        height: u32,
+        pitch: u32,
+    ) -> shm_datastructs::KVMFRCursor {
+        let mut header: shm_datastructs::KVMFRCursor = unsafe { std::mem::zeroed() };
+        header.type_ = cursor_type.into();
+        header.width = width;
+        header.height = height;
+        header.pitch = pitch;
+        header.hx = 1;
+        header.hy = 2;
+        header
+    }
+
+    #[test]
+    fn decode_color_shape() {
+        let header = cursor_header(CursorType::Color, 2, 1, 8);
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let shape = decode_shape(&header, &data).unwrap();
+        assert_eq!(shape.hotspot, (1, 2));
+        assert_eq!(shape.rgba, vec![3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn decode_monochrome_shape() {
+        //One row of four pixels covering every AND/XOR combination, padded to 2 bytes
+        let header = cursor_header(CursorType::Monochrome, 4, 2, 2);
+        let data = [0b0011_0000, 0, 0b0101_0000, 0];
+        let shape = decode_shape(&header, &data).unwrap();
+        assert_eq!((shape.width, shape.height), (4, 1));
+        assert_eq!(
+            shape.rgba,
+            vec![0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn decode_masked_color_shape() {
+        let header = cursor_header(CursorType::MaskedColor, 3, 1, 12);
+        let data = [1, 2, 3, 0, 0, 0, 0, 255, 1, 2, 3, 255];
+        let shape = decode_shape(&header, &data).unwrap();
+        assert_eq!(shape.rgba, vec![3, 2, 1, 255, 0, 0, 0, 0, 3, 2, 1, 255]);
+    }
+
+    #[test]
+    fn decode_truncated_shape() {
+        let header = cursor_header(CursorType::Color, 2, 2, 8);
+        assert!(matches!(
+            decode_shape(&header, &[0; 12]),
+            Err(LGError::CursorChannelMessageTooSmall)
+        ));
+    }
+
+    #[test]
+    fn decode_oversized_shape() {
+        let header = cursor_header(CursorType::Color, u32::MAX, u32::MAX, u32::MAX);
+        assert!(matches!(
+            decode_shape(&header, &[0; 16]),
+            Err(LGError::CursorChannelMessageTooSmall)
+        ));
+    }
+
+    #[test]
+    fn tracker_combines_updates() {
+        let mut tracker = CursorTracker::new();
+        let mut header = cursor_header(CursorType::Color, 1, 1, 4);
+        tracker
+            .apply(&header, false, true, Some(&[1, 2, 3, 4]))
+            .unwrap();
+
+        header.x = 10;
+        header.y = 20;
+        tracker.apply(&header, true, true, None).unwrap();
+
+        let state = tracker.snapshot();
+        assert_eq!(state.position, Some((10, 20)));
+        assert!(state.visible);
+        assert_eq!(state.shape_generation, 1);
+        assert_eq!(state.shape.unwrap().rgba, vec![3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn tracker_ignores_resent_shapes() {
+        let mut tracker = CursorTracker::new();
+        let header = cursor_header(CursorType::Color, 1, 1, 4);
+        assert!(tracker
+            .apply(&header, true, true, Some(&[1, 2, 3, 4]))
+            .unwrap());
+        let shape = tracker.snapshot().shape.unwrap();
+
+        //The same bitmap again keeps the decoded shape
+        assert!(!tracker
+            .apply(&header, true, true, Some(&[1, 2, 3, 4]))
+            .unwrap());
+        let state = tracker.snapshot();
+        assert_eq!(state.shape_generation, 1);
+        assert!(Arc::ptr_eq(&state.shape.unwrap(), &shape));
+
+        assert!(tracker
+            .apply(&header, true, true, Some(&[5, 6, 7, 8]))
+            .unwrap());
+        assert_eq!(tracker.snapshot().shape_generation, 2);
+    }
+
+    #[test]
+    fn exports_native_cursor_formats() {
+        let shape = CursorShape {
+            width: 2,
+            height: 1,
+            hotspot: (5, -1),
+            rgba: vec![0xff, 0x80, 0x00, 0xff, 0xff, 0xff, 0xff, 0x80],
+        };
+        assert_eq!(shape.to_premultiplied_argb(), [0xffff8000, 0x80808080]);
+
+        let xcursor = shape.to_xcursor();
+        let words: Vec<u32> = xcursor
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(&xcursor[..4], b"Xcur");
+        //Table of contents points at the image chunk
+        assert_eq!(words[6], 28);
+        assert_eq!(&words[7..16], [36, 0xfffd0002, 2, 1, 2, 1, 1, 0, 0]);
+        assert_eq!(&words[16..], [0xffff8000, 0x80808080]);
+    }
+}
@@ -0,0 +1,416 @@
+//! Estimation of frame damage by comparing frames against their predecessors, for use
+//! when the capture backend is unable to report which regions have changed.
+use std::time::{Duration, Instant};
+
+use xxhash_rust::xxh3::Xxh3;
+
+#[cfg(feature = "lgmp")]
+use crate::{client::KVMFRFrameHandle, error::LGError};
+use crate::{parallel, shm_datastructs, types::DamageRect};
+
+/// Options controlling the cost of damage estimation.
+#[derive(Debug, Clone)]
+pub struct DamageEstimatorOpts {
+    /// Width and height of the square tiles which are compared between frames. Smaller
+    /// tiles give tighter damage rects at the cost of more hashing overhead.
+    pub tile_size: u32,
+    /// Maximum time to spend hashing a single frame. Any tiles which have not been
+    /// checked once this runs out are assumed to be damaged, and will be checked first
+    /// on the next frame.
+    pub budget: Option<Duration>,
+}
+
+impl Default for DamageEstimatorOpts {
+    fn default() -> Self {
+        DamageEstimatorOpts {
+            tile_size: 64,
+            budget: None,
+        }
+    }
+}
+
+/// Works out which regions of a frame have changed by hashing it in tiles and comparing
+/// the hashes against those of the previous frame.
+pub struct DamageEstimator {
+    opts: DamageEstimatorOpts,
+    /// Layout of the previous frame as (width, height, bytes per pixel)
+    prev_layout: Option<(u32, u32, u32)>,
+    /// Tile hashes from the previous frame, or None where a tile was not hashed
+    prev_hashes: Vec<Option<u64>>,
+    /// Tile at which hashing should start, so that tiles skipped due to the budget
+    /// running out are prioritised next time
+    start_tile: usize,
+}
+
+impl DamageEstimator {
+    pub fn new(opts: DamageEstimatorOpts) -> DamageEstimator {
+        DamageEstimator {
+            opts: DamageEstimatorOpts {
+                tile_size: opts.tile_size.max(1),
+                ..opts
+            },
+            prev_layout: None,
+            prev_hashes: Vec::new(),
+            start_tile: 0,
+        }
+    }
+
+    /// Forgets the previous frame, so that the next frame will be reported as fully damaged.
+    pub fn reset(&mut self) {
+        self.prev_layout = None;
+        self.prev_hashes.clear();
+        self.start_tile = 0;
+    }
+
+    /// Compares a frame against the previous one passed to this estimator.
+    ///
+    /// Returns None if the whole frame should be considered damaged, which happens on the
+    /// first frame, whenever the frame layout changes, or if the damage could not be
+    /// described within the KVMFR damage rect limit. Otherwise returns the list of damaged
+    /// regions, which will be empty if nothing changed or the frame has no pixels.
+    ///
+    /// Panics if `data` is too small for a frame with the provided dimensions.
+    pub fn estimate(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        pitch: u32,
+        bytes_per_pixel: u32,
+    ) -> Option<Vec<DamageRect>> {
+        let tile = self.opts.tile_size;
+        let tiles_x = width.div_ceil(tile) as usize;
+        let tiles_y = height.div_ceil(tile) as usize;
+        let tile_count = tiles_x * tiles_y;
+        if tile_count == 0 {
+            //Whatever comes next is compared against nothing
+            self.reset();
+            return Some(Vec::new());
+        }
+
+        let layout = Some((width, height, bytes_per_pixel));
+        let full_damage = self.prev_layout != layout;
+        if full_damage {
+            self.prev_layout = layout;
+            self.prev_hashes = vec![None; tile_count];
+            self.start_tile = 0;
+        }
+
+        let mut damaged = vec![false; tile_count];
+        if self.opts.budget.is_none() {
+            //Without a budget tiles can be hashed in any order, so rows of them are split up
+            let mut hashes = vec![0; tile_count];
+            let min_rows = parallel::min_band_chunks(tile as usize * pitch as usize);
+            parallel::for_each_band(&mut hashes, tiles_x, min_rows, |first_row, band| {
+                for (i, hash) in band.iter_mut().enumerate() {
+                    let idx = first_row * tiles_x + i;
+                    let (tx, ty) = ((idx % tiles_x) as u32, (idx / tiles_x) as u32);
+                    *hash = hash_tile(
+                        data,
+                        tx * tile,
+                        ty * tile,
+                        width,
+                        height,
+                        pitch,
+                        bytes_per_pixel,
+                        tile,
+                    );
+                }
+            });
+            for (idx, hash) in hashes.into_iter().enumerate() {
+                damaged[idx] = self.prev_hashes[idx] != Some(hash);
+                self.prev_hashes[idx] = Some(hash);
+            }
+            return self.finish(damaged, tiles_x, width, height, full_damage);
+        }
+
+        let started = Instant::now();
+        let mut next_start = self.start_tile;
+        for i in 0..tile_count {
+            let idx = (self.start_tile + i) % tile_count;
+            let out_of_budget = self
+                .opts
+                .budget
+                .is_some_and(|budget| started.elapsed() > budget);
+            if out_of_budget {
+                //Assume everything we haven't got to has changed
+                for j in i..tile_count {
+                    let idx = (self.start_tile + j) % tile_count;
+                    damaged[idx] = true;
+                    self.prev_hashes[idx] = None;
+                }
+                next_start = idx;
+                break;
+            }
+
+            let (tx, ty) = ((idx % tiles_x) as u32, (idx / tiles_x) as u32);
+            let hash = hash_tile(
+                data,
+                tx * tile,
+                ty * tile,
+                width,
+                height,
+                pitch,
+                bytes_per_pixel,
+                tile,
+            );
+            damaged[idx] = self.prev_hashes[idx] != Some(hash);
+            self.prev_hashes[idx] = Some(hash);
+        }
+        self.start_tile = next_start;
+        self.finish(damaged, tiles_x, width, height, full_damage)
+    }
+
+    /// Turns the damaged tiles of a frame into the return value of [Self::estimate].
+    fn finish(
+        &self,
+        damaged: Vec<bool>,
+        tiles_x: usize,
+        width: u32,
+        height: u32,
+        full_damage: bool,
+    ) -> Option<Vec<DamageRect>> {
+        if full_damage {
+            return None;
+        }
+        let rects = merge_tiles(&damaged, tiles_x, self.opts.tile_size, width, height);
+        if rects.len() > shm_datastructs::KVMFR_MAX_DAMAGE_RECTS as usize {
+            None
+        } else {
+            Some(rects)
+        }
+    }
+}
+
+#[cfg(feature = "lgmp")]
+impl DamageEstimator {
+    /// Returns the damage of a frame received from a host, estimating it with
+    /// [Self::estimate] if the host did not send any damage rects.
+    ///
+    /// The return value has the same meaning as that of [Self::estimate]. Frames which
+    /// the host did send damage for are not hashed, so the estimator is reset and the next
+    /// frame which needs estimating will be reported as fully damaged.
+    pub fn frame_damage(
+        &mut self,
+        frame: &KVMFRFrameHandle,
+    ) -> Result<Option<Vec<DamageRect>>, LGError> {
+        let rects = frame.damage_rects()?;
+        if !rects.is_empty() {
+            self.reset();
+            return Ok(Some(rects.to_vec()));
+        }
+        let info = frame.info()?;
+        Ok(self.estimate(
+            frame.data()?,
+            info.data_width,
+            info.data_height,
+            info.pitch,
+            info.format.bytes_per_pixel(),
+        ))
+    }
+}
+
+/// Hashes the pixels contained within a single tile.
+#[allow(clippy::too_many_arguments)]
+fn hash_tile(
+    data: &[u8],
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bytes_per_pixel: u32,
+    tile: u32,
+) -> u64 {
+    let row_start = (x * bytes_per_pixel) as usize;
+    let row_len = ((tile.min(width - x)) * bytes_per_pixel) as usize;
+    let mut hasher = Xxh3::new();
+    for row in y..(y + tile).min(height) {
+        let offset = row as usize * pitch as usize + row_start;
+        hasher.update(&data[offset..offset + row_len]);
+    }
+    hasher.digest()
+}
+
+/// Converts a grid of damaged tiles into rects, merging horizontal runs of tiles and
+/// then stacking runs which span the same columns on consecutive rows.
+fn merge_tiles(
+    damaged: &[bool],
+    tiles_x: usize,
+    tile: u32,
+    width: u32,
+    height: u32,
+) -> Vec<DamageRect> {
+    let mut rects: Vec<DamageRect> = Vec::new();
+    if tiles_x == 0 {
+        return rects;
+    }
+    //Index into rects of runs on the previous row of tiles, for vertical merging
+    let mut prev_row: Vec<usize> = Vec::new();
+
+    for (ty, row) in damaged.chunks(tiles_x).enumerate() {
+        let mut cur_row = Vec::new();
+        let mut tx = 0;
+        while tx < row.len() {
+            if !row[tx] {
+                tx += 1;
+                continue;
+            }
+            let run_start = tx;
+            while tx < row.len() && row[tx] {
+                tx += 1;
+            }
+
+            let x = run_start as u32 * tile;
+            let y = ty as u32 * tile;
+            let rect_width = (tx as u32 * tile).min(width) - x;
+            let rect_height = (y + tile).min(height) - y;
+
+            //Extend the rect above if it covers exactly the same columns
+            let above = prev_row.iter().copied().find(|&i| {
+                let r = &rects[i];
+                r.x == x && r.width == rect_width && r.y + r.height == y
+            });
+            match above {
+                Some(i) => {
+                    rects[i].height += rect_height;
+                    cur_row.push(i);
+                }
+                None => {
+                    rects.push(DamageRect {
+                        x,
+                        y,
+                        width: rect_width,
+                        height: rect_height,
+                    });
+                    cur_row.push(rects.len() - 1);
+                }
+            }
+        }
+        prev_row = cur_row;
+    }
+
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 100;
+    const HEIGHT: u32 = 60;
+    const PITCH: u32 = WIDTH * 4;
+
+    fn estimator() -> DamageEstimator {
+        DamageEstimator::new(DamageEstimatorOpts {
+            tile_size: 16,
+            budget: None,
+        })
+    }
+
+    fn set_pixel(frame: &mut [u8], x: u32, y: u32) {
+        let offset = (y * PITCH + x * 4) as usize;
+        frame[offset] ^= 0xff;
+    }
+
+    #[test]
+    fn first_frame_is_fully_damaged() {
+        let frame = vec![0u8; (PITCH * HEIGHT) as usize];
+        assert_eq!(estimator().estimate(&frame, WIDTH, HEIGHT, PITCH, 4), None);
+    }
+
+    #[test]
+    fn unchanged_frame_has_no_damage() {
+        let frame = vec![0u8; (PITCH * HEIGHT) as usize];
+        let mut est = estimator();
+        est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4);
+        assert_eq!(est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4), Some(vec![]));
+    }
+
+    #[test]
+    fn changed_pixels_are_reported() {
+        let mut frame = vec![0u8; (PITCH * HEIGHT) as usize];
+        let mut est = estimator();
+        est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4);
+
+        //Two horizontally adjacent tiles, plus one in the clipped bottom-right corner
+        set_pixel(&mut frame, 20, 20);
+        set_pixel(&mut frame, 40, 20);
+        set_pixel(&mut frame, 99, 59);
+        let damage = est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4).unwrap();
+        assert_eq!(
+            damage,
+            vec![
+                DamageRect {
+                    x: 16,
+                    y: 16,
+                    width: 32,
+                    height: 16
+                },
+                DamageRect {
+                    x: 96,
+                    y: 48,
+                    width: 4,
+                    height: 12
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn vertical_runs_are_merged() {
+        let mut frame = vec![0u8; (PITCH * HEIGHT) as usize];
+        let mut est = estimator();
+        est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4);
+
+        set_pixel(&mut frame, 5, 5);
+        set_pixel(&mut frame, 5, 20);
+        let damage = est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4).unwrap();
+        assert_eq!(
+            damage,
+            vec![DamageRect {
+                x: 0,
+                y: 0,
+                width: 16,
+                height: 32
+            }]
+        );
+    }
+
+    #[test]
+    fn empty_frame_has_no_damage() {
+        let frame = vec![0u8; (PITCH * HEIGHT) as usize];
+        let mut est = estimator();
+        est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4);
+        assert_eq!(est.estimate(&[], 0, HEIGHT, 0, 4), Some(vec![]));
+        assert_eq!(est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4), None);
+    }
+
+    #[test]
+    fn layout_change_is_fully_damaged() {
+        let frame = vec![0u8; (PITCH * HEIGHT) as usize];
+        let mut est = estimator();
+        est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4);
+        assert_eq!(est.estimate(&frame, WIDTH / 2, HEIGHT, PITCH, 4), None);
+    }
+
+    #[test]
+    fn exhausted_budget_assumes_damage() {
+        let frame = vec![0u8; (PITCH * HEIGHT) as usize];
+        let mut est = DamageEstimator::new(DamageEstimatorOpts {
+            tile_size: 16,
+            budget: Some(Duration::ZERO),
+        });
+        est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4);
+        let damage = est.estimate(&frame, WIDTH, HEIGHT, PITCH, 4).unwrap();
+        assert_eq!(
+            damage,
+            vec![DamageRect {
+                x: 0,
+                y: 0,
+                width: WIDTH,
+                height: HEIGHT
+            }]
+        );
+    }
+}
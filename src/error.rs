@@ -3,19 +3,302 @@ use std::sync::PoisonError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum LGError {
+    #[cfg(feature = "lgmp")]
     #[error("Encountered error during host communication: {0}")]
-    LGMPCommunicationError(#[from] ligmars::error::Error),
+    LGMPCommunicationError(crate::lgmp_impl::error::Error),
+    #[error("The LGMP session is no longer valid, and must be re-initialised")]
+    SessionInvalid,
+    #[error("The host timed this client out for not emptying a queue quickly enough")]
+    ClientTimedOut,
+    #[error("The host is not running on this shared memory")]
+    HostUnavailable,
+    #[error("Message recieved from host was corrupted")]
+    CorruptMessage,
+    #[cfg(feature = "lgmp")]
+    #[error("LGMP queue could not complete the operation yet: {0}")]
+    Transient(crate::lgmp_impl::error::Status),
+    #[cfg(feature = "lgmp")]
     #[error("Failed to open SHM device due to error {0}")]
     SHMDeviceError(#[from] shared_memory::ShmemError),
+    #[error("Failed to map SHM file due to error {0}")]
+    SHMFileError(#[from] std::io::Error),
+    #[error("Requested SHM size of {0} bytes is larger than the shared memory")]
+    SHMSizeTooLarge(usize),
     #[error("A thread panicked whilst holing lock on LGMP client")]
     LGMPClientLockPoisonError,
+    #[error("The background worker thread panicked whilst connecting")]
+    WorkerPanicked,
+    #[error("Failed to start background thread due to error {0}")]
+    ThreadSpawnError(std::io::Error),
     #[error("The host appication is not compatible with this client; Expected KVMFR version {0}")]
     KVMFRVersionMismatch(u32),
     #[error("Message recieved from host on frame channel was smaller than expected")]
     FrameChannelMessageTooSmall,
     #[error("Message recieved from host on cursor channel was smaller than expected")]
     CursorChannelMessageTooSmall,
+    #[error("Frame recieved from host had unknown format {0}")]
+    UnknownFrameFormat(u32),
+    #[error("Frame recieved from host had unknown rotation {0}")]
+    UnknownFrameRotation(u32),
+    #[error("Frame recieved from host had pitch {0}, which is too small for its width")]
+    InvalidFramePitch(u32),
+    #[error("Frame recieved from host had an empty size of {0}x{1}")]
+    EmptyFrame(u32, u32),
+    #[error("Frame recieved from host described data outside of its message")]
+    FrameDataOutOfBounds,
+    #[error("Frame recieved from host had an invalid damage rect count of {0}")]
+    InvalidDamageRectCount(u32),
+    #[error("Frame recieved from host had a damage rect outside of the frame")]
+    DamageRectOutOfBounds,
+    #[error("Message recieved from host was not aligned for its header")]
+    MisalignedMessage,
+    #[error("Shared memory is not backed by a kvmfr device, so can not be exported as a dmabuf")]
+    DmabufUnsupported,
+    #[cfg(feature = "png")]
+    #[error("Failed to encode PNG due to error {0}")]
+    PngEncodingError(#[from] png::EncodingError),
+    #[error("No frame was recieved from the host before the capture timed out")]
+    CaptureTimedOut,
+    #[error("Failed to read LGMP recording: {0}")]
+    InvalidRecording(String),
+    #[cfg(feature = "opencl")]
+    #[error("OpenCL call failed with error code {0}")]
+    OpenCLError(i32),
+    #[cfg(feature = "capi")]
+    #[error("A required pointer passed through the C API was null")]
+    NullPointer,
+    #[error("Texture does not match the format or size of the frame")]
+    TextureMismatch,
+    #[error("Destination buffer or pitch is too small for the frame")]
+    DestinationTooSmall,
+    #[error("Pixel format {0:?} is not supported by this operation")]
+    UnsupportedPixelFormat(crate::types::PixelFormat),
+    #[error("Failed to parse cube LUT: {0}")]
+    InvalidCubeLut(String),
+    #[error("Pixel data provided to host was too small for the frame's pitch and height")]
+    HostFrameDataTooSmall,
+    #[error("A row of the frame provided to host was larger than the host's maximum frame size")]
+    HostFrameRowTooLarge,
+    #[error("Cursor shape provided to host was larger than the maximum supported size")]
+    CursorShapeTooLarge,
+    #[error("Cursor shape recieved from host had unknown type {0}")]
+    UnknownCursorType(u32),
+    #[error("The host application does not support required features {0:?}")]
+    UnsupportedFeature(crate::types::HostFeatures),
+    #[error("Message recieved from client had unknown type {0}")]
+    UnknownMessageType(u32),
+    #[error("Message recieved from client was smaller than expected")]
+    ClientMessageTooSmall,
+}
+
+impl LGError {
+    /// Returns true if the same call may succeed if it is retried later, without doing
+    /// anything else first.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            #[cfg(feature = "lgmp")]
+            LGError::Transient(_) => true,
+            LGError::HostUnavailable | LGError::CaptureTimedOut => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the session has been lost, so the connection must be re-initialised
+    /// (or re-opened, if the host has restarted) before it can be used again.
+    pub fn requires_reconnect(&self) -> bool {
+        matches!(
+            self,
+            LGError::SessionInvalid | LGError::ClientTimedOut | LGError::HostUnavailable
+        )
+    }
+
+    /// Returns the broad kind of problem, which decides the exit code of tools built on
+    /// this crate.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            LGError::HostUnavailable | LGError::CaptureTimedOut => ErrorCategory::HostUnavailable,
+            #[cfg(feature = "lgmp")]
+            LGError::Transient(_) => ErrorCategory::SessionLost,
+            LGError::SessionInvalid | LGError::ClientTimedOut => ErrorCategory::SessionLost,
+            LGError::KVMFRVersionMismatch(_)
+            | LGError::UnsupportedFeature(_)
+            | LGError::DmabufUnsupported
+            | LGError::UnsupportedPixelFormat(_)
+            | LGError::UnknownFrameFormat(_)
+            | LGError::UnknownFrameRotation(_)
+            | LGError::UnknownCursorType(_)
+            | LGError::UnknownMessageType(_) => ErrorCategory::Incompatible,
+            LGError::CorruptMessage
+            | LGError::FrameChannelMessageTooSmall
+            | LGError::CursorChannelMessageTooSmall
+            | LGError::InvalidFramePitch(_)
+            | LGError::EmptyFrame(..)
+            | LGError::FrameDataOutOfBounds
+            | LGError::InvalidDamageRectCount(_)
+            | LGError::DamageRectOutOfBounds
+            | LGError::MisalignedMessage
+            | LGError::ClientMessageTooSmall
+            | LGError::InvalidRecording(_)
+            | LGError::InvalidCubeLut(_) => ErrorCategory::BadData,
+            #[cfg(feature = "lgmp")]
+            LGError::LGMPCommunicationError(_) | LGError::SHMDeviceError(_) => ErrorCategory::Io,
+            #[cfg(feature = "png")]
+            LGError::PngEncodingError(_) => ErrorCategory::Io,
+            LGError::SHMFileError(_) => ErrorCategory::Io,
+            #[cfg(feature = "capi")]
+            LGError::NullPointer => ErrorCategory::Usage,
+            LGError::TextureMismatch
+            | LGError::DestinationTooSmall
+            | LGError::SHMSizeTooLarge(_)
+            | LGError::HostFrameDataTooSmall
+            | LGError::HostFrameRowTooLarge
+            | LGError::CursorShapeTooLarge => ErrorCategory::Usage,
+            #[cfg(feature = "opencl")]
+            LGError::OpenCLError(_) => ErrorCategory::Internal,
+            LGError::LGMPClientLockPoisonError
+            | LGError::WorkerPanicked
+            | LGError::ThreadSpawnError(_) => ErrorCategory::Internal,
+        }
+    }
+
+    /// Returns the process exit code for this error. See [ErrorCategory::exit_code].
+    pub fn exit_code(&self) -> u8 {
+        self.category().exit_code()
+    }
+
+    /// Returns a suggestion of what probably caused the error and how to fix it, for the
+    /// errors where there is a likely answer.
+    pub fn hint(&self) -> Option<&'static str> {
+        let hint = match self {
+            LGError::HostUnavailable => {
+                "Check that the Looking Glass host is running in the guest, and that the shared \
+                 memory path matches the one given to the VM"
+            }
+            LGError::SessionInvalid | LGError::ClientTimedOut => {
+                "The host restarted, or this client stopped ticking its queues for longer than \
+                 the timeout; reconnect, and tick more often if this keeps happening"
+            }
+            LGError::KVMFRVersionMismatch(_) => {
+                "Use a client built for the same Looking Glass release as the host"
+            }
+            LGError::UnsupportedFeature(_) => {
+                "Update the host, or stop requiring the feature with require_features"
+            }
+            #[cfg(feature = "lgmp")]
+            LGError::SHMDeviceError(_) => {
+                "Check that the shared memory file exists and that this user can read and \
+                 write it"
+            }
+            LGError::SHMFileError(_) => {
+                "Check that the shared memory file or kvmfr device exists and that this user \
+                 can read and write it"
+            }
+            LGError::DmabufUnsupported => {
+                "Open the connection through a kvmfr device such as /dev/kvmfr0 rather than a \
+                 file under /dev/shm"
+            }
+            LGError::CaptureTimedOut => {
+                "Hosts only send frames when the screen changes, so check that the guest \
+                 display is not asleep"
+            }
+            LGError::CorruptMessage
+            | LGError::FrameChannelMessageTooSmall
+            | LGError::CursorChannelMessageTooSmall
+            | LGError::FrameDataOutOfBounds
+            | LGError::MisalignedMessage => {
+                "The host may be using a different protocol version, or something else is \
+                 writing to the shared memory"
+            }
+            _ => return None,
+        };
+        Some(hint)
+    }
+
+    /// Returns a human readable report of the error, including its sources and any
+    /// [hint](Self::hint), for printing from binaries.
+    pub fn report(&self) -> Report<'_> {
+        Report(self)
+    }
+}
+
+/// Broad kinds of [LGError], which scripts wrapping tools built on this crate can tell
+/// apart by exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The host is not running, or is not sending anything
+    HostUnavailable,
+    /// The session was lost and the connection must be re-initialised
+    SessionLost,
+    /// The host uses a protocol version or feature this client does not support
+    Incompatible,
+    /// Data recieved from the host or read from a file was malformed
+    BadData,
+    /// The shared memory or another file could not be accessed
+    Io,
+    /// The crate was called with invalid arguments
+    Usage,
+    /// A bug in this crate or the calling program
+    Internal,
+}
+
+impl ErrorCategory {
+    /// Returns the exit code for this category, taken from the BSD `sysexits.h` values.
+    /// These are stable, and will not change between releases.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCategory::Usage => 64,
+            ErrorCategory::BadData => 65,
+            ErrorCategory::HostUnavailable => 69,
+            ErrorCategory::Internal => 70,
+            ErrorCategory::Io => 74,
+            ErrorCategory::SessionLost => 75,
+            ErrorCategory::Incompatible => 76,
+        }
+    }
+}
+
+/// Human readable report of an [LGError], returned by [LGError::report].
+pub struct Report<'a>(&'a LGError);
+
+impl std::fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut msg = self.0.to_string();
+        write!(f, "error: {msg}")?;
+        let mut source = std::error::Error::source(self.0);
+        while let Some(err) = source {
+            //Most variants already include their source in the message
+            let next = err.to_string();
+            if !msg.contains(&next) {
+                write!(f, "\n  caused by: {next}")?;
+            }
+            msg = next;
+            source = err.source();
+        }
+        if let Some(hint) = self.0.hint() {
+            write!(f, "\n  hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "lgmp")]
+impl From<crate::lgmp_impl::error::Error> for LGError {
+    /// Sorts the statuses which callers are likely to act on into their own variants.
+    fn from(e: crate::lgmp_impl::error::Error) -> Self {
+        use crate::lgmp_impl::error::{Error, Status};
+        match e {
+            Error::InternalError(Status::LGMPErrInvalidSession) => Self::SessionInvalid,
+            Error::InternalError(Status::LGMPErrQueueTimeout) => Self::ClientTimedOut,
+            Error::InternalError(Status::LGMPErrInvalidMagic) => Self::HostUnavailable,
+            Error::InternalError(Status::LGMPErrCorrupted) => Self::CorruptMessage,
+            Error::InternalError(
+                status @ (Status::LGMPErrQueueFull | Status::LGMPErrQueueEmpty),
+            ) => Self::Transient(status),
+            e => Self::LGMPCommunicationError(e),
+        }
+    }
 }
 
 impl<T> From<PoisonError<T>> for LGError {
@@ -23,3 +306,47 @@ impl<T> From<PoisonError<T>> for LGError {
         Self::LGMPClientLockPoisonError
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "lgmp")]
+    fn classifies_lgmp_errors() {
+        use crate::lgmp_impl::error::{Error, Status};
+
+        let err = LGError::from(Error::InternalError(Status::LGMPErrQueueTimeout));
+        assert!(matches!(err, LGError::ClientTimedOut));
+        assert!(err.requires_reconnect() && !err.is_retryable());
+
+        let err = LGError::from(Error::InternalError(Status::LGMPErrQueueFull));
+        assert!(matches!(err, LGError::Transient(Status::LGMPErrQueueFull)));
+        assert!(err.is_retryable() && !err.requires_reconnect());
+
+        let err = LGError::from(Error::InternalError(Status::LGMPErrNoMem));
+        assert!(matches!(err, LGError::LGMPCommunicationError(_)));
+        assert!(!err.is_retryable() && !err.requires_reconnect());
+    }
+
+    #[test]
+    fn reports_errors_for_binaries() {
+        let err = LGError::HostUnavailable;
+        assert_eq!(err.exit_code(), 69);
+        let report = err.report().to_string();
+        assert!(report.starts_with("error: The host is not running"));
+        assert!(report.contains("\n  hint: "));
+
+        let err = LGError::from(std::io::Error::other("denied"));
+        assert_eq!(err.category(), ErrorCategory::Io);
+        assert!(err.report().to_string().contains("due to error denied"));
+        assert!(!err.report().to_string().contains("caused by"));
+    }
+
+    #[test]
+    fn errors_can_be_boxed() {
+        //Needed for use with anyhow and Box<dyn Error>
+        fn check<E: std::error::Error + Send + Sync + 'static>() {}
+        check::<LGError>();
+    }
+}
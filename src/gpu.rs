@@ -0,0 +1,113 @@
+//! Helpers for uploading frames into `wgpu` textures.
+//!
+//! Only the `wgpu` API is depended on, without any backends enabled, so the application
+//! remains in control of which backends are compiled in.
+use crate::{
+    client::KVMFRFrameHandle,
+    error::LGError,
+    types::{DamageRect, PixelFormat},
+};
+
+/// Returns the texture format which holds frames of the given pixel format without
+/// conversion, or None if there isn't one.
+///
+/// [PixelFormat::Bgr32] maps to a BGRA format, so the alpha channel should be ignored
+/// when sampling. [PixelFormat::Rgb24] has no 24 bit texture equivalent, so must first be
+/// converted with [crate::convert::to_rgba8].
+pub fn texture_format(format: PixelFormat) -> Option<wgpu::TextureFormat> {
+    match format {
+        PixelFormat::Bgra | PixelFormat::Bgr32 => Some(wgpu::TextureFormat::Bgra8Unorm),
+        PixelFormat::Rgba => Some(wgpu::TextureFormat::Rgba8Unorm),
+        PixelFormat::Rgba10 => Some(wgpu::TextureFormat::Rgb10a2Unorm),
+        PixelFormat::Rgba16F => Some(wgpu::TextureFormat::Rgba16Float),
+        PixelFormat::Rgb24 => None,
+    }
+}
+
+/// Writes a frame into a texture, only uploading the regions which the host reported as
+/// damaged.
+///
+/// The texture must have the format given by [texture_format] and be at least as large as
+/// the frame data, otherwise [LGError::TextureMismatch] is returned. It also needs the
+/// `COPY_DST` usage.
+pub fn upload_frame(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    frame: &KVMFRFrameHandle,
+) -> Result<(), LGError> {
+    let info = frame.info()?;
+    let format = texture_format(info.format).ok_or(LGError::UnsupportedPixelFormat(info.format))?;
+    if texture.format() != format
+        || texture.width() < info.data_width
+        || texture.height() < info.data_height
+    {
+        Err(LGError::TextureMismatch)?
+    }
+    let data = frame.data()?;
+    let bpp = info.format.bytes_per_pixel();
+
+    let full = [DamageRect {
+        x: 0,
+        y: 0,
+        width: info.data_width,
+        height: info.data_height,
+    }];
+    let rects = match frame.damage_rects()? {
+        [] => &full[..],
+        rects => rects,
+    };
+    for rect in rects {
+        //Don't trust the host to keep rects within the frame
+        let x = rect.x.min(info.data_width);
+        let y = rect.y.min(info.data_height);
+        let width = rect.width.min(info.data_width - x);
+        let height = rect.height.min(info.data_height - y);
+        if width == 0 || height == 0 {
+            continue;
+        }
+
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::TexelCopyBufferLayout {
+                offset: u64::from(y) * u64::from(info.pitch) + u64::from(x * bpp),
+                bytes_per_row: Some(info.pitch),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_formats_match_pixel_size() {
+        for format in [
+            PixelFormat::Bgra,
+            PixelFormat::Rgba,
+            PixelFormat::Rgba10,
+            PixelFormat::Rgba16F,
+            PixelFormat::Bgr32,
+        ] {
+            let texture = texture_format(format).unwrap();
+            assert_eq!(
+                texture.block_copy_size(None),
+                Some(format.bytes_per_pixel())
+            );
+        }
+        assert_eq!(texture_format(PixelFormat::Rgb24), None);
+    }
+}
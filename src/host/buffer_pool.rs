@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+
+use crate::lgmp_impl::host::LGMPMemoryAllocation;
+
+use crate::error::LGError;
+
+/// A fixed set of shared memory buffers which are handed out in turn.
+///
+/// Buffers are zeroed when the pool is created, and whenever a buffer is reused any bytes
+/// written last time which will not be overwritten are cleared, so that clients never see
+/// stale data from an earlier message.
+pub(super) struct BufferPool {
+    buffers: Vec<Arc<Mutex<LGMPMemoryAllocation>>>,
+    /// Number of bytes at the start of each buffer which may hold data
+    used: Vec<usize>,
+    next: usize,
+}
+
+impl BufferPool {
+    pub(super) fn new(buffers: Vec<Arc<Mutex<LGMPMemoryAllocation>>>) -> Result<Self, LGError> {
+        let mut pool = BufferPool {
+            used: buffers.iter().map(|_| usize::MAX).collect(),
+            buffers,
+            next: 0,
+        };
+        //The shared memory may have been used by a previous host
+        pool.scrub()?;
+        Ok(pool)
+    }
+
+    /// Returns the index of the next buffer to use along with a handle to it.
+    pub(super) fn take(&mut self) -> (usize, Arc<Mutex<LGMPMemoryAllocation>>) {
+        let idx = self.next;
+        self.next = (idx + 1) % self.buffers.len();
+        (idx, self.buffers[idx].clone())
+    }
+
+    pub(super) fn get(&self, idx: usize) -> &Arc<Mutex<LGMPMemoryAllocation>> {
+        &self.buffers[idx]
+    }
+
+    /// Prepares a buffer returned by [Self::take] to have `len` bytes written to it,
+    /// clearing anything beyond that which was written the last time it was used.
+    pub(super) fn recycle(
+        &mut self,
+        idx: usize,
+        alloc: &mut LGMPMemoryAllocation,
+        len: usize,
+    ) -> Result<(), LGError> {
+        zero(alloc, len, self.used[idx])?;
+        self.used[idx] = len;
+        Ok(())
+    }
+
+    /// Zeroes every buffer in the pool.
+    pub(super) fn scrub(&mut self) -> Result<(), LGError> {
+        for (buffer, used) in self.buffers.iter().zip(self.used.iter_mut()) {
+            zero(&mut *buffer.lock()?, 0, *used)?;
+            *used = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Zeroes the bytes from `start` to `end` of an allocation, clamped to its size.
+fn zero(alloc: &mut LGMPMemoryAllocation, start: usize, end: usize) -> Result<(), LGError> {
+    let end = end.min(alloc.len());
+    if start >= end {
+        return Ok(());
+    }
+    let base = alloc.mem_ptr()? as *mut u8;
+    //The range has been clamped to the allocation
+    unsafe { std::ptr::write_bytes(base.add(start), 0, end - start) };
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use super::LGMPHostConnection;
+use crate::{
+    error::LGError,
+    threads::{ThreadMonitor, ThreadRole},
+};
+
+/// A background thread which runs [LGMPHostConnection::process_if_due] at the host's
+/// process interval, in the same way as the official host's timer.
+///
+/// This keeps the heartbeat fresh and times out stalled clients even while nothing is
+/// being published. The thread is stopped when this is dropped.
+pub struct HostHeartbeat {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HostHeartbeat {
+    /// Starts running housekeeping on a shared host connection.
+    ///
+    /// The thread exits early if processing fails, as this means that the shared memory
+    /// is no longer usable. Publishing will report the same error.
+    pub fn spawn(host: Arc<Mutex<LGMPHostConnection>>) -> Result<HostHeartbeat, LGError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name("lg-heartbeat".into())
+            .spawn(move || {
+                let monitor = ThreadMonitor::register(ThreadRole::HostHeartbeat);
+                while !thread_stop.load(Ordering::Relaxed) {
+                    let interval = {
+                        let Ok(mut host) = host.lock() else {
+                            return;
+                        };
+                        if host.process_if_due().is_err() {
+                            return;
+                        }
+                        host.process_interval()
+                    };
+                    monitor.beat();
+                    std::thread::sleep(interval);
+                }
+            })
+            .map_err(LGError::ThreadSpawnError)?;
+
+        Ok(HostHeartbeat {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for HostHeartbeat {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
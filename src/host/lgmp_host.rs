@@ -0,0 +1,511 @@
+use std::{
+    mem::size_of,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+use zerocopy::{FromZeros, IntoBytes};
+
+use crate::lgmp_impl::{
+    error::Status,
+    host::{Host, LGMPHostQueue, LGMPQueueConfig},
+};
+
+use super::buffer_pool::BufferPool;
+use crate::{
+    client::SharedMem,
+    copy::CopyStrategy,
+    damage::{DamageEstimator, DamageEstimatorOpts},
+    error::LGError,
+    shm_datastructs,
+    types::{
+        CursorFlags, CursorType, DamageRect, FrameFlags, HdrMetadata, HostFeatures, HostMessage,
+        PixelFormat, Rotation,
+    },
+};
+
+/// Space reserved at the start of each frame buffer for the KVMFRFrame header. Frame data
+/// begins immediately after this, so that it is page aligned.
+const FRAME_HEADER_SPACE: u32 = 4096;
+/// Largest cursor shape bitmap which can be published, in bytes.
+const MAX_POINTER_SHAPE_SIZE: u32 = 512 * 512 * 4;
+/// Number of buffers to rotate between when publishing cursor shapes.
+const POINTER_SHAPE_BUFFERS: u32 = 3;
+/// Time in milliseconds after which the host will drop a subscriber which has stopped
+/// reading from a queue.
+const QUEUE_SUB_TIMEOUT_MS: u32 = 1000;
+/// Interval at which the official host runs LGMP housekeeping.
+pub const DEFAULT_PROCESS_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Everything about a frame which clients need to reconfigure for when it changes: its
+/// format, width, height, rows present, stride, pitch, rotation and HDR metadata.
+type FrameFormat = (
+    PixelFormat,
+    u32,
+    u32,
+    u32,
+    u32,
+    u32,
+    Rotation,
+    Option<HdrMetadata>,
+);
+
+#[derive(Clone)]
+pub struct LGMPHostOpts {
+    /// Path at which the shared memory flink file will be created
+    pub shm_path: String,
+    /// Total size of the shared memory region in bytes
+    pub shm_size: usize,
+    /// Largest frame (in bytes of pixel data) which will be published
+    pub max_frame_size: u32,
+    /// Version string reported to clients
+    pub host_version: String,
+    /// Optional features advertised to clients. Clients will only send the
+    /// [HostMessage]s needed by these, which can be read with
+    /// [LGMPHostConnection::read_message].
+    pub features: HostFeatures,
+    /// If set, frames published without damage information will be compared against the
+    /// previous frame to work out which regions have changed
+    pub damage_estimation: Option<DamageEstimatorOpts>,
+    /// How frame data is copied into shared memory. Clients are notified of progress
+    /// after each chunk, so smaller chunks let them start reading sooner.
+    pub copy_strategy: CopyStrategy,
+    /// How often LGMP housekeeping should run, which refreshes the heartbeat timestamp
+    /// that clients use to detect a live host and drops subscribers which have timed out.
+    /// See [LGMPHostConnection::process_if_due].
+    pub process_interval: Duration,
+}
+
+/// Description of a frame being published by the host.
+#[derive(Debug, Clone)]
+pub struct HostFrame {
+    pub format: PixelFormat,
+    /// Width of the guest screen
+    pub screen_width: u32,
+    /// Height of the guest screen
+    pub screen_height: u32,
+    /// Width of the frame data
+    pub width: u32,
+    /// Height of the frame data
+    pub height: u32,
+    /// Row length in pixels
+    pub stride: u32,
+    /// Row length in bytes
+    pub pitch: u32,
+    pub rotation: Rotation,
+    /// Regions of the frame which have changed since the previous frame, if known by the
+    /// capture backend. If None, damage will be estimated if enabled in [LGMPHostOpts],
+    /// otherwise the whole frame is treated as damaged.
+    pub damage: Option<Vec<DamageRect>>,
+    /// HDR metadata of the captured surface, or None if it is SDR
+    pub hdr: Option<HdrMetadata>,
+}
+
+/// A cursor update being published by the host.
+#[derive(Debug, Clone)]
+pub struct HostCursor<'a> {
+    /// New position of the cursor, if it has moved
+    pub position: Option<(i16, i16)>,
+    pub visible: bool,
+    /// New shape of the cursor, if it has changed
+    pub shape: Option<HostCursorShape<'a>>,
+}
+
+/// A new cursor shape being published by the host.
+#[derive(Debug, Clone)]
+pub struct HostCursorShape<'a> {
+    pub cursor_type: CursorType,
+    pub width: u32,
+    pub height: u32,
+    /// Row length in bytes
+    pub pitch: u32,
+    pub hotspot: (i8, i8),
+    pub data: &'a [u8],
+}
+
+pub struct LGMPHostConnection {
+    frame_queue: LGMPHostQueue,
+    cursor_queue: LGMPHostQueue,
+
+    frame_buffers: BufferPool,
+    cursor_buffers: BufferPool,
+    shape_buffers: BufferPool,
+    last_frame_buffer: Option<usize>,
+    last_shape: Option<(usize, u32)>,
+
+    frame_serial: u32,
+    format_ver: u32,
+    last_format: Option<FrameFormat>,
+    damage_estimator: Option<DamageEstimator>,
+    last_process: Instant,
+
+    opts: LGMPHostOpts,
+    //Must be dropped last, as it owns the shared memory mapping
+    host: Host,
+}
+
+impl LGMPHostConnection {
+    /// Creates the shared memory region, writes the KVMFR header and sets up the frame
+    /// and cursor queues.
+    ///
+    /// Clients will be able to connect as soon as this returns, although [Self::process]
+    /// must be called regularly from then on.
+    pub fn create(opts: LGMPHostOpts) -> Result<LGMPHostConnection, LGError> {
+        let shm_file = shared_memory::ShmemConf::new()
+            .size(opts.shm_size)
+            .flink(&opts.shm_path)
+            .create()?;
+        let udata = kvmfr_udata(&opts.host_version, opts.features);
+        let mut host = Host::init(Box::new(SharedMem::new(shm_file)), &udata)?;
+
+        //Create queues
+        let frame_queue = host.queue_new(LGMPQueueConfig {
+            queue_id: shm_datastructs::LGMP_Q_FRAME,
+            num_messages: shm_datastructs::LGMP_Q_FRAME_LEN,
+            sub_timeout: QUEUE_SUB_TIMEOUT_MS,
+        })?;
+        let cursor_queue = host.queue_new(LGMPQueueConfig {
+            queue_id: shm_datastructs::LGMP_Q_POINTER,
+            num_messages: shm_datastructs::LGMP_Q_POINTER_LEN,
+            sub_timeout: QUEUE_SUB_TIMEOUT_MS,
+        })?;
+
+        //Allocate message buffers
+        let cursor_size = size_of::<shm_datastructs::KVMFRCursor>() as u32;
+        let frame_buffers = BufferPool::new(
+            (0..shm_datastructs::LGMP_Q_FRAME_LEN)
+                .map(|_| host.mem_alloc_aligned(FRAME_HEADER_SPACE + opts.max_frame_size, 4096))
+                .collect::<Result<Vec<_>, _>>()?,
+        )?;
+        let cursor_buffers = BufferPool::new(
+            (0..shm_datastructs::LGMP_Q_POINTER_LEN)
+                .map(|_| host.mem_alloc(cursor_size))
+                .collect::<Result<Vec<_>, _>>()?,
+        )?;
+        let shape_buffers = BufferPool::new(
+            (0..POINTER_SHAPE_BUFFERS)
+                .map(|_| host.mem_alloc(cursor_size + MAX_POINTER_SHAPE_SIZE))
+                .collect::<Result<Vec<_>, _>>()?,
+        )?;
+
+        let damage_estimator = opts.damage_estimation.clone().map(DamageEstimator::new);
+
+        Ok(LGMPHostConnection {
+            frame_queue,
+            cursor_queue,
+            frame_buffers,
+            cursor_buffers,
+            shape_buffers,
+            last_frame_buffer: None,
+            last_shape: None,
+            frame_serial: 0,
+            format_ver: 0,
+            last_format: None,
+            damage_estimator,
+            last_process: Instant::now(),
+            opts,
+            host,
+        })
+    }
+
+    /// Runs housekeeping on the LGMP queues, and re-sends the most recent frame and cursor
+    /// shape to any clients which have subscribed since this was last called.
+    ///
+    /// This should be called regularly, every few milliseconds.
+    pub fn process(&mut self) -> Result<(), LGError> {
+        self.last_process = Instant::now();
+        self.host.process()?;
+
+        if self.frame_queue.new_subs() > 0 {
+            if let Some(idx) = self.last_frame_buffer {
+                let alloc = self.frame_buffers.get(idx).lock()?;
+                ignore_queue_full(self.frame_queue.post_shared_mem(0, &*alloc))?;
+            }
+        }
+        if self.cursor_queue.new_subs() > 0 {
+            if let Some((idx, flags)) = self.last_shape {
+                let alloc = self.shape_buffers.get(idx).lock()?;
+                ignore_queue_full(self.cursor_queue.post_shared_mem(flags, &*alloc))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls [Self::process] if the `process_interval` has passed since it last ran,
+    /// returning whether it did.
+    ///
+    /// This is called before publishing anything, so that clients which stall are still
+    /// timed out and their queue positions released, but a host which publishes
+    /// infrequently must also call this itself or use [HostHeartbeat].
+    pub fn process_if_due(&mut self) -> Result<bool, LGError> {
+        if self.last_process.elapsed() < self.opts.process_interval {
+            return Ok(false);
+        }
+        self.process()?;
+        Ok(true)
+    }
+
+    pub(super) fn process_interval(&self) -> Duration {
+        self.opts.process_interval
+    }
+
+    /// Publishes a new frame on the frame queue.
+    ///
+    /// `data` should contain the pixel data laid out as described by `frame`. If it is
+    /// larger than the `max_frame_size` the host was created with, the frame is sent with
+    /// [FrameFlags::TRUNCATED] and only the rows which fit, as Looking Glass does for frames
+    /// too large for its buffers.
+    /// Returns [LGError::HostFrameDataTooSmall] if `data` holds fewer than `pitch * height`
+    /// bytes, or the pitch is too small for a row of pixels,
+    /// [LGError::HostFrameRowTooLarge] if not even one row fits in `max_frame_size`, and
+    /// [LGError::Transient] with LGMPErrQueueFull if clients have not yet read enough of
+    /// the previous frames for a buffer to be free.
+    pub fn publish_frame(&mut self, frame: &HostFrame, data: &[u8]) -> Result<(), LGError> {
+        let row_len = u64::from(frame.width) * u64::from(frame.format.bytes_per_pixel());
+        let needed = u64::from(frame.pitch) * u64::from(frame.height);
+        if u64::from(frame.pitch) < row_len || (data.len() as u64) < needed {
+            Err(LGError::HostFrameDataTooSmall)?
+        }
+        let data = &data[..data.len().min(self.opts.max_frame_size as usize)];
+        let rows = present_rows(frame, data.len());
+        if rows == 0 && frame.height > 0 {
+            Err(LGError::HostFrameRowTooLarge)?
+        }
+        self.process_if_due()?;
+        if self.frame_queue.pending() >= shm_datastructs::LGMP_Q_FRAME_LEN {
+            Err(crate::lgmp_impl::error::Error::InternalError(
+                Status::LGMPErrQueueFull,
+            ))?
+        }
+
+        //Clients use the format version to detect when they need to reconfigure
+        let format = (
+            frame.format,
+            frame.width,
+            frame.height,
+            rows,
+            frame.stride,
+            frame.pitch,
+            frame.rotation,
+            frame.hdr,
+        );
+        if self.last_format != Some(format) {
+            self.format_ver = self.format_ver.wrapping_add(1);
+            self.last_format = Some(format);
+        }
+        self.frame_serial = self.frame_serial.wrapping_add(1);
+
+        //Always run the estimator so that it has an up to date copy of the previous frame
+        let estimated = self.damage_estimator.as_mut().and_then(|est| {
+            est.estimate(
+                data,
+                frame.width,
+                rows,
+                frame.pitch,
+                frame.format.bytes_per_pixel(),
+            )
+        });
+        let damage = frame.damage.clone().or(estimated);
+
+        let (idx, handle) = self.frame_buffers.take();
+        let mut alloc = handle.lock()?;
+        self.frame_buffers
+            .recycle(idx, &mut alloc, FRAME_HEADER_SPACE as usize + data.len())?;
+        let base = alloc.mem_ptr()? as *mut u8;
+
+        let mut header = shm_datastructs::KVMFRFrame::new_zeroed();
+        header.formatVer = self.format_ver;
+        header.frameSerial = self.frame_serial;
+        header.type_ = frame.format.into();
+        header.screenWidth = frame.screen_width;
+        header.screenHeight = frame.screen_height;
+        header.dataWidth = frame.width;
+        header.dataHeight = rows;
+        header.frameWidth = frame.width;
+        header.frameHeight = frame.height;
+        header.rotation = frame.rotation.into();
+        header.stride = frame.stride;
+        header.pitch = frame.pitch;
+        let mut flags = frame.hdr.map_or(FrameFlags::empty(), |hdr| hdr.flags());
+        flags.set(FrameFlags::TRUNCATED, rows < frame.height);
+        header.flags = flags.bits();
+        header.offset = FRAME_HEADER_SPACE - shm_datastructs::FRAME_BUFFER_HEADER_SIZE as u32;
+        //A count of zero means that the whole frame is damaged, so there is no way to express
+        //an unchanged frame; these are sent as fully damaged.
+        if let Some(rects) = damage.filter(|rects| rects.len() <= header.damageRects.len()) {
+            for (dst, src) in header.damageRects.iter_mut().zip(rects.iter()) {
+                *dst = (*src).into();
+            }
+            header.damageRectsCount = rects.len() as u32;
+        }
+
+        // The allocation is at least FRAME_HEADER_SPACE + max_frame_size bytes, page aligned,
+        // and we have already checked that data fits.
+        let write_ptr = unsafe {
+            std::ptr::write(base.cast::<shm_datastructs::KVMFRFrame>(), header);
+            &*base.add(header.offset as usize).cast::<AtomicU32>()
+        };
+        write_ptr.store(0, Ordering::Release);
+
+        //Post before copying so that clients can start reading as data arrives
+        self.frame_queue.post_shared_mem(0, &*alloc)?;
+        unsafe {
+            self.opts.copy_strategy.copy_to_ptr(
+                data,
+                base.add(FRAME_HEADER_SPACE as usize),
+                |written| write_ptr.store(written as u32, Ordering::Release),
+            )
+        };
+
+        self.last_frame_buffer = Some(idx);
+        Ok(())
+    }
+
+    /// Publishes a cursor update on the pointer queue.
+    pub fn publish_cursor(&mut self, cursor: &HostCursor) -> Result<(), LGError> {
+        self.process_if_due()?;
+        let mut flags = CursorFlags::empty();
+        let mut header = shm_datastructs::KVMFRCursor::new_zeroed();
+        if let Some((x, y)) = cursor.position {
+            flags |= CursorFlags::POSITION;
+            header.x = x;
+            header.y = y;
+        }
+        if cursor.visible {
+            flags |= CursorFlags::VISIBLE;
+        }
+
+        let (pool, shape_len) = match cursor.shape {
+            Some(ref shape) => {
+                if shape.data.len() > MAX_POINTER_SHAPE_SIZE as usize {
+                    Err(LGError::CursorShapeTooLarge)?
+                }
+                flags |= CursorFlags::SHAPE;
+                header.type_ = shape.cursor_type.into();
+                header.hx = shape.hotspot.0;
+                header.hy = shape.hotspot.1;
+                header.width = shape.width;
+                header.height = shape.height;
+                header.pitch = shape.pitch;
+
+                (&mut self.shape_buffers, shape.data.len())
+            }
+            None => (&mut self.cursor_buffers, 0),
+        };
+        let (idx, handle) = pool.take();
+        let mut alloc = handle.lock()?;
+        pool.recycle(
+            idx,
+            &mut alloc,
+            size_of::<shm_datastructs::KVMFRCursor>() + shape_len,
+        )?;
+        if cursor.shape.is_some() {
+            self.last_shape = Some((idx, flags.bits()));
+        }
+
+        let base = alloc.mem_ptr()? as *mut u8;
+        // Shape buffers have room for the header plus MAX_POINTER_SHAPE_SIZE bytes, and
+        // position buffers are only ever written with the header.
+        unsafe {
+            std::ptr::write_unaligned(base.cast::<shm_datastructs::KVMFRCursor>(), header);
+            if let Some(ref shape) = cursor.shape {
+                std::ptr::copy_nonoverlapping(
+                    shape.data.as_ptr(),
+                    base.add(size_of::<shm_datastructs::KVMFRCursor>()),
+                    shape.data.len(),
+                );
+            }
+        }
+
+        self.cursor_queue.post_shared_mem(flags.bits(), &*alloc)?;
+        Ok(())
+    }
+
+    /// Zeroes every frame and cursor buffer in shared memory, and forgets the last frame and
+    /// cursor shape so that they are not re-sent to new clients.
+    ///
+    /// This is intended for use when a session ends, so that guest screen contents are not
+    /// left behind in the shared memory. Clients which are still connected may see
+    /// blank frames if they read a message after it has been scrubbed.
+    pub fn scrub_buffers(&mut self) -> Result<(), LGError> {
+        self.frame_buffers.scrub()?;
+        self.cursor_buffers.scrub()?;
+        self.shape_buffers.scrub()?;
+        self.last_frame_buffer = None;
+        self.last_shape = None;
+        Ok(())
+    }
+
+    /// Reads the next message sent by a client on the cursor queue, or None if there are
+    /// none waiting.
+    ///
+    /// Messages which cannot be parsed are still consumed, so that one bad message does not
+    /// block the queue.
+    pub fn read_message(&mut self) -> Result<Option<HostMessage>, LGError> {
+        let data = match self.cursor_queue.read_data() {
+            Ok(data) => data,
+            Err(crate::lgmp_impl::error::Error::InternalError(Status::LGMPErrQueueEmpty)) => {
+                return Ok(None)
+            }
+            Err(e) => Err(e)?,
+        };
+        self.cursor_queue.ack_data()?;
+        HostMessage::parse(&data).map(Some)
+    }
+
+    /// Returns true if any clients are currently subscribed to the frame queue.
+    pub fn has_frame_subscribers(&self) -> bool {
+        self.frame_queue.has_subs()
+    }
+
+    /// Returns true if any clients are currently subscribed to the cursor queue.
+    pub fn has_cursor_subscribers(&self) -> bool {
+        self.cursor_queue.has_subs()
+    }
+}
+
+/// Builds the KVMFR header which is passed to clients as LGMP udata.
+fn kvmfr_udata(host_version: &str, features: HostFeatures) -> Vec<u8> {
+    let mut udata = shm_datastructs::KVMFR::new_zeroed();
+    for (dst, src) in udata
+        .magic
+        .iter_mut()
+        .zip(shm_datastructs::KVMFR_MAGIC.iter())
+    {
+        *dst = *src as _;
+    }
+    udata.version = shm_datastructs::KVMFR_VERSION;
+    udata.features = features.bits();
+    //Leave room for the terminating NUL
+    let max_ver_len = udata.hostver.len() - 1;
+    for (dst, src) in udata
+        .hostver
+        .iter_mut()
+        .take(max_ver_len)
+        .zip(host_version.bytes())
+    {
+        *dst = src as _;
+    }
+
+    udata.as_bytes().to_vec()
+}
+
+/// Returns the number of rows of `frame` held in `len` bytes of pixel data, up to its height.
+fn present_rows(frame: &HostFrame, len: usize) -> u32 {
+    let row_len = frame.width as usize * frame.format.bytes_per_pixel() as usize;
+    match (len.checked_sub(row_len), frame.pitch as usize) {
+        (_, 0) => frame.height,
+        (Some(rest), pitch) => (rest / pitch + 1).min(frame.height as usize) as u32,
+        (None, _) => 0,
+    }
+}
+
+/// Treats a full queue as success, for use when re-sending messages that clients are
+/// likely to already have pending.
+fn ignore_queue_full(res: crate::lgmp_impl::error::LGMPResult<()>) -> Result<(), LGError> {
+    match res {
+        Err(crate::lgmp_impl::error::Error::InternalError(Status::LGMPErrQueueFull)) => Ok(()),
+        res => Ok(res?),
+    }
+}
@@ -0,0 +1,9 @@
+mod buffer_pool;
+mod heartbeat;
+mod lgmp_host;
+
+pub use heartbeat::HostHeartbeat;
+pub use lgmp_host::{
+    HostCursor, HostCursorShape, HostFrame, LGMPHostConnection, LGMPHostOpts,
+    DEFAULT_PROCESS_INTERVAL,
+};
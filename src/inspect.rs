@@ -0,0 +1,231 @@
+//! Parsing of raw KVMFR messages from byte buffers, for working with captured dumps rather
+//! than a live connection.
+//!
+//! Unlike the client and host, this does not depend on LGMP or shared memory, so is
+//! available with the `lgmp` feature disabled, including on wasm32.
+use std::mem::size_of;
+
+use zerocopy::FromBytes;
+
+use crate::{
+    error::LGError,
+    shm_datastructs,
+    types::{FrameInfo, HostFeatures, HostInfo, OsInfo, OsType, VmInfo},
+};
+
+/// Raw KVMFR structs returned by the parsers, laid out as in shared memory.
+pub use crate::shm_datastructs::{FrameDamageRect, KVMFRCursor, KVMFRFrame, KVMFR};
+
+/// Parses the KVMFR header which the host passes to clients as LGMP udata, checking the
+/// magic and protocol version.
+///
+/// Any data following the header is ignored.
+pub fn parse_kvmfr_udata(bytes: &[u8]) -> Result<KVMFR, LGError> {
+    let udata: KVMFR = read_struct(bytes).ok_or(LGError::KVMFRVersionMismatch(
+        shm_datastructs::KVMFR_VERSION,
+    ))?;
+    let magic = udata.magic.map(|c| c as u8);
+    if magic[..] != shm_datastructs::KVMFR_MAGIC[0..magic.len()]
+        || udata.version != shm_datastructs::KVMFR_VERSION
+    {
+        Err(LGError::KVMFRVersionMismatch(
+            shm_datastructs::KVMFR_VERSION,
+        ))?
+    }
+    Ok(udata)
+}
+
+/// Parses the full KVMFR udata, including any records which follow the header.
+///
+/// Records which are truncated or of an unknown type are ignored, as older hosts send none
+/// and newer ones may add more.
+pub fn parse_host_info(bytes: &[u8]) -> Result<HostInfo, LGError> {
+    let udata = parse_kvmfr_udata(bytes)?;
+    let mut info = HostInfo {
+        version: udata.version,
+        host_version: c_string(&udata.hostver.map(|c| c as u8)),
+        features: HostFeatures::from_bits_retain(udata.features),
+        vm: None,
+        os: None,
+    };
+
+    //Each record is a packed type byte and 32 bit length, followed by its data
+    let header_size = size_of::<shm_datastructs::KVMFRRecord>();
+    let mut rest = &bytes[size_of::<KVMFR>()..];
+    while rest.len() >= header_size {
+        let record_type = u32::from(rest[0]);
+        let size = u32::from_ne_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+        let end = header_size.saturating_add(size);
+        let Some(data) = rest.get(header_size..end) else {
+            break;
+        };
+        rest = &rest[end..];
+
+        match record_type {
+            shm_datastructs::KVMFR_RECORD_VMINFO => info.vm = parse_vm_info(data),
+            shm_datastructs::KVMFR_RECORD_OSINFO => info.os = parse_os_info(data),
+            _ => (),
+        }
+    }
+    Ok(info)
+}
+
+fn parse_vm_info(data: &[u8]) -> Option<VmInfo> {
+    //The model name is a variable length string in place of the final field
+    let fixed = size_of::<shm_datastructs::KVMFRRecord_VMInfo>() - 1;
+    let vm: shm_datastructs::KVMFRRecord_VMInfo = read_struct(data)?;
+    Some(VmInfo {
+        uuid: vm.uuid,
+        capture: c_string(&vm.capture.map(|c| c as u8)),
+        cpus: vm.cpus,
+        cores: vm.cores,
+        sockets: vm.sockets,
+        model: c_string(&data[fixed..]),
+    })
+}
+
+fn parse_os_info(data: &[u8]) -> Option<OsInfo> {
+    let (&os, name) = data.split_first()?;
+    Some(OsInfo {
+        os: OsType::try_from(u32::from(os)).unwrap_or(OsType::Other),
+        name: c_string(name),
+    })
+}
+
+/// Reads a string which is terminated by a NUL or the end of the buffer, replacing any
+/// invalid UTF-8.
+fn c_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// Parses the header of a message from the frame queue.
+pub fn parse_frame(bytes: &[u8]) -> Result<KVMFRFrame, LGError> {
+    read_struct(bytes).ok_or(LGError::FrameChannelMessageTooSmall)
+}
+
+/// Parses and validates the header of a message from the frame queue.
+pub fn parse_frame_info(bytes: &[u8]) -> Result<FrameInfo, LGError> {
+    FrameInfo::try_from(&parse_frame(bytes)?)
+}
+
+/// Parses a message from the frame queue, returning the header along with the pixel data
+/// which follows it.
+///
+/// The returned data is limited to `pitch * dataHeight` bytes, and may be shorter than
+/// that if the dump was truncated.
+pub fn parse_frame_data(bytes: &[u8]) -> Result<(KVMFRFrame, &[u8]), LGError> {
+    let frame = parse_frame(bytes)?;
+    //Pixel data follows the frame buffer header, which holds the host's write pointer
+    let start = (frame.offset as usize).saturating_add(shm_datastructs::FRAME_BUFFER_HEADER_SIZE);
+    let len = (frame.pitch as usize).saturating_mul(frame.dataHeight as usize);
+    let data = bytes
+        .get(start..)
+        .ok_or(LGError::FrameChannelMessageTooSmall)?;
+    Ok((frame, &data[..len.min(data.len())]))
+}
+
+/// Parses a message from the cursor queue, returning the header along with any shape
+/// bitmap which follows it.
+pub fn parse_cursor(bytes: &[u8]) -> Result<(KVMFRCursor, &[u8]), LGError> {
+    let cursor = read_struct(bytes).ok_or(LGError::CursorChannelMessageTooSmall)?;
+    Ok((cursor, &bytes[size_of::<KVMFRCursor>()..]))
+}
+
+/// Copies a plain C struct out of the start of a byte buffer, which need not be aligned.
+///
+/// Returns None if the buffer is too small.
+fn read_struct<T: FromBytes>(bytes: &[u8]) -> Option<T> {
+    T::read_from_prefix(bytes).ok().map(|(value, _)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use zerocopy::IntoBytes;
+
+    use super::*;
+
+    #[test]
+    fn parses_frame_and_data() {
+        let mut frame: shm_datastructs::KVMFRFrame = unsafe { std::mem::zeroed() };
+        frame.frameSerial = 7;
+        frame.pitch = 8;
+        frame.dataHeight = 2;
+        frame.offset = size_of::<shm_datastructs::KVMFRFrame>() as u32;
+
+        //Start at an odd offset to check that alignment doesn't matter
+        let mut dump = vec![0xff];
+        dump.extend(frame.as_bytes());
+        dump.extend([0; shm_datastructs::FRAME_BUFFER_HEADER_SIZE]);
+        dump.extend(1..=20u8);
+
+        let (parsed, data) = parse_frame_data(&dump[1..]).unwrap();
+        assert_eq!(parsed.frameSerial, 7);
+        assert_eq!(data, (1..=16u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        assert!(matches!(
+            parse_frame(&[0; 4]),
+            Err(LGError::FrameChannelMessageTooSmall)
+        ));
+        assert!(matches!(
+            parse_cursor(&[0; 4]),
+            Err(LGError::CursorChannelMessageTooSmall)
+        ));
+    }
+
+    #[test]
+    fn checks_udata_magic() {
+        let mut udata: shm_datastructs::KVMFR = unsafe { std::mem::zeroed() };
+        udata.version = shm_datastructs::KVMFR_VERSION;
+        assert!(parse_kvmfr_udata(udata.as_bytes()).is_err());
+
+        for (dst, src) in udata
+            .magic
+            .iter_mut()
+            .zip(shm_datastructs::KVMFR_MAGIC.iter())
+        {
+            *dst = *src as _;
+        }
+        assert!(parse_kvmfr_udata(udata.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn parses_host_info_records() {
+        let mut udata: shm_datastructs::KVMFR = unsafe { std::mem::zeroed() };
+        udata.version = shm_datastructs::KVMFR_VERSION;
+        udata.features = shm_datastructs::KVMFR_FEATURE_WINDOWSIZE;
+        for (dst, src) in udata
+            .magic
+            .iter_mut()
+            .zip(shm_datastructs::KVMFR_MAGIC.iter())
+        {
+            *dst = *src as _;
+        }
+        udata.hostver[..3].copy_from_slice(&[b'B' as _, b'7' as _, 0]);
+
+        let mut bytes = udata.as_bytes().to_vec();
+        let os_name = b"Windows 11\0";
+        bytes.push(shm_datastructs::KVMFR_RECORD_OSINFO as u8);
+        bytes.extend((os_name.len() as u32 + 1).to_ne_bytes());
+        bytes.push(shm_datastructs::KVMFR_OS_WINDOWS as u8);
+        bytes.extend(os_name);
+        //Records of unknown types and truncated records are skipped
+        bytes.extend([0x7f, 1, 0, 0, 0, 0]);
+        bytes.extend([shm_datastructs::KVMFR_RECORD_VMINFO as u8, 0xff, 0, 0, 0]);
+
+        let info = parse_host_info(&bytes).unwrap();
+        assert_eq!(info.host_version, "B7");
+        assert_eq!(info.features, HostFeatures::WINDOW_SIZE);
+        assert_eq!(
+            info.os,
+            Some(OsInfo {
+                os: OsType::Windows,
+                name: "Windows 11".to_string(),
+            })
+        );
+        assert_eq!(info.vm, None);
+    }
+}
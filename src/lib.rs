@@ -1,6 +1,65 @@
+#[cfg(any(test, feature = "alloc-count"))]
+pub mod alloc_count;
+pub mod capabilities;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "png")]
+pub mod capture;
+pub mod classify;
+#[cfg(feature = "lgmp")]
 pub mod client;
+pub mod color;
+pub mod convert;
+pub mod copy;
+pub mod crop;
+pub mod cursor;
+pub mod damage;
 pub mod error;
+#[cfg(feature = "wgpu")]
+pub mod gpu;
+#[cfg(feature = "lgmp")]
+pub mod host;
+pub mod inspect;
+//Mirrors the ligmars API, so has calls the client and host never make
+#[cfg(feature = "native-lgmp")]
+#[allow(dead_code)]
+pub(crate) mod native_lgmp;
+#[cfg(feature = "opencl")]
+pub mod opencl;
+mod parallel;
+pub mod pool;
+pub mod prelude;
+#[cfg(feature = "serde")]
+mod serde_impls;
 mod shm_datastructs;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod threads;
+pub mod types;
+
+pub use capabilities::capabilities;
+pub use threads::threads;
+
+#[cfg(all(
+    feature = "lgmp",
+    not(any(feature = "c-lgmp", feature = "native-lgmp"))
+))]
+compile_error!("The lgmp feature needs a backend, enable either c-lgmp or native-lgmp");
+
+//LGMP backend used by the client and host
+#[cfg(all(feature = "c-lgmp", not(feature = "native-lgmp")))]
+use ligmars as lgmp_impl;
+#[cfg(feature = "native-lgmp")]
+use native_lgmp as lgmp_impl;
+
+/// Errors from whichever LGMP backend is in use, as carried by [error::LGError].
+#[cfg(feature = "lgmp")]
+pub use lgmp_impl::error as lgmp_error;
+
+//Lets unit tests check how many allocations they make
+#[cfg(test)]
+#[global_allocator]
+static ALLOC: alloc_count::CountingAllocator = alloc_count::CountingAllocator;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
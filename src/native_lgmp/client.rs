@@ -0,0 +1,635 @@
+//! Structs and functions for connecting to an LGMP shared memory connection as a client.
+use std::{
+    ops::Deref,
+    sync::{atomic::Ordering::SeqCst, Arc, Mutex, MutexGuard},
+};
+
+use super::{
+    error::{fail, LGMPResult, Status},
+    headers::{
+        clock_ms, random_id, subs_bad, subs_clear, subs_on, subs_set, HeaderQueue, ShmRegion,
+        SpinGuard, LGMP_HEARTBEAT_TIMEOUT, LGMP_MAX_QUEUES, LGMP_MSGS_MAX, LGMP_MSGS_SIZE,
+        LGMP_PROTOCOL_MAGIC, LGMP_PROTOCOL_VERSION, UDATA_OFFSET,
+    },
+    shm_file::ShmFileHandle,
+};
+
+/// Handle to the client side of an SHM communication file
+pub struct Client {
+    shared: Arc<ClientShared>,
+}
+
+/// State shared between a client and its queues, which keeps the mapping alive for as
+/// long as any of them exist.
+struct ClientShared {
+    region: ShmRegion,
+    session: Mutex<Session>,
+}
+
+struct Session {
+    id: u32,
+    session_id: u32,
+    hosttime: u64,
+    last_heartbeat: u64,
+}
+
+impl Client {
+    /// Initialises a handle to the client side of a LGMP connection
+    /// given a handle to a memory mapped SHM file.
+    /// The SHM file and memory mapped region must be large enough to
+    /// handle all communications, as it cannot be resized during use.
+    pub fn init(file: Box<dyn ShmFileHandle>) -> LGMPResult<Client> {
+        let region = ShmRegion::new(file)?;
+        let hosttime = region.header().timestamp.load(SeqCst);
+        let session = Mutex::new(Session {
+            id: 0,
+            session_id: 0,
+            hosttime,
+            last_heartbeat: 0,
+        });
+        Ok(Client {
+            shared: Arc::new(ClientShared { region, session }),
+        })
+    }
+
+    /// Initialises a client session on the already-initialised client.
+    ///
+    /// This will return an error if a host has not already been initialised on the same SHM
+    /// file.
+    /// Returns both a copy of the udata byte array set by the host upon startup and
+    /// the clientID assigned by the host.
+    pub fn client_session_init(&mut self) -> LGMPResult<(Vec<u8>, u32)> {
+        let region = &self.shared.region;
+        let header = region.header();
+        if header.magic.load(SeqCst) != LGMP_PROTOCOL_MAGIC {
+            return fail(Status::LGMPErrInvalidMagic);
+        }
+        if header.version.load(SeqCst) != LGMP_PROTOCOL_VERSION {
+            return fail(Status::LGMPErrInvalidVersion);
+        }
+
+        let mut session = self.shared.session.lock()?;
+        //Check the host's timestamp is updating
+        let timestamp = header.timestamp.load(SeqCst);
+        if timestamp == session.hosttime {
+            return fail(Status::LGMPErrInvalidSession);
+        }
+
+        let udata_size = header.udata_size.load(SeqCst) as usize;
+        let udata = region.ptr(UDATA_OFFSET, udata_size)?;
+        let udata = unsafe { std::slice::from_raw_parts(udata, udata_size) }.to_vec();
+
+        *session = Session {
+            id: random_id(),
+            session_id: header.session_id.load(SeqCst),
+            hosttime: timestamp,
+            last_heartbeat: clock_ms(),
+        };
+        Ok((udata, session.id))
+    }
+
+    /// Returns true if the session running on the current client is still valid.
+    ///
+    /// This may return false if the host has been restarted or if the last heartbeat
+    /// recieved from the host has passed the timout (1000ms).
+    pub fn client_session_valid(&self) -> bool {
+        self.shared.session_valid()
+    }
+
+    /// Subscribe to the queue indicated by the provided ID.
+    ///
+    /// Returns a handle to the queue if it exists, may alternatively return `LGMPErrNoSuchQueue`
+    /// if a queue does not exist at the provided ID.
+    pub fn client_subscribe(&mut self, queue_id: u32) -> LGMPResult<ClientQueueHandle> {
+        let region = &self.shared.region;
+        let header = region.header();
+        let num_queues = (header.num_queues.load(SeqCst) as usize).min(LGMP_MAX_QUEUES);
+        let Some(index) =
+            (0..num_queues).find(|&i| region.queue(i).queue_id.load(SeqCst) == queue_id)
+        else {
+            return fail(Status::LGMPErrNoSuchQueue);
+        };
+        let hq = region.queue(index);
+        let client_id = self.shared.session.lock()?.id;
+
+        let _lock = SpinGuard::lock(&hq.lock);
+        let mut subs = hq.subs.load(SeqCst);
+
+        //Recover subs which have been flagged as bad and have exceeded the queue timeout
+        if subs_on(subs) != 0 {
+            let hosttime = header.timestamp.load(SeqCst);
+            let mut reap = 0;
+            for id in 0..32 {
+                let bit = 1u32 << id;
+                if subs_bad(subs) & bit != 0 && hosttime > hq.timeout[id].load(SeqCst) {
+                    reap |= bit;
+                    hq.timeout[id].store(0, SeqCst);
+                    hq.client_id[id].store(0, SeqCst);
+                }
+            }
+            subs = subs_clear(subs, reap);
+        }
+
+        //Find the next free subscriber ID
+        let used = subs_on(subs) | subs_bad(subs);
+        let Some(id) = (0..32).find(|id| used & (1 << id) == 0) else {
+            return fail(Status::LGMPErrQueueFull);
+        };
+
+        hq.timeout[id as usize].store(0, SeqCst);
+        hq.client_id[id as usize].store(client_id, SeqCst);
+        hq.subs.store(subs_set(subs, 1 << id), SeqCst);
+        hq.new_sub_count.fetch_add(1, SeqCst);
+
+        Ok(ClientQueueHandle {
+            state: Mutex::new(QueueState {
+                index,
+                id,
+                position: hq.position.load(SeqCst),
+                subscribed: true,
+            }),
+            client: self.shared.clone(),
+        })
+    }
+}
+
+impl ClientShared {
+    fn session_valid(&self) -> bool {
+        let Ok(mut session) = self.session.lock() else {
+            return false;
+        };
+        let header = self.region.header();
+
+        //Check if the host has been restarted
+        if session.session_id != header.session_id.load(SeqCst) {
+            return false;
+        }
+
+        //Check if the heartbeat changed
+        let hosttime = header.timestamp.load(SeqCst);
+        let now = clock_ms();
+        if session.hosttime != hosttime {
+            session.last_heartbeat = now;
+            session.hosttime = hosttime;
+            return true;
+        }
+
+        now.saturating_sub(session.last_heartbeat) <= LGMP_HEARTBEAT_TIMEOUT
+    }
+
+    /// Checks that a queue is still subscribed, returning the error the C library would
+    /// if it is not.
+    fn check_subscribed(&self, hq: &HeaderQueue, queue: &QueueState) -> LGMPResult<()> {
+        let subs = hq.subs.load(SeqCst);
+        let bit = queue.bit();
+        if !queue.subscribed {
+            fail(Status::LGMPErrQueueUnsubscribed)
+        } else if subs_bad(subs) & bit != 0 {
+            fail(Status::LGMPErrQueueTimeout)
+        } else if subs_on(subs) & bit == 0 {
+            match self.session_valid() {
+                true => fail(Status::LGMPErrQueueUnsubscribed),
+                false => fail(Status::LGMPErrInvalidSession),
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    fn process(&self, queue: &QueueState) -> LGMPResult<SharedMemoryBlock> {
+        let hq = self.region.queue(queue.index);
+        self.check_subscribed(hq, queue)?;
+        if hq.position.load(SeqCst) == queue.position {
+            return fail(Status::LGMPErrQueueEmpty);
+        }
+
+        let messages = self.region.messages(hq)?;
+        let Some(msg) = messages.get(queue.position as usize) else {
+            return fail(Status::LGMPErrCorrupted);
+        };
+        let size = msg.size.load(SeqCst) as usize;
+        let mem = self.region.ptr(msg.offset.load(SeqCst) as usize, size)?;
+        Ok(SharedMemoryBlock {
+            udata: msg.udata.load(SeqCst),
+            mem: mem.cast(),
+            size,
+        })
+    }
+
+    fn message_done(&self, queue: &mut QueueState) -> LGMPResult<()> {
+        let hq = self.region.queue(queue.index);
+        self.check_subscribed(hq, queue)?;
+        if hq.position.load(SeqCst) == queue.position {
+            return fail(Status::LGMPErrQueueEmpty);
+        }
+
+        let messages = self.region.messages(hq)?;
+        let Some(msg) = messages.get(queue.position as usize) else {
+            return fail(Status::LGMPErrCorrupted);
+        };
+        let num_messages = messages.len() as u32;
+
+        //Turn off the pending bit for our queue and try to dequeue the message if it's
+        //finished
+        let bit = queue.bit();
+        if msg.pending_subs.fetch_and(!bit, SeqCst) & !bit == 0 {
+            if let Some(_lock) = SpinGuard::try_lock(&hq.lock) {
+                //Someone else may have done this before we got the lock
+                if hq.start.load(SeqCst) == queue.position {
+                    hq.start.store(next(queue.position, num_messages), SeqCst);
+                    if hq.count.fetch_sub(1, SeqCst) == 0 {
+                        //Underflow, which should never happen
+                        hq.count.store(0, SeqCst);
+                        return fail(Status::LGMPErrCorrupted);
+                    }
+                    let timestamp = self.region.header().timestamp.load(SeqCst);
+                    hq.msg_timeout
+                        .store(timestamp + hq.max_time.load(SeqCst) as u64, SeqCst);
+                }
+            }
+        }
+
+        queue.position = next(queue.position, num_messages);
+        Ok(())
+    }
+}
+
+/// Position after `position` in a ring of `len` messages.
+fn next(position: u32, len: u32) -> u32 {
+    match position + 1 {
+        next if next >= len => 0,
+        next => next,
+    }
+}
+
+struct QueueState {
+    index: usize,
+    id: u32,
+    position: u32,
+    subscribed: bool,
+}
+
+impl QueueState {
+    fn bit(&self) -> u32 {
+        1 << self.id
+    }
+}
+
+/// A handle to the client end of a queue.
+///
+/// The queue will be unsubscribed from when this is dropped. Unsubscribing is falliable,
+/// so unsubscribe can be called directly if errors need to be handled.
+pub struct ClientQueueHandle {
+    state: Mutex<QueueState>,
+    client: Arc<ClientShared>,
+}
+
+impl ClientQueueHandle {
+    /// Attempts to unsubscribe from the channel.
+    pub fn unsubscribe(&mut self) -> LGMPResult<()> {
+        let queue = self.state.get_mut()?;
+        if !queue.subscribed {
+            return Ok(());
+        }
+        let hq = self.client.region.queue(queue.index);
+        let bit = queue.bit();
+
+        let _lock = SpinGuard::lock(&hq.lock);
+        let subs = hq.subs.load(SeqCst);
+        if subs_bad(subs) & bit != 0 {
+            return fail(Status::LGMPErrQueueTimeout);
+        }
+        hq.subs.store(subs_clear(subs, bit), SeqCst);
+        hq.timeout[queue.id as usize].store(0, SeqCst);
+        hq.client_id[queue.id as usize].store(0, SeqCst);
+        queue.subscribed = false;
+        Ok(())
+    }
+
+    /// Marks all messages except the most recent one in the queue as read.
+    ///
+    /// As such, all messages apart from the most recent will be discarded, and the
+    /// next call to process will return the most recent message available (as of
+    /// this function being called).
+    pub fn advance_to_last(&mut self) -> LGMPResult<()> {
+        let client = &self.client;
+        let queue = self.state.get_mut()?;
+        let hq = client.region.queue(queue.index);
+        let client_id = client.session.lock()?.id;
+        if queue.subscribed && hq.client_id[queue.id as usize].load(SeqCst) != client_id {
+            return fail(Status::LGMPErrQueueTimeout);
+        }
+        client.check_subscribed(hq, queue)?;
+
+        let end = hq.position.load(SeqCst);
+        if end == queue.position {
+            return fail(Status::LGMPErrQueueEmpty);
+        }
+
+        let messages = client.region.messages(hq)?;
+        let num_messages = messages.len() as u32;
+        if end >= num_messages || queue.position >= num_messages {
+            return fail(Status::LGMPErrCorrupted);
+        }
+        let bit = queue.bit();
+        let mut next_pos = queue.position;
+        let mut last;
+        let mut cleanup = true;
+        let mut lock = None;
+        loop {
+            last = next_pos;
+            next_pos = next(next_pos, num_messages);
+            if next_pos == end {
+                break;
+            }
+
+            //Turn off the pending bit for our queue
+            let msg = &messages[last as usize];
+            if msg.pending_subs.fetch_and(!bit, SeqCst) & !bit != 0 || !cleanup {
+                continue;
+            }
+            if lock.is_none() {
+                lock = SpinGuard::try_lock(&hq.lock);
+                if lock.is_none() {
+                    cleanup = false;
+                    continue;
+                }
+            }
+
+            //Someone else may have done this before we got the lock
+            if hq.start.load(SeqCst) != last {
+                lock = None;
+                cleanup = false;
+                continue;
+            }
+
+            //Message finished
+            hq.start.store(next_pos, SeqCst);
+            if hq.count.fetch_sub(1, SeqCst) == 0 {
+                //Underflow, which should never happen
+                hq.count.store(0, SeqCst);
+                return fail(Status::LGMPErrCorrupted);
+            }
+        }
+
+        if lock.is_some() {
+            let timestamp = client.region.header().timestamp.load(SeqCst);
+            hq.msg_timeout
+                .store(timestamp + hq.max_time.load(SeqCst) as u64, SeqCst);
+        }
+
+        queue.position = last;
+        Ok(())
+    }
+
+    /// Returns a copy of the next unread message in this channel but does not mark it as read.
+    /// Equivalent to `.process` in the original LGMP library
+    pub fn peek(&self) -> LGMPResult<Message> {
+        let queue = self.state.lock()?;
+        let block = self.client.process(&queue)?;
+        Ok(Message {
+            udata: block.udata,
+            mem: unsafe { block.as_slice() }.to_vec(),
+        })
+    }
+
+    /// Returns a copy of the next unread message in this channel and marks it as read.
+    /// This will also invalidate any in-place pointers to messages
+    pub fn pop(&mut self) -> LGMPResult<Message> {
+        let msg = self.peek()?;
+        self.message_done()?;
+        Ok(msg)
+    }
+
+    /// Returns a struct containing a raw pointer to the block of shared memory
+    /// sent as the next message, as well as the size of the memory block.
+    ///
+    /// This memory may be deallocated by the host at any time, although it should
+    /// be retained until either the client channel times out or `message_done`
+    /// is called.
+    pub fn peek_raw(&self) -> LGMPResult<SharedMemoryBlock> {
+        self.client.process(&*self.state.lock()?)
+    }
+
+    /// Returns the next message in place, holding a lock on the channel which prevents
+    /// `message_done` from being called until it is dropped.
+    pub fn peek_in_place(&self) -> LGMPResult<InPlaceMessage<'_>> {
+        self.in_place(false)
+    }
+
+    /// Returns the next message in place, holding a lock on the channel which prevents
+    /// `message_done` from being called until it is dropped.
+    ///
+    /// `message_done` will also be called on the channel upon drop, thereby
+    /// advancing the queue.
+    pub fn pop_in_place(&mut self) -> LGMPResult<InPlaceMessage<'_>> {
+        self.in_place(true)
+    }
+
+    fn in_place(&self, mark_done: bool) -> LGMPResult<InPlaceMessage<'_>> {
+        let queue = self.state.lock()?;
+        let mem = self.client.process(&queue)?;
+        Ok(InPlaceMessage {
+            mem,
+            queue,
+            client: &self.client,
+            mark_done,
+        })
+    }
+
+    /// Marks the first unread message in this channel as read.
+    ///
+    /// This will also invalidate any in-place pointers to messages
+    pub fn message_done(&mut self) -> LGMPResult<()> {
+        self.client.message_done(self.state.get_mut()?)
+    }
+
+    /// Sends an array of bytes to the host over the channel. The host will then
+    /// forward it to any listening clients on this channel.
+    ///
+    /// Returns the unique, incrementing ID assigned to this message, which can be
+    /// compared with that returned by `get_serial` to check if the host has processed it.
+    pub fn send_data(&mut self, data: Vec<u8>) -> LGMPResult<u32> {
+        let queue = self.state.get_mut()?;
+        let hq = self.client.region.queue(queue.index);
+        if data.len() > LGMP_MSGS_SIZE {
+            return fail(Status::LGMPErrInvalidSize);
+        }
+        if subs_bad(hq.subs.load(SeqCst)) & queue.bit() != 0 {
+            return fail(Status::LGMPErrQueueTimeout);
+        }
+
+        //If there is no room, just return
+        if hq.c_msg_avail.load(SeqCst) == 0 {
+            return fail(Status::LGMPErrQueueFull);
+        }
+        let _lock = SpinGuard::lock(&hq.c_msg_lock);
+        if hq.c_msg_avail.load(SeqCst) == 0 {
+            return fail(Status::LGMPErrQueueFull);
+        }
+
+        let w_pos = hq.c_msg_w_pos.load(SeqCst);
+        let Some(msg) = hq.c_msgs.get(w_pos as usize) else {
+            return fail(Status::LGMPErrCorrupted);
+        };
+        msg.size.store(data.len() as u32, SeqCst);
+        for (dst, src) in msg.data.iter().zip(data) {
+            dst.store(src, SeqCst);
+        }
+
+        hq.c_msg_w_pos.store(next(w_pos, LGMP_MSGS_MAX), SeqCst);
+        hq.c_msg_avail.fetch_sub(1, SeqCst);
+        Ok(hq.c_msg_w_serial.fetch_add(1, SeqCst).wrapping_add(1))
+    }
+
+    /// Returns the number of messages that have been processed by the host.
+    ///
+    /// This will also represent the serial number of the message most recently processed.
+    pub fn get_serial(&mut self) -> LGMPResult<u32> {
+        let queue = self.state.get_mut()?;
+        let hq = self.client.region.queue(queue.index);
+        if subs_bad(hq.subs.load(SeqCst)) & queue.bit() != 0 {
+            return fail(Status::LGMPErrQueueTimeout);
+        }
+        Ok(hq.c_msg_r_serial.load(SeqCst))
+    }
+}
+
+impl Drop for ClientQueueHandle {
+    fn drop(&mut self) {
+        //A queue which has timed out will be cleaned up by the host
+        let _ = self.unsubscribe();
+    }
+}
+
+/// Struct representing a message sent by a host, returned by successful calls to process
+pub struct Message {
+    /// User-defined data, may be used to hold eg. message identifiers
+    pub udata: u32,
+    /// Copy of the binary data sent by the host
+    pub mem: Vec<u8>,
+}
+
+/// Raw pointer to block of shared memory
+pub struct SharedMemoryBlock {
+    /// User-defined data, may be used to hold eg. message identifiers
+    pub udata: u32,
+    /// Pointer to the shared data sent by the host
+    pub mem: *mut std::ffi::c_void,
+    /// Size of the shared data region in bytes
+    pub size: usize,
+}
+
+unsafe impl Send for SharedMemoryBlock {}
+
+impl SharedMemoryBlock {
+    /// Returns the contents of the referenced shared memory block as a
+    /// slice of bytes.
+    ///
+    /// # Safety
+    /// This memory block could be deallocated and reused by the host. This
+    /// shouldn't happen as long as `done` is not called on the channel which sent
+    /// this message, but it cannot be guaranteed.
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.mem as *mut u8, self.size) }
+    }
+}
+
+/// A message in shared memory, which holds a lock on its channel until dropped.
+pub struct InPlaceMessage<'a> {
+    /// Pointer to the shared data sent by the host
+    pub mem: SharedMemoryBlock,
+    queue: MutexGuard<'a, QueueState>,
+    client: &'a ClientShared,
+    mark_done: bool,
+}
+
+impl Deref for InPlaceMessage<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        //Should be safe as we have a lock on the channel
+        unsafe { self.mem.as_slice() }
+    }
+}
+
+impl Drop for InPlaceMessage<'_> {
+    fn drop(&mut self) {
+        if self.mark_done {
+            //Errors will be returned again by the next operation on the channel
+            let _ = self.client.message_done(&mut self.queue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native_lgmp::{
+        error::Error,
+        host::{Host, LGMPQueueConfig},
+    };
+
+    const SIZE: usize = 64 * 1024;
+
+    /// Shared memory on the heap, with the alignment needed for the header.
+    struct HeapShm(*mut u64);
+
+    //Leaked, so valid for as long as any handle to it
+    unsafe impl Send for HeapShm {}
+    unsafe impl Sync for HeapShm {}
+
+    impl ShmFileHandle for HeapShm {
+        fn get_mut_ptr(&mut self) -> *mut std::ffi::c_void {
+            self.0.cast()
+        }
+
+        fn get_size(&self) -> usize {
+            SIZE
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_messages() {
+        let mem = vec![0u64; SIZE / 8].leak().as_mut_ptr();
+        let mut host = Host::init(Box::new(HeapShm(mem)), b"udata").expect("Failed to start host");
+        let mut queue = host
+            .queue_new(LGMPQueueConfig {
+                queue_id: 7,
+                num_messages: 2,
+                sub_timeout: 1000,
+            })
+            .expect("Failed to create queue");
+        let alloc = host.mem_alloc(16).expect("Failed to allocate");
+        alloc
+            .lock()
+            .unwrap()
+            .copy_from_bytes(&[42; 16])
+            .expect("Failed to write");
+
+        let mut client = Client::init(Box::new(HeapShm(mem))).expect("Failed to start client");
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        host.process().expect("Failed to process");
+        let (udata, _) = client.client_session_init().expect("Failed to init");
+        assert_eq!(udata, b"udata");
+        let mut chan = client.client_subscribe(7).expect("Failed to subscribe");
+
+        queue.post_shared_mem(1, &*alloc.lock().unwrap()).unwrap();
+        let msg = chan.peek().expect("Failed to peek");
+        assert_eq!((msg.udata, msg.mem), (1, vec![42; 16]));
+
+        //Point the message past the end of the region
+        let hq = client.shared.region.queue(0);
+        let messages = client.shared.region.messages(hq).unwrap();
+        messages[0].offset.store(SIZE as u32 - 8, SeqCst);
+        assert!(matches!(
+            chan.peek_raw(),
+            Err(Error::InternalError(Status::LGMPErrCorrupted))
+        ));
+        hq.messages_offset.store(u32::MAX, SeqCst);
+        assert!(matches!(
+            chan.message_done(),
+            Err(Error::InternalError(Status::LGMPErrCorrupted))
+        ));
+    }
+}
@@ -0,0 +1,78 @@
+//! Types used to represent different LGMP results
+use std::sync::PoisonError;
+
+use thiserror::Error;
+
+#[allow(missing_docs)]
+/// Status returned by LGMP operations, one for each of the C library's `LGMP_STATUS` values
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum Status {
+    #[error("Success returned")]
+    LGMPStatusOk,
+    #[error("SHMEM communication clock failure")]
+    LGMPErrClockFailure,
+    #[error("Invalid argument")]
+    LGMPErrInvalidArgument,
+    #[error("Invalid size")]
+    LGMPErrInvalidSize,
+    #[error("Invalid memory alignment")]
+    LGMPErrInvalidAlignment,
+    #[error("Invalid LGMP session")]
+    LGMPErrInvalidSession,
+    #[error("Memory allocation failed")]
+    LGMPErrNoMem,
+    #[error("Insufficient space in shared memory region")]
+    LGMPErrNoSharedMem,
+    #[error("Host already started")]
+    LGMPErrHostStarted,
+    #[error("The maximum number of LGMP communication queues has already been opened")]
+    LGMPErrNoQueues,
+    #[error("Unable to send message as LGMP queue was full")]
+    LGMPErrQueueFull,
+    #[error("LGMP queue was empty")]
+    LGMPErrQueueEmpty,
+    #[error("LGMP queue was already unsubscribed from")]
+    LGMPErrQueueUnsubscribed,
+    #[error("LGMP queue timed out")]
+    LGMPErrQueueTimeout,
+    #[error("Invalid magic number found")]
+    LGMPErrInvalidMagic,
+    #[error("Remote version did not match")]
+    LGMPErrInvalidVersion,
+    #[error("Requested LGMP queue did not exist")]
+    LGMPErrNoSuchQueue,
+    #[error("LGMP message was corrupted")]
+    LGMPErrCorrupted,
+    #[error("Unexpected error code {0} returned by LGMP library")]
+    LgmpUnknownErr(u32),
+}
+
+#[allow(missing_docs)]
+/// Error type returned by LGMP operations
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Internal library returned status {0}")]
+    InternalError(#[from] Status),
+    #[error("Encountered IO error whilst opening shared memory: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Failed to convert size to the format expected by internal library")]
+    ConversionError(#[from] std::num::TryFromIntError),
+    #[error("Attempted to access memory region allocated by closed host")]
+    HostClosedError,
+    #[error("Lock was poisoned by a panicking thread")]
+    LockPoisonedError,
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_err: PoisonError<T>) -> Self {
+        Self::LockPoisonedError
+    }
+}
+
+/// Alias for result type returned by LGMP operations
+pub type LGMPResult<T> = Result<T, Error>;
+
+/// Shorthand for returning an LGMP status as an error
+pub(super) fn fail<T>(status: Status) -> LGMPResult<T> {
+    Err(Error::InternalError(status))
+}
@@ -0,0 +1,236 @@
+//! Layout of the LGMP header at the start of the shared memory region, matching
+//! `headers.h` from the C library.
+//!
+//! Every field is atomic, as any of them may be written by the other side of the
+//! connection at any time. The C library only uses atomics for some of them, but the
+//! layout is the same.
+use std::{
+    hash::{BuildHasher, Hasher},
+    mem::{align_of, offset_of, size_of},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering::SeqCst},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+use super::{
+    error::{fail, LGMPResult, Status},
+    shm_file::ShmFileHandle,
+};
+
+pub(super) const LGMP_PROTOCOL_MAGIC: u32 = 0x504d474c;
+pub(super) const LGMP_PROTOCOL_VERSION: u32 = 6;
+pub(super) const LGMP_MAX_QUEUES: usize = 5;
+/// Maximum number of client messages which can be waiting in a queue
+pub(super) const LGMP_MSGS_MAX: u32 = 10;
+/// Largest client message, in bytes
+pub const LGMP_MSGS_SIZE: usize = 64;
+/// Time in milliseconds after which a host which has stopped updating its timestamp is
+/// lost
+pub(super) const LGMP_HEARTBEAT_TIMEOUT: u64 = 1000;
+
+#[repr(C)]
+pub(super) struct HeaderMessage {
+    pub udata: AtomicU32,
+    pub size: AtomicU32,
+    pub offset: AtomicU32,
+    pub pending_subs: AtomicU32,
+}
+
+#[repr(C)]
+pub(super) struct ClientMessage {
+    pub size: AtomicU32,
+    pub data: [AtomicU8; LGMP_MSGS_SIZE],
+}
+
+#[repr(C)]
+pub(super) struct HeaderQueue {
+    pub queue_id: AtomicU32,
+    pub num_messages: AtomicU32,
+    pub new_sub_count: AtomicU32,
+    pub max_time: AtomicU32,
+
+    pub position: AtomicU32,
+    pub messages_offset: AtomicU32,
+    pub timeout: [AtomicU64; 32],
+    pub client_id: [AtomicU32; 32],
+
+    //The lock must be held to use the following values
+    pub lock: AtomicU32,
+    pub subs: AtomicU64,
+    pub start: AtomicU32,
+    pub msg_timeout: AtomicU64,
+    pub count: AtomicU32,
+
+    //Messages submitted by clients
+    pub c_msg_lock: AtomicU32,
+    pub c_msg_avail: AtomicU32,
+    pub c_msg_w_pos: AtomicU32,
+    pub c_msg_w_serial: AtomicU32,
+    pub c_msg_r_serial: AtomicU32,
+    pub c_msgs: [ClientMessage; LGMP_MSGS_MAX as usize],
+}
+
+/// The header, without the trailing `udata` array.
+#[repr(C)]
+pub(super) struct Header {
+    pub magic: AtomicU32,
+    pub version: AtomicU32,
+    pub session_id: AtomicU32,
+    pub timestamp: AtomicU64,
+    pub num_queues: AtomicU32,
+    pub queues: [HeaderQueue; LGMP_MAX_QUEUES],
+    pub udata_size: AtomicU32,
+}
+
+/// Offset of the host's user data, which immediately follows `udataSize`
+pub(super) const UDATA_OFFSET: usize = offset_of!(Header, udata_size) + size_of::<u32>();
+
+//Sizes and offsets from the C library, so that a layout mismatch fails to build
+const _: () = {
+    assert!(size_of::<HeaderMessage>() == 16);
+    assert!(size_of::<ClientMessage>() == 68);
+    assert!(offset_of!(HeaderQueue, timeout) == 24);
+    assert!(offset_of!(HeaderQueue, lock) == 408);
+    assert!(offset_of!(HeaderQueue, subs) == 416);
+    assert!(offset_of!(HeaderQueue, msg_timeout) == 432);
+    assert!(offset_of!(HeaderQueue, c_msgs) == 464);
+    assert!(size_of::<HeaderQueue>() == 1144);
+    assert!(offset_of!(Header, timestamp) == 16);
+    assert!(offset_of!(Header, queues) == 32);
+    assert!(UDATA_OFFSET == 5756);
+    assert!(size_of::<Header>() == 5760);
+};
+
+/// Spin lock stored in shared memory, as used by `LGMP_LOCK`. Unlocked when dropped.
+pub(super) struct SpinGuard<'a>(&'a AtomicU32);
+
+impl<'a> SpinGuard<'a> {
+    pub fn lock(lock: &'a AtomicU32) -> SpinGuard<'a> {
+        while lock.compare_exchange(0, 1, SeqCst, SeqCst).is_err() {
+            std::hint::spin_loop();
+        }
+        SpinGuard(lock)
+    }
+
+    pub fn try_lock(lock: &'a AtomicU32) -> Option<SpinGuard<'a>> {
+        lock.compare_exchange(0, 1, SeqCst, SeqCst)
+            .ok()
+            .map(|_| SpinGuard(lock))
+    }
+}
+
+impl Drop for SpinGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(0, SeqCst);
+    }
+}
+
+/// Subscribers which are connected, from the top half of `subs`
+pub(super) fn subs_on(subs: u64) -> u32 {
+    (subs >> 32) as u32
+}
+
+/// Subscribers which have timed out, from the bottom half of `subs`
+pub(super) fn subs_bad(subs: u64) -> u32 {
+    subs as u32
+}
+
+/// Removes subscribers from both halves of `subs`
+pub(super) fn subs_clear(subs: u64, clear: u32) -> u64 {
+    subs & !(clear as u64 | (clear as u64) << 32)
+}
+
+/// Marks subscribers as connected
+pub(super) fn subs_set(subs: u64, set: u32) -> u64 {
+    subs | (set as u64) << 32
+}
+
+/// Returns a millisecond resolution monotonic counter, which never returns zero.
+pub(super) fn clock_ms() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64 + 1
+}
+
+/// Returns a random ID, used in place of `rand()`.
+pub(super) fn random_id() -> u32 {
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(Instant::now().elapsed().as_nanos());
+    hasher.finish() as u32
+}
+
+/// A mapped shared memory region, with bounds checked access to the structures inside
+/// it.
+pub(super) struct ShmRegion {
+    mem: RegionPtr,
+    size: usize,
+    //Keeps the mapping alive
+    _file: Box<dyn ShmFileHandle>,
+}
+
+/// Pointer to the start of a mapping owned by a [ShmFileHandle].
+///
+/// What the pointer refers to is shared with another process, so is only ever accessed
+/// through atomics and bounds checked copies, whichever thread holds the region. The
+/// handle which owns the mapping is required to be `Send + Sync` itself.
+#[derive(Clone, Copy)]
+struct RegionPtr(*mut u8);
+
+unsafe impl Send for RegionPtr {}
+unsafe impl Sync for RegionPtr {}
+
+impl ShmRegion {
+    pub fn new(mut file: Box<dyn ShmFileHandle>) -> LGMPResult<ShmRegion> {
+        let mem = file.get_mut_ptr().cast::<u8>();
+        let size = file.get_size();
+        if mem.is_null() || size < size_of::<Header>() {
+            return fail(Status::LGMPErrInvalidSize);
+        }
+        if mem.align_offset(align_of::<Header>()) != 0 {
+            return fail(Status::LGMPErrInvalidAlignment);
+        }
+        Ok(ShmRegion {
+            mem: RegionPtr(mem),
+            size,
+            _file: file,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn header(&self) -> &Header {
+        //The region is large enough and suitably aligned, as checked in new
+        unsafe { &*self.mem.0.cast::<Header>() }
+    }
+
+    /// Returns the queue at `index`, which must be less than [LGMP_MAX_QUEUES].
+    pub fn queue(&self, index: usize) -> &HeaderQueue {
+        &self.header().queues[index]
+    }
+
+    /// Returns the message ring of a queue, checking that it lies within the region.
+    pub fn messages(&self, hq: &HeaderQueue) -> LGMPResult<&[HeaderMessage]> {
+        let offset = hq.messages_offset.load(SeqCst) as usize;
+        let count = hq.num_messages.load(SeqCst) as usize;
+        let in_bounds = count
+            .checked_mul(size_of::<HeaderMessage>())
+            .and_then(|len| len.checked_add(offset))
+            .is_some_and(|end| end <= self.size);
+        if count == 0 || !in_bounds || !offset.is_multiple_of(align_of::<HeaderMessage>()) {
+            return fail(Status::LGMPErrCorrupted);
+        }
+        Ok(unsafe { std::slice::from_raw_parts(self.mem.0.add(offset).cast(), count) })
+    }
+
+    /// Returns a pointer to `size` bytes at `offset`, checking that they lie within the
+    /// region.
+    pub fn ptr(&self, offset: usize, size: usize) -> LGMPResult<*mut u8> {
+        match offset.checked_add(size) {
+            Some(end) if end <= self.size => Ok(unsafe { self.mem.0.add(offset) }),
+            _ => fail(Status::LGMPErrCorrupted),
+        }
+    }
+}
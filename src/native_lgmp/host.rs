@@ -0,0 +1,454 @@
+//! Structs and functions for connecting to an LGMP shared memory connection as a host.
+use std::{
+    mem::size_of,
+    sync::{atomic::Ordering::SeqCst, Arc, Mutex},
+};
+
+use super::{
+    error::{fail, Error, LGMPResult, Status},
+    headers::{
+        clock_ms, random_id, subs_bad, subs_on, HeaderMessage, ShmRegion, SpinGuard,
+        LGMP_MAX_QUEUES, LGMP_MSGS_MAX, LGMP_MSGS_SIZE, LGMP_PROTOCOL_MAGIC, LGMP_PROTOCOL_VERSION,
+        UDATA_OFFSET,
+    },
+    shm_file::ShmFileHandle,
+};
+
+/// Rounds `x` up to a multiple of four, as with `ALIGN` in the C library.
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+/// Handle to a SHM communication file as the host.
+pub struct Host {
+    region: Arc<ShmRegion>,
+    avail: usize,
+    next_free: usize,
+    num_queues: usize,
+    allocations: Vec<Arc<Mutex<LGMPMemoryAllocation>>>,
+}
+
+impl Host {
+    /// Initialises a handle to the host side of a LGMP connection
+    /// given a handle to a memory mapped SHM file.
+    /// The SHM file and memory mapped region must be large enough to
+    /// handle all communications, as it cannot be resized during use.
+    pub fn init(file: Box<dyn ShmFileHandle>, udata: &[u8]) -> LGMPResult<Host> {
+        let region = ShmRegion::new(file)?;
+        let size = u32::try_from(region.size())? as usize;
+        let udata_size = u32::try_from(udata.len())?;
+        let next_free = align4(size_of::<super::headers::Header>() + udata.len());
+        if size < next_free {
+            return fail(Status::LGMPErrInvalidSize);
+        }
+
+        //Ensure the session ID changes so that clients can tell the host was restarted
+        let header = region.header();
+        let old_session = header.session_id.load(SeqCst);
+        let session_id = std::iter::repeat_with(random_id)
+            .find(|&id| id != old_session)
+            .expect("repeat_with never ends");
+        header.session_id.store(session_id, SeqCst);
+
+        header.magic.store(LGMP_PROTOCOL_MAGIC, SeqCst);
+        header.timestamp.store(clock_ms(), SeqCst);
+        header.version.store(LGMP_PROTOCOL_VERSION, SeqCst);
+        header.num_queues.store(0, SeqCst);
+        header.udata_size.store(udata_size, SeqCst);
+        let dst = region.ptr(UDATA_OFFSET, udata.len())?;
+        unsafe { std::ptr::copy_nonoverlapping(udata.as_ptr(), dst, udata.len()) };
+
+        Ok(Host {
+            region: Arc::new(region),
+            avail: size - next_free,
+            next_free,
+            num_queues: 0,
+            allocations: Vec::new(),
+        })
+    }
+
+    /// Runs the garbage collector on message queues.
+    ///
+    /// When run, this function reads messages from each of the queues and removes any that have
+    /// been read by all listeners.
+    /// It also checks for any messages that have passed their timeout even if they have not been
+    /// read by all listeners. In this case, it sets a timeout at which point any listeners can
+    /// be considered disconnected and therefore garbage collected. It will also remove any such
+    /// messages.
+    ///
+    /// This function should be called regularly to ensure that messages are properly cleaned up.
+    pub fn process(&mut self) -> LGMPResult<()> {
+        let header = self.region.header();
+        //Something external sometimes zeroes the shared memory after it has been
+        //initialised, so report it
+        if header.magic.load(SeqCst) != LGMP_PROTOCOL_MAGIC {
+            return fail(Status::LGMPErrCorrupted);
+        }
+
+        let now = clock_ms();
+        header.timestamp.store(now, SeqCst);
+
+        for index in 0..self.num_queues {
+            let hq = self.region.queue(index);
+            let messages = self.region.messages(hq)?;
+            let num_messages = messages.len() as u32;
+            let max_time = hq.max_time.load(SeqCst) as u64;
+
+            let _lock = SpinGuard::lock(&hq.lock);
+            if hq.count.load(SeqCst) == 0 {
+                continue;
+            }
+
+            let mut subs = hq.subs.load(SeqCst);
+            loop {
+                let start = hq.start.load(SeqCst);
+                let Some(msg) = messages.get(start as usize) else {
+                    return fail(Status::LGMPErrCorrupted);
+                };
+                let mut pend = msg.pending_subs.load(SeqCst) & subs_on(subs);
+
+                let new_bad_subs = pend & !subs_bad(subs);
+                if new_bad_subs != 0 && now > hq.msg_timeout.load(SeqCst) {
+                    //Reset the garbage collection timeout for new bad subs
+                    subs |= new_bad_subs as u64;
+                    for id in (0..32).filter(|id| new_bad_subs & (1 << id) != 0) {
+                        hq.timeout[id].store(now + max_time, SeqCst);
+                    }
+
+                    //Clear the pending subs
+                    msg.pending_subs.store(0, SeqCst);
+                    pend = 0;
+                }
+
+                //If there are still valid pending subs break out
+                if pend & !subs_bad(subs) != 0 {
+                    break;
+                }
+
+                //Message finished
+                hq.start.store((start + 1) % num_messages, SeqCst);
+                if hq.count.fetch_sub(1, SeqCst) == 1 {
+                    break;
+                }
+                hq.msg_timeout.store(now + max_time, SeqCst);
+            }
+
+            hq.subs.store(subs, SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new queue from the provided options.
+    pub fn queue_new(&mut self, config: LGMPQueueConfig) -> LGMPResult<LGMPHostQueue> {
+        if self.num_queues == LGMP_MAX_QUEUES {
+            return fail(Status::LGMPErrNoQueues);
+        }
+
+        //Plus one for the end marker
+        let num_messages = config
+            .num_messages
+            .checked_add(1)
+            .ok_or(Status::LGMPErrInvalidArgument)?;
+        let needed = size_of::<HeaderMessage>() * num_messages as usize;
+        if self.avail < needed {
+            return fail(Status::LGMPErrNoSharedMem);
+        }
+
+        let index = self.num_queues;
+        let hq = self.region.queue(index);
+        hq.queue_id.store(config.queue_id, SeqCst);
+        hq.num_messages.store(num_messages, SeqCst);
+        hq.new_sub_count.store(0, SeqCst);
+        hq.lock.store(0, SeqCst);
+        hq.subs.store(0, SeqCst);
+        hq.position.store(0, SeqCst);
+        hq.messages_offset
+            .store(u32::try_from(self.next_free)?, SeqCst);
+        hq.start.store(0, SeqCst);
+        hq.msg_timeout.store(0, SeqCst);
+        hq.max_time.store(config.sub_timeout, SeqCst);
+        hq.count.store(0, SeqCst);
+
+        hq.c_msg_lock.store(0, SeqCst);
+        hq.c_msg_avail.store(LGMP_MSGS_MAX, SeqCst);
+        hq.c_msg_w_pos.store(0, SeqCst);
+        hq.c_msg_w_serial.store(0, SeqCst);
+        hq.c_msg_r_serial.store(0, SeqCst);
+
+        self.avail -= align4(needed);
+        self.next_free += align4(needed);
+        self.num_queues += 1;
+        self.region.header().num_queues.fetch_add(1, SeqCst);
+
+        Ok(LGMPHostQueue {
+            region: self.region.clone(),
+            index,
+            position: 0,
+            c_msg_pos: 0,
+        })
+    }
+
+    /// Returns the amount of memory in bytes which is still available to be
+    /// allocated within the shared memory region
+    pub fn mem_available(&self) -> usize {
+        self.avail
+    }
+
+    /// Allocates a block of memory of size `size` bytes within the shared
+    /// memory region.
+    ///
+    /// This is equivalent to calling `self.mem_alloc_aligned(size, 4)`
+    ///
+    /// Note that allocations are permanent; whilst the struct pointing to memory
+    /// allocations will be freed upon being dropped, the memory within the shared
+    /// memory location will never be recovered until the host is closed.
+    pub fn mem_alloc(&mut self, size: u32) -> LGMPResult<Arc<Mutex<LGMPMemoryAllocation>>> {
+        self.mem_alloc_aligned(size, 4)
+    }
+
+    /// Allocates a block of memory of size `size` bytes within the shared
+    /// memory region aligned to the specified byte alignment.
+    ///
+    /// This will consume up to `size + alignment` bytes from the available pool.
+    ///
+    /// Note that allocations are permanent; whilst the struct pointing to memory
+    /// allocations will be freed upon being dropped, the memory within the shared
+    /// memory location will never be recovered until the host is closed.
+    pub fn mem_alloc_aligned(
+        &mut self,
+        size: u32,
+        alignment: u32,
+    ) -> LGMPResult<Arc<Mutex<LGMPMemoryAllocation>>> {
+        let mut size = size as usize;
+        let mut next_free = self.next_free;
+        if alignment > 0 {
+            if !alignment.is_power_of_two() {
+                return fail(Status::LGMPErrInvalidAlignment);
+            }
+            let mask = alignment as usize - 1;
+            size = (size + mask) & !mask;
+            next_free = (next_free + mask) & !mask;
+        }
+
+        let padding = next_free - self.next_free;
+        if self.avail < padding || size > self.avail - padding {
+            return fail(Status::LGMPErrNoSharedMem);
+        }
+
+        let allocation = Arc::new(Mutex::new(LGMPMemoryAllocation {
+            inner: Some(Allocation {
+                region: self.region.clone(),
+                offset: next_free,
+                size,
+            }),
+        }));
+        self.avail -= padding + size;
+        self.next_free = next_free + size;
+
+        self.allocations.push(allocation.clone());
+        Ok(allocation)
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        //Consume any allocations
+        for alloc_ptr in self.allocations.iter() {
+            if let Ok(mut allocation) = alloc_ptr.lock() {
+                allocation.consume();
+            }
+        }
+    }
+}
+
+/// Configuration struct to be used when creating a new queue
+pub struct LGMPQueueConfig {
+    /// Application defined queue ID
+    pub queue_id: u32,
+    /// Number of messages in the queue
+    pub num_messages: u32,
+    /// Length of time in milliseconds to wait before removing a subscriber
+    pub sub_timeout: u32,
+}
+
+/// Handle to an LGMP queue from the host side.
+pub struct LGMPHostQueue {
+    region: Arc<ShmRegion>,
+    index: usize,
+    position: u32,
+    c_msg_pos: u32,
+}
+
+impl LGMPHostQueue {
+    /// Returns true iff there are one or more subscribers listening
+    /// on the queue
+    pub fn has_subs(&self) -> bool {
+        subs_on(self.region.queue(self.index).subs.load(SeqCst)) != 0
+    }
+
+    /// Returns the number of new listeners that have subscribed to this channel
+    /// since the last time this function was called.
+    pub fn new_subs(&self) -> u32 {
+        self.region.queue(self.index).new_sub_count.swap(0, SeqCst)
+    }
+
+    /// Returns the number of pending messages that currently exist in this
+    /// channel
+    pub fn pending(&self) -> u32 {
+        self.region.queue(self.index).count.load(SeqCst)
+    }
+
+    /// Posts a new message to the channel containing both a reference to an allocated
+    /// block of shared memory, and a 32-bit integer of user-specified data.
+    ///
+    /// Whilst this 32-bit udata field can contain any value, it is probably most useful
+    /// for use as eg. a sequential message ID.
+    pub fn post_shared_mem<P: std::ops::Deref<Target = LGMPMemoryAllocation>>(
+        &mut self,
+        udata: u32,
+        payload: P,
+    ) -> LGMPResult<()> {
+        let payload = payload.deref().inner()?;
+        if !Arc::ptr_eq(&payload.region, &self.region) {
+            return fail(Status::LGMPErrInvalidArgument);
+        }
+        let hq = self.region.queue(self.index);
+        let messages = self.region.messages(hq)?;
+
+        let _lock = SpinGuard::lock(&hq.lock);
+
+        //If nobody has subscribed there is no point in posting the message
+        let subs = hq.subs.load(SeqCst);
+        let pend = subs_on(subs) & !subs_bad(subs);
+        if pend == 0 {
+            return Ok(());
+        }
+
+        //The buffer should never be completely filled
+        if hq.count.load(SeqCst) as usize == messages.len() - 1 {
+            return fail(Status::LGMPErrQueueFull);
+        }
+
+        let msg = &messages[self.position as usize];
+        msg.udata.store(udata, SeqCst);
+        msg.size.store(payload.size as u32, SeqCst);
+        msg.offset.store(payload.offset as u32, SeqCst);
+        msg.pending_subs.store(pend, SeqCst);
+
+        //Start the timeout if the queue was empty
+        if hq.count.fetch_add(1, SeqCst) == 0 {
+            let max_time = hq.max_time.load(SeqCst) as u64;
+            hq.msg_timeout.store(clock_ms() + max_time, SeqCst);
+        }
+
+        self.position = (self.position + 1) % messages.len() as u32;
+        hq.position.store(self.position, SeqCst);
+        Ok(())
+    }
+
+    /// Reads a message from the queue, sent by a client, returning LGMPErrQueueEmpty if
+    /// no messages exist.
+    pub fn read_data(&mut self) -> LGMPResult<Vec<u8>> {
+        let hq = self.region.queue(self.index);
+        if hq.c_msg_avail.load(SeqCst) == LGMP_MSGS_MAX {
+            return fail(Status::LGMPErrQueueEmpty);
+        }
+
+        let _lock = SpinGuard::lock(&hq.c_msg_lock);
+        let msg = &hq.c_msgs[self.c_msg_pos as usize];
+        self.c_msg_pos = (self.c_msg_pos + 1) % LGMP_MSGS_MAX;
+
+        let size = msg.size.load(SeqCst) as usize;
+        let data = msg.data[..size.min(LGMP_MSGS_SIZE)]
+            .iter()
+            .map(|byte| byte.load(SeqCst))
+            .collect();
+        hq.c_msg_avail.fetch_add(1, SeqCst);
+
+        match size <= LGMP_MSGS_SIZE {
+            true => Ok(data),
+            false => fail(Status::LGMPErrCorrupted),
+        }
+    }
+
+    /// Increments the client message read serial to indicate that a client message
+    /// has been read from the channel.
+    pub fn ack_data(&mut self) -> LGMPResult<()> {
+        self.region
+            .queue(self.index)
+            .c_msg_r_serial
+            .fetch_add(1, SeqCst);
+        Ok(())
+    }
+
+    /// Retrieves a list of IDs of clients which are subscribed to the current
+    /// channel.
+    pub fn get_client_ids(&self) -> LGMPResult<Vec<u32>> {
+        let hq = self.region.queue(self.index);
+        let _lock = SpinGuard::lock(&hq.lock);
+        let now = clock_ms();
+
+        let subs = hq.subs.load(SeqCst);
+        Ok((0..32)
+            .filter(|&id| {
+                let bit = 1 << id;
+                let timed_out = subs_bad(subs) & bit != 0 && now > hq.timeout[id].load(SeqCst);
+                subs_on(subs) & bit != 0 && !timed_out
+            })
+            .map(|id| hq.client_id[id].load(SeqCst))
+            .collect())
+    }
+}
+
+struct Allocation {
+    region: Arc<ShmRegion>,
+    offset: usize,
+    size: usize,
+}
+
+/// A handle to an allocation of shared memory.
+///
+/// Note that whilst the handle itself will be correctly freed upon drop,
+/// LGMP memory allocations are permanent, and as such any allocated memory
+/// will never be freed until the host program restarts.
+pub struct LGMPMemoryAllocation {
+    inner: Option<Allocation>,
+}
+
+impl LGMPMemoryAllocation {
+    /// Returns a raw mutable pointer to a chunk of allocated memory.
+    pub fn mem_ptr(&mut self) -> LGMPResult<*mut std::ffi::c_void> {
+        let alloc = self.inner()?;
+        Ok(alloc.region.ptr(alloc.offset, alloc.size)?.cast())
+    }
+
+    /// Returns the allocation iff it has not yet been consumed, otherwise
+    /// throws a HostClosedError
+    fn inner(&self) -> LGMPResult<&Allocation> {
+        self.inner.as_ref().ok_or(Error::HostClosedError)
+    }
+
+    /// Copies a slice of bytes into the allocated memory chunk.
+    pub fn copy_from_bytes(&mut self, data: &[u8]) -> LGMPResult<()> {
+        if data.len() > self.len() {
+            return fail(Status::LGMPErrNoSharedMem);
+        }
+        let ptr = self.mem_ptr()?;
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.cast::<u8>(), data.len()) };
+        Ok(())
+    }
+
+    pub fn consume(&mut self) {
+        self.inner = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.as_ref().map_or(0, |alloc| alloc.size)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
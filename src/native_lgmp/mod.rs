@@ -0,0 +1,12 @@
+//! A pure Rust implementation of the LGMP protocol, used in place of `ligmars` and the
+//! C library when the `native-lgmp` feature is enabled.
+//!
+//! The API mirrors the parts of `ligmars` used by this crate. Unlike the C library,
+//! every offset read from shared memory is checked against the size of the region
+//! before it is used, so a corrupt or malicious peer results in
+//! [error::Status::LGMPErrCorrupted] rather than out of bounds accesses.
+pub mod client;
+pub mod error;
+mod headers;
+pub mod host;
+pub mod shm_file;
@@ -0,0 +1,10 @@
+//! Trait used for access to a memory-mapped SHM file.
+
+/// Trait for a handle to shared memory of some kind, through which
+/// interprocess communication can take place.
+///
+/// Clients and hosts may be used from any thread, so handles must be `Send + Sync`.
+pub trait ShmFileHandle: Send + Sync {
+    fn get_mut_ptr(&mut self) -> *mut std::ffi::c_void;
+    fn get_size(&self) -> usize;
+}
@@ -0,0 +1,137 @@
+//! Helpers for passing frames to OpenCL kernels.
+//!
+//! The OpenCL library is loaded at runtime by `cl3`, so this feature does not require it to
+//! be present when building.
+use std::{alloc::Layout, ptr::NonNull};
+
+use cl3::{
+    memory::{CL_MEM_READ_ONLY, CL_MEM_USE_HOST_PTR},
+    types::{cl_context, cl_mem},
+};
+
+use crate::{client::KVMFRFrameHandle, error::LGError, types::FrameInfo};
+
+/// Alignment of host memory given to OpenCL. Intel devices only use host memory in place
+/// if it is page aligned, which also satisfies the base address alignment of other devices.
+const HOST_ALIGN: usize = 4096;
+
+/// A frame copied into page aligned host memory and wrapped in a read only OpenCL buffer
+/// created with `CL_MEM_USE_HOST_PTR`.
+///
+/// Devices which share memory with the CPU, such as integrated GPUs, can then read the
+/// frame without any further copy. Discrete devices may still copy it into their own
+/// memory when it is first used.
+///
+/// The host memory is freed when this is dropped, so any commands using the buffer must
+/// have finished by then.
+pub struct ClFrameBuffer {
+    mem: cl_mem,
+    host: HostBuffer,
+    info: FrameInfo,
+}
+
+//The buffer is only read through OpenCL, which is thread safe
+unsafe impl Send for ClFrameBuffer {}
+
+impl ClFrameBuffer {
+    /// Copies the pixel data of a frame out of shared memory and creates a buffer for it
+    /// in the given context.
+    ///
+    /// The frame's data is laid out as in [KVMFRFrameHandle::data], so kernels should
+    /// index rows by [FrameInfo::pitch].
+    ///
+    /// # Safety
+    /// `context` must be a valid OpenCL context.
+    pub unsafe fn new(
+        context: cl_context,
+        frame: &KVMFRFrameHandle,
+    ) -> Result<ClFrameBuffer, LGError> {
+        let info = frame.info()?;
+        let host = HostBuffer::copy_from(frame.data()?);
+        //The host memory is kept alive until the buffer has been released
+        let mem = unsafe {
+            cl3::memory::create_buffer(
+                context,
+                CL_MEM_READ_ONLY | CL_MEM_USE_HOST_PTR,
+                host.len,
+                host.ptr.as_ptr().cast(),
+            )
+        }
+        .map_err(LGError::OpenCLError)?;
+        Ok(ClFrameBuffer { mem, host, info })
+    }
+
+    /// Returns the OpenCL buffer, which remains valid until this is dropped.
+    pub fn mem(&self) -> cl_mem {
+        self.mem
+    }
+
+    /// Returns the description of the frame which was copied.
+    pub fn info(&self) -> &FrameInfo {
+        &self.info
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.host.len
+    }
+
+    /// Returns true if the buffer holds no data.
+    pub fn is_empty(&self) -> bool {
+        self.host.len == 0
+    }
+}
+
+impl Drop for ClFrameBuffer {
+    fn drop(&mut self) {
+        //The host memory is freed once this returns
+        let _ = unsafe { cl3::memory::release_mem_object(self.mem) };
+    }
+}
+
+/// Page aligned heap memory.
+struct HostBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+}
+
+impl HostBuffer {
+    fn copy_from(data: &[u8]) -> HostBuffer {
+        //Rounded up to whole pages, and never zero sized
+        let size = data.len().next_multiple_of(HOST_ALIGN).max(HOST_ALIGN);
+        let layout = Layout::from_size_align(size, HOST_ALIGN).expect("Frame is too large");
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            std::alloc::handle_alloc_error(layout);
+        };
+        //The allocation is at least as long as the data
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.as_ptr(), data.len()) };
+        HostBuffer {
+            ptr,
+            len: data.len(),
+            layout,
+        }
+    }
+}
+
+impl Drop for HostBuffer {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_buffer_is_page_aligned() {
+        let data = [7u8; 100];
+        let host = HostBuffer::copy_from(&data);
+        assert_eq!(host.ptr.as_ptr() as usize % HOST_ALIGN, 0);
+        assert_eq!(host.len, 100);
+        let copied = unsafe { std::slice::from_raw_parts(host.ptr.as_ptr(), host.len) };
+        assert_eq!(copied, data);
+    }
+}
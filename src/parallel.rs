@@ -0,0 +1,74 @@
+//! Splitting of row based work across threads, for the `parallel` feature.
+//!
+//! Without the feature all work runs on the calling thread, so callers need no cfgs of
+//! their own. With it, threads are spawned for each call rather than kept in a pool,
+//! which costs in the order of tens of microseconds per thread, so work is only split up
+//! when every thread gets at least [MIN_BAND_BYTES] of it.
+
+/// Fewest bytes of pixels handed to a single thread, below which spawning it costs more
+/// than it saves.
+pub(crate) const MIN_BAND_BYTES: usize = 1024 * 1024;
+
+/// Returns the number of `chunk_len` byte chunks, such as rows, making up the smallest band
+/// worth handing to a thread in [for_each_band].
+pub(crate) fn min_band_chunks(chunk_len: usize) -> usize {
+    MIN_BAND_BYTES.div_ceil(chunk_len.max(1))
+}
+
+/// Calls `f` on contiguous bands of `items`, each made up of whole `chunk_len` sized
+/// chunks such as rows, along with the index of the first chunk in the band.
+///
+/// With the `parallel` feature, bands of at least `min_chunks` chunks are processed on
+/// separate threads, up to one per available core. Spawning the threads allocates, so
+/// work too small to be split stays allocation free.
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+pub(crate) fn for_each_band<T: Send>(
+    items: &mut [T],
+    chunk_len: usize,
+    min_chunks: usize,
+    f: impl Fn(usize, &mut [T]) + Sync,
+) {
+    #[cfg(feature = "parallel")]
+    {
+        let chunk_len = chunk_len.max(1);
+        let chunks = items.len().div_ceil(chunk_len);
+        let bands = (chunks / min_chunks.max(1)).min(threads());
+        if bands > 1 {
+            let band_chunks = chunks.div_ceil(bands);
+            let f = &f;
+            std::thread::scope(|scope| {
+                for (i, band) in items.chunks_mut(band_chunks * chunk_len).enumerate() {
+                    scope.spawn(move || f(i * band_chunks, band));
+                }
+            });
+            return;
+        }
+    }
+    f(0, items);
+}
+
+/// Returns the number of threads work can be split across, which is looked up once as it
+/// can involve reading cgroup limits.
+#[cfg(feature = "parallel")]
+fn threads() -> usize {
+    static THREADS: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+    *THREADS.get_or_init(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visits_every_chunk_once() {
+        let mut items = vec![0usize; 1001 * 3];
+        for_each_band(&mut items, 3, 1, |first, band| {
+            for (i, chunk) in band.chunks_mut(3).enumerate() {
+                chunk.iter_mut().for_each(|item| *item += first + i + 1);
+            }
+        });
+        for (i, chunk) in items.chunks(3).enumerate() {
+            assert_eq!(chunk, [i + 1; 3]);
+        }
+    }
+}
@@ -0,0 +1,453 @@
+//! A pool of reusable buffers for frames copied out of shared memory, which are resized
+//! when the guest resolution changes.
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::types::{FrameInfo, PixelFormat};
+#[cfg(feature = "lgmp")]
+use crate::{
+    client::{KVMFRFrameHandle, OwnedFrame},
+    error::LGError,
+};
+
+/// How a [FramePool] resizes its buffers when the size of frames changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReallocStrategy {
+    /// Reallocate whenever the frame size changes, so buffers are never larger than needed
+    ExactFit,
+    /// Only grow buffers, keeping them at the largest frame size seen so that switching
+    /// back and forth between resolutions doesn't reallocate. Once this many frames in a row
+    /// have been smaller than the buffers, they are trimmed down to the current size.
+    GrowOnly { trim_after: u32 },
+}
+
+impl Default for ReallocStrategy {
+    fn default() -> Self {
+        ReallocStrategy::GrowOnly { trim_after: 600 }
+    }
+}
+
+/// Options for a [FramePool].
+#[derive(Debug, Clone)]
+pub struct FramePoolOpts {
+    /// Largest number of released buffers to keep for reuse
+    pub buffers: usize,
+    pub strategy: ReallocStrategy,
+    /// Largest number of bytes to hold in buffers from the pool, counting both those
+    /// handed out and those kept for reuse. Once exceeded, kept buffers are dropped and
+    /// [PoolEvent::MemoryPressure] is reported if that is not enough.
+    pub memory_limit: Option<usize>,
+    /// Kept buffers which have not been reused for this long are dropped, so that memory
+    /// is given back when frames stop arriving. Checked by [FramePool::trim] as well as
+    /// whenever buffers are acquired or released.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for FramePoolOpts {
+    fn default() -> Self {
+        FramePoolOpts {
+            buffers: 3,
+            strategy: ReallocStrategy::default(),
+            memory_limit: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+/// Layout of the frames being stored in a [FramePool].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLayout {
+    pub width: u32,
+    pub height: u32,
+    /// Row length in bytes
+    pub pitch: u32,
+    pub format: PixelFormat,
+}
+
+impl From<&FrameInfo> for FrameLayout {
+    fn from(value: &FrameInfo) -> Self {
+        FrameLayout {
+            width: value.data_width,
+            height: value.data_height,
+            pitch: value.pitch,
+            format: value.format,
+        }
+    }
+}
+
+impl FrameLayout {
+    /// Returns the number of bytes needed to hold a frame with this layout.
+    pub fn len(&self) -> usize {
+        self.pitch as usize * self.height as usize
+    }
+
+    /// Returns true if a frame with this layout holds no data.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Changes reported by [FramePool::poll_event], so that resources which mirror the pool's
+/// frames, such as GPU textures, can be resized at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolEvent {
+    /// Frames now have a different layout
+    LayoutChanged(FrameLayout),
+    /// Buffers have been reallocated to hold this many bytes
+    Reallocated(usize),
+    /// The buffers handed out hold more than the memory limit, even after dropping all
+    /// of those kept for reuse. Reported once each time the limit is exceeded
+    MemoryPressure { used: usize, limit: usize },
+    /// Kept buffers holding this many bytes have been dropped, either because they were
+    /// idle or to stay within the memory limit
+    Trimmed(usize),
+}
+
+/// Hands out buffers for copied frames, reusing those which have been released.
+pub struct FramePool {
+    opts: FramePoolOpts,
+    /// Buffers kept for reuse, along with when they were released
+    free: Vec<(Vec<u8>, Instant)>,
+    /// Number of buffers which have been acquired but not released
+    outstanding: usize,
+    /// Set once the memory limit has been exceeded, until usage drops back below it
+    under_pressure: bool,
+    /// Size in bytes that buffers are currently allocated with
+    capacity: usize,
+    layout: Option<FrameLayout>,
+    /// Number of frames in a row which have been smaller than the capacity
+    smaller_frames: u32,
+    /// Number of times a buffer had to be allocated because none were kept for reuse
+    exhausted: u64,
+    events: VecDeque<PoolEvent>,
+}
+
+impl FramePool {
+    pub fn new(opts: FramePoolOpts) -> FramePool {
+        FramePool {
+            opts,
+            free: Vec::new(),
+            outstanding: 0,
+            under_pressure: false,
+            capacity: 0,
+            layout: None,
+            smaller_frames: 0,
+            exhausted: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns the size in bytes that buffers are currently allocated with.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of bytes held by buffers from the pool. Buffers which have been
+    /// handed out are counted at the current capacity, as they may have grown since.
+    pub fn memory_used(&self) -> usize {
+        let kept: usize = self.free.iter().map(|(buf, _)| buf.capacity()).sum();
+        kept + self.outstanding * self.capacity
+    }
+
+    /// Returns the number of times a buffer has been acquired while none were kept for
+    /// reuse, so a new one had to be allocated. Allocations made because the frame size
+    /// changed are not counted. A steadily rising count means more buffers are being held
+    /// at once than the pool keeps, and `buffers` should be raised.
+    pub fn exhausted(&self) -> u64 {
+        self.exhausted
+    }
+
+    /// Allocates buffers for frames with the given layout ahead of time, so that the first
+    /// frames copied don't have to. At most `buffers` are kept.
+    pub fn preallocate(&mut self, layout: FrameLayout, count: usize) {
+        //Filling the pool isn't exhaustion
+        let exhausted = self.exhausted;
+        let bufs: Vec<_> = (0..count.min(self.opts.buffers))
+            .map(|_| self.acquire(layout))
+            .collect();
+        self.exhausted = exhausted;
+        for buf in bufs {
+            self.release(buf);
+        }
+    }
+
+    /// Returns a zero length buffer with room for a frame with the given layout, which
+    /// should be passed back to [Self::release] once finished with.
+    pub fn acquire(&mut self, layout: FrameLayout) -> Vec<u8> {
+        if self.layout != Some(layout) {
+            self.layout = Some(layout);
+            self.events.push_back(PoolEvent::LayoutChanged(layout));
+        }
+
+        let len = layout.len();
+        let realloc = match self.opts.strategy {
+            ReallocStrategy::ExactFit => len != self.capacity,
+            ReallocStrategy::GrowOnly { trim_after } => {
+                if len < self.capacity {
+                    self.smaller_frames += 1;
+                } else {
+                    self.smaller_frames = 0;
+                }
+                len > self.capacity || self.smaller_frames >= trim_after.max(1)
+            }
+        };
+        if realloc {
+            self.capacity = len;
+            self.smaller_frames = 0;
+            self.free.clear();
+            self.events.push_back(PoolEvent::Reallocated(len));
+        }
+
+        let mut buf = match self.free.pop() {
+            Some((buf, _)) => buf,
+            None => {
+                if !realloc {
+                    self.exhausted += 1;
+                }
+                Vec::with_capacity(self.capacity)
+            }
+        };
+        buf.clear();
+        self.outstanding += 1;
+        self.trim();
+        self.check_limit();
+        buf
+    }
+
+    /// Returns a buffer to the pool for reuse. Buffers which are too small for the
+    /// current capacity are dropped, and those which are larger, such as ones acquired
+    /// before [ReallocStrategy::GrowOnly] trimmed it, are shrunk to fit.
+    pub fn release(&mut self, mut buf: Vec<u8>) {
+        self.outstanding = self.outstanding.saturating_sub(1);
+        self.trim();
+        if buf.capacity() < self.capacity || self.free.len() >= self.opts.buffers {
+            return;
+        }
+        buf.shrink_to(self.capacity);
+        let over_limit = self
+            .opts
+            .memory_limit
+            .is_some_and(|limit| self.memory_used() + buf.capacity() > limit);
+        if !over_limit {
+            self.free.push((buf, Instant::now()));
+        }
+    }
+
+    /// Drops kept buffers which have been idle for longer than the `idle_timeout`.
+    ///
+    /// This is done whenever buffers are acquired or released, so only needs to be called
+    /// while no frames are being copied.
+    pub fn trim(&mut self) {
+        let Some(timeout) = self.opts.idle_timeout else {
+            return;
+        };
+        let now = Instant::now();
+        let before = self.memory_used();
+        self.free
+            .retain(|(_, released)| now.duration_since(*released) < timeout);
+        self.report_trimmed(before);
+    }
+
+    /// Drops kept buffers until the pool is within its memory limit, reporting pressure
+    /// if that is not enough.
+    fn check_limit(&mut self) {
+        let Some(limit) = self.opts.memory_limit else {
+            return;
+        };
+        let before = self.memory_used();
+        while self.memory_used() > limit && !self.free.is_empty() {
+            //The oldest buffers are at the start
+            self.free.remove(0);
+        }
+        self.report_trimmed(before);
+
+        let used = self.memory_used();
+        if used <= limit {
+            self.under_pressure = false;
+        } else if !self.under_pressure {
+            self.under_pressure = true;
+            self.events
+                .push_back(PoolEvent::MemoryPressure { used, limit });
+        }
+    }
+
+    fn report_trimmed(&mut self, before: usize) {
+        let trimmed = before - self.memory_used();
+        if trimmed > 0 {
+            self.events.push_back(PoolEvent::Trimmed(trimmed));
+        }
+    }
+
+    /// Copies a frame into a buffer from the pool along with its metadata, using the
+    /// copy strategy of the connection it came from. The buffer can be returned with
+    /// [OwnedFrame::release_to] once processing is done.
+    ///
+    /// This is the same as [KVMFRFrameHandle::to_owned_in].
+    #[cfg(feature = "lgmp")]
+    pub fn copy_frame(&mut self, frame: &KVMFRFrameHandle) -> Result<OwnedFrame, LGError> {
+        frame.to_owned_in(self)
+    }
+
+    /// Returns the next change to the pool's buffers, if any have happened since this was
+    /// last called.
+    pub fn poll_event(&mut self) -> Option<PoolEvent> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(width: u32, height: u32) -> FrameLayout {
+        FrameLayout {
+            width,
+            height,
+            pitch: width * 4,
+            format: PixelFormat::Bgra,
+        }
+    }
+
+    #[test]
+    fn grow_only_trims_after_smaller_frames() {
+        let mut pool = FramePool::new(FramePoolOpts {
+            buffers: 2,
+            strategy: ReallocStrategy::GrowOnly { trim_after: 2 },
+            ..Default::default()
+        });
+        let buf = pool.acquire(layout(8, 8));
+        assert_eq!(
+            pool.poll_event(),
+            Some(PoolEvent::LayoutChanged(layout(8, 8)))
+        );
+        assert_eq!(pool.poll_event(), Some(PoolEvent::Reallocated(256)));
+        pool.release(buf);
+
+        //A smaller frame reuses the larger buffer at first
+        let buf = pool.acquire(layout(4, 4));
+        assert!(buf.capacity() >= 256);
+        assert_eq!(
+            pool.poll_event(),
+            Some(PoolEvent::LayoutChanged(layout(4, 4)))
+        );
+        assert_eq!(pool.poll_event(), None);
+        pool.release(buf);
+
+        let _ = pool.acquire(layout(4, 4));
+        assert_eq!(pool.poll_event(), Some(PoolEvent::Reallocated(64)));
+        assert_eq!(pool.capacity(), 64);
+    }
+
+    #[test]
+    fn grow_only_shrinks_buffers_released_after_trim() {
+        let mut pool = FramePool::new(FramePoolOpts {
+            buffers: 2,
+            strategy: ReallocStrategy::GrowOnly { trim_after: 2 },
+            ..Default::default()
+        });
+        let large = pool.acquire(layout(8, 8));
+        let buf = pool.acquire(layout(4, 4));
+        pool.release(buf);
+        let buf = pool.acquire(layout(4, 4));
+        assert_eq!(pool.capacity(), 64);
+
+        //The large buffer was still held when the pool was trimmed
+        pool.release(buf);
+        pool.release(large);
+        assert_eq!(pool.memory_used(), 128);
+    }
+
+    #[test]
+    fn exact_fit_reallocates_on_every_change() {
+        let mut pool = FramePool::new(FramePoolOpts {
+            buffers: 2,
+            strategy: ReallocStrategy::ExactFit,
+            ..Default::default()
+        });
+        let buf = pool_buf(&mut pool, layout(8, 8));
+        pool.release(buf);
+        let buf = pool_buf(&mut pool, layout(4, 4));
+        pool.release(buf);
+        let events: Vec<_> = std::iter::from_fn(|| pool.poll_event()).collect();
+        assert_eq!(
+            events,
+            [
+                PoolEvent::LayoutChanged(layout(8, 8)),
+                PoolEvent::Reallocated(256),
+                PoolEvent::LayoutChanged(layout(4, 4)),
+                PoolEvent::Reallocated(64),
+            ]
+        );
+    }
+
+    #[test]
+    fn stays_within_memory_limit() {
+        let mut pool = FramePool::new(FramePoolOpts {
+            buffers: 4,
+            memory_limit: Some(600),
+            ..Default::default()
+        });
+        let bufs: Vec<_> = (0..2).map(|_| pool.acquire(layout(8, 8))).collect();
+        assert_eq!(pool.memory_used(), 512);
+        let third = pool.acquire(layout(8, 8));
+        let events: Vec<_> = std::iter::from_fn(|| pool.poll_event()).collect();
+        assert_eq!(
+            events[2..],
+            [PoolEvent::MemoryPressure {
+                used: 768,
+                limit: 600
+            }]
+        );
+
+        //Only as many buffers as fit within the limit are kept
+        pool.release(third);
+        for buf in bufs {
+            pool.release(buf);
+        }
+        assert_eq!(pool.memory_used(), 512);
+        let _ = pool.acquire(layout(8, 8));
+        assert_eq!(pool.poll_event(), None);
+    }
+
+    #[test]
+    fn counts_exhaustion() {
+        let mut pool = FramePool::new(FramePoolOpts {
+            buffers: 2,
+            ..Default::default()
+        });
+        pool.preallocate(layout(8, 8), 4);
+        assert_eq!(pool.memory_used(), 512);
+        assert_eq!(pool.exhausted(), 0);
+
+        let bufs: Vec<_> = (0..3).map(|_| pool.acquire(layout(8, 8))).collect();
+        assert_eq!(pool.exhausted(), 1);
+        for buf in bufs {
+            pool.release(buf);
+        }
+        let _ = pool.acquire(layout(8, 8));
+        assert_eq!(pool.exhausted(), 1);
+    }
+
+    #[test]
+    fn trims_idle_buffers() {
+        let mut pool = FramePool::new(FramePoolOpts {
+            idle_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        });
+        let buf = pool.acquire(layout(8, 8));
+        while pool.poll_event().is_some() {}
+        pool.release(buf);
+        assert_eq!(pool.memory_used(), 256);
+        pool.trim();
+        assert_eq!(pool.poll_event(), Some(PoolEvent::Trimmed(256)));
+        assert_eq!(pool.memory_used(), 0);
+    }
+
+    fn pool_buf(pool: &mut FramePool, layout: FrameLayout) -> Vec<u8> {
+        let mut buf = pool.acquire(layout);
+        buf.resize(layout.len(), 0);
+        buf
+    }
+}
@@ -0,0 +1,13 @@
+//! The types needed by most clients, for glob importing with
+//! `use lookinggla_rs::prelude::*`.
+#[cfg(feature = "lgmp")]
+pub use crate::client::{
+    ArcFrame, ConnectionStats, KVMFRCursorHandle, KVMFRFrameHandle, LGEvent, LGMPConnection,
+    LGMPOpts, LGMPSource, LookingGlass,
+};
+pub use crate::{
+    convert::ToneMap,
+    cursor::{CursorState, CursorTracker},
+    error::LGError,
+    types::{FrameInfo, HostFeatures, HostInfo, PixelFormat, Rotation},
+};
@@ -0,0 +1,267 @@
+//! `Serialize` and `Deserialize` implementations for metadata types, so that they can be
+//! logged, sent to other processes or stored alongside recordings.
+//!
+//! Structs are written as maps of their fields, unit enums as their variant names and
+//! flags as `|` separated flag names, matching what `serde` derives would produce.
+use std::fmt;
+
+use serde_core::{
+    de::{self, EnumAccess, IgnoredAny, MapAccess, SeqAccess, VariantAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+#[cfg(feature = "lgmp")]
+use crate::client::{ConnectionStats, QueueErrorStats};
+use crate::{
+    cursor::{CursorShape, CursorState},
+    types::{
+        FrameFlags, FrameInfo, HostFeatures, HostInfo, OsInfo, OsType, PixelFormat, Rotation,
+        VmInfo,
+    },
+};
+
+/// Implements both traits for a struct with public fields.
+macro_rules! serde_struct {
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                const FIELDS: &[&str] = &[$(stringify!($field)),*];
+                let mut state = serializer.serialize_struct(stringify!($ty), FIELDS.len())?;
+                $(state.serialize_field(stringify!($field), &self.$field)?;)*
+                state.end()
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct StructVisitor;
+
+                impl<'de> Visitor<'de> for StructVisitor {
+                    type Value = $ty;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str(concat!("struct ", stringify!($ty)))
+                    }
+
+                    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<$ty, A::Error> {
+                        let mut _len = 0;
+                        $(
+                            let $field = seq
+                                .next_element()?
+                                .ok_or_else(|| de::Error::invalid_length(_len, &self))?;
+                            _len += 1;
+                        )*
+                        Ok($ty { $($field),* })
+                    }
+
+                    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<$ty, A::Error> {
+                        $(let mut $field = None;)*
+                        while let Some(key) = map.next_key::<String>()? {
+                            match key.as_str() {
+                                $(stringify!($field) => {
+                                    if $field.is_some() {
+                                        return Err(de::Error::duplicate_field(stringify!($field)));
+                                    }
+                                    $field = Some(map.next_value()?);
+                                })*
+                                _ => {
+                                    map.next_value::<IgnoredAny>()?;
+                                }
+                            }
+                        }
+                        $(
+                            let $field = $field
+                                .ok_or_else(|| de::Error::missing_field(stringify!($field)))?;
+                        )*
+                        Ok($ty { $($field),* })
+                    }
+                }
+
+                const FIELDS: &[&str] = &[$(stringify!($field)),*];
+                deserializer.deserialize_struct(stringify!($ty), FIELDS, StructVisitor)
+            }
+        }
+    };
+}
+
+/// Implements both traits for an enum whose variants carry no data.
+macro_rules! serde_unit_enum {
+    ($ty:ident { $($variant:ident),* $(,)? }) => {
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                const VARIANTS: &[&str] = &[$(stringify!($variant)),*];
+                let name = match self {
+                    $($ty::$variant => stringify!($variant),)*
+                };
+                let index = VARIANTS.iter().position(|v| *v == name).unwrap_or_default();
+                serializer.serialize_unit_variant(stringify!($ty), index as u32, name)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct EnumVisitor;
+
+                impl<'de> Visitor<'de> for EnumVisitor {
+                    type Value = $ty;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str(concat!("enum ", stringify!($ty)))
+                    }
+
+                    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<$ty, A::Error> {
+                        let (name, variant) = data.variant::<String>()?;
+                        variant.unit_variant()?;
+                        match name.as_str() {
+                            $(stringify!($variant) => Ok($ty::$variant),)*
+                            _ => Err(de::Error::unknown_variant(&name, VARIANTS)),
+                        }
+                    }
+                }
+
+                const VARIANTS: &[&str] = &[$(stringify!($variant)),*];
+                deserializer.deserialize_enum(stringify!($ty), VARIANTS, EnumVisitor)
+            }
+        }
+    };
+}
+
+/// Implements both traits for a set of flags.
+macro_rules! serde_flags {
+    ($($ty:ident),*) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                bitflags::serde::serialize(self, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                bitflags::serde::deserialize(deserializer)
+            }
+        }
+    )*};
+}
+
+serde_unit_enum!(Rotation {
+    Rot0,
+    Rot90,
+    Rot180,
+    Rot270
+});
+serde_unit_enum!(PixelFormat {
+    Bgra,
+    Rgba,
+    Rgba10,
+    Rgba16F,
+    Bgr32,
+    Rgb24
+});
+serde_unit_enum!(OsType {
+    Linux,
+    Bsd,
+    Osx,
+    Windows,
+    Other
+});
+serde_flags!(FrameFlags, HostFeatures);
+
+serde_struct!(FrameInfo {
+    format_ver,
+    serial,
+    format,
+    screen_width,
+    screen_height,
+    data_width,
+    data_height,
+    frame_width,
+    frame_height,
+    rotation,
+    stride,
+    pitch,
+    flags,
+});
+serde_struct!(VmInfo {
+    uuid,
+    capture,
+    cpus,
+    cores,
+    sockets,
+    model
+});
+serde_struct!(OsInfo { os, name });
+serde_struct!(HostInfo {
+    version,
+    host_version,
+    features,
+    vm,
+    os
+});
+serde_struct!(CursorShape {
+    width,
+    height,
+    hotspot,
+    rgba
+});
+serde_struct!(CursorState {
+    position,
+    visible,
+    shape,
+    shape_generation
+});
+
+#[cfg(feature = "lgmp")]
+serde_struct!(QueueErrorStats {
+    reads,
+    empty,
+    corrupted,
+    timeouts,
+    invalid_session,
+    other,
+});
+#[cfg(feature = "lgmp")]
+serde_struct!(ConnectionStats {
+    frames,
+    cursor_updates,
+    frames_skipped,
+    anomalies,
+    reconnects,
+    frame_queue,
+    cursor_queue,
+    cursor_shapes,
+    cursor_shapes_dropped,
+    cursor_updates_per_sec,
+    cursor_shapes_per_sec,
+    fast_forwards,
+    frames_per_sec,
+    frame_latency_avg,
+    frame_latency_max,
+});
+
+#[cfg(test)]
+mod tests {
+    use serde_core::de::value::{Error, MapDeserializer, StrDeserializer};
+
+    use super::*;
+
+    #[test]
+    fn deserializes_enums_and_structs() {
+        let format = PixelFormat::deserialize(StrDeserializer::<Error>::new("Rgba16F"));
+        assert_eq!(format.unwrap(), PixelFormat::Rgba16F);
+        assert!(Rotation::deserialize(StrDeserializer::<Error>::new("Rot45")).is_err());
+
+        let fields = [("os", "Windows"), ("name", "Windows 11"), ("extra", "")];
+        let map = MapDeserializer::<_, Error>::new(fields.into_iter());
+        assert_eq!(
+            OsInfo::deserialize(map).unwrap(),
+            OsInfo {
+                os: OsType::Windows,
+                name: "Windows 11".into(),
+            }
+        );
+
+        let map = MapDeserializer::<_, Error>::new([("os", "Linux")].into_iter());
+        assert!(OsInfo::deserialize(map).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Hand written equivalents of the definitions in `KVMFR.h`, named as bindgen would name
+//! them so that they can be checked against its output with the `bindgen` feature.
+//!
+//! The structs implement zerocopy's [FromBytes], so they can be read out of shared memory
+//! without unsafe casts, and those without padding implement [IntoBytes] so that they can
+//! be written the same way.
+use std::os::raw::{c_char, c_uint};
+
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+pub const KVMFR_MAGIC: &[u8; 9] = b"KVMFR---\0";
+pub const KVMFR_VERSION: u32 = 20;
+pub const KVMFR_MAX_DAMAGE_RECTS: u32 = 64;
+
+pub const LGMP_Q_POINTER: u32 = 1;
+pub const LGMP_Q_FRAME: u32 = 2;
+pub const LGMP_Q_FRAME_LEN: u32 = 2;
+pub const LGMP_Q_POINTER_LEN: u32 = 20;
+
+pub type FrameType = c_uint;
+pub const FrameType_FRAME_TYPE_INVALID: FrameType = 0;
+pub const FrameType_FRAME_TYPE_BGRA: FrameType = 1;
+pub const FrameType_FRAME_TYPE_RGBA: FrameType = 2;
+pub const FrameType_FRAME_TYPE_RGBA10: FrameType = 3;
+pub const FrameType_FRAME_TYPE_RGBA16F: FrameType = 4;
+pub const FrameType_FRAME_TYPE_BGR_32: FrameType = 5;
+pub const FrameType_FRAME_TYPE_RGB_24: FrameType = 6;
+pub const FrameType_FRAME_TYPE_MAX: FrameType = 7;
+
+pub type FrameRotation = c_uint;
+pub const FrameRotation_FRAME_ROT_0: FrameRotation = 0;
+pub const FrameRotation_FRAME_ROT_90: FrameRotation = 1;
+pub const FrameRotation_FRAME_ROT_180: FrameRotation = 2;
+pub const FrameRotation_FRAME_ROT_270: FrameRotation = 3;
+
+pub type CursorType = c_uint;
+pub const CursorType_CURSOR_TYPE_COLOR: CursorType = 0;
+pub const CursorType_CURSOR_TYPE_MONOCHROME: CursorType = 1;
+pub const CursorType_CURSOR_TYPE_MASKED_COLOR: CursorType = 2;
+
+pub type KVMFRCursorFlags = u32;
+pub const CURSOR_FLAG_POSITION: c_uint = 1;
+pub const CURSOR_FLAG_VISIBLE: c_uint = 2;
+pub const CURSOR_FLAG_SHAPE: c_uint = 4;
+
+pub type KVMFRFeatureFlags = u32;
+pub const KVMFR_FEATURE_SETCURSORPOS: c_uint = 1;
+pub const KVMFR_FEATURE_WINDOWSIZE: c_uint = 2;
+
+pub const KVMFR_MESSAGE_SETCURSORPOS: c_uint = 0;
+pub const KVMFR_MESSAGE_WINDOWSIZE: c_uint = 1;
+
+pub const KVMFR_RECORD_VMINFO: c_uint = 1;
+pub const KVMFR_RECORD_OSINFO: c_uint = 2;
+
+pub const KVMFR_OS_LINUX: c_uint = 0;
+pub const KVMFR_OS_BSD: c_uint = 1;
+pub const KVMFR_OS_OSX: c_uint = 2;
+pub const KVMFR_OS_WINDOWS: c_uint = 3;
+pub const KVMFR_OS_OTHER: c_uint = 4;
+
+pub type KVMFRFrameFlags = u32;
+pub const FRAME_FLAG_BLOCK_SCREENSAVER: c_uint = 1;
+pub const FRAME_FLAG_REQUEST_ACTIVATION: c_uint = 2;
+pub const FRAME_FLAG_TRUNCATED: c_uint = 4;
+pub const FRAME_FLAG_HDR: c_uint = 8;
+pub const FRAME_FLAG_HDR_PQ: c_uint = 16;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct FrameDamageRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Header held in the LGMP udata, which the host passes to clients when a session starts.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct KVMFR {
+    pub magic: [c_char; 8],
+    pub version: u32,
+    pub hostver: [c_char; 32],
+    pub features: KVMFRFeatureFlags,
+}
+
+/// Header of each record following [KVMFR] in the udata.
+#[repr(C, packed)]
+pub struct KVMFRRecord {
+    pub type_: u8,
+    pub size: u32,
+    pub data: [u8; 0],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, Immutable, KnownLayout)]
+pub struct KVMFRRecord_VMInfo {
+    pub uuid: [u8; 16],
+    pub capture: [c_char; 32],
+    pub cpus: u8,
+    pub cores: u8,
+    pub sockets: u8,
+    /// First byte of the variable length model name
+    pub model: [c_char; 1],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, Immutable, KnownLayout)]
+pub struct KVMFRRecord_OSInfo {
+    pub os: u8,
+    /// First byte of the variable length name
+    pub name: [c_char; 1],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, Immutable, KnownLayout)]
+pub struct KVMFRCursor {
+    pub x: i16,
+    pub y: i16,
+    pub type_: CursorType,
+    pub hx: i8,
+    pub hy: i8,
+    pub width: u32,
+    pub height: u32,
+    pub pitch: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct KVMFRFrame {
+    pub formatVer: u32,
+    pub frameSerial: u32,
+    pub type_: FrameType,
+    pub screenWidth: u32,
+    pub screenHeight: u32,
+    pub dataWidth: u32,
+    pub dataHeight: u32,
+    pub frameWidth: u32,
+    pub frameHeight: u32,
+    pub rotation: FrameRotation,
+    pub stride: u32,
+    pub pitch: u32,
+    pub offset: u32,
+    pub damageRectsCount: u32,
+    pub damageRects: [FrameDamageRect; KVMFR_MAX_DAMAGE_RECTS as usize],
+    pub flags: KVMFRFrameFlags,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct KVMFRMessage {
+    pub type_: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct KVMFRSetCursorPos {
+    pub msg: KVMFRMessage,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct KVMFRWindowSize {
+    pub msg: KVMFRMessage,
+    pub w: u32,
+    pub h: u32,
+}
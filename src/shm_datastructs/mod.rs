@@ -1,5 +1,84 @@
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
+#![allow(dead_code)]
+#![allow(clippy::upper_case_acronyms)]
 
-include!(concat!(env!("OUT_DIR"), "/common_bindings.rs"));
+mod kvmfr;
+
+pub use kvmfr::*;
+
+/// Bindings generated from the Looking Glass headers, only used to check that the hand
+/// written definitions still match them.
+#[cfg(feature = "bindgen")]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/common_bindings.rs"));
+}
+
+#[cfg(feature = "bindgen")]
+mod verify {
+    use std::mem::{align_of, offset_of, size_of};
+
+    use super::{generated, kvmfr};
+
+    macro_rules! check_layout {
+        ($($ty:ident { $($field:ident),* }),* $(,)?) => {$(
+            const _: () = assert!(size_of::<kvmfr::$ty>() == size_of::<generated::$ty>());
+            const _: () = assert!(align_of::<kvmfr::$ty>() == align_of::<generated::$ty>());
+            $(const _: () = assert!(
+                offset_of!(kvmfr::$ty, $field) == offset_of!(generated::$ty, $field)
+            );)*
+        )*};
+    }
+
+    macro_rules! check_consts {
+        ($($name:ident),* $(,)?) => {$(
+            const _: () = assert!(kvmfr::$name as u64 == generated::$name as u64);
+        )*};
+    }
+
+    check_layout! {
+        FrameDamageRect { x, y, width, height },
+        KVMFR { magic, version, hostver, features },
+        KVMFRRecord { type_, size },
+        KVMFRRecord_VMInfo { uuid, capture, cpus, cores, sockets, model },
+        KVMFRRecord_OSInfo { os, name },
+        KVMFRCursor { x, y, type_, hx, hy, width, height, pitch },
+        KVMFRFrame {
+            formatVer, frameSerial, type_, screenWidth, screenHeight, dataWidth, dataHeight,
+            frameWidth, frameHeight, rotation, stride, pitch, offset, damageRectsCount,
+            damageRects, flags
+        },
+        KVMFRMessage { type_ },
+        KVMFRSetCursorPos { msg, x, y },
+        KVMFRWindowSize { msg, w, h },
+    }
+
+    check_consts! {
+        KVMFR_VERSION, KVMFR_MAX_DAMAGE_RECTS, LGMP_Q_POINTER, LGMP_Q_FRAME, LGMP_Q_FRAME_LEN,
+        LGMP_Q_POINTER_LEN, FrameType_FRAME_TYPE_INVALID, FrameType_FRAME_TYPE_BGRA,
+        FrameType_FRAME_TYPE_RGBA, FrameType_FRAME_TYPE_RGBA10, FrameType_FRAME_TYPE_RGBA16F,
+        FrameType_FRAME_TYPE_BGR_32, FrameType_FRAME_TYPE_RGB_24, FrameType_FRAME_TYPE_MAX,
+        FrameRotation_FRAME_ROT_0, FrameRotation_FRAME_ROT_90, FrameRotation_FRAME_ROT_180,
+        FrameRotation_FRAME_ROT_270, CursorType_CURSOR_TYPE_COLOR,
+        CursorType_CURSOR_TYPE_MONOCHROME, CursorType_CURSOR_TYPE_MASKED_COLOR,
+        CURSOR_FLAG_POSITION, CURSOR_FLAG_VISIBLE, CURSOR_FLAG_SHAPE,
+        KVMFR_FEATURE_SETCURSORPOS, KVMFR_FEATURE_WINDOWSIZE, KVMFR_MESSAGE_SETCURSORPOS,
+        KVMFR_MESSAGE_WINDOWSIZE, KVMFR_RECORD_VMINFO, KVMFR_RECORD_OSINFO, KVMFR_OS_LINUX,
+        KVMFR_OS_BSD, KVMFR_OS_OSX, KVMFR_OS_WINDOWS, KVMFR_OS_OTHER,
+        FRAME_FLAG_BLOCK_SCREENSAVER, FRAME_FLAG_REQUEST_ACTIVATION, FRAME_FLAG_TRUNCATED,
+        FRAME_FLAG_HDR, FRAME_FLAG_HDR_PQ,
+    }
+
+    #[test]
+    fn magic_matches_header() {
+        assert_eq!(kvmfr::KVMFR_MAGIC, generated::KVMFR_MAGIC);
+    }
+}
+
+/// Size of the `FrameBuffer` header which precedes the pixel data of each frame.
+///
+/// This consists only of an atomic write pointer recording how many bytes of the frame
+/// the host has written so far. It is defined outside of KVMFR.h, so is not picked up
+/// by bindgen.
+pub const FRAME_BUFFER_HEADER_SIZE: usize = std::mem::size_of::<u32>();
@@ -0,0 +1,169 @@
+//! Utilities for testing code built on this crate without a real Looking Glass host.
+//! Requires the `testing` feature enabled to use.
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    client::{LGMPConnection, LGMPOpts, LGMPOptsBuilder, LGMPSource},
+    error::LGError,
+    host::{HostCursor, HostFrame, LGMPHostConnection, LGMPHostOpts, DEFAULT_PROCESS_INTERVAL},
+    types::{HostFeatures, PixelFormat, Rotation},
+};
+
+/// Size of the shared memory region created for each mock host.
+const MOCK_SHM_SIZE: usize = 32 * 1024 * 1024;
+/// Largest frame which can be injected, enough for a 1080p BGRA frame.
+const MOCK_MAX_FRAME_SIZE: u32 = 1920 * 1080 * 4;
+
+/// Used to give each mock host in a process its own shared memory file.
+static MOCK_HOST_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A fake Looking Glass host running on a temporary shared memory region.
+///
+/// The mock host does not run any background threads, so [MockHost::process] needs to be
+/// called regularly to keep clients' sessions alive.
+pub struct MockHost {
+    host: LGMPHostConnection,
+    shm_path: PathBuf,
+}
+
+impl MockHost {
+    /// Creates a new shared memory region with valid KVMFR udata, and starts a host on it.
+    pub fn new() -> Result<MockHost, LGError> {
+        MockHost::with_features(HostFeatures::empty())
+    }
+
+    /// As [MockHost::new], but advertising the provided features to clients.
+    pub fn with_features(features: HostFeatures) -> Result<MockHost, LGError> {
+        let shm_path = std::env::temp_dir().join(format!(
+            "lookinggla-rs-mock-{}-{}",
+            std::process::id(),
+            MOCK_HOST_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let host = create_host(&shm_path, features)?;
+
+        Ok(MockHost { host, shm_path })
+    }
+
+    /// Stops this host and starts a new one on the same shared memory file, advertising the
+    /// provided features, as if the host application had been restarted.
+    pub fn restart(self, features: HostFeatures) -> Result<MockHost, LGError> {
+        let MockHost { host, shm_path } = self;
+        drop(host);
+        let host = create_host(&shm_path, features)?;
+        Ok(MockHost { host, shm_path })
+    }
+
+    /// Returns the path to the shared memory file used by this host.
+    pub fn shm_path(&self) -> &std::path::Path {
+        &self.shm_path
+    }
+
+    /// Returns options which can be used to open a client connection to this host.
+    pub fn client_opts(&self) -> LGMPOpts {
+        self.client_opts_builder().build()
+    }
+
+    /// Returns a builder for client options pointing at this host, for tests which need
+    /// to customise the connection.
+    pub fn client_opts_builder(&self) -> LGMPOptsBuilder {
+        LGMPOpts::builder(LGMPSource::Flink(
+            self.shm_path.to_string_lossy().into_owned(),
+        ))
+    }
+
+    /// Opens a client connection to this host and initialises its session.
+    ///
+    /// This takes care of waiting for the host timestamp to advance, which the underlying
+    /// library requires before it will accept a session.
+    pub fn connect(&mut self) -> Result<LGMPConnection, LGError> {
+        self.connect_with(self.client_opts())
+    }
+
+    /// As [MockHost::connect], but using the provided options. These should be built from
+    /// [MockHost::client_opts_builder].
+    pub fn connect_with(&mut self, opts: LGMPOpts) -> Result<LGMPConnection, LGError> {
+        let mut conn = LGMPConnection::open(opts)?;
+        std::thread::sleep(Duration::from_millis(10));
+        self.process()?;
+        conn.init()?;
+        //Pick up the new subscriptions
+        self.process()?;
+
+        Ok(conn)
+    }
+
+    /// Runs housekeeping on the host. See [LGMPHostConnection::process].
+    pub fn process(&mut self) -> Result<(), LGError> {
+        self.host.process()
+    }
+
+    /// Returns a reference to the underlying host connection.
+    pub fn host(&mut self) -> &mut LGMPHostConnection {
+        &mut self.host
+    }
+
+    /// Returns the underlying host connection, for tests which need to share it.
+    pub fn into_host(self) -> LGMPHostConnection {
+        self.host
+    }
+
+    /// Injects a frame with the provided description and pixel data.
+    pub fn inject_frame(&mut self, frame: &HostFrame, data: &[u8]) -> Result<(), LGError> {
+        self.host.publish_frame(frame, data)
+    }
+
+    /// Injects a BGRA frame of the requested size with every pixel set to `pixel`.
+    pub fn inject_solid_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixel: [u8; 4],
+    ) -> Result<(), LGError> {
+        let frame = HostFrame {
+            format: PixelFormat::Bgra,
+            screen_width: width,
+            screen_height: height,
+            width,
+            height,
+            stride: width,
+            pitch: width * 4,
+            rotation: Rotation::Rot0,
+            damage: None,
+            hdr: None,
+        };
+        let data = pixel.repeat((width * height) as usize);
+        self.host.publish_frame(&frame, &data)
+    }
+
+    /// Injects a cursor update.
+    pub fn inject_cursor(&mut self, cursor: &HostCursor) -> Result<(), LGError> {
+        self.host.publish_cursor(cursor)
+    }
+
+    /// Injects a cursor update which only moves the (visible) cursor.
+    pub fn inject_cursor_position(&mut self, x: i16, y: i16) -> Result<(), LGError> {
+        self.host.publish_cursor(&HostCursor {
+            position: Some((x, y)),
+            visible: true,
+            shape: None,
+        })
+    }
+}
+
+/// Starts a host on a new shared memory file at `shm_path`.
+fn create_host(shm_path: &Path, features: HostFeatures) -> Result<LGMPHostConnection, LGError> {
+    LGMPHostConnection::create(LGMPHostOpts {
+        shm_path: shm_path.to_string_lossy().into_owned(),
+        shm_size: MOCK_SHM_SIZE,
+        max_frame_size: MOCK_MAX_FRAME_SIZE,
+        host_version: "lookinggla-rs mock host".to_string(),
+        features,
+        damage_estimation: None,
+        copy_strategy: Default::default(),
+        process_interval: DEFAULT_PROCESS_INTERVAL,
+    })
+}
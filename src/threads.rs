@@ -0,0 +1,141 @@
+//! Diagnostics for the background threads spawned by the crate, so that anyone inspecting
+//! a hung client can see which threads belong to it and whether they are still making
+//! progress.
+//!
+//! Only long running threads are listed. The short lived threads used to split up large
+//! copies and conversions with the `parallel` feature are not.
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    thread::ThreadId,
+    time::Instant,
+};
+
+//Threads currently running, added to and removed by their ThreadMonitors
+static THREADS: Mutex<Vec<Arc<Entry>>> = Mutex::new(Vec::new());
+
+/// What one of the crate's background threads is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ThreadRole {
+    /// Reads from the host for a [crate::client::LookingGlass], named `lg-worker`
+    ClientWorker,
+    /// Runs housekeeping for a [crate::host::HostHeartbeat], named `lg-heartbeat`
+    HostHeartbeat,
+    /// Runs the classifier of a [crate::classify::ClassifierWorker], named `lg-classifier`
+    Classifier,
+    /// Writes the black box started with [crate::client::LGMPConnection::start_black_box],
+    /// named `lg-black-box`
+    BlackBox,
+}
+
+/// A snapshot of one of the crate's background threads, returned by [threads].
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    /// Name that the thread was spawned with
+    pub name: String,
+    pub role: ThreadRole,
+    /// When the thread last finished a unit of work, or started if it has not yet.
+    ///
+    /// Threads which wait for work, such as the classifier, do not update this while they
+    /// are waiting.
+    pub last_heartbeat: Instant,
+}
+
+struct Entry {
+    id: ThreadId,
+    name: String,
+    role: ThreadRole,
+    last_heartbeat: Mutex<Instant>,
+}
+
+/// Lists the crate's background threads which are currently running.
+pub fn threads() -> Vec<ThreadInfo> {
+    registry()
+        .iter()
+        .map(|entry| ThreadInfo {
+            id: entry.id,
+            name: entry.name.clone(),
+            role: entry.role,
+            last_heartbeat: *lock(&entry.last_heartbeat),
+        })
+        .collect()
+}
+
+/// Registers the current thread with [threads] for as long as this is kept alive.
+pub(crate) struct ThreadMonitor {
+    entry: Arc<Entry>,
+}
+
+impl ThreadMonitor {
+    /// Registers the current thread, which should be called as soon as it starts.
+    pub(crate) fn register(role: ThreadRole) -> ThreadMonitor {
+        let current = std::thread::current();
+        let entry = Arc::new(Entry {
+            id: current.id(),
+            name: current.name().unwrap_or_default().to_owned(),
+            role,
+            last_heartbeat: Mutex::new(Instant::now()),
+        });
+        registry().push(entry.clone());
+        ThreadMonitor { entry }
+    }
+
+    /// Records that the thread has finished a unit of work.
+    pub(crate) fn beat(&self) {
+        *lock(&self.entry.last_heartbeat) = Instant::now();
+    }
+}
+
+impl Drop for ThreadMonitor {
+    fn drop(&mut self) {
+        registry().retain(|entry| !Arc::ptr_eq(entry, &self.entry));
+    }
+}
+
+fn registry() -> MutexGuard<'static, Vec<Arc<Entry>>> {
+    lock(&THREADS)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    //Entries are only ever replaced whole, so a panic while holding the lock is harmless
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(id: ThreadId) -> Option<ThreadInfo> {
+        threads().into_iter().find(|info| info.id == id)
+    }
+
+    #[test]
+    fn lists_registered_threads() {
+        let (registered_tx, registered_rx) = std::sync::mpsc::channel();
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("lg-test".into())
+            .spawn(move || {
+                let monitor = ThreadMonitor::register(ThreadRole::Classifier);
+                let started = find(std::thread::current().id()).unwrap().last_heartbeat;
+                monitor.beat();
+                registered_tx.send(started).unwrap();
+                let _ = stop_rx.recv();
+            })
+            .unwrap();
+        let started = registered_rx.recv().unwrap();
+
+        let info = find(thread.thread().id()).expect("Thread was not listed");
+        assert_eq!(info.name, "lg-test");
+        assert_eq!(info.role, ThreadRole::Classifier);
+        assert!(info.last_heartbeat >= started);
+
+        drop(stop_tx);
+        let id = thread.thread().id();
+        thread.join().unwrap();
+        assert!(find(id).is_none());
+    }
+}
@@ -0,0 +1,653 @@
+//! Safe Rust representations of the constants used by the KVMFR protocol.
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::{error::LGError, shm_datastructs};
+
+/// Rotation applied by the host to a captured frame, measured clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Rotation {
+    #[default]
+    Rot0,
+    Rot90,
+    Rot180,
+    Rot270,
+}
+
+impl Rotation {
+    /// Returns true if this rotation swaps the width and height of the frame.
+    pub fn swaps_dimensions(self) -> bool {
+        matches!(self, Rotation::Rot90 | Rotation::Rot270)
+    }
+}
+
+impl TryFrom<u32> for Rotation {
+    type Error = u32;
+
+    /// Converts a raw `FrameRotation` value, returning the value back if it is unknown.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            shm_datastructs::FrameRotation_FRAME_ROT_0 => Ok(Rotation::Rot0),
+            shm_datastructs::FrameRotation_FRAME_ROT_90 => Ok(Rotation::Rot90),
+            shm_datastructs::FrameRotation_FRAME_ROT_180 => Ok(Rotation::Rot180),
+            shm_datastructs::FrameRotation_FRAME_ROT_270 => Ok(Rotation::Rot270),
+            v => Err(v),
+        }
+    }
+}
+
+impl From<Rotation> for u32 {
+    fn from(value: Rotation) -> Self {
+        match value {
+            Rotation::Rot0 => shm_datastructs::FrameRotation_FRAME_ROT_0,
+            Rotation::Rot90 => shm_datastructs::FrameRotation_FRAME_ROT_90,
+            Rotation::Rot180 => shm_datastructs::FrameRotation_FRAME_ROT_180,
+            Rotation::Rot270 => shm_datastructs::FrameRotation_FRAME_ROT_270,
+        }
+    }
+}
+
+/// The KVMFR name for [Rotation].
+pub type FrameRotation = Rotation;
+
+/// Layout of the pixel data contained within a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// 8 bits per channel, stored in B, G, R, A order
+    Bgra,
+    /// 8 bits per channel, stored in R, G, B, A order
+    Rgba,
+    /// 10 bits per colour channel and 2 bits of alpha, packed into 32 bits
+    Rgba10,
+    /// 16 bit floating point per channel
+    Rgba16F,
+    /// 8 bits per channel, stored in B, G, R order with an unused fourth byte
+    Bgr32,
+    /// 8 bits per channel, stored tightly packed in R, G, B order
+    Rgb24,
+}
+
+/// The KVMFR name for [PixelFormat].
+pub type FrameType = PixelFormat;
+
+impl TryFrom<u32> for PixelFormat {
+    type Error = u32;
+
+    /// Converts a raw `FrameType` value, returning the value back if it is unknown or invalid.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            shm_datastructs::FrameType_FRAME_TYPE_BGRA => Ok(PixelFormat::Bgra),
+            shm_datastructs::FrameType_FRAME_TYPE_RGBA => Ok(PixelFormat::Rgba),
+            shm_datastructs::FrameType_FRAME_TYPE_RGBA10 => Ok(PixelFormat::Rgba10),
+            shm_datastructs::FrameType_FRAME_TYPE_RGBA16F => Ok(PixelFormat::Rgba16F),
+            shm_datastructs::FrameType_FRAME_TYPE_BGR_32 => Ok(PixelFormat::Bgr32),
+            shm_datastructs::FrameType_FRAME_TYPE_RGB_24 => Ok(PixelFormat::Rgb24),
+            v => Err(v),
+        }
+    }
+}
+
+impl From<PixelFormat> for u32 {
+    fn from(value: PixelFormat) -> Self {
+        match value {
+            PixelFormat::Bgra => shm_datastructs::FrameType_FRAME_TYPE_BGRA,
+            PixelFormat::Rgba => shm_datastructs::FrameType_FRAME_TYPE_RGBA,
+            PixelFormat::Rgba10 => shm_datastructs::FrameType_FRAME_TYPE_RGBA10,
+            PixelFormat::Rgba16F => shm_datastructs::FrameType_FRAME_TYPE_RGBA16F,
+            PixelFormat::Bgr32 => shm_datastructs::FrameType_FRAME_TYPE_BGR_32,
+            PixelFormat::Rgb24 => shm_datastructs::FrameType_FRAME_TYPE_RGB_24,
+        }
+    }
+}
+
+/// Encoding of the bitmap data attached to a cursor shape update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CursorType {
+    /// 32 bit BGRA colour cursor
+    Color,
+    /// 1 bit per pixel AND mask followed by a 1 bit per pixel XOR mask
+    Monochrome,
+    /// 32 bit colour cursor where the alpha channel selects between replacing and
+    /// XORing with the pixels beneath it
+    MaskedColor,
+}
+
+impl TryFrom<u32> for CursorType {
+    type Error = u32;
+
+    /// Converts a raw `CursorType` value, returning the value back if it is unknown.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            shm_datastructs::CursorType_CURSOR_TYPE_COLOR => Ok(CursorType::Color),
+            shm_datastructs::CursorType_CURSOR_TYPE_MONOCHROME => Ok(CursorType::Monochrome),
+            shm_datastructs::CursorType_CURSOR_TYPE_MASKED_COLOR => Ok(CursorType::MaskedColor),
+            v => Err(v),
+        }
+    }
+}
+
+impl From<CursorType> for u32 {
+    fn from(value: CursorType) -> Self {
+        match value {
+            CursorType::Color => shm_datastructs::CursorType_CURSOR_TYPE_COLOR,
+            CursorType::Monochrome => shm_datastructs::CursorType_CURSOR_TYPE_MONOCHROME,
+            CursorType::MaskedColor => shm_datastructs::CursorType_CURSOR_TYPE_MASKED_COLOR,
+        }
+    }
+}
+
+impl PixelFormat {
+    /// Returns the number of bytes used to store a single pixel in this format.
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            PixelFormat::Bgra | PixelFormat::Rgba | PixelFormat::Rgba10 | PixelFormat::Bgr32 => 4,
+            PixelFormat::Rgba16F => 8,
+            PixelFormat::Rgb24 => 3,
+        }
+    }
+
+    /// Returns true for formats with more than 8 bits per channel, which are used by the
+    /// host for HDR captures.
+    pub fn is_hdr(self) -> bool {
+        matches!(self, PixelFormat::Rgba10 | PixelFormat::Rgba16F)
+    }
+}
+
+/// A rectangular region of a frame which has changed since the previous frame.
+///
+/// This has the same layout as the KVMFR `FrameDamageRect`, so that rects can be read
+/// directly out of shared memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, FromBytes, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct DamageRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+const _: () = assert!(
+    std::mem::size_of::<DamageRect>() == std::mem::size_of::<shm_datastructs::FrameDamageRect>()
+        && std::mem::align_of::<DamageRect>()
+            == std::mem::align_of::<shm_datastructs::FrameDamageRect>()
+);
+
+impl From<shm_datastructs::FrameDamageRect> for DamageRect {
+    fn from(value: shm_datastructs::FrameDamageRect) -> Self {
+        DamageRect {
+            x: value.x,
+            y: value.y,
+            width: value.width,
+            height: value.height,
+        }
+    }
+}
+
+impl From<DamageRect> for shm_datastructs::FrameDamageRect {
+    fn from(value: DamageRect) -> Self {
+        shm_datastructs::FrameDamageRect {
+            x: value.x,
+            y: value.y,
+            width: value.width,
+            height: value.height,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags attached to a frame by the host.
+    ///
+    /// Unknown bits set by newer hosts are preserved rather than rejected.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct FrameFlags: u32 {
+        /// The guest wants the client's screensaver to be inhibited
+        const BLOCK_SCREENSAVER = shm_datastructs::FRAME_FLAG_BLOCK_SCREENSAVER;
+        /// The guest wants the client window to be brought to the front
+        const REQUEST_ACTIVATION = shm_datastructs::FRAME_FLAG_REQUEST_ACTIVATION;
+        /// The frame was too large for the shared memory, and has been cut short
+        const TRUNCATED = shm_datastructs::FRAME_FLAG_TRUNCATED;
+        /// The frame contains HDR content
+        const HDR = shm_datastructs::FRAME_FLAG_HDR;
+        /// The HDR content uses the PQ transfer function
+        const HDR_PQ = shm_datastructs::FRAME_FLAG_HDR_PQ;
+    }
+}
+
+/// Transfer function used by an HDR frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HdrTransfer {
+    /// Linear scRGB, where 1.0 is SDR white, as captured from Windows in `RGBA16F`
+    Linear,
+    /// SMPTE ST 2084 perceptual quantizer, as used by HDR10
+    Pq,
+}
+
+/// Colour primaries of an HDR frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorPrimaries {
+    Bt709,
+    Bt2020,
+}
+
+/// Describes how the colour values of an HDR frame should be interpreted.
+///
+/// KVMFR only signals this through [FrameFlags::HDR] and [FrameFlags::HDR_PQ], so the
+/// primaries are implied by the transfer function in the same way as the official client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HdrMetadata {
+    pub transfer: HdrTransfer,
+}
+
+impl HdrMetadata {
+    /// Reads the HDR metadata from a frame's flags, returning None for SDR frames.
+    pub fn from_flags(flags: FrameFlags) -> Option<HdrMetadata> {
+        if !flags.contains(FrameFlags::HDR) {
+            return None;
+        }
+        let transfer = match flags.contains(FrameFlags::HDR_PQ) {
+            true => HdrTransfer::Pq,
+            false => HdrTransfer::Linear,
+        };
+        Some(HdrMetadata { transfer })
+    }
+
+    /// Returns the frame flags which signal this metadata.
+    pub fn flags(&self) -> FrameFlags {
+        match self.transfer {
+            HdrTransfer::Linear => FrameFlags::HDR,
+            HdrTransfer::Pq => FrameFlags::HDR | FrameFlags::HDR_PQ,
+        }
+    }
+
+    /// Returns the colour primaries implied by the transfer function.
+    pub fn primaries(&self) -> ColorPrimaries {
+        match self.transfer {
+            HdrTransfer::Linear => ColorPrimaries::Bt709,
+            HdrTransfer::Pq => ColorPrimaries::Bt2020,
+        }
+    }
+}
+
+impl TryFrom<u32> for FrameFlags {
+    type Error = u32;
+
+    /// Converts raw `KVMFRFrameFlags`, returning the value back if any bits are unknown.
+    /// Use [FrameFlags::from_bits_retain] to accept unknown bits.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        FrameFlags::from_bits(value).ok_or(value)
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags sent alongside each cursor update, describing what it contains.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct CursorFlags: u32 {
+        /// The update carries a new cursor position
+        const POSITION = shm_datastructs::CURSOR_FLAG_POSITION;
+        /// The cursor should be drawn
+        const VISIBLE = shm_datastructs::CURSOR_FLAG_VISIBLE;
+        /// The update carries a new cursor shape
+        const SHAPE = shm_datastructs::CURSOR_FLAG_SHAPE;
+    }
+}
+
+impl TryFrom<u32> for CursorFlags {
+    type Error = u32;
+
+    /// Converts raw `KVMFRCursorFlags`, returning the value back if any bits are unknown.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        CursorFlags::from_bits(value).ok_or(value)
+    }
+}
+
+bitflags::bitflags! {
+    /// Optional features supported by the host.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct HostFeatures: u32 {
+        /// The host can move the guest cursor when asked to by the client
+        const SET_CURSOR_POS = shm_datastructs::KVMFR_FEATURE_SETCURSORPOS;
+        /// The host can resize the guest display when asked to by the client
+        const WINDOW_SIZE = shm_datastructs::KVMFR_FEATURE_WINDOWSIZE;
+    }
+}
+
+/// A message sent by the client to the host on the cursor queue, asking it to act on the
+/// guest.
+///
+/// Each message needs a feature which older hosts may not support, see
+/// [HostMessage::required_features].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostMessage {
+    /// Move the guest cursor to a position on the guest screen
+    SetCursorPos { x: i32, y: i32 },
+    /// Resize the guest display to match the client's window
+    WindowSize { width: u32, height: u32 },
+}
+
+impl HostMessage {
+    /// Returns the host features needed for the host to act on this message.
+    pub fn required_features(&self) -> HostFeatures {
+        match self {
+            HostMessage::SetCursorPos { .. } => HostFeatures::SET_CURSOR_POS,
+            HostMessage::WindowSize { .. } => HostFeatures::WINDOW_SIZE,
+        }
+    }
+
+    /// Encodes the message as the matching KVMFR message struct.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            HostMessage::SetCursorPos { x, y } => shm_datastructs::KVMFRSetCursorPos {
+                msg: shm_datastructs::KVMFRMessage {
+                    type_: shm_datastructs::KVMFR_MESSAGE_SETCURSORPOS,
+                },
+                x,
+                y,
+            }
+            .as_bytes()
+            .to_vec(),
+            HostMessage::WindowSize { width, height } => shm_datastructs::KVMFRWindowSize {
+                msg: shm_datastructs::KVMFRMessage {
+                    type_: shm_datastructs::KVMFR_MESSAGE_WINDOWSIZE,
+                },
+                w: width,
+                h: height,
+            }
+            .as_bytes()
+            .to_vec(),
+        }
+    }
+
+    /// Decodes a message sent by a client, as read by the host.
+    pub fn parse(bytes: &[u8]) -> Result<HostMessage, LGError> {
+        let msg: shm_datastructs::KVMFRMessage = read_struct(bytes)?;
+        match msg.type_ {
+            shm_datastructs::KVMFR_MESSAGE_SETCURSORPOS => {
+                let msg: shm_datastructs::KVMFRSetCursorPos = read_struct(bytes)?;
+                Ok(HostMessage::SetCursorPos { x: msg.x, y: msg.y })
+            }
+            shm_datastructs::KVMFR_MESSAGE_WINDOWSIZE => {
+                let msg: shm_datastructs::KVMFRWindowSize = read_struct(bytes)?;
+                Ok(HostMessage::WindowSize {
+                    width: msg.w,
+                    height: msg.h,
+                })
+            }
+            t => Err(LGError::UnknownMessageType(t)),
+        }
+    }
+}
+
+fn read_struct<T: FromBytes>(bytes: &[u8]) -> Result<T, LGError> {
+    //Messages are only byte aligned in the buffers LGMP hands back
+    T::read_from_prefix(bytes)
+        .map(|(value, _)| value)
+        .map_err(|_| LGError::ClientMessageTooSmall)
+}
+
+/// Operating system running in the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OsType {
+    Linux,
+    Bsd,
+    Osx,
+    Windows,
+    Other,
+}
+
+impl TryFrom<u32> for OsType {
+    type Error = u32;
+
+    /// Converts a raw `KVMFROS` value, returning the value back if it is unknown.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            shm_datastructs::KVMFR_OS_LINUX => Ok(OsType::Linux),
+            shm_datastructs::KVMFR_OS_BSD => Ok(OsType::Bsd),
+            shm_datastructs::KVMFR_OS_OSX => Ok(OsType::Osx),
+            shm_datastructs::KVMFR_OS_WINDOWS => Ok(OsType::Windows),
+            shm_datastructs::KVMFR_OS_OTHER => Ok(OsType::Other),
+            v => Err(v),
+        }
+    }
+}
+
+/// Details of the guest VM, sent by hosts which support the `VMINFO` record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmInfo {
+    /// UUID of the guest VM
+    pub uuid: [u8; 16],
+    /// Name of the capture backend used by the host, such as "DXGI" or "NvFBC"
+    pub capture: String,
+    pub cpus: u8,
+    pub cores: u8,
+    pub sockets: u8,
+    /// CPU model name
+    pub model: String,
+}
+
+/// Details of the guest operating system, sent by hosts which support the `OSINFO` record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OsInfo {
+    /// Unknown operating systems are reported as [OsType::Other]
+    pub os: OsType,
+    /// Human readable name and version of the operating system
+    pub name: String,
+}
+
+/// Information about the host, parsed from the KVMFR udata it sends when a session starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostInfo {
+    /// KVMFR protocol version
+    pub version: u32,
+    /// Looking Glass version string of the host application
+    pub host_version: String,
+    /// Unknown bits set by newer hosts are preserved
+    pub features: HostFeatures,
+    pub vm: Option<VmInfo>,
+    pub os: Option<OsInfo>,
+}
+
+impl HostInfo {
+    /// Returns true if the host supports all of the provided features.
+    pub fn supports(&self, features: HostFeatures) -> bool {
+        self.features.contains(features)
+    }
+
+    /// Checks that the host supports all of the provided features, returning
+    /// [LGError::UnsupportedFeature] with the missing ones if not.
+    pub fn require(&self, features: HostFeatures) -> Result<(), LGError> {
+        let missing = features.difference(self.features);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(LGError::UnsupportedFeature(missing))
+        }
+    }
+}
+
+/// A validated description of a frame, built from the raw KVMFR frame header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Incremented by the host whenever the format or layout of frames changes
+    pub format_ver: u32,
+    /// Incremented by the host for each frame
+    pub serial: u32,
+    pub format: PixelFormat,
+    /// Width of the guest screen
+    pub screen_width: u32,
+    /// Height of the guest screen
+    pub screen_height: u32,
+    /// Width of the pixel data, which may differ from the frame size if it was scaled
+    pub data_width: u32,
+    /// Height of the pixel data
+    pub data_height: u32,
+    /// Width of the frame
+    pub frame_width: u32,
+    /// Height of the frame
+    pub frame_height: u32,
+    pub rotation: Rotation,
+    /// Row length in pixels
+    pub stride: u32,
+    /// Row length in bytes
+    pub pitch: u32,
+    pub flags: FrameFlags,
+}
+
+impl FrameInfo {
+    /// Returns the HDR metadata published with the frame, or None if it is SDR.
+    pub fn hdr(&self) -> Option<HdrMetadata> {
+        HdrMetadata::from_flags(self.flags)
+    }
+
+    /// Returns true if the host could not fit the whole frame into shared memory, in which
+    /// case only the first `data_height` rows are present and the rest of the frame is
+    /// missing. Renderers should either skip the frame or present just those rows.
+    pub fn is_truncated(&self) -> bool {
+        self.flags.contains(FrameFlags::TRUNCATED)
+    }
+
+    /// Returns true if the guest wants the client's screensaver to be inhibited.
+    pub fn blocks_screensaver(&self) -> bool {
+        self.flags.contains(FrameFlags::BLOCK_SCREENSAVER)
+    }
+
+    /// Returns true if the guest wants the client window to be brought to the front.
+    pub fn requests_activation(&self) -> bool {
+        self.flags.contains(FrameFlags::REQUEST_ACTIVATION)
+    }
+}
+
+impl TryFrom<&shm_datastructs::KVMFRFrame> for FrameInfo {
+    type Error = LGError;
+
+    /// Fails if the format or rotation are unknown, if the frame has no pixels, or if the
+    /// pitch is too small to hold a row of pixels in the frame's format.
+    fn try_from(value: &shm_datastructs::KVMFRFrame) -> Result<Self, Self::Error> {
+        let format = PixelFormat::try_from(value.type_).map_err(LGError::UnknownFrameFormat)?;
+        if value.dataWidth == 0 || value.dataHeight == 0 {
+            Err(LGError::EmptyFrame(value.dataWidth, value.dataHeight))?
+        }
+        if u64::from(value.pitch) < u64::from(value.dataWidth) * u64::from(format.bytes_per_pixel())
+        {
+            Err(LGError::InvalidFramePitch(value.pitch))?
+        }
+        Ok(FrameInfo {
+            format_ver: value.formatVer,
+            serial: value.frameSerial,
+            format,
+            screen_width: value.screenWidth,
+            screen_height: value.screenHeight,
+            data_width: value.dataWidth,
+            data_height: value.dataHeight,
+            frame_width: value.frameWidth,
+            frame_height: value.frameHeight,
+            rotation: Rotation::try_from(value.rotation).map_err(LGError::UnknownFrameRotation)?,
+            stride: value.stride,
+            pitch: value.pitch,
+            flags: FrameFlags::from_bits_retain(value.flags),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_info_rejects_unknown_values() {
+        let mut frame: shm_datastructs::KVMFRFrame = unsafe { std::mem::zeroed() };
+        frame.type_ = PixelFormat::Rgba.into();
+        frame.flags = shm_datastructs::FRAME_FLAG_HDR | 0x8000;
+        assert!(matches!(
+            FrameInfo::try_from(&frame),
+            Err(LGError::EmptyFrame(0, 0))
+        ));
+        frame.dataWidth = 4;
+        frame.dataHeight = 1;
+        frame.pitch = 16;
+        let info = FrameInfo::try_from(&frame).unwrap();
+        assert_eq!(info.format, PixelFormat::Rgba);
+        assert!(info.flags.contains(FrameFlags::HDR));
+        assert_eq!(
+            info.hdr(),
+            Some(HdrMetadata {
+                transfer: HdrTransfer::Linear
+            })
+        );
+
+        frame.pitch = 8;
+        assert!(matches!(
+            FrameInfo::try_from(&frame),
+            Err(LGError::InvalidFramePitch(8))
+        ));
+        frame.pitch = 0;
+        assert!(matches!(
+            FrameInfo::try_from(&frame),
+            Err(LGError::InvalidFramePitch(0))
+        ));
+        frame.type_ = PixelFormat::Rgba16F.into();
+        frame.pitch = 32;
+        assert!(FrameInfo::try_from(&frame).is_ok());
+
+        frame.rotation = 17;
+        assert!(matches!(
+            FrameInfo::try_from(&frame),
+            Err(LGError::UnknownFrameRotation(17))
+        ));
+        frame.type_ = shm_datastructs::FrameType_FRAME_TYPE_INVALID;
+        assert!(matches!(
+            FrameInfo::try_from(&frame),
+            Err(LGError::UnknownFrameFormat(_))
+        ));
+    }
+
+    #[test]
+    fn flags_round_trip_raw_values() {
+        let raw = shm_datastructs::CURSOR_FLAG_POSITION | shm_datastructs::CURSOR_FLAG_SHAPE;
+        let flags = CursorFlags::try_from(raw).unwrap();
+        assert_eq!(flags, CursorFlags::POSITION | CursorFlags::SHAPE);
+        assert_eq!(flags.bits(), raw);
+        assert_eq!(CursorFlags::try_from(0x100), Err(0x100));
+
+        assert_eq!(
+            FrameFlags::try_from(shm_datastructs::FRAME_FLAG_TRUNCATED),
+            Ok(FrameFlags::TRUNCATED)
+        );
+        assert_eq!(FrameFlags::try_from(0x8000), Err(0x8000));
+    }
+
+    #[test]
+    fn reports_missing_host_features() {
+        let info = HostInfo {
+            version: shm_datastructs::KVMFR_VERSION,
+            host_version: String::new(),
+            features: HostFeatures::WINDOW_SIZE,
+            vm: None,
+            os: None,
+        };
+        assert!(info.require(HostFeatures::WINDOW_SIZE).is_ok());
+        assert!(matches!(
+            info.require(HostFeatures::all()),
+            Err(LGError::UnsupportedFeature(missing)) if missing == HostFeatures::SET_CURSOR_POS
+        ));
+    }
+
+    #[test]
+    fn host_messages_round_trip() {
+        for msg in [
+            HostMessage::SetCursorPos { x: -5, y: 300 },
+            HostMessage::WindowSize {
+                width: 1920,
+                height: 1080,
+            },
+        ] {
+            assert_eq!(HostMessage::parse(&msg.to_bytes()).unwrap(), msg);
+        }
+        assert!(matches!(
+            HostMessage::parse(&[1, 0]),
+            Err(LGError::ClientMessageTooSmall)
+        ));
+        assert!(matches!(
+            HostMessage::parse(&[7, 0, 0, 0]),
+            Err(LGError::UnknownMessageType(7))
+        ));
+    }
+}
@@ -0,0 +1,1805 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use lookinggla_rs::{
+    client::{
+        Anomaly, Backpressure, ChannelPriority, Dispatcher, HealthStatus, KVMFRChans, LGEvent,
+        LGMPConnection, LookingGlass, Quirks, ReplayConnection, FRAME_QUEUE_ID,
+    },
+    convert::ToneMap,
+    damage::{DamageEstimator, DamageEstimatorOpts},
+    error::LGError,
+    host::{HostCursor, HostCursorShape, HostFrame, HostHeartbeat},
+    inspect,
+    testing::MockHost,
+    threads::ThreadRole,
+    types::{
+        ColorPrimaries, CursorType, DamageRect, HdrMetadata, HdrTransfer, HostFeatures,
+        HostMessage, PixelFormat, Rotation,
+    },
+};
+
+#[cfg(feature = "alloc-count")]
+#[global_allocator]
+static ALLOC: lookinggla_rs::alloc_count::CountingAllocator =
+    lookinggla_rs::alloc_count::CountingAllocator;
+
+/// A writer which can be read back after being handed to a connection.
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Polls until a frame or cursor event arrives, returning which it was and how many polls
+/// it took.
+fn poll_until_message(conn: &mut LGMPConnection) -> (&'static str, usize) {
+    for polls in 1..=10 {
+        match conn.poll_event().expect("Failed to poll for events") {
+            LGEvent::Frame(_) => return ("frame", polls),
+            LGEvent::Cursor(_) => return ("cursor", polls),
+            _ => (),
+        }
+    }
+    panic!("No message was received");
+}
+
+#[test]
+fn receives_injected_frame() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    host.inject_solid_frame(64, 32, [0x10, 0x20, 0x30, 0xff])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    let header = frame.as_frame().expect("Frame message was malformed");
+    assert_eq!(header.frameWidth, 64);
+    assert_eq!(header.frameHeight, 32);
+    assert_eq!(header.pitch, 64 * 4);
+
+    let data = frame.copy_data().expect("Frame data was out of bounds");
+    assert_eq!(data.len(), 64 * 32 * 4);
+    assert_eq!(&data[..4], &[0x10, 0x20, 0x30, 0xff]);
+}
+
+#[test]
+fn receives_injected_cursor() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    host.inject_cursor_position(12, 34)
+        .expect("Failed to inject cursor update");
+
+    let event = conn.poll_event().expect("Failed to poll for events");
+    match event {
+        LGEvent::Cursor(cursor) => {
+            let msg = cursor.as_ptr_msg().expect("Cursor message was malformed");
+            assert_eq!((msg.x, msg.y), (12, 34));
+        }
+        _ => panic!("Expected a cursor event"),
+    }
+}
+
+#[test]
+fn reports_format_change_before_first_frame() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    host.inject_solid_frame(64, 32, [0; 4])
+        .expect("Failed to inject frame");
+
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::FormatChanged(_)
+    ));
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::Frame(_)
+    ));
+}
+
+#[test]
+fn subscribes_to_extra_queues() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    assert!(conn.subscribe_extra(99).is_err());
+
+    //Subscribe to the frame queue a second time to read its messages raw
+    let mut chan = conn
+        .subscribe_extra(FRAME_QUEUE_ID)
+        .expect("Failed to subscribe to frame queue");
+    assert_eq!(chan.queue_id(), FRAME_QUEUE_ID);
+    assert!(chan.recv().expect("Failed to read queue").is_none());
+
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 64,
+        screen_height: 32,
+        width: 64,
+        height: 32,
+        stride: 64,
+        pitch: 64 * 4,
+        rotation: Rotation::Rot0,
+        damage: None,
+        hdr: None,
+    };
+    host.inject_frame(&frame, &[0; 64 * 32 * 4])
+        .expect("Failed to inject frame");
+
+    let msg = chan
+        .recv()
+        .expect("Failed to read queue")
+        .expect("No message was received");
+    let header: inspect::KVMFRFrame =
+        inspect::parse_frame(msg.data()).expect("Failed to parse frame");
+    assert_eq!((header.frameWidth, header.frameHeight), (64, 32));
+    drop(msg);
+    assert!(chan.recv().expect("Failed to read queue").is_none());
+}
+
+#[test]
+fn limits_frame_rate() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host.client_opts_builder().max_frame_rate(Some(20)).build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+
+    //Frames arriving before the interval has passed are coalesced
+    host.inject_solid_frame(16, 16, [1; 4])
+        .expect("Failed to inject frame");
+    host.inject_solid_frame(16, 16, [2; 4])
+        .expect("Failed to inject frame");
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_none());
+
+    std::thread::sleep(Duration::from_millis(60));
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.data().expect("Invalid frame data")[0], 2);
+    assert_eq!(frame.dropped_since_last(), 1);
+}
+
+#[test]
+fn receives_damage_rects() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let damage = vec![DamageRect {
+        x: 8,
+        y: 4,
+        width: 16,
+        height: 8,
+    }];
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 64,
+        screen_height: 32,
+        width: 64,
+        height: 32,
+        stride: 64,
+        pitch: 64 * 4,
+        rotation: Rotation::Rot0,
+        damage: Some(damage.clone()),
+        hdr: None,
+    };
+    host.inject_frame(&frame, &[0; 64 * 32 * 4])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(
+        frame.damage_rects().expect("Invalid damage rects"),
+        &damage[..]
+    );
+}
+
+#[test]
+fn receives_truncated_frames() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 1920,
+        screen_height: 1200,
+        width: 1920,
+        height: 1200,
+        stride: 1920,
+        pitch: 1920 * 4,
+        rotation: Rotation::Rot0,
+        damage: None,
+        hdr: None,
+    };
+    //The mock host's buffers only fit the first 1080 rows
+    host.inject_frame(&frame, &vec![0x40; 1920 * 1200 * 4])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    let info = frame.info().expect("Frame info was invalid");
+    assert!(info.is_truncated());
+    assert!(!info.blocks_screensaver());
+    assert_eq!((info.data_height, info.frame_height), (1080, 1200));
+    assert_eq!(frame.valid_rows().expect("Frame header was invalid"), 1080);
+    let data = frame.valid_data().expect("Frame data was invalid");
+    assert_eq!(data.len(), 1920 * 1080 * 4);
+    assert!(data.iter().all(|b| *b == 0x40));
+}
+
+#[test]
+fn rejects_short_frame_data() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 64,
+        screen_height: 32,
+        width: 64,
+        height: 32,
+        stride: 64,
+        pitch: 64 * 4,
+        rotation: Rotation::Rot0,
+        damage: None,
+        hdr: None,
+    };
+    assert!(matches!(
+        host.inject_frame(&frame, &[0; 64 * 20 * 4]),
+        Err(LGError::HostFrameDataTooSmall)
+    ));
+    let frame = HostFrame { pitch: 8, ..frame };
+    assert!(matches!(
+        host.inject_frame(&frame, &[0; 64 * 32 * 4]),
+        Err(LGError::HostFrameDataTooSmall)
+    ));
+}
+
+#[test]
+fn rejects_rows_larger_than_frame_buffers() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    //One pixel more than the mock host's buffers hold
+    let width = 1920 * 1080 + 1;
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: width,
+        screen_height: 1,
+        width,
+        height: 1,
+        stride: width,
+        pitch: width * 4,
+        rotation: Rotation::Rot0,
+        damage: None,
+        hdr: None,
+    };
+    assert!(matches!(
+        host.inject_frame(&frame, &vec![0; width as usize * 4]),
+        Err(LGError::HostFrameRowTooLarge)
+    ));
+}
+
+#[test]
+fn reports_bytes_written() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 64,
+        screen_height: 32,
+        width: 64,
+        height: 32,
+        stride: 64,
+        pitch: 64 * 4,
+        rotation: Rotation::Rot0,
+        damage: None,
+        hdr: None,
+    };
+    host.inject_frame(&frame, &[0; 64 * 32 * 4])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.bytes_written().expect("Invalid header"), 64 * 32 * 4);
+}
+
+#[test]
+fn rejects_damage_outside_frame() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 64,
+        screen_height: 32,
+        width: 64,
+        height: 32,
+        stride: 64,
+        pitch: 64 * 4,
+        rotation: Rotation::Rot0,
+        damage: Some(vec![DamageRect {
+            x: 60,
+            y: 0,
+            width: 16,
+            height: 8,
+        }]),
+        hdr: None,
+    };
+    host.inject_frame(&frame, &[0; 64 * 32 * 4])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert!(matches!(
+        frame.damage_rects(),
+        Err(LGError::DamageRectOutOfBounds)
+    ));
+}
+
+#[test]
+fn estimates_missing_damage() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    let mut estimator = DamageEstimator::new(DamageEstimatorOpts {
+        tile_size: 16,
+        budget: None,
+    });
+
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 64,
+        screen_height: 32,
+        width: 64,
+        height: 32,
+        stride: 64,
+        pitch: 64 * 4,
+        rotation: Rotation::Rot0,
+        damage: None,
+        hdr: None,
+    };
+    let mut data = vec![0; 64 * 32 * 4];
+    let mut damage = Vec::new();
+    for _ in 0..2 {
+        host.inject_frame(&frame, &data)
+            .expect("Failed to inject frame");
+        let frame = conn
+            .get_frame_update()
+            .expect("Failed to read from frame channel")
+            .expect("No frame was received");
+        damage.push(estimator.frame_damage(&frame).expect("Invalid frame"));
+        //Change one pixel in the second row of tiles
+        data[20 * 64 * 4 + 40 * 4] = 0xff;
+    }
+    assert_eq!(damage[0], None);
+    assert_eq!(
+        damage[1],
+        Some(vec![DamageRect {
+            x: 32,
+            y: 16,
+            width: 16,
+            height: 16
+        }])
+    );
+}
+
+#[test]
+fn detects_duplicate_frames() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let mut duplicates = Vec::new();
+    for color in [[1, 2, 3, 4], [1, 2, 3, 4], [5, 6, 7, 8]] {
+        host.inject_solid_frame(16, 16, color)
+            .expect("Failed to inject frame");
+        let frame = conn
+            .get_frame_update()
+            .expect("Failed to read from frame channel")
+            .expect("No frame was received");
+        duplicates.push(
+            frame
+                .is_duplicate_of_previous()
+                .expect("Failed to hash frame"),
+        );
+    }
+    assert_eq!(duplicates, [false, true, false]);
+}
+
+#[test]
+fn applies_quirk_overrides() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .force_quirks(Quirks::IGNORE_DAMAGE | Quirks::LIMIT_CURSOR_SHAPES)
+        .disable_quirks(Quirks::LIMIT_CURSOR_SHAPES)
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+    assert_eq!(conn.quirks(), Some(Quirks::IGNORE_DAMAGE));
+
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 64,
+        screen_height: 32,
+        width: 64,
+        height: 32,
+        stride: 64,
+        pitch: 64 * 4,
+        rotation: Rotation::Rot0,
+        damage: Some(vec![DamageRect {
+            x: 8,
+            y: 4,
+            width: 16,
+            height: 8,
+        }]),
+        hdr: None,
+    };
+    host.inject_frame(&frame, &[0; 64 * 32 * 4])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert!(frame
+        .damage_rects()
+        .expect("Invalid damage rects")
+        .is_empty());
+}
+
+#[test]
+fn publishes_hdr_metadata() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let hdr = HdrMetadata {
+        transfer: HdrTransfer::Pq,
+    };
+    let frame = HostFrame {
+        format: PixelFormat::Rgba10,
+        screen_width: 16,
+        screen_height: 16,
+        width: 16,
+        height: 16,
+        stride: 16,
+        pitch: 16 * 4,
+        rotation: Rotation::Rot0,
+        damage: None,
+        hdr: Some(hdr),
+    };
+    host.inject_frame(&frame, &[0; 16 * 16 * 4])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    let info = frame.info().expect("Frame message was malformed");
+    assert_eq!(info.hdr(), Some(hdr));
+    assert_eq!(hdr.primaries(), ColorPrimaries::Bt2020);
+}
+
+#[test]
+fn cursor_burst_does_not_delay_frames() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    for i in 0..10 {
+        host.inject_cursor_position(i, i)
+            .expect("Failed to inject cursor update");
+    }
+    host.inject_solid_frame(64, 32, [0; 4])
+        .expect("Failed to inject frame");
+
+    //The format change is reported first, then the frame
+    assert_eq!(poll_until_message(&mut conn), ("frame", 2));
+}
+
+#[test]
+fn alternate_priority_interleaves_channels() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .priority(ChannelPriority::Alternate)
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    for i in 0..10 {
+        host.inject_cursor_position(i, i)
+            .expect("Failed to inject cursor update");
+    }
+    host.inject_solid_frame(64, 32, [0; 4])
+        .expect("Failed to inject frame");
+
+    assert_eq!(poll_until_message(&mut conn).0, "cursor");
+    let (kind, polls) = poll_until_message(&mut conn);
+    assert_eq!(kind, "frame");
+    assert!(polls <= 2, "Frame was delayed by {polls} polls");
+}
+
+#[test]
+fn subscribes_to_selected_channels() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host.client_opts_builder().subscribe_cursor(false).build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+    host.process().expect("Failed to process host");
+    assert!(host.host().has_frame_subscribers());
+    assert!(!host.host().has_cursor_subscribers());
+
+    conn.tick_cursor().expect("Failed to tick cursor queue");
+    assert!(conn
+        .get_cursor_update()
+        .expect("Failed to read from cursor channel")
+        .is_none());
+}
+
+#[test]
+fn sends_messages_to_host() {
+    let mut host =
+        MockHost::with_features(HostFeatures::WINDOW_SIZE).expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let resize = HostMessage::WindowSize {
+        width: 1280,
+        height: 720,
+    };
+    conn.send_message(resize).expect("Failed to send message");
+    assert!(matches!(
+        conn.send_message(HostMessage::SetCursorPos { x: 1, y: 2 }),
+        Err(LGError::UnsupportedFeature(missing)) if missing == HostFeatures::SET_CURSOR_POS
+    ));
+
+    assert_eq!(
+        host.host().read_message().expect("Failed to read message"),
+        Some(resize)
+    );
+    assert_eq!(
+        host.host().read_message().expect("Failed to read message"),
+        None
+    );
+}
+
+#[test]
+fn closes_session() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    host.process().expect("Failed to process host");
+    assert!(host.host().has_frame_subscribers());
+
+    conn.close_session().expect("Failed to close session");
+    assert!(conn.host_info().is_none());
+    host.process().expect("Failed to process host");
+    assert!(!host.host().has_frame_subscribers());
+    assert!(!host.host().has_cursor_subscribers());
+
+    //The host's timestamp must move on before a new session can start
+    std::thread::sleep(Duration::from_millis(10));
+    host.process().expect("Failed to process host");
+    conn.init().expect("Failed to start a new session");
+    drop(conn);
+    host.process().expect("Failed to process host");
+    assert!(!host.host().has_frame_subscribers());
+}
+
+#[test]
+fn reports_queue_status() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let status = conn
+        .queue_status(KVMFRChans::Frame)
+        .expect("Failed to check queue")
+        .expect("No session");
+    assert!(status.subscribed && !status.pending);
+    assert_eq!(status.head_serial, None);
+
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    let status = conn
+        .queue_status(KVMFRChans::Frame)
+        .expect("Failed to check queue")
+        .expect("No session");
+    assert!(status.pending);
+    assert!(status.head_serial.is_some());
+    assert_eq!(status.last_serial, None);
+    assert!(status.until_timeout <= Duration::from_secs(1));
+}
+
+#[test]
+fn reports_queue_deadline() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let timeout = Duration::from_millis(500);
+    let opts = host.client_opts_builder().frame_timeout(timeout).build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    //Finding the queue empty restarts the deadline
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_none());
+    let deadline = conn.deadline(KVMFRChans::Frame).expect("No frame deadline");
+    assert!(!deadline.due_within(std::time::Instant::now(), Duration::ZERO));
+    assert!(deadline.remaining(std::time::Instant::now()) <= timeout);
+
+    //A waiting frame holds the deadline back until the queue is found empty again
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+    assert_eq!(conn.deadline(KVMFRChans::Frame), Some(deadline));
+    assert!(deadline.elapsed(std::time::Instant::now()) >= Duration::from_millis(20));
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_none());
+    let deadline = conn.deadline(KVMFRChans::Frame).expect("No frame deadline");
+    assert!(deadline.elapsed(std::time::Instant::now()) < Duration::from_millis(20));
+
+    conn.close_session().expect("Failed to close session");
+    assert_eq!(conn.deadline(KVMFRChans::Frame), None);
+}
+
+#[test]
+fn reports_host_liveness() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let health = conn.connection_health().expect("Failed to check health");
+    assert!(health.host_alive);
+    let frame = health.frame.expect("No frame queue status");
+    assert_eq!(frame.since_message, None);
+    assert!(health
+        .until_timeout()
+        .is_some_and(|t| t <= Duration::from_secs(1)));
+
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+    let health = conn.connection_health().expect("Failed to check health");
+    let frame = health.frame.expect("No frame queue status");
+    assert!(frame.since_message.is_some());
+    assert_eq!(
+        health.cursor.expect("No cursor queue status").since_message,
+        None
+    );
+
+    //The host is presumed gone once its heartbeat has stopped for a second
+    drop(host);
+    std::thread::sleep(Duration::from_millis(1100));
+    let health = conn.connection_health().expect("Failed to check health");
+    assert!(!health.host_alive);
+    assert_eq!(health.until_timeout(), None);
+}
+
+#[test]
+fn reports_session_lifecycle() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host.client_opts_builder().lifecycle_events(true).build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    let client_id = conn.client_id().expect("No client id after init");
+    assert!(conn.session_started().is_some());
+    let version = conn.host_info().map(|info| info.version);
+    assert_eq!(conn.kvmfr_version(), version);
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::SessionStarted(id) if id == client_id
+    ));
+    //The client only times the heartbeat from when it last saw the host's timestamp change
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::Idle
+    ));
+
+    drop(host);
+    std::thread::sleep(Duration::from_millis(1100));
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::SessionLost(id) if id == client_id
+    ));
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::HostLost
+    ));
+}
+
+#[test]
+fn reports_host_info() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let conn = host.connect().expect("Failed to connect to mock host");
+
+    let info = conn.host_info().expect("No host info after init");
+    assert_eq!(info.host_version, "lookinggla-rs mock host");
+    assert_eq!(info.vm, None);
+}
+
+#[test]
+fn rejects_host_missing_required_features() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .require_features(HostFeatures::SET_CURSOR_POS)
+        .build();
+    assert!(matches!(
+        host.connect_with(opts),
+        Err(LGError::UnsupportedFeature(_))
+    ));
+}
+
+#[test]
+fn counts_empty_queue_reads() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::Idle
+    ));
+    let stats = conn.stats();
+    assert!(stats.frame_queue.empty > 0);
+    assert!(stats.cursor_queue.empty > 0);
+    assert_eq!(stats.frame_queue.corrupted, 0);
+    assert!(stats.frame_queue.reads >= stats.frame_queue.empty);
+    assert!(stats.frame_queue.empty_ratio() > 0.0);
+}
+
+#[test]
+fn heartbeat_runs_in_background() {
+    let host = MockHost::new().expect("Failed to create mock host");
+    let opts = host.client_opts();
+    let host = std::sync::Arc::new(std::sync::Mutex::new(host.into_host()));
+    let heartbeat = HostHeartbeat::spawn(host.clone()).expect("Failed to start heartbeat");
+
+    //The client's subscriptions are only picked up by host processing
+    let mut conn = LGMPConnection::open(opts).expect("Failed to open client");
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    conn.init().expect("Failed to init client session");
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    host.lock()
+        .unwrap()
+        .publish_frame(
+            &HostFrame {
+                format: PixelFormat::Bgra,
+                screen_width: 64,
+                screen_height: 32,
+                width: 64,
+                height: 32,
+                stride: 64,
+                pitch: 64 * 4,
+                rotation: Rotation::Rot0,
+                damage: None,
+                hdr: None,
+            },
+            &[0; 64 * 32 * 4],
+        )
+        .expect("Failed to publish frame");
+    drop(heartbeat);
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+}
+
+#[test]
+fn scrubbed_frames_are_not_resent() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    host.inject_solid_frame(64, 32, [0xff; 4])
+        .expect("Failed to inject frame");
+    host.host()
+        .scrub_buffers()
+        .expect("Failed to scrub buffers");
+
+    //New subscribers would normally be sent the most recent frame
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    host.process().expect("Failed to process host");
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_none());
+}
+
+#[test]
+fn captures_frame_at_serial() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    //The host numbers frames from 1
+    conn.capture_at_serial(2);
+    for shade in [0x10, 0x20, 0x30] {
+        host.inject_solid_frame(16, 16, [shade, shade, shade, 0xff])
+            .expect("Failed to inject frame");
+        conn.get_frame_update()
+            .expect("Failed to read from frame channel")
+            .expect("No frame was received");
+        if shade == 0x10 {
+            assert!(conn.take_capture().is_none());
+        }
+    }
+
+    let capture = conn.take_capture().expect("No frame was captured");
+    assert_eq!(capture.serial, 2);
+    assert_eq!(capture.info.data_width, 16);
+    assert_eq!(&capture.data[..4], &[0x20, 0x20, 0x20, 0xff]);
+    assert!(conn.take_capture().is_none());
+}
+
+#[test]
+fn limits_cursor_shape_storms() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .cursor_shape_limit(Some(2))
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    let mut shapes = Vec::new();
+    for i in 0..4u8 {
+        let data = [i; 4 * 4 * 4];
+        host.inject_cursor(&HostCursor {
+            position: None,
+            visible: true,
+            shape: Some(HostCursorShape {
+                cursor_type: CursorType::Color,
+                width: 4,
+                height: 4,
+                pitch: 16,
+                hotspot: (0, 0),
+                data: &data,
+            }),
+        })
+        .expect("Failed to inject cursor update");
+        let cursor = conn
+            .get_cursor_update()
+            .expect("Failed to read from cursor channel")
+            .expect("No cursor update was received");
+        shapes.push(cursor.has_shape());
+    }
+    assert_eq!(shapes, [true, true, false, false]);
+
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::Anomaly(Anomaly::CursorShapeStorm)
+    ));
+    let stats = conn.stats();
+    assert_eq!(stats.cursor_shapes, 4);
+    assert_eq!(stats.cursor_shapes_dropped, 2);
+}
+
+#[test]
+fn replays_recorded_traffic() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    let recording = SharedBuf::default();
+    conn.start_recording(recording.clone())
+        .expect("Failed to start recording");
+
+    host.inject_solid_frame(16, 8, [0x10, 0x20, 0x30, 0xff])
+        .expect("Failed to inject frame");
+    assert_eq!(poll_until_message(&mut conn).0, "frame");
+    host.inject_cursor_position(5, 6)
+        .expect("Failed to inject cursor update");
+    assert_eq!(poll_until_message(&mut conn).0, "cursor");
+    conn.stop_recording();
+
+    let bytes = recording.0.lock().unwrap().clone();
+    let mut replay =
+        ReplayConnection::from_reader(&bytes[..], false).expect("Failed to load recording");
+    assert!(matches!(
+        replay.poll_event().expect("Failed to replay"),
+        LGEvent::FormatChanged(_)
+    ));
+    match replay.poll_event().expect("Failed to replay") {
+        LGEvent::Frame(frame) => {
+            let info = frame.info().expect("Frame message was malformed");
+            assert_eq!((info.data_width, info.data_height), (16, 8));
+            let data = frame.data().expect("Frame data was out of bounds");
+            assert_eq!(&data[..4], &[0x10, 0x20, 0x30, 0xff]);
+        }
+        _ => panic!("Expected a frame event"),
+    }
+    match replay.poll_event().expect("Failed to replay") {
+        LGEvent::Cursor(cursor) => {
+            let msg = cursor.as_ptr_msg().expect("Cursor message was malformed");
+            assert_eq!((msg.x, msg.y), (5, 6));
+        }
+        _ => panic!("Expected a cursor event"),
+    }
+    assert!(replay.is_finished());
+}
+
+#[cfg(feature = "black-box")]
+#[test]
+fn keeps_last_frames_in_black_box() {
+    let dir = std::env::temp_dir().join(format!("lookinggla-rs-black-box-{}", std::process::id()));
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    conn.start_black_box(&dir, 2)
+        .expect("Failed to start black box");
+
+    for i in 0..3 {
+        host.inject_solid_frame(16, 8, [i; 4])
+            .expect("Failed to inject frame");
+        assert_eq!(poll_until_message(&mut conn).0, "frame");
+        //Frames arriving while the previous one is being written are not kept
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    //Dropping the connection with the black box running gathers up its frames
+    drop(conn);
+
+    let mut replay = ReplayConnection::open(dir.join("recording.lgrec"), false)
+        .expect("Failed to load recording");
+    let mut kept = Vec::new();
+    while !replay.is_finished() {
+        if let LGEvent::Frame(frame) = replay.poll_event().expect("Failed to replay") {
+            kept.push(frame.data().expect("Frame data was out of bounds")[0]);
+        }
+    }
+    assert_eq!(kept, [1, 2]);
+
+    let journal = std::fs::read_to_string(dir.join("journal.log")).expect("No journal");
+    assert!(journal.contains("format changed"));
+    assert!(journal.contains("kept in slot 1"));
+    assert!(journal.contains("finalized with 2 frames"));
+    std::fs::remove_dir_all(&dir).expect("Failed to remove black box");
+}
+
+#[test]
+fn counts_fast_forwards() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .frame_timeout(Duration::ZERO)
+        .collect_metrics(true)
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    //With no timeout, every tick fast forwards the queue
+    conn.tick_frame().expect("Failed to tick frame queue");
+    assert_eq!(conn.stats().fast_forwards, 1);
+}
+
+#[test]
+fn applies_backpressure_policy() {
+    let read_frames = |backpressure| {
+        let mut host = MockHost::new().expect("Failed to create mock host");
+        let opts = host
+            .client_opts_builder()
+            .frame_timeout(Duration::ZERO)
+            .backpressure(backpressure)
+            .build();
+        let mut conn = host
+            .connect_with(opts)
+            .expect("Failed to connect to mock host");
+        let mut inject = |value| {
+            host.inject_solid_frame(16, 16, [value; 4])
+                .expect("Failed to inject frame")
+        };
+        inject(0);
+        inject(1);
+        conn.tick_frame().expect("Failed to tick frame queue");
+        let mut read = || {
+            conn.get_frame_update()
+                .expect("Failed to read from frame channel")
+                .map(|frame| frame.data().expect("Invalid frame data")[0])
+        };
+        let mut values = vec![read().expect("No frame was received")];
+        //The third frame arrives while the client is still behind
+        inject(2);
+        values.extend(std::iter::from_fn(read));
+        values
+    };
+
+    //With no timeout, the default policy skips on every tick
+    assert_eq!(read_frames(Backpressure::AvoidTimeout), [1, 2]);
+    assert_eq!(read_frames(Backpressure::Latest), [1, 2]);
+    assert_eq!(read_frames(Backpressure::Never), [0, 1, 2]);
+    assert_eq!(read_frames(Backpressure::BoundedLag(0)), [0, 2]);
+    assert_eq!(read_frames(Backpressure::BoundedLag(1)), [0, 1, 2]);
+}
+
+#[test]
+fn reports_frames_dropped_since_last() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .frame_timeout(Duration::ZERO)
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.dropped_since_last(), 0);
+    drop(frame);
+
+    //The frame queue only holds two frames
+    for _ in 0..2 {
+        host.inject_solid_frame(16, 16, [0; 4])
+            .expect("Failed to inject frame");
+    }
+    //Fast forwarding skips all but the newest frame
+    conn.tick_frame().expect("Failed to tick frame queue");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.dropped_since_last(), 1);
+}
+
+#[test]
+fn copies_frame_into_pitched_buffer() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    host.inject_solid_frame(4, 2, [1, 2, 3, 4])
+        .expect("Failed to inject frame");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+
+    let mut dst = vec![0; 2 * 32];
+    frame.copy_to(&mut dst, 32).expect("Failed to copy frame");
+    assert_eq!(&dst[..16], &[1, 2, 3, 4].repeat(4)[..]);
+    assert_eq!(&dst[16..32], &[0; 16]);
+    assert_eq!(&dst[32..48], &[1, 2, 3, 4].repeat(4)[..]);
+
+    frame
+        .copy_to_rgba8(&mut dst, 32, ToneMap::Clamp)
+        .expect("Failed to convert frame");
+    assert_eq!(&dst[..4], &[3, 2, 1, 4]);
+
+    assert!(matches!(
+        frame.copy_to(&mut dst, 8),
+        Err(LGError::DestinationTooSmall)
+    ));
+    assert!(matches!(
+        frame.copy_to(&mut dst[..40], 32),
+        Err(LGError::DestinationTooSmall)
+    ));
+}
+
+#[test]
+fn copies_with_calibrated_strategy() {
+    use lookinggla_rs::copy::CopyStrategy;
+
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .copy_strategy(CopyStrategy::with_chunk_size(64))
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+    host.inject_solid_frame(64, 64, [1, 2, 3, 4])
+        .expect("Failed to inject frame");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(
+        &frame.copy_data().expect("Failed to copy frame")[..],
+        frame.data().expect("Frame data was invalid")
+    );
+
+    //Timed against the shared memory the frame is in
+    let strategy = CopyStrategy::calibrate(frame.data().expect("Frame data was invalid"));
+    drop(frame);
+    conn.set_copy_strategy(strategy);
+    host.inject_solid_frame(64, 64, [5, 6, 7, 8])
+        .expect("Failed to inject frame");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    let mut dst = vec![0; 64 * 256];
+    frame.copy_to(&mut dst, 256).expect("Failed to copy frame");
+    assert_eq!(dst, [5, 6, 7, 8].repeat(64 * 64));
+}
+
+#[test]
+fn iterates_frame_rows_and_tiles() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    host.inject_solid_frame(10, 6, [1, 2, 3, 4])
+        .expect("Failed to inject frame");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+
+    let rows = frame.rows().expect("Frame data was invalid");
+    assert_eq!(rows.len(), 6);
+    for row in rows {
+        assert_eq!(row, &[1, 2, 3, 4].repeat(10)[..]);
+    }
+
+    let tiles: Vec<_> = frame.tiles(4, 4).expect("Frame data was invalid").collect();
+    assert_eq!(tiles.len(), 6);
+    let last = tiles.last().unwrap();
+    assert_eq!((last.x, last.y, last.width, last.height), (8, 4, 2, 2));
+    assert!(last.rows().all(|row| row == [1, 2, 3, 4, 1, 2, 3, 4]));
+}
+
+#[test]
+fn copies_frame_into_arc() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    host.inject_solid_frame(4, 2, [1, 2, 3, 4])
+        .expect("Failed to inject frame");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    let raw = frame.to_arc().expect("Failed to copy frame");
+    let rgba = frame
+        .to_arc_rgba8(ToneMap::Clamp)
+        .expect("Failed to convert frame");
+    drop(frame);
+
+    assert_eq!(raw.format, PixelFormat::Bgra);
+    assert_eq!((raw.width, raw.height, raw.pitch), (4, 2, 16));
+    assert_eq!(raw.data(), &[1, 2, 3, 4].repeat(8)[..]);
+    assert_eq!(rgba.format, PixelFormat::Rgba);
+    assert!(rgba.is_packed());
+    assert_eq!(&rgba.shared_data()[..4], &[3, 2, 1, 4]);
+}
+
+#[test]
+fn copies_frame_into_owned_frame() {
+    use lookinggla_rs::pool::{FramePool, FramePoolOpts};
+
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    let mut pool = FramePool::new(FramePoolOpts::default());
+    let damage = vec![DamageRect {
+        x: 0,
+        y: 0,
+        width: 2,
+        height: 1,
+    }];
+    let frame = HostFrame {
+        format: PixelFormat::Bgra,
+        screen_width: 4,
+        screen_height: 2,
+        width: 4,
+        height: 2,
+        stride: 4,
+        pitch: 16,
+        rotation: Rotation::Rot90,
+        damage: Some(damage.clone()),
+        hdr: None,
+    };
+    host.inject_frame(&frame, &[1, 2, 3, 4].repeat(8))
+        .expect("Failed to inject frame");
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    let owned = frame.to_owned().expect("Failed to copy frame");
+    let pooled = frame.to_owned_in(&mut pool).expect("Failed to copy frame");
+    drop(frame);
+
+    //The next frame can be read while the copies are still held
+    host.inject_solid_frame(4, 2, [5, 6, 7, 8])
+        .expect("Failed to inject frame");
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+
+    assert_eq!(owned.info.format, PixelFormat::Bgra);
+    assert_eq!((owned.info.data_width, owned.info.pitch), (4, 16));
+    assert_eq!(owned.info.rotation, Rotation::Rot90);
+    assert_eq!(owned.damage, damage);
+    assert_eq!(owned.data(), &[1, 2, 3, 4].repeat(8)[..]);
+    assert_eq!(pooled.info, owned.info);
+    assert_eq!(pooled.damage, owned.damage);
+    assert_eq!(pooled.data(), owned.data());
+    pooled.release_to(&mut pool);
+    assert!(pool.memory_used() >= 32);
+}
+
+#[test]
+fn reads_from_background_thread() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host.client_opts_builder().auto_tick(true).build();
+    //The host needs processing while the client connects
+    let connecting = std::thread::spawn(move || LookingGlass::connect_with(opts));
+    while !connecting.is_finished() {
+        host.process().expect("Failed to process host");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let mut lg = connecting
+        .join()
+        .unwrap()
+        .expect("Failed to connect to mock host");
+    host.process().expect("Failed to process host");
+    assert!(lg.host_info().is_some());
+    assert!(lookinggla_rs::threads()
+        .iter()
+        .any(|info| info.role == ThreadRole::ClientWorker && info.name == "lg-worker"));
+
+    host.inject_solid_frame(16, 16, [1, 2, 3, 4])
+        .expect("Failed to inject frame");
+    host.inject_cursor(&HostCursor {
+        position: Some((5, 6)),
+        visible: true,
+        shape: None,
+    })
+    .expect("Failed to inject cursor update");
+    let frame = lg
+        .next_frame(Duration::from_secs(1))
+        .expect("No frame was received");
+    assert_eq!(frame.data()[..4], [1, 2, 3, 4]);
+    assert!(lg.next_frame(Duration::from_millis(10)).is_none());
+    assert_eq!(lg.latest_frame().map(|f| f.serial), Some(frame.serial));
+
+    for _ in 0..100 {
+        if lg.cursor().position.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(lg.cursor().position, Some((5, 6)));
+    assert!(lg.is_running());
+}
+
+#[test]
+fn sends_messages_from_background_thread() {
+    let mut host =
+        MockHost::with_features(HostFeatures::WINDOW_SIZE).expect("Failed to create mock host");
+    let opts = host.client_opts_builder().auto_tick(true).build();
+    let connecting = std::thread::spawn(move || LookingGlass::connect_with(opts));
+    while !connecting.is_finished() {
+        host.process().expect("Failed to process host");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let lg = connecting
+        .join()
+        .unwrap()
+        .expect("Failed to connect to mock host");
+
+    let resize = HostMessage::WindowSize {
+        width: 1280,
+        height: 720,
+    };
+    lg.send_message(resize).expect("Failed to queue message");
+    assert!(matches!(
+        lg.send_message(HostMessage::SetCursorPos { x: 1, y: 2 }),
+        Err(LGError::UnsupportedFeature(_))
+    ));
+
+    let mut received = None;
+    for _ in 0..100 {
+        received = host.host().read_message().expect("Failed to read message");
+        if received.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(received, Some(resize));
+    assert!(lg.is_running());
+}
+
+#[test]
+fn sends_messages_from_split_cursor() {
+    let mut host =
+        MockHost::with_features(HostFeatures::SET_CURSOR_POS).expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    let mut cursor = conn.split_cursor().expect("Failed to split cursor queue");
+
+    let warp = HostMessage::SetCursorPos { x: 10, y: 20 };
+    cursor.send_message(warp).expect("Failed to send message");
+    assert!(matches!(
+        cursor.send_message(HostMessage::WindowSize {
+            width: 1,
+            height: 1
+        }),
+        Err(LGError::UnsupportedFeature(missing)) if missing == HostFeatures::WINDOW_SIZE
+    ));
+    assert_eq!(
+        host.host().read_message().expect("Failed to read message"),
+        Some(warp)
+    );
+}
+
+#[test]
+fn reads_split_cursor_on_another_thread() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    let mut cursor = conn.split_cursor().expect("Failed to split cursor queue");
+    assert!(conn.split_cursor().is_err());
+
+    host.inject_cursor(&HostCursor {
+        position: Some((7, 8)),
+        visible: true,
+        shape: None,
+    })
+    .expect("Failed to inject cursor update");
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+
+    let reader = std::thread::spawn(move || {
+        let update = cursor
+            .get_cursor_update()
+            .expect("Failed to read from cursor channel")
+            .expect("No cursor update was received");
+        let msg = update.as_ptr_msg().expect("Cursor message was invalid");
+        let position = (msg.x, msg.y);
+        drop(update);
+        assert_eq!(cursor.stats().cursor_updates, 1);
+        position
+    });
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+    assert!(conn
+        .get_cursor_update()
+        .expect("Failed to read from cursor channel")
+        .is_none());
+    assert_eq!(reader.join().unwrap(), (7, 8));
+}
+
+#[test]
+fn splits_into_receivers() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let conn = host.connect().expect("Failed to connect to mock host");
+    let (mut frames, mut cursor) = conn.split().expect("Failed to split connection");
+
+    host.inject_solid_frame(16, 16, [3; 4])
+        .expect("Failed to inject frame");
+    host.inject_cursor(&HostCursor {
+        position: Some((1, 1)),
+        visible: true,
+        shape: None,
+    })
+    .expect("Failed to inject cursor update");
+
+    let reader = std::thread::spawn(move || {
+        let update = cursor
+            .get_cursor_update()
+            .expect("Failed to read from cursor channel");
+        update.is_some()
+    });
+    let frame = frames
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.data().expect("Frame data was invalid")[0], 3);
+    drop(frame);
+    frames.tick().expect("Failed to tick frame queue");
+    assert!(reader.join().unwrap());
+
+    host.process().expect("Failed to process host");
+    assert!(host.host().has_frame_subscribers());
+    drop(frames);
+    host.process().expect("Failed to process host");
+    assert!(!host.host().has_frame_subscribers());
+}
+
+#[test]
+fn waits_for_frame() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    let start = std::time::Instant::now();
+    assert!(conn
+        .wait_for_frame(Duration::from_millis(20))
+        .expect("Failed to wait for frame")
+        .is_none());
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    host.inject_solid_frame(16, 16, [5; 4])
+        .expect("Failed to inject frame");
+    let frame = conn
+        .wait_for_frame(Duration::from_secs(1))
+        .expect("Failed to wait for frame")
+        .expect("No frame was received");
+    assert_eq!(frame.data().expect("Frame data was invalid")[0], 5);
+}
+
+#[test]
+fn iterates_over_frames() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    for i in 0..2 {
+        host.inject_solid_frame(16, 16, [i; 4])
+            .expect("Failed to inject frame");
+    }
+
+    let frames: Vec<_> = conn
+        .frames()
+        .take(2)
+        .map(|frame| frame.expect("Failed to read frame"))
+        .collect();
+    let pixels: Vec<_> = frames.iter().map(|frame| frame.data()[0]).collect();
+    assert_eq!(pixels, [0, 1]);
+    assert_eq!(frames[0].info.data_width, 16);
+    assert_eq!(frames[1].info.rotation, Rotation::Rot0);
+
+    conn.close_session().expect("Failed to close session");
+    assert!(conn.frames().next().is_none());
+}
+
+#[test]
+fn reports_host_config_change_after_restart() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .auto_reconnect(true)
+        .detect_config_changes(true)
+        .settle_period(Duration::ZERO)
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    let mut host = host
+        .restart(HostFeatures::WINDOW_SIZE)
+        .expect("Failed to restart mock host");
+    let mut events = Vec::new();
+    let start = std::time::Instant::now();
+    while !events.contains(&"config") && start.elapsed() < Duration::from_secs(5) {
+        host.process().expect("Failed to process host");
+        match conn.poll_event().expect("Failed to poll for events") {
+            LGEvent::Reconnected => events.push("reconnected"),
+            LGEvent::HostConfigChanged => events.push("config"),
+            _ => std::thread::sleep(Duration::from_millis(1)),
+        }
+    }
+    assert_eq!(events, ["reconnected", "config"]);
+    assert!(conn.supports(HostFeatures::WINDOW_SIZE));
+}
+
+#[test]
+fn resumes_frames_after_host_restart() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .auto_reconnect(true)
+        .settle_period(Duration::ZERO)
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+    host.inject_solid_frame(16, 16, [1; 4])
+        .expect("Failed to inject frame");
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+
+    let mut host = host
+        .restart(HostFeatures::empty())
+        .expect("Failed to restart mock host");
+    let mut reconnected = false;
+    let mut pixel = None;
+    let start = std::time::Instant::now();
+    while pixel.is_none() && start.elapsed() < Duration::from_secs(5) {
+        host.process().expect("Failed to process host");
+        match conn.poll_event().expect("Failed to poll for events") {
+            LGEvent::Reconnected => {
+                reconnected = true;
+                host.inject_solid_frame(16, 16, [2; 4])
+                    .expect("Failed to inject frame");
+            }
+            LGEvent::Frame(frame) => pixel = Some(frame.data().expect("Invalid frame data")[0]),
+            _ => std::thread::sleep(Duration::from_millis(1)),
+        }
+    }
+    assert!(reconnected);
+    assert_eq!(pixel, Some(2));
+}
+
+#[test]
+fn reconnects_after_host_times_client_out() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host
+        .client_opts_builder()
+        .auto_reconnect(true)
+        .lifecycle_events(true)
+        .settle_period(Duration::ZERO)
+        .build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+    let client_id = conn.client_id().expect("No client id after init");
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::SessionStarted(_)
+    ));
+
+    //Leaving a frame unread for longer than the queue's timeout gets the client dropped
+    host.inject_solid_frame(16, 16, [1; 4])
+        .expect("Failed to inject frame");
+    std::thread::sleep(Duration::from_millis(1100));
+    host.process().expect("Failed to process host");
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::SessionLost(id) if id == client_id
+    ));
+
+    let start = std::time::Instant::now();
+    while conn.stats().reconnects == 0 && start.elapsed() < Duration::from_secs(5) {
+        host.process().expect("Failed to process host");
+        conn.poll_event().expect("Failed to poll for events");
+    }
+    assert_eq!(conn.stats().reconnects, 1);
+}
+
+#[test]
+fn reports_events_for_pulled_frames() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_some());
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::FormatChanged(_)
+    ));
+
+    //The frame queue only holds two frames
+    for i in 1..3 {
+        host.inject_solid_frame(16, 16, [i; 4])
+            .expect("Failed to inject frame");
+    }
+    let frame = conn
+        .get_latest_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.dropped_since_last(), 1);
+    drop(frame);
+    assert_eq!(conn.stats().frames_skipped, 1);
+    assert!(matches!(
+        conn.poll_event().expect("Failed to poll for events"),
+        LGEvent::Anomaly(Anomaly::FramesSkipped(1))
+    ));
+}
+
+#[test]
+fn counts_pulled_frames() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host.client_opts_builder().collect_metrics(true).build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    let mut read = |value| {
+        host.inject_solid_frame(16, 16, [value; 4])
+            .expect("Failed to inject frame");
+        assert!(conn
+            .get_frame_update()
+            .expect("Failed to read from frame channel")
+            .is_some());
+        conn.stats()
+    };
+    read(0);
+    assert_eq!(read(1).frames, 2);
+    //Rates are only published once the window they were counted over has passed
+    std::thread::sleep(Duration::from_millis(1100));
+    let stats = read(2);
+    assert_eq!(stats.frames, 3);
+    assert_eq!(stats.frames_per_sec, 2);
+    assert_eq!(stats.frames_skipped, 0);
+}
+
+#[test]
+fn returns_latest_frame() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+
+    //The frame queue only holds two frames
+    for i in 0..2 {
+        host.inject_solid_frame(16, 16, [i; 4])
+            .expect("Failed to inject frame");
+    }
+    let frame = conn
+        .get_latest_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.data().expect("Frame data was invalid")[0], 1);
+    assert_eq!(frame.dropped_since_last(), 0);
+    drop(frame);
+    assert!(conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .is_none());
+}
+
+#[test]
+fn ticks_from_accessors() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let opts = host.client_opts_builder().auto_tick(true).build();
+    let mut conn = host
+        .connect_with(opts)
+        .expect("Failed to connect to mock host");
+
+    //A new session has not emptied its queue yet, so is projected to time out straight away
+    for i in 0..2 {
+        host.inject_solid_frame(16, 16, [i; 4])
+            .expect("Failed to inject frame");
+    }
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read from frame channel")
+        .expect("No frame was received");
+    assert_eq!(frame.data().expect("Frame data was invalid")[0], 1);
+    drop(frame);
+    assert_eq!(conn.stats().fast_forwards, 1);
+}
+
+#[test]
+fn reports_connection_health() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    host.inject_solid_frame(16, 16, [0; 4])
+        .expect("Failed to inject frame");
+    poll_until_message(&mut conn);
+    //Reading from the empty queue shows that the client has caught up
+    conn.poll_event().expect("Failed to poll for events");
+    let report = conn.health().expect("Failed to check health");
+    assert_eq!(report.status, HealthStatus::Good);
+    assert!(report.reasons.is_empty());
+}
+
+#[cfg(feature = "alloc-count")]
+#[test]
+fn steady_state_frames_do_not_allocate() {
+    use lookinggla_rs::{
+        alloc_count::count_allocations,
+        convert::{self, ToneMap},
+        pool::{FramePool, FramePoolOpts},
+    };
+
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    let mut pool = FramePool::new(FramePoolOpts::default());
+    let mut rgba = Vec::new();
+
+    for i in 0..10 {
+        host.inject_solid_frame(64, 32, [0x10, 0x20, 0x30, 0xff])
+            .expect("Failed to inject frame");
+        let (_, count) = count_allocations(|| {
+            let frame = loop {
+                if let LGEvent::Frame(frame) = conn.poll_event().expect("Failed to poll") {
+                    break frame;
+                }
+            };
+            let owned = pool.copy_frame(&frame).expect("Failed to copy frame");
+            drop(frame);
+            let info = &owned.info;
+            convert::to_rgba8_into(
+                owned.data(),
+                info.data_width,
+                info.data_height,
+                info.pitch,
+                info.format,
+                ToneMap::Clamp,
+                &mut rgba,
+            );
+            while pool.poll_event().is_some() {}
+            owned.release_to(&mut pool);
+        });
+        //The first frame allocates buffers and reports a format change
+        if i > 0 {
+            assert_eq!(count, 0);
+        }
+    }
+}
+
+#[test]
+fn dispatches_to_callbacks() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let conn = host.connect().expect("Failed to connect to mock host");
+    host.inject_solid_frame(16, 16, [7; 4])
+        .expect("Failed to inject frame");
+    host.inject_cursor_position(12, 34)
+        .expect("Failed to inject cursor update");
+
+    let mut frames = Vec::new();
+    let mut cursors = Vec::new();
+    let mut dispatcher = Dispatcher::new(conn);
+    dispatcher
+        .on_frame(|info, data| frames.push((info.frame_width, data[0])))
+        .on_cursor(|cursor| {
+            let msg = cursor.as_ptr_msg().expect("Cursor message was malformed");
+            cursors.push((msg.x, msg.y));
+        });
+    let handled = dispatcher
+        .dispatch_pending()
+        .expect("Failed to dispatch events");
+    assert_eq!(handled, 2);
+
+    //With the session closed, run returns straight away
+    dispatcher
+        .connection()
+        .close_session()
+        .expect("Failed to close session");
+    dispatcher.run().expect("Failed to run dispatcher");
+    drop(dispatcher);
+    assert_eq!(frames, [(16, 7)]);
+    assert_eq!(cursors, [(12, 34)]);
+}
+
+#[test]
+fn splits_frame_into_chunks() {
+    let mut host = MockHost::new().expect("Failed to create mock host");
+    let mut conn = host.connect().expect("Failed to connect to mock host");
+    host.inject_solid_frame(16, 16, [3; 4])
+        .expect("Failed to inject frame");
+
+    let frame = conn
+        .get_frame_update()
+        .expect("Failed to read frame")
+        .expect("No frame was waiting");
+    let pitch = frame.info().expect("Frame was malformed").pitch as usize;
+    let chunks: Vec<_> = frame
+        .chunks(pitch * 5)
+        .expect("Failed to split frame")
+        .map(|chunk| chunk.rows)
+        .collect();
+    assert_eq!(chunks, [5, 5, 5, 1]);
+}